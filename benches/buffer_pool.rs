@@ -0,0 +1,71 @@
+//! Benchmarks of receiving request bodies with and without `BufferPool`
+//!
+//! Every iteration sends a `POST` request to a keep-alive connection
+//! served by `BufferedDispatcher`, so a body buffer is either allocated
+//! (and freed when the request is dropped) or drawn from the pool (and
+//! returned back) for each request.
+#[macro_use] extern crate criterion;
+extern crate futures;
+extern crate tk_bufstream;
+extern crate tokio_core;
+extern crate tokio_io;
+extern crate tk_http;
+
+mod common;
+
+use std::sync::Arc;
+
+use criterion::Criterion;
+use futures::future::ok;
+use tk_bufstream::MockData;
+
+use tk_http::Status;
+use tk_http::server::{Config, Encoder, BufferPool};
+use tk_http::server::buffered::{BufferedDispatcher, Request};
+
+use common::Server;
+
+
+fn request(size: usize) -> Vec<u8> {
+    let mut req = format!("POST / HTTP/1.1\r\nHost: example.com\r\n\
+                           Content-Length: {}\r\n\r\n", size).into_bytes();
+    req.extend(vec![b'x'; size]);
+    req
+}
+
+/// Benchmarks requests with `size` bytes of body
+fn serve(c: &mut Criterion, size: usize, pool: Option<Arc<BufferPool>>) {
+    let name = match pool {
+        Some(_) => format!("body_{}_pool", size),
+        None => format!("body_{}_alloc", size),
+    };
+    let mut server = Server::new(&Config::new().done(), |handle| {
+        let mut disp = BufferedDispatcher::new(
+            "127.0.0.1:1".parse().unwrap(), handle,
+            || |req: Request, mut e: Encoder<MockData>| {
+                e.status(Status::Ok);
+                e.add_length(0).unwrap();
+                e.done_headers().unwrap();
+                drop(req);
+                ok(e.done())
+            });
+        if let Some(ref pool) = pool {
+            disp.buffer_pool(pool);
+        }
+        disp
+    });
+    let request = request(size);
+    c.bench_function(&name, move |b| {
+        b.iter(|| server.serve(&request))
+    });
+}
+
+fn bodies(c: &mut Criterion) {
+    for &size in &[1024, 16384, 262144] {
+        serve(c, size, None);
+        serve(c, size, Some(Arc::new(BufferPool::new(size, 16))));
+    }
+}
+
+criterion_group!(benches, bodies);
+criterion_main!(benches);
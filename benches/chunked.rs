@@ -0,0 +1,91 @@
+//! Benchmarks of writing chunked response bodies
+//!
+//! Every iteration serves a request whose response is written as many
+//! small chunks, so the cost of chunk size lines dominates. To compare
+//! changes, run `cargo bench --bench chunked -- --save-baseline before` on
+//! the old code and `cargo bench --bench chunked -- --baseline before` on
+//! the new one.
+#[macro_use] extern crate criterion;
+extern crate futures;
+extern crate tk_bufstream;
+extern crate tokio_core;
+extern crate tokio_io;
+extern crate tk_http;
+
+mod common;
+
+use std::sync::Arc;
+
+use criterion::Criterion;
+use futures::Async;
+use futures::future::{ok, FutureResult};
+use tk_bufstream::MockData;
+
+use tk_http::Status;
+use tk_http::server::{Config, Dispatcher, Codec, Head, RecvMode};
+use tk_http::server::{Encoder, EncoderDone, Error};
+
+use common::Server;
+
+
+const REQUEST: &[u8] = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+
+struct Disp {
+    chunks: usize,
+    chunk: Arc<Vec<u8>>,
+}
+struct Chunks {
+    chunks: usize,
+    chunk: Arc<Vec<u8>>,
+}
+
+impl Dispatcher<MockData> for Disp {
+    type Codec = Chunks;
+    fn headers_received(&mut self, _headers: &Head)
+        -> Result<Self::Codec, Error>
+    {
+        Ok(Chunks { chunks: self.chunks, chunk: self.chunk.clone() })
+    }
+}
+
+impl Codec<MockData> for Chunks {
+    type ResponseFuture = FutureResult<EncoderDone<MockData>, Error>;
+    fn recv_mode(&mut self) -> RecvMode {
+        RecvMode::buffered_upfront(0)
+    }
+    fn data_received(&mut self, data: &[u8], end: bool)
+        -> Result<Async<usize>, Error>
+    {
+        assert!(end);
+        Ok(Async::Ready(data.len()))
+    }
+    fn start_response(&mut self, mut e: Encoder<MockData>)
+        -> Self::ResponseFuture
+    {
+        e.status(Status::Ok);
+        e.add_chunked().unwrap();
+        e.done_headers().unwrap();
+        for _ in 0..self.chunks {
+            e.write_body(&self.chunk);
+        }
+        ok(e.done())
+    }
+}
+
+/// Benchmarks responses of `chunks` chunks of `size` bytes each
+fn serve(c: &mut Criterion, chunks: usize, size: usize) {
+    let disp = Disp { chunks: chunks, chunk: Arc::new(vec![b'x'; size]) };
+    let mut server = Server::new(&Config::new().done(), |_| disp);
+    c.bench_function(&format!("chunked_{}x{}", chunks, size), move |b| {
+        b.iter(|| server.serve(REQUEST))
+    });
+}
+
+fn small_chunks(c: &mut Criterion) {
+    serve(c, 1024, 1);
+    serve(c, 1024, 16);
+    serve(c, 256, 1024);
+}
+
+criterion_group!(benches, small_chunks);
+criterion_main!(benches);
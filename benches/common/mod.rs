@@ -0,0 +1,66 @@
+//! Harness shared by the benchmarks of the server protocol
+#![allow(dead_code)]
+
+use std::sync::Arc;
+
+use futures::executor::{spawn, Notify, Spawn};
+use tk_bufstream::MockData;
+use tokio_core::reactor::{Core, Handle};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use tk_http::server::{Proto, Config, Dispatcher};
+
+
+/// Notifier of the task that is polled right in the benchmark loop
+pub struct Ignore;
+
+impl Notify for Ignore {
+    fn notify(&self, _id: usize) {}
+}
+
+/// A keep-alive connection served within the benchmark loop
+pub struct Server<S, D: Dispatcher<S>> {
+    // timeouts of the protocol are registered in the core
+    core: Core,
+    mock: MockData,
+    proto: Spawn<Proto<S, D>>,
+    notify: Arc<Ignore>,
+}
+
+impl<D: Dispatcher<MockData>> Server<MockData, D> {
+    /// Creates a connection, `disp` creates the dispatcher
+    pub fn new<F>(cfg: &Arc<Config>, disp: F) -> Server<MockData, D>
+        where F: FnOnce(&Handle) -> D,
+    {
+        let mock = MockData::new();
+        Server::with_transport(mock.clone(), mock, cfg, disp)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite, D: Dispatcher<S>> Server<S, D> {
+    /// Same as `new`, but `conn` is a transport wrapping `mock`
+    pub fn with_transport<F>(mock: MockData, conn: S, cfg: &Arc<Config>,
+        disp: F)
+        -> Server<S, D>
+        where F: FnOnce(&Handle) -> D,
+    {
+        let core = Core::new().unwrap();
+        let disp = disp(&core.handle());
+        // timeouts of the protocol must be polled within a task
+        let proto = spawn(Proto::new(conn, cfg, disp, &core.handle()));
+        Server {
+            core: core,
+            mock: mock,
+            proto: proto,
+            notify: Arc::new(Ignore),
+        }
+    }
+    /// Feeds `input` to the connection and returns everything written
+    pub fn serve(&mut self, input: &[u8]) -> Vec<u8> {
+        self.mock.add_input(input);
+        assert!(self.proto.poll_future_notify(&self.notify, 0).unwrap()
+                .is_not_ready());
+        let written = self.mock.output(..).len();
+        self.mock.get_output(written)
+    }
+}
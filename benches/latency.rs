@@ -0,0 +1,85 @@
+//! Benchmarks of the time until a response reaches the wire
+//!
+//! Every iteration sends a request on a keep-alive connection with
+//! pipelining disabled and measures the time until the whole response is
+//! written to the transport, for both values of
+//! `Config::no_delay_on_last_write`.
+#[macro_use] extern crate criterion;
+extern crate futures;
+extern crate tk_bufstream;
+extern crate tokio_core;
+extern crate tokio_io;
+extern crate tk_http;
+
+mod common;
+
+use criterion::Criterion;
+use futures::Async;
+use futures::future::{ok, FutureResult};
+use tk_bufstream::MockData;
+
+use tk_http::Status;
+use tk_http::server::{Config, Dispatcher, Codec, Head, RecvMode};
+use tk_http::server::{Encoder, EncoderDone, Error};
+
+use common::Server;
+
+
+const REQUEST: &[u8] = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+const RESPONSE: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello";
+
+struct Disp;
+struct Hello;
+
+impl Dispatcher<MockData> for Disp {
+    type Codec = Hello;
+    fn headers_received(&mut self, _headers: &Head)
+        -> Result<Self::Codec, Error>
+    {
+        Ok(Hello)
+    }
+}
+
+impl Codec<MockData> for Hello {
+    type ResponseFuture = FutureResult<EncoderDone<MockData>, Error>;
+    fn recv_mode(&mut self) -> RecvMode {
+        RecvMode::buffered_upfront(0)
+    }
+    fn data_received(&mut self, data: &[u8], end: bool)
+        -> Result<Async<usize>, Error>
+    {
+        assert!(end);
+        Ok(Async::Ready(data.len()))
+    }
+    fn start_response(&mut self, mut e: Encoder<MockData>)
+        -> Self::ResponseFuture
+    {
+        e.status(Status::Ok);
+        e.add_length(5).unwrap();
+        e.done_headers().unwrap();
+        e.write_body(b"hello");
+        ok(e.done())
+    }
+}
+
+fn serve(c: &mut Criterion, no_delay: bool) {
+    let cfg = Config::new()
+        .disable_pipelining(true)
+        .no_delay_on_last_write(no_delay)
+        .done();
+    let mut server = Server::new(&cfg, |_| Disp);
+    c.bench_function(&format!("latency_no_delay_{}", no_delay), move |b| {
+        b.iter(|| {
+            // response is on the wire by the time poll returns
+            assert_eq!(&server.serve(REQUEST)[..], RESPONSE);
+        })
+    });
+}
+
+fn latency(c: &mut Criterion) {
+    serve(c, true);
+    serve(c, false);
+}
+
+criterion_group!(benches, latency);
+criterion_main!(benches);
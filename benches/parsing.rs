@@ -0,0 +1,144 @@
+//! Benchmarks of the request parsing path of the server
+//!
+//! Every iteration feeds a request to a keep-alive connection and lets the
+//! protocol parse it, dispatch it and write a tiny response, so it
+//! measures the request line, headers and body parsing through the public
+//! `server::Proto` interface.
+#[macro_use] extern crate criterion;
+extern crate futures;
+extern crate tk_bufstream;
+extern crate tokio_core;
+extern crate tokio_io;
+extern crate tk_http;
+
+mod common;
+
+use criterion::Criterion;
+use futures::Async;
+use futures::future::{ok, FutureResult};
+use tk_bufstream::MockData;
+
+use tk_http::Status;
+use tk_http::server::{Config, Dispatcher, Codec, Head, RecvMode};
+use tk_http::server::{Encoder, EncoderDone, Error};
+
+use common::Server;
+
+
+struct Disp;
+struct NoContent;
+
+impl Dispatcher<MockData> for Disp {
+    type Codec = NoContent;
+    fn headers_received(&mut self, _headers: &Head)
+        -> Result<Self::Codec, Error>
+    {
+        Ok(NoContent)
+    }
+}
+
+impl Codec<MockData> for NoContent {
+    type ResponseFuture = FutureResult<EncoderDone<MockData>, Error>;
+    fn recv_mode(&mut self) -> RecvMode {
+        RecvMode::buffered_upfront(1 << 20)
+    }
+    fn data_received(&mut self, data: &[u8], end: bool)
+        -> Result<Async<usize>, Error>
+    {
+        assert!(end);
+        Ok(Async::Ready(data.len()))
+    }
+    fn start_response(&mut self, mut e: Encoder<MockData>)
+        -> Self::ResponseFuture
+    {
+        e.status(Status::NoContent);
+        e.done_headers().unwrap();
+        ok(e.done())
+    }
+}
+
+const CHROME: &str = "\
+    GET /static/js/app.min.js?v=20180414 HTTP/1.1\r\n\
+    Host: www.example.com\r\n\
+    Connection: keep-alive\r\n\
+    User-Agent: Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 \
+        (KHTML, like Gecko) Chrome/65.0.3325.181 Safari/537.36\r\n\
+    Accept: */*\r\n\
+    Referer: https://www.example.com/articles/2018/04/http-parsing\r\n\
+    Accept-Encoding: gzip, deflate, br\r\n\
+    Accept-Language: en-US,en;q=0.9,ru;q=0.8\r\n\
+    Cookie: _ga=GA1.2.1496783261.1523702619; \
+        _gid=GA1.2.612603378.1523702619; \
+        session=8f1a3b2c9d4e5f60718293a4b5c6d7e8\r\n\
+    If-None-Match: \"5ad1a0c4-1f2a3\"\r\n\
+    If-Modified-Since: Sat, 14 Apr 2018 06:24:04 GMT\r\n\
+    \r\n";
+
+const FIREFOX: &str = "\
+    GET /articles/2018/04/http-parsing HTTP/1.1\r\n\
+    Host: www.example.com\r\n\
+    User-Agent: Mozilla/5.0 (X11; Ubuntu; Linux x86_64; rv:59.0) \
+        Gecko/20100101 Firefox/59.0\r\n\
+    Accept: text/html,application/xhtml+xml,application/xml;q=0.9,\
+        */*;q=0.8\r\n\
+    Accept-Language: en-US,en;q=0.5\r\n\
+    Accept-Encoding: gzip, deflate, br\r\n\
+    DNT: 1\r\n\
+    Connection: keep-alive\r\n\
+    Upgrade-Insecure-Requests: 1\r\n\
+    Cache-Control: max-age=0\r\n\
+    \r\n";
+
+/// Benchmarks serving `request` over and over on the same connection
+fn serve(c: &mut Criterion, name: &str, request: Vec<u8>) {
+    let mut server = Server::new(&Config::new().done(), |_| Disp);
+    c.bench_function(name, move |b| {
+        b.iter(|| server.serve(&request))
+    });
+}
+
+fn with_headers(num: usize) -> Vec<u8> {
+    let mut request = String::from("GET / HTTP/1.1\r\nHost: example.com\r\n");
+    for i in 0..num {
+        request.push_str(&format!("X-Header-{}: some value {}\r\n", i, i));
+    }
+    request.push_str("\r\n");
+    request.into_bytes()
+}
+
+fn chunked(chunks: usize, size: usize) -> Vec<u8> {
+    let mut request = b"POST /upload HTTP/1.1\r\nHost: example.com\r\n\
+                        Transfer-Encoding: chunked\r\n\r\n".to_vec();
+    for _ in 0..chunks {
+        request.extend(format!("{:x}\r\n", size).as_bytes());
+        request.extend(vec![b'x'; size]);
+        request.extend(b"\r\n");
+    }
+    request.extend(b"0\r\n\r\n");
+    request
+}
+
+fn request_line(c: &mut Criterion) {
+    serve(c, "request_line_minimal",
+          b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n".to_vec());
+    serve(c, "request_line_long_target",
+          format!("GET /search?q={}&page=2 HTTP/1.1\r\n\
+                   Host: example.com\r\n\r\n", "a".repeat(1024))
+          .into_bytes());
+}
+
+fn headers(c: &mut Criterion) {
+    for &num in &[4, 16, 64] {
+        serve(c, &format!("headers_{}", num), with_headers(num));
+    }
+    serve(c, "headers_chrome", CHROME.as_bytes().to_vec());
+    serve(c, "headers_firefox", FIREFOX.as_bytes().to_vec());
+}
+
+fn chunked_body(c: &mut Criterion) {
+    serve(c, "chunked_16x1k", chunked(16, 1024));
+    serve(c, "chunked_1024x16", chunked(1024, 16));
+}
+
+criterion_group!(benches, request_line, headers, chunked_body);
+criterion_main!(benches);
@@ -0,0 +1,122 @@
+//! Benchmarks of writing small responses, counting write syscalls
+//!
+//! Head and body of a small response should reach the transport in a
+//! single write. Every iteration serves a request over a transport that
+//! counts `write` calls, and checks that every response took exactly one.
+#[macro_use] extern crate criterion;
+extern crate futures;
+extern crate tk_bufstream;
+extern crate tokio_core;
+extern crate tokio_io;
+extern crate tk_http;
+
+mod common;
+
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use criterion::Criterion;
+use futures::{Async, Poll};
+use futures::future::{ok, FutureResult};
+use tk_bufstream::MockData;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use tk_http::Status;
+use tk_http::server::{Config, Dispatcher, Codec, Head, RecvMode};
+use tk_http::server::{Encoder, EncoderDone, Error};
+
+use common::Server;
+
+
+const REQUEST: &[u8] = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+const RESPONSE: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello";
+
+/// Counts write calls (i.e. syscalls) made to the mock
+#[derive(Clone)]
+struct CountWrites(MockData, Arc<AtomicUsize>);
+
+struct Disp;
+struct Hello;
+
+impl io::Read for CountWrites {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl AsyncRead for CountWrites {}
+
+impl io::Write for CountWrites {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.1.fetch_add(1, Ordering::SeqCst);
+        self.0.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncWrite for CountWrites {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        Ok(Async::Ready(()))
+    }
+}
+
+impl Dispatcher<CountWrites> for Disp {
+    type Codec = Hello;
+    fn headers_received(&mut self, _headers: &Head)
+        -> Result<Self::Codec, Error>
+    {
+        Ok(Hello)
+    }
+}
+
+impl Codec<CountWrites> for Hello {
+    type ResponseFuture = FutureResult<EncoderDone<CountWrites>, Error>;
+    fn recv_mode(&mut self) -> RecvMode {
+        RecvMode::buffered_upfront(0)
+    }
+    fn data_received(&mut self, data: &[u8], end: bool)
+        -> Result<Async<usize>, Error>
+    {
+        assert!(end);
+        Ok(Async::Ready(data.len()))
+    }
+    fn start_response(&mut self, mut e: Encoder<CountWrites>)
+        -> Self::ResponseFuture
+    {
+        e.status(Status::Ok);
+        e.add_length(5).unwrap();
+        e.done_headers().unwrap();
+        e.write_body(b"hello");
+        ok(e.done())
+    }
+}
+
+/// Benchmarks `pipelined` requests sent at once
+fn serve(c: &mut Criterion, pipelined: usize) {
+    let mock = MockData::new();
+    let writes = Arc::new(AtomicUsize::new(0));
+    let conn = CountWrites(mock.clone(), writes.clone());
+    let mut server = Server::with_transport(mock, conn,
+                                            &Config::new().done(), |_| Disp);
+    let input = REQUEST.repeat(pipelined);
+    let output = RESPONSE.repeat(pipelined);
+    c.bench_function(&format!("small_responses_{}", pipelined), move |b| {
+        b.iter(|| {
+            let before = writes.load(Ordering::SeqCst);
+            assert_eq!(&server.serve(&input)[..], &output[..]);
+            // each response is written by a single syscall
+            assert_eq!(writes.load(Ordering::SeqCst) - before, pipelined);
+        })
+    });
+}
+
+fn small_responses(c: &mut Criterion) {
+    serve(c, 1);
+    serve(c, 4);
+}
+
+criterion_group!(benches, small_responses);
+criterion_main!(benches);
@@ -11,39 +11,61 @@ use tk_bufstream::Buf;
 
 use enums::Version;
 
+/// Hex digits of 64-bit `usize::MAX` plus `\r\n`
+const CHUNK_HEADER_MAX: usize = 18;
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
 quick_error! {
+    /// Error adding a header to the message
     #[derive(Debug)]
     pub enum HeaderError {
+        /// `Content-Length` is added twice
         DuplicateContentLength {
             description("Content-Length is added twice")
         }
+        /// `Transfer-Encoding` is added twice
         DuplicateTransferEncoding {
             description("Transfer-Encoding is added twice")
         }
+        /// Header name contains invalid characters
         InvalidHeaderName {
             description("Header name contains invalid characters")
         }
+        /// Header value contains invalid characters (i.e. newlines)
         InvalidHeaderValue {
             description("Header value contains invalid characters")
         }
+        /// `Transfer-Encoding` is added after `Content-Length`
         TransferEncodingAfterContentLength {
             description("Transfer encoding added when Content-Length is \
                 already specified")
         }
+        /// `Content-Length` is added after `Transfer-Encoding`
         ContentLengthAfterTransferEncoding {
             description("Content-Length added after Transfer-Encoding")
         }
+        /// Headers are finished without body length headers
         CantDetermineBodySize {
             description("Neither Content-Length nor Transfer-Encoding \
                 is present in the headers")
         }
+        /// Body length header is added as a normal header
         BodyLengthHeader {
             description("Content-Length and Transfer-Encoding must be set \
                 using the specialized methods")
         }
+        /// `Transfer-Encoding: chunked` is added to HTTP/1.0 message
+        ChunkedNotAllowed {
+            description("Chunked encoding is not allowed in HTTP/1.0")
+        }
+        /// Body length header is added to a message that can't have a body
         RequireBodyless {
             description("This message must not contain body length fields.")
         }
+        /// Header is added after headers are finished
+        HeadersDone {
+            description("Header is added after headers are finished")
+        }
     }
 }
 
@@ -61,7 +83,7 @@ pub enum MessageState {
     #[allow(dead_code)] // until we implement client requests
     RequestStart,
     /// Status line is already in the buffer.
-    Headers { body: Body, close: bool },
+    Headers { body: Body, close: bool, version: Version },
     /// The message contains a fixed size body.
     FixedHeaders { is_head: bool, close: bool, content_length: u64 },
     /// The message contains a chunked body.
@@ -100,6 +122,37 @@ fn invalid_header(value: &[u8]) -> bool {
     return value.iter().any(|&x| x == b'\r' || x == b'\n')
 }
 
+fn body_length_header(name: &str) -> bool {
+    name.eq_ignore_ascii_case("Content-Length")
+        || name.eq_ignore_ascii_case("Transfer-Encoding")
+}
+
+/// Validate headers before adding them to the message
+///
+/// Runs the same checks as `add_header` method of both server and client
+/// encoders: header name and value must not contain newlines, and body
+/// length headers (`Content-Length` and `Transfer-Encoding`) are not
+/// allowed. This is useful for proxies that collect headers from multiple
+/// sources, to fail before starting to write the message.
+pub fn validate_headers<N, V>(headers: &[(N, V)]) -> Result<(), HeaderError>
+    where N: AsRef<str>,
+          V: AsRef<[u8]>,
+{
+    for &(ref name, ref value) in headers {
+        let name = name.as_ref();
+        if body_length_header(name) {
+            return Err(HeaderError::BodyLengthHeader);
+        }
+        if invalid_header(name.as_bytes()) {
+            return Err(HeaderError::InvalidHeaderName);
+        }
+        if invalid_header(value.as_ref()) {
+            return Err(HeaderError::InvalidHeaderValue);
+        }
+    }
+    Ok(())
+}
+
 impl MessageState {
     /// Write status line.
     ///
@@ -132,7 +185,8 @@ impl MessageState {
                 if (code >= 100 && code < 200) || code == 204 || code == 304 {
                     body = Denied
                 }
-                *self = Headers { body: body, close: close };
+                *self = Headers { body: body, close: close,
+                                  version: version };
             }
             ref state => {
                 panic!("Called response_status() method on response \
@@ -141,6 +195,50 @@ impl MessageState {
         }
     }
 
+    /// Replace status line of the response started at `start` of the buffer
+    ///
+    /// Headers written so far are kept. Returns `RequireBodyless` if the
+    /// new status doesn't allow a body but body length is already added.
+    ///
+    /// # Panics
+    ///
+    /// When status line is not written or headers are finished, and when
+    /// the status code is 100 (Continue).
+    pub fn replace_status(&mut self, buf: &mut Buf, start: usize,
+        is_head: bool, code: u16, reason: &str)
+        -> Result<(), HeaderError>
+    {
+        use self::Body::*;
+        use self::MessageState::*;
+        assert!(code != 100);
+        let bodyless = (code >= 100 && code < 200) ||
+            code == 204 || code == 304;
+        match *self {
+            Headers { ref mut body, .. } => {
+                *body = if bodyless { Denied }
+                    else if is_head { Head }
+                    else { Normal };
+            }
+            FixedHeaders { .. } | ChunkedHeaders { .. } if bodyless => {
+                return Err(HeaderError::RequireBodyless);
+            }
+            FixedHeaders { .. } | ChunkedHeaders { .. } => {}
+            ref state => {
+                panic!("Called replace_status() method on response \
+                    in state {:?}", state)
+            }
+        }
+        let line_end = start + buf[start..].iter()
+            .position(|&c| c == b'\n')
+            .expect("status line is written") + 1;
+        let tail = buf[line_end..].to_vec();
+        // keep the version, i.e. `HTTP/1.1 `
+        buf.remove_range(start + 9..);
+        write!(buf, "{} {}\r\n", code, reason).unwrap();
+        buf.extend(&tail);
+        Ok(())
+    }
+
     /// Write request line.
     ///
     /// This puts request line into a buffer immediately. If you don't
@@ -161,7 +259,8 @@ impl MessageState {
                     method, path, version).unwrap();
                 // All requests may contain a body although it is uncommon for
                 // GET and HEAD requests to contain one.
-                *self = Headers { body: Request, close: false };
+                *self = Headers { body: Request, close: false,
+                                  version: version };
             }
             ref state => {
                 panic!("Called request_line() method on request in state {:?}",
@@ -195,6 +294,45 @@ impl MessageState {
         }
     }
 
+    /// Write a 103 (Early Hints) response with a `Link` header per link
+    ///
+    /// Can be called multiple times before the final status line. Nothing
+    /// is written for HTTP/1.0 as 1xx responses must not be sent to such
+    /// clients.
+    ///
+    /// # Panics
+    ///
+    /// When the final status line is already written.
+    pub fn response_early_hints(&mut self, buf: &mut Buf, links: &[&str])
+        -> Result<(), HeaderError>
+    {
+        use self::MessageState::*;
+        match *self {
+            ResponseStart { version, body, close } |
+            FinalResponseStart { version, body, close } => {
+                if version == Version::Http10 {
+                    return Ok(());
+                }
+                if links.iter().any(|x| invalid_header(x.as_bytes())) {
+                    return Err(HeaderError::InvalidHeaderValue);
+                }
+                write!(buf, "{} 103 Early Hints\r\n", version).unwrap();
+                for link in links {
+                    write!(buf, "Link: {}\r\n", link).unwrap();
+                }
+                buf.write_all(b"\r\n").unwrap();
+                *self = FinalResponseStart { version: version,
+                                            body: body,
+                                            close: close }
+            }
+            ref state => {
+                panic!("Called early_hints() method on response in state {:?}",
+                       state)
+            }
+        }
+        Ok(())
+    }
+
     fn write_header(&mut self, buf: &mut Buf, name: &str, value: &[u8])
         -> Result<(), HeaderError>
     {
@@ -262,8 +400,7 @@ impl MessageState {
     {
         use self::MessageState::*;
         use self::HeaderError::*;
-        if name.eq_ignore_ascii_case("Content-Length")
-            || name.eq_ignore_ascii_case("Transfer-Encoding") {
+        if body_length_header(name) {
             return Err(BodyLengthHeader)
         }
         match *self {
@@ -289,8 +426,7 @@ impl MessageState {
     {
         use self::MessageState::*;
         use self::HeaderError::*;
-        if name.eq_ignore_ascii_case("Content-Length")
-            || name.eq_ignore_ascii_case("Transfer-Encoding") {
+        if body_length_header(name) {
             return Err(BodyLengthHeader)
         }
         match *self {
@@ -323,7 +459,7 @@ impl MessageState {
             FixedHeaders { .. } => Err(DuplicateContentLength),
             ChunkedHeaders { .. } => Err(ContentLengthAfterTransferEncoding),
             Headers { body: Denied, .. } => Err(RequireBodyless),
-            Headers { body, close } => {
+            Headers { body, close, .. } => {
                 self.write_formatted(buf, "Content-Length", n)?;
                 *self = FixedHeaders { is_head: body == Head,
                                         close: close,
@@ -343,6 +479,9 @@ impl MessageState {
     /// It is assured that there is only one body length header is present
     /// and the body is written in chunked encoding.
     ///
+    /// Returns `ChunkedNotAllowed` for HTTP/1.0 messages, as chunked
+    /// encoding is defined only since HTTP/1.1.
+    ///
     /// # Panics
     ///
     /// Panics when `add_chunked` is called in the wrong state.
@@ -355,7 +494,9 @@ impl MessageState {
                 FixedHeaders { .. } => Err(TransferEncodingAfterContentLength),
                 ChunkedHeaders { .. } => Err(DuplicateTransferEncoding),
                 Headers { body: Denied, .. } => Err(RequireBodyless),
-                Headers { body, close } => {
+                Headers { version: Version::Http10, .. }
+                => Err(ChunkedNotAllowed),
+                Headers { body, close, .. } => {
                     self.write_header(buf, "Transfer-Encoding", b"chunked")?;
                     *self = ChunkedHeaders { is_head: body == Head,
                                               close: close };
@@ -368,6 +509,12 @@ impl MessageState {
         }
     }
 
+    /// Returns false if message is HTTP/1.0, so chunked encoding can't be used
+    pub fn chunked_allowed(&self) -> bool {
+        !matches!(*self,
+            MessageState::Headers { version: Version::Http10, .. })
+    }
+
     /// Returns true if at least `status()` method has been called
     ///
     /// This is mostly useful to find out whether we can build an error page
@@ -379,6 +526,27 @@ impl MessageState {
             MessageState::FinalResponseStart { .. })
     }
 
+    /// Returns true if message body is going to be sent
+    ///
+    /// This is `false` for responses to `HEAD` requests and for 1xx, 204
+    /// and 304 responses (the latter is only known after status is written).
+    /// Same value is returned by `done_headers()`.
+    pub fn allows_body(&self) -> bool {
+        use self::Body::*;
+        use self::MessageState::*;
+        match *self {
+            RequestStart => true,
+            ResponseStart { body, .. } |
+            FinalResponseStart { body, .. } |
+            Headers { body, .. } => body == Normal || body == Request,
+            FixedHeaders { is_head, .. } |
+            ChunkedHeaders { is_head, .. } |
+            FixedBody { is_head, .. } |
+            ChunkedBody { is_head } => !is_head,
+            Bodyless | Done => false,
+        }
+    }
+
     /// Closes the HTTP header and returns `true` if entity body is expected.
     ///
     /// Specifically `false` is returned when status is 1xx, 204, 304 or in
@@ -467,7 +635,8 @@ impl MessageState {
                 *content_length -= data.len() as u64;
             }
             ChunkedBody { is_head } => if !is_head && data.len() > 0 {
-                write!(buf, "{:x}\r\n", data.len()).unwrap();
+                let mut header = [0u8; CHUNK_HEADER_MAX];
+                buf.extend(chunk_header(data.len(), &mut header));
                 buf.write(data).unwrap();
                 buf.write(b"\r\n").unwrap();
             },
@@ -477,6 +646,25 @@ impl MessageState {
             }
         }
     }
+    /// Write exactly one chunk of the chunked message body.
+    ///
+    /// Unlike `write_body` this asserts that the body is chunked, so the
+    /// caller controls chunk boundaries. Still, empty `data` is ignored,
+    /// because zero-length chunk would terminate the body.
+    ///
+    /// # Panics
+    ///
+    /// When message is not in the chunked body state.
+    pub fn write_chunk(&mut self, buf: &mut Buf, data: &[u8]) {
+        use self::MessageState::*;
+        match *self {
+            ChunkedBody { .. } => self.write_body(buf, data),
+            ref state => {
+                panic!("Called write_chunk() method on message \
+                    in state {:?}", state)
+            }
+        }
+    }
     /// Returns true if headers are already sent (buffered)
     pub fn is_after_headers(&self) -> bool {
         use self::MessageState::*;
@@ -513,19 +701,44 @@ impl MessageState {
                 *self = Done;
             }
             Done => {}  // multiple invocations are okay.
-            ref state => {
-                panic!("Called done() method on response in state {:?}",
-                       state);
+            Headers { .. } | FixedHeaders { .. } | ChunkedHeaders { .. } => {
+                panic!("Called done() before done_headers(), \
+                        headers of the message are not finished");
+            }
+            ResponseStart { .. } | FinalResponseStart { .. } | RequestStart
+            => {
+                panic!("Called done() before the status line \
+                        (or request line) is written");
             }
         }
     }
 }
 
+/// Formats chunk size line (e.g. `1f\r\n`) into a stack buffer
+///
+/// This is called for every write of a chunked body, so we avoid going
+/// through `fmt` machinery here.
+fn chunk_header(mut len: usize, buf: &mut [u8; CHUNK_HEADER_MAX]) -> &[u8] {
+    let mut pos = CHUNK_HEADER_MAX - 2;
+    buf[pos] = b'\r';
+    buf[pos+1] = b'\n';
+    loop {
+        pos -= 1;
+        buf[pos] = HEX_DIGITS[len & 0xF];
+        len >>= 4;
+        if len == 0 {
+            break;
+        }
+    }
+    &buf[pos..]
+}
+
 #[cfg(test)]
 mod test {
     use tk_bufstream::{Buf};
 
-    use super::{MessageState, Body};
+    use super::{MessageState, Body, HeaderError, validate_headers};
+    use super::{chunk_header, CHUNK_HEADER_MAX};
     use enums::Version;
 
     #[test]
@@ -594,6 +807,33 @@ mod test {
         })[..], "HTTP/1.0 200 OK\r\nContent-Length: 0\r\n\r\n".as_bytes());
     }
 
+    #[test]
+    fn chunked_response10() {
+        do_response10(|mut msg, buf| {
+            msg.response_status(buf, 200, "OK");
+            assert!(!msg.chunked_allowed());
+            assert_matches!(msg.add_chunked(buf),
+                Err(HeaderError::ChunkedNotAllowed));
+            msg.add_length(buf, 0).unwrap();
+        });
+        do_request(|mut msg, buf| {
+            msg.request_line(buf, "POST", "/", Version::Http10);
+            assert_matches!(msg.add_chunked(buf),
+                Err(HeaderError::ChunkedNotAllowed));
+        });
+    }
+
+    #[test]
+    fn chunked_response11() {
+        assert_eq!(&do_response11(false, |mut msg, buf| {
+            msg.response_status(buf, 200, "OK");
+            assert!(msg.chunked_allowed());
+            msg.add_chunked(buf).unwrap();
+            msg.done_headers(buf).unwrap();
+        })[..], "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n"
+                .as_bytes());
+    }
+
     #[test]
     fn minimal_response11() {
         assert_eq!(&do_response11(false, |mut msg, buf| {
@@ -623,6 +863,51 @@ mod test {
         })[..], "HEAD / HTTP/1.1\r\nContent-Length: 5\r\n\r\nHello".as_bytes());
     }
 
+    #[test]
+    fn chunks() {
+        assert_eq!(&do_response11(false, |mut msg, buf| {
+            msg.response_status(buf, 200, "OK");
+            msg.add_chunked(buf).unwrap();
+            msg.done_headers(buf).unwrap();
+            msg.write_chunk(buf, b"Hello");
+            msg.write_chunk(buf, b"");
+            msg.write_chunk(buf, b" world!");
+            msg.done(buf);
+        })[..], concat!("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n",
+                        "\r\n5\r\nHello\r\n7\r\n world!\r\n0\r\n\r\n")
+                .as_bytes());
+    }
+
+    #[test]
+    fn chunk_headers() {
+        let mut buf = [0u8; CHUNK_HEADER_MAX];
+        for &len in &[1, 9, 10, 15, 16, 255, 4096, 0xdeadbeef,
+                      ::std::u32::MAX as usize, ::std::usize::MAX] {
+            assert_eq!(chunk_header(len, &mut buf),
+                       format!("{:x}\r\n", len).as_bytes());
+        }
+        // long chunks go through the same path as short ones
+        let body = vec![b'x'; 0x1234];
+        assert!(do_response11(false, |mut msg, buf| {
+            msg.response_status(buf, 200, "OK");
+            msg.add_chunked(buf).unwrap();
+            msg.done_headers(buf).unwrap();
+            msg.write_body(buf, &body);
+        })[..].ends_with(&[&b"\r\n\r\n1234\r\n"[..], &body, b"\r\n"]
+                          .concat()));
+    }
+
+    #[test]
+    #[should_panic(expected="write_chunk")]
+    fn chunk_in_fixed_body() {
+        do_response11(false, |mut msg, buf| {
+            msg.response_status(buf, 200, "OK");
+            msg.add_length(buf, 5).unwrap();
+            msg.done_headers(buf).unwrap();
+            msg.write_chunk(buf, b"Hello");
+        });
+    }
+
     #[test]
     fn head_response() {
         // The response to a HEAD request may contain the real body length.
@@ -642,4 +927,72 @@ mod test {
             msg.done_headers(buf).unwrap();
         })[..], "HTTP/1.1 142 Foo\r\n\r\n".as_bytes());
     }
+
+    #[test]
+    fn done_headers_expects_body() {
+        do_request(|mut msg, buf| {
+            msg.request_line(buf, "GET", "/", Version::Http11);
+            assert!(msg.done_headers(buf).unwrap());
+            assert!(msg.allows_body());
+        });
+        do_response11(false, |mut msg, buf| {
+            msg.response_status(buf, 200, "OK");
+            msg.add_length(buf, 2).unwrap();
+            assert!(msg.done_headers(buf).unwrap());
+            msg.write_body(buf, b"ok");
+        });
+        do_head_response11(false, |mut msg, buf| {
+            msg.response_status(buf, 200, "OK");
+            msg.add_chunked(buf).unwrap();
+            assert!(!msg.done_headers(buf).unwrap());
+            // body is silently dropped
+            msg.write_body(buf, b"ok");
+            msg.done(buf);
+        });
+        assert_eq!(&do_response11(false, |mut msg, buf| {
+            msg.response_status(buf, 204, "No Content");
+            assert!(!msg.done_headers(buf).unwrap());
+            assert!(!msg.allows_body());
+            msg.done(buf);
+        })[..], "HTTP/1.1 204 No Content\r\n\r\n".as_bytes());
+    }
+
+    #[test]
+    #[should_panic(expected="must not contain body")]
+    fn body_in_bodyless_response() {
+        do_response11(false, |mut msg, buf| {
+            msg.response_status(buf, 304, "Not Modified");
+            assert!(!msg.done_headers(buf).unwrap());
+            msg.write_body(buf, b"ok");
+        });
+    }
+
+    #[test]
+    fn validate_valid_headers() {
+        validate_headers(&[("Content-Type", "text/plain"),
+                           ("X-Custom", "")]).unwrap();
+        validate_headers(&[(String::from("X-Bytes"), vec![0xFFu8, 0x20])])
+            .unwrap();
+        validate_headers::<&str, &str>(&[]).unwrap();
+    }
+
+    #[test]
+    fn validate_injection() {
+        assert_matches!(validate_headers(&[
+                ("X-Ok", "value"),
+                ("X-Injected", "a\r\nSet-Cookie: evil=1"),
+            ]), Err(HeaderError::InvalidHeaderValue));
+        assert_matches!(validate_headers(&[("X-Bad\r\nX-Other", "a")]),
+            Err(HeaderError::InvalidHeaderName));
+    }
+
+    #[test]
+    fn validate_length_header() {
+        assert_matches!(validate_headers(&[
+                ("Content-Type", "text/plain"),
+                ("content-length", "10"),
+            ]), Err(HeaderError::BodyLengthHeader));
+        assert_matches!(validate_headers(&[("Transfer-Encoding", "chunked")]),
+            Err(HeaderError::BodyLengthHeader));
+    }
 }
@@ -4,10 +4,9 @@ use tk_bufstream::ReadBuf;
 
 use chunked;
 
-// TODO(tailhook) review usizes here, probaby we may accept u64
 #[derive(Debug, Clone)]
 pub enum BodyProgress {
-    Fixed(usize), // bytes left
+    Fixed(u64), // bytes left (may not fit usize on 32-bit platforms)
     Eof, // only for client implemementation
     Chunked(chunked::State),
 }
@@ -17,7 +16,7 @@ impl BodyProgress {
     pub fn check_buf<S>(&self, io: &ReadBuf<S>) -> (usize, bool) {
         use self::BodyProgress::*;
         match *self {
-            Fixed(x) if x <= io.in_buf.len() => (x, true),
+            Fixed(x) if x <= io.in_buf.len() as u64 => (x as usize, true),
             Fixed(_) => (io.in_buf.len(), false),
             Chunked(ref s) => (s.buffered(), s.is_done()),
             Eof => (io.in_buf.len(), io.done()),
@@ -39,8 +38,8 @@ impl BodyProgress {
         io.in_buf.consume(n);
         match *self {
             Fixed(ref mut x) => {
-                assert!(*x >= n);
-                *x -= n;
+                assert!(*x >= n as u64);
+                *x -= n as u64;
             }
             Chunked(ref mut s) => s.consume(n),
             Eof => {}
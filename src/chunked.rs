@@ -1,6 +1,18 @@
+use std::str;
+use std::usize;
+
 use httparse::{InvalidChunkSize, parse_chunk_size};
 use tk_bufstream::Buf;
 
+use negotiation::valid_token;
+
+
+/// Maximum number of trailer fields, same as the limit on headers
+const MAX_TRAILER_FIELDS: usize = 1024;
+/// Maximum size of the whole trailer in bytes
+///
+/// Trailer fields are skipped, so there is no reason to accept large ones
+const MAX_TRAILER_SIZE: usize = 65536;
 
 // TODO(tailhook) review usizes here, probaby we may accept u64
 #[derive(Debug, Clone, PartialEq)]
@@ -8,35 +20,117 @@ pub struct State {
     buffered: usize,
     pending: usize,
     done: bool,
+    max_chunk_size: u64,
+    max_trailer_value_size: usize,
+    lenient: bool,
+    trailer: Option<Trailer>,
+}
+
+/// Progress of skipping the trailer, offsets are relative to `buffered`
+#[derive(Debug, Clone, PartialEq, Default)]
+struct Trailer {
+    /// Bytes of complete (and validated) trailer lines
+    line_start: usize,
+    /// Bytes already searched for the line end
+    searched: usize,
+    fields: usize,
+}
+
+/// Returns true if chunk size is followed by whitespace and a line end
+///
+/// E.g. `5 \r\n`, this is not allowed by the grammar. Note: whitespace
+/// before chunk extension (`5 ;ext`) is allowed.
+fn trailing_whitespace(line: &[u8]) -> bool {
+    let digits = line.iter()
+        .position(|c| !c.is_ascii_hexdigit())
+        .unwrap_or(line.len());
+    let rest = &line[digits..];
+    let spaces = rest.iter()
+        .position(|&c| c != b' ' && c != b'\t')
+        .unwrap_or(rest.len());
+    spaces > 0 && rest.get(spaces) == Some(&b'\r')
+}
+
+/// Returns true if line (without `\r\n`) is a valid trailer field
+fn valid_trailer_field(line: &[u8], max_value_size: usize) -> bool {
+    let colon = match line.iter().position(|&c| c == b':') {
+        Some(x) => x,
+        None => return false,
+    };
+    let value = &line[colon+1..];
+    let ows = |c: &u8| *c == b' ' || *c == b'\t';
+    let trimmed = match value.iter().position(|c| !ows(c)) {
+        Some(first) => value.iter().rposition(|c| !ows(c))
+            .map_or(0, |last| last + 1 - first),
+        None => 0,
+    };
+    str::from_utf8(&line[..colon]).map(valid_token).unwrap_or(false) &&
+        trimmed <= max_value_size &&
+        value.iter().all(|&c| c == b'\t' || c >= b' ' && c != 0x7F)
 }
 
 impl State {
     pub fn new() -> State {
+        State::with_max_chunk_size(usize::MAX)
+    }
+    pub fn with_max_chunk_size(max_chunk_size: usize) -> State {
         State {
             buffered: 0,
             pending: 0,
             done: false,
+            max_chunk_size: max_chunk_size as u64,
+            max_trailer_value_size: usize::MAX,
+            lenient: false,
+            trailer: None,
         }
     }
+    /// Maximum size of a value of a trailer field
+    ///
+    /// Trailer as a whole is limited to 1024 fields and 64KiB regardless
+    /// of this setting.
+    pub fn max_trailer_value_size(self, value: usize) -> State {
+        State { max_trailer_value_size: value, ..self }
+    }
+    /// Allow whitespace after the chunk size (e.g. `5 \r\n`)
+    ///
+    /// By default such chunk size lines are rejected
+    pub fn lenient(self, value: bool) -> State {
+        State { lenient: value, ..self }
+    }
     pub fn parse(&mut self, buf: &mut Buf) -> Result<(), InvalidChunkSize> {
-        let State { ref mut buffered, ref mut pending, ref mut done } = *self;
+        if self.trailer.is_some() {
+            return self.skip_trailer(buf);
+        }
+        let State { ref mut buffered, ref mut pending, ref mut done,
+                    ref mut trailer, max_chunk_size, lenient, .. } = *self;
         if *done {
             return Ok(());
         }
         while *buffered < buf.len() {
             if *pending == 0 {
                 use httparse::Status::*;
+                if !lenient && trailing_whitespace(&buf[*buffered..]) {
+                    return Err(InvalidChunkSize);
+                }
                 match parse_chunk_size(&buf[*buffered..])? {
                     Complete((bytes, 0)) => {
-                        buf.remove_range(
-                            *buffered..*buffered+bytes);
-                        *done = true;
+                        // body is complete only when the empty line ending
+                        // the message is received (trailer fields, if any,
+                        // are skipped), anything after it is the next
+                        // message
+                        buf.remove_range(*buffered..*buffered+bytes);
+                        *trailer = Some(Trailer::default());
+                        break;
                     }
                     Complete((bytes, chunk_size)) => {
+                        // httparse rejects sizes that overflow u64, but we
+                        // also must fit in usize and in the limit
+                        if chunk_size > max_chunk_size {
+                            return Err(InvalidChunkSize);
+                        }
                         // TODO(tailhook) optimized multiple removes
                         buf.remove_range(
                             *buffered..*buffered+bytes);
-                        // TODO(tailhook) check that chunk_size < u32
                         *pending = chunk_size as usize;
                     }
                     Partial => {
@@ -55,8 +149,63 @@ impl State {
                 }
             }
         }
+        if self.trailer.is_some() {
+            return self.skip_trailer(buf);
+        }
         Ok(())
     }
+    /// Skips trailer fields up to and including the empty line
+    ///
+    /// Only the part received since the previous call is searched for the
+    /// line end, so a slowly received trailer isn't rescanned each time.
+    fn skip_trailer(&mut self, buf: &mut Buf)
+        -> Result<(), InvalidChunkSize>
+    {
+        let start = self.buffered;
+        let max_value_size = self.max_trailer_value_size;
+        loop {
+            let end = {
+                let t = self.trailer.as_mut().expect("skipping trailer");
+                let search = start + t.searched;
+                let end = match buf[search..].iter().position(|&c| c == b'\n')
+                {
+                    Some(pos) => search + pos,
+                    None => {
+                        t.searched = buf.len() - start;
+                        if t.searched > MAX_TRAILER_SIZE {
+                            return Err(InvalidChunkSize);
+                        }
+                        return Ok(());
+                    }
+                };
+                let line = &buf[start + t.line_start..end];
+                if line.len() == 0 || line[line.len()-1] != b'\r' {
+                    // bare LF
+                    return Err(InvalidChunkSize);
+                }
+                let line = &line[..line.len()-1];
+                if line.len() > 0 {
+                    if !valid_trailer_field(line, max_value_size) {
+                        return Err(InvalidChunkSize);
+                    }
+                    t.fields += 1;
+                    t.line_start = end + 1 - start;
+                    t.searched = t.line_start;
+                    if t.fields > MAX_TRAILER_FIELDS ||
+                        t.line_start > MAX_TRAILER_SIZE
+                    {
+                        return Err(InvalidChunkSize);
+                    }
+                    continue;
+                }
+                end + 1
+            };
+            buf.remove_range(start..end);
+            self.trailer = None;
+            self.done = true;
+            return Ok(());
+        }
+    }
     pub fn buffered(&self) -> usize {
         self.buffered
     }
@@ -72,6 +221,7 @@ impl State {
 #[cfg(test)]
 mod test {
     use super::State;
+    use httparse::InvalidChunkSize;
     use tk_bufstream::Buf;
 
     #[test]
@@ -80,12 +230,162 @@ mod test {
         let mut buf = Buf::new();
         buf.extend(b"4\r\nhell\r\n");
         assert_eq!(state.parse(&mut buf), Ok(()));
-        assert_eq!(state, State { buffered: 4, pending: 0, done: false });
+        assert_eq!(state, State { buffered: 4, pending: 0, done: false,
+                                   ..State::new() });
         state.consume(4);
         buf.consume(4);
         assert_eq!(state.buffered, 0);
         buf.extend(b"0\r\n");
         assert_eq!(state.parse(&mut buf), Ok(()));
-        assert_eq!(state, State { buffered: 0, pending: 0, done: true });
+        assert_eq!(state, State { buffered: 0, pending: 0, done: false,
+                                   trailer: Some(Default::default()),
+                                   ..State::new() });
+        buf.extend(b"\r\n");
+        assert_eq!(state.parse(&mut buf), Ok(()));
+        assert_eq!(state, State { buffered: 0, pending: 0, done: true,
+                                   ..State::new() });
+        assert_eq!(buf.len(), 0);
+    }
+
+    #[test]
+    fn trailers() {
+        let mut state = State::new();
+        let mut buf = Buf::new();
+        buf.extend(b"5\r\nhello\r\n0\r\nX-Checksum: 1");
+        assert_eq!(state.parse(&mut buf), Ok(()));
+        assert!(!state.is_done());
+        buf.extend(b"\r\nX-Other: 2\r\n\r\nnext");
+        assert_eq!(state.parse(&mut buf), Ok(()));
+        assert!(state.is_done());
+        assert_eq!(&buf[..], b"hellonext");
+    }
+
+    #[test]
+    fn trailer_byte_by_byte() {
+        let mut state = State::new();
+        let mut buf = Buf::new();
+        buf.extend(b"5\r\nhello\r\n0\r\n");
+        assert_eq!(state.parse(&mut buf), Ok(()));
+        for &c in b"X-Checksum: 1\r\nX-Other: 2\r\n" {
+            buf.extend(&[c]);
+            assert_eq!(state.parse(&mut buf), Ok(()));
+            assert!(!state.is_done());
+        }
+        assert_eq!(state.trailer.as_ref().unwrap().fields, 2);
+        buf.extend(b"\r");
+        assert_eq!(state.parse(&mut buf), Ok(()));
+        assert!(!state.is_done());
+        buf.extend(b"\n");
+        assert_eq!(state.parse(&mut buf), Ok(()));
+        assert!(state.is_done());
+        assert_eq!(&buf[..], b"hello");
+    }
+
+    #[test]
+    fn invalid_trailer() {
+        for trailer in &[&b"X-Checksum\r\n"[..], b"X Checksum: 1\r\n",
+                         b": 1\r\n", b"X-Checksum: \x01\r\n",
+                         b" folded\r\n", b"X-Checksum: 1\n\r\n"]
+        {
+            let mut state = State::new();
+            let mut buf = Buf::new();
+            buf.extend(b"5\r\nhello\r\n0\r\n");
+            buf.extend(trailer);
+            assert_eq!(state.parse(&mut buf), Err(InvalidChunkSize),
+                       "{:?}", String::from_utf8_lossy(trailer));
+        }
+    }
+
+    #[test]
+    fn trailer_limits() {
+        let mut state = State::new();
+        let mut buf = Buf::new();
+        buf.extend(b"0\r\n");
+        for _ in 0..1025 {
+            buf.extend(b"X: 1\r\n");
+        }
+        assert_eq!(state.parse(&mut buf), Err(InvalidChunkSize));
+
+        let mut state = State::new();
+        let mut buf = Buf::new();
+        buf.extend(b"0\r\nX: ");
+        assert_eq!(state.parse(&mut buf), Ok(()));
+        buf.extend(&vec![b'x'; 65536]);
+        assert_eq!(state.parse(&mut buf), Err(InvalidChunkSize));
+
+        let mut state = State::new().max_trailer_value_size(5);
+        let mut buf = Buf::new();
+        buf.extend(b"0\r\nX:  12345 \r\nX: 123456\r\n\r\n");
+        assert_eq!(state.parse(&mut buf), Err(InvalidChunkSize));
+    }
+
+    #[test]
+    fn next_message() {
+        let mut state = State::new();
+        let mut buf = Buf::new();
+        buf.extend(b"5\r\nhello\r\n0\r\n\r\nHTTP/1.1 200 OK\r\n");
+        assert_eq!(state.parse(&mut buf), Ok(()));
+        assert!(state.is_done());
+        assert_eq!(&buf[..], b"helloHTTP/1.1 200 OK\r\n");
+    }
+
+    #[test]
+    fn leading_zeros() {
+        let mut state = State::new();
+        let mut buf = Buf::new();
+        buf.extend(b"0000000000000004\r\nhell\r\n");
+        assert_eq!(state.parse(&mut buf), Ok(()));
+        assert_eq!(state.buffered(), 4);
+        assert_eq!(&buf[..], b"hell");
+    }
+
+    #[test]
+    fn overflow() {
+        let mut state = State::new();
+        let mut buf = Buf::new();
+        buf.extend(b"10000000000000000\r\n");
+        assert_eq!(state.parse(&mut buf), Err(InvalidChunkSize));
+        let mut state = State::new();
+        let mut buf = Buf::new();
+        buf.extend(b"fffffffffffffffffff\r\n");
+        assert_eq!(state.parse(&mut buf), Err(InvalidChunkSize));
+    }
+
+    #[test]
+    fn max_chunk_size() {
+        let mut state = State::with_max_chunk_size(4);
+        let mut buf = Buf::new();
+        buf.extend(b"4\r\nhell\r\n5\r\nhello\r\n");
+        assert_eq!(state.parse(&mut buf), Err(InvalidChunkSize));
+        assert_eq!(state.buffered(), 4);
+    }
+
+    #[test]
+    fn trailing_whitespace_strict() {
+        let mut state = State::new();
+        let mut buf = Buf::new();
+        buf.extend(b"5 \r\nhello\r\n");
+        assert_eq!(state.parse(&mut buf), Err(InvalidChunkSize));
+        let mut state = State::new();
+        let mut buf = Buf::new();
+        buf.extend(b"5\t\r\nhello\r\n");
+        assert_eq!(state.parse(&mut buf), Err(InvalidChunkSize));
+        // whitespace before extension is fine
+        let mut state = State::new();
+        let mut buf = Buf::new();
+        buf.extend(b"5 ;x=y\r\nhello\r\n");
+        assert_eq!(state.parse(&mut buf), Ok(()));
+        assert_eq!(&buf[..], b"hello");
+    }
+
+    #[test]
+    fn trailing_whitespace_lenient() {
+        let mut state = State::new().lenient(true);
+        let mut buf = Buf::new();
+        buf.extend(b"5 \r\nhello\r\n0  \r\n\r\n");
+        assert_eq!(state.parse(&mut buf), Ok(()));
+        assert_eq!(state.buffered(), 5);
+        assert!(state.is_done());
+        assert_eq!(&buf[..], b"hello");
     }
 }
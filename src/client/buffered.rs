@@ -9,6 +9,9 @@
 //! but requires more boilerplate. You can mix and match different
 //! styles on single HTTP connection.
 //!
+use std::borrow::Cow;
+use std::slice;
+
 use url::Url;
 use futures::Async;
 use futures::future::{FutureResult, ok};
@@ -16,14 +19,16 @@ use futures::sync::oneshot::{channel, Sender, Receiver};
 
 use enums::Status;
 use enums::Version;
+use enums::Method;
 use client::{Error, Codec, Encoder, EncoderDone, Head, RecvMode};
+use headers::intern_name;
 use client::errors::ErrorEnum;
 
 /// Fully buffered (in-memory) writing request and reading response
 ///
 /// This coded should be used when you don't have any special needs
 pub struct Buffered {
-    method: &'static str,
+    method: Method,
     url: Url,
     sender: Option<Sender<Result<Response, Error>>>,
     response: Option<Response>,
@@ -34,17 +39,41 @@ pub struct Buffered {
 /// A buffered response holds contains a body as contiguous chunk of data
 pub struct Response {
     status: Status,
-    headers: Vec<(String, Vec<u8>)>,
+    headers: Vec<(Cow<'static, str>, Vec<u8>)>,
     body: Vec<u8>,
 }
 
+/// Iterator over the headers of the buffered response
+#[derive(Debug)]
+pub struct HeaderIter<'a> {
+    iter: slice::Iter<'a, (Cow<'static, str>, Vec<u8>)>,
+}
+
+impl<'a> Iterator for HeaderIter<'a> {
+    type Item = (&'a str, &'a [u8]);
+    fn next(&mut self) -> Option<(&'a str, &'a [u8])> {
+        self.iter.next().map(|&(ref name, ref value)| (&name[..], &value[..]))
+    }
+}
+
 impl Response {
     /// Get response status
     pub fn status(&self) -> Status {
         self.status
     }
     /// Get response headers
-    pub fn headers(&self) -> &[(String, Vec<u8>)] {
+    ///
+    /// Note: since names of well-known headers are interned, this is an
+    /// iterator rather than a slice of owned strings, use
+    /// `interned_headers()` to get the underlying slice.
+    pub fn headers(&self) -> HeaderIter {
+        HeaderIter { iter: self.headers.iter() }
+    }
+    /// Get response headers with well-known names being static strings
+    ///
+    /// Names of well-known headers are static strings, so they don't take
+    /// memory for every response.
+    pub fn interned_headers(&self) -> &[(Cow<'static, str>, Vec<u8>)] {
         &self.headers
     }
     /// Get response body
@@ -56,7 +85,7 @@ impl Response {
 impl<S> Codec<S> for Buffered {
     type Future = FutureResult<EncoderDone<S>, Error>;
     fn start_write(&mut self, mut e: Encoder<S>) -> Self::Future {
-        e.request_line(self.method, self.url.path(), Version::Http11);
+        e.request_line(&self.method, self.url.path(), Version::Http11);
         self.url.host_str().map(|x| {
             e.add_header("Host", x).unwrap();
         });
@@ -69,7 +98,7 @@ impl<S> Codec<S> for Buffered {
         self.response = Some(Response {
             status: status,
             headers: headers.headers().map(|(k, v)| {
-                (k.to_string(), v.to_vec())
+                (intern_name(k), v.to_vec())
             }).collect(),
             body: Vec::new(),
         });
@@ -92,7 +121,7 @@ impl Buffered {
     pub fn get(url: Url) -> (Buffered, Receiver<Result<Response, Error>>) {
         let (tx, rx) = channel();
         (Buffered {
-                method: "GET",
+                method: Method::Get,
                 url: url,
                 sender: Some(tx),
                 max_response_length: 10_485_760,
@@ -1,6 +1,7 @@
 use futures::sink::Sink;
 use futures::future::FutureResult;
 use futures::{Async, AsyncSink, Future, IntoFuture};
+use tk_bufstream::{ReadBuf, WriteBuf};
 
 use client::{Error, Encoder, EncoderDone, Head, RecvMode};
 use client::errors::ErrorEnum;
@@ -66,10 +67,25 @@ pub trait Codec<S> {
     /// might complete on request completion without spawning another ones,
     /// but note that next request can't start reading in the meantime).
     ///
+    /// Method may return `Async::NotReady` to apply backpressure: no more
+    /// data is read from the connection until the task is woken up, so
+    /// codec must make sure it is (e.g. by flushing the buffer it writes
+    /// data to).
+    ///
     /// Protocol panics if returned number of bytes larger than `data.len()`.
     ///
     fn data_received(&mut self, data: &[u8], end: bool)
         -> Result<Async<usize>, Error>;
+
+    /// Called when response headers are received if recv mode is `Hijack`
+    /// and request is fully written
+    ///
+    /// Note: input buffer can contain some data received after the response
+    /// headers (i.e. the beginning of the tunnelled stream).
+    fn hijack(&mut self, _output: WriteBuf<S>, _input: ReadBuf<S>) {
+        panic!("`Codec::headers_received` returned `Hijack` but \
+            no hijack() method implemented");
+    }
 }
 
 impl<S, F> Codec<S> for Box<Codec<S, Future=F>>
@@ -87,6 +103,9 @@ impl<S, F> Codec<S> for Box<Codec<S, Future=F>>
     {
         (**self).data_received(data, end)
     }
+    fn hijack(&mut self, output: WriteBuf<S>, input: ReadBuf<S>) {
+        (**self).hijack(output, input)
+    }
 }
 
 impl<S, F> Codec<S> for Box<Codec<S, Future=F>+Send>
@@ -104,6 +123,9 @@ impl<S, F> Codec<S> for Box<Codec<S, Future=F>+Send>
     {
         (**self).data_received(data, end)
     }
+    fn hijack(&mut self, output: WriteBuf<S>, input: ReadBuf<S>) {
+        (**self).hijack(output, input)
+    }
 }
 
 /// A marker trait that applies to a Sink that is essentially a HTTP client
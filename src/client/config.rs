@@ -1,7 +1,9 @@
+use std::usize;
 use std::sync::Arc;
 use std::time::Duration;
 
-use client::{Config};
+use client::{Config, ReuseCounters, UnframedBody, TrailingData};
+use header_hook::{HeaderHook, OutgoingHeaders};
 
 impl Config {
     /// Create a config with defaults
@@ -12,6 +14,13 @@ impl Config {
             keep_alive_timeout: Duration::new(4, 0),
             safe_pipeline_timeout: Duration::from_millis(300),
             max_request_timeout: Duration::new(15, 0),
+            reuse_counters: None,
+            max_status_line_length: usize::MAX,
+            header_hook: None,
+            lenient_chunk_size: false,
+            max_interim_responses: 16,
+            unframed_body: UnframedBody::ReadToEof,
+            trailing_data: TrailingData::Reject,
         }
     }
     /// A number of inflight requests until we start returning
@@ -97,6 +106,84 @@ impl Config {
         self
     }
 
+    /// Count decisions whether connections are reused or closed
+    ///
+    /// Counters may be shared between many connections (and configs). See
+    /// `ReuseCounters` for the description of each counter.
+    pub fn reuse_counters(&mut self, counters: &Arc<ReuseCounters>)
+        -> &mut Self
+    {
+        self.reuse_counters = Some(counters.clone());
+        self
+    }
+
+    /// Maximum length of the status line of the response
+    ///
+    /// The length doesn't include the line ending. When the limit is
+    /// exceeded, `StatusLineTooLong` error is returned as soon as we notice
+    /// it, without waiting for the end of the line, so a misbehaving server
+    /// can't make us buffer an arbitrary amount of data.
+    ///
+    /// Default is `usize::MAX` (no limit)
+    pub fn max_status_line_length(&mut self, value: usize) -> &mut Self {
+        self.max_status_line_length = value;
+        self
+    }
+
+    /// Maximum number of interim (1xx) responses before the final one
+    ///
+    /// Interim responses like `100 Continue` or `103 Early Hints` are
+    /// skipped. When server sends more of them than this limit for a single
+    /// request, `TooManyInterimResponses` error is returned and connection
+    /// is closed.
+    ///
+    /// Default is 16
+    pub fn max_interim_responses(&mut self, value: usize) -> &mut Self {
+        self.max_interim_responses = value;
+        self
+    }
+
+    /// What to do with HTTP/1.1 response without body length
+    ///
+    /// See `UnframedBody` for details. Default is `ReadToEof`.
+    pub fn unframed_body(&mut self, value: UnframedBody) -> &mut Self {
+        self.unframed_body = value;
+        self
+    }
+
+    /// What to do with unexpected data after a complete response
+    ///
+    /// See `TrailingData` for details. Default is `Reject`.
+    pub fn trailing_data(&mut self, value: TrailingData) -> &mut Self {
+        self.trailing_data = value;
+        self
+    }
+
+    /// Call a function right before request headers are finished
+    ///
+    /// The function receives headers added by the codec and may add or
+    /// remove headers. It's called in `Encoder::done_headers()`, so added
+    /// headers are validated the usual way (including detection of
+    /// `Connection: close`) and an error is returned from `done_headers()`
+    /// if they are invalid. Useful for adding request ids or signing
+    /// requests. Default is no hook.
+    pub fn header_hook<F>(&mut self, f: F) -> &mut Self
+        where F: Fn(&mut OutgoingHeaders) + Send + Sync + 'static
+    {
+        self.header_hook = Some(HeaderHook::new(f));
+        self
+    }
+
+    /// Accept whitespace after the chunk size in chunked responses
+    ///
+    /// By default chunk size lines like `5 \r\n` are rejected, as the
+    /// grammar doesn't allow whitespace there, but some servers emit them.
+    /// Whitespace before chunk extensions (`5 ;ext`) is always allowed.
+    pub fn lenient_chunk_size(&mut self, value: bool) -> &mut Self {
+        self.lenient_chunk_size = value;
+        self
+    }
+
     /// Create a Arc'd config clone to pass to the constructor
     ///
     /// This is just a convenience method.
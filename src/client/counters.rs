@@ -0,0 +1,84 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+
+/// Counters of decisions whether connection is reused or closed
+///
+/// Create one instance, pass it to `Config::reuse_counters` and share it
+/// between all connections to find out why connections churn. All counters
+/// are monotonic, so you may export them to your metrics system as is.
+///
+/// Each connection increments at most one of the `closed_*` counters (the
+/// first reason it's closed for), while `reused` is incremented for every
+/// response after which connection is kept open for the next request.
+#[derive(Debug)]
+pub struct ReuseCounters {
+    reused: AtomicUsize,
+    closed_by_server: AtomicUsize,
+    closed_by_client: AtomicUsize,
+    closed_due_to_error: AtomicUsize,
+    closed_due_to_timeout: AtomicUsize,
+}
+
+impl ReuseCounters {
+    /// Create a new set of zero counters
+    pub fn new() -> ReuseCounters {
+        ReuseCounters {
+            reused: AtomicUsize::new(0),
+            closed_by_server: AtomicUsize::new(0),
+            closed_by_client: AtomicUsize::new(0),
+            closed_due_to_error: AtomicUsize::new(0),
+            closed_due_to_timeout: AtomicUsize::new(0),
+        }
+    }
+    /// Number of responses after which connection was kept for next request
+    pub fn reused(&self) -> usize {
+        self.reused.load(Ordering::Relaxed)
+    }
+    /// Connections closed by server (or with `Connection: close` in
+    /// response)
+    pub fn closed_by_server(&self) -> usize {
+        self.closed_by_server.load(Ordering::Relaxed)
+    }
+    /// Connections that can't be reused because of the request (i.e.
+    /// `Connection: close` in request, HTTP/1.0 or upgrade)
+    pub fn closed_by_client(&self) -> usize {
+        self.closed_by_client.load(Ordering::Relaxed)
+    }
+    /// Connections closed because of protocol or I/O error
+    pub fn closed_due_to_error(&self) -> usize {
+        self.closed_due_to_error.load(Ordering::Relaxed)
+    }
+    /// Connections closed on request or keep-alive timeout
+    pub fn closed_due_to_timeout(&self) -> usize {
+        self.closed_due_to_timeout.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for ReuseCounters {
+    fn default() -> ReuseCounters {
+        ReuseCounters::new()
+    }
+}
+
+/// A decision on the connection made by protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Reused,
+    ClosedByServer,
+    ClosedByClient,
+    ClosedDueToError,
+    ClosedDueToTimeout,
+}
+
+impl ReuseCounters {
+    pub(crate) fn record(&self, decision: Decision) {
+        use self::Decision::*;
+        match decision {
+            Reused => &self.reused,
+            ClosedByServer => &self.closed_by_server,
+            ClosedByClient => &self.closed_by_client,
+            ClosedDueToError => &self.closed_due_to_error,
+            ClosedDueToTimeout => &self.closed_due_to_timeout,
+        }.fetch_add(1, Ordering::Relaxed);
+    }
+}
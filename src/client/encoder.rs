@@ -1,17 +1,25 @@
 use std::io;
+use std::io::Read;
+use std::cmp::min;
 use std::fmt::Display;
 #[allow(unused_imports)]
 use std::ascii::AsciiExt;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, AtomicBool, Ordering};
+use std::time::Duration;
 
 use tk_bufstream::WriteBuf;
 use futures::{Future, Async};
-use tokio_io::AsyncWrite;
+use tokio_io::{AsyncRead, AsyncWrite};
 
 use enums::Version;
-use headers::is_close;
-use base_serializer::{MessageState, HeaderError};
+use headers::{is_close, deadline_millis, REQUEST_DEADLINE};
+use base_serializer::{MessageState, HeaderError, validate_headers};
+use header_hook::{self, HeaderHook};
+use client::errors::{Error, ErrorEnum};
+
+/// Size of a single read from the file and the buffer watermark
+const FILE_CHUNK: usize = 65536;
 
 pub enum RequestState {
     Empty = 0,
@@ -29,12 +37,33 @@ pub struct Encoder<S> {
     // TODO(tailhook) we could use smaller atomic, but they are unstable
     state: Arc<AtomicUsize>,
     close_signal: Arc<AtomicBool>,
+    close_reason: Option<CloseReason>,
+    header_hook: Option<HeaderHook>,
+    /// Offset of the request in the output buffer, `None` if it is
+    /// partially flushed
+    headers_start: Option<usize>,
 }
 
 /// This structure returned from `Encoder::done` and works as a continuation
 /// that should be returned from the future that writes request.
 pub struct EncoderDone<S> {
     buf: WriteBuf<S>,
+    close_reason: Option<CloseReason>,
+    state: Arc<AtomicUsize>,
+    close_signal: Arc<AtomicBool>,
+    header_hook: Option<HeaderHook>,
+}
+
+/// A reason why connection can't be reused after this request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    /// Request contains `Connection: close` header
+    ConnectionHeader,
+    /// Request is HTTP/1.0 (we don't implement `Connection: keep-alive`)
+    Http10,
+    /// Request contains `Upgrade` header, which means connection is
+    /// switched to another protocol if server accepts it
+    Upgrade,
 }
 
 /// A future that yields `Encoder` again after buffer has less bytes
@@ -42,6 +71,65 @@ pub struct EncoderDone<S> {
 /// This future is created by `Encoder::wait_flush(x)``
 pub struct WaitFlush<S>(Option<Encoder<S>>, usize);
 
+/// A future that writes request body from a file and yields `EncoderDone`
+///
+/// This future is created by `Encoder::file_body(file, length)`
+pub struct FileBody<S, F> {
+    encoder: Option<Encoder<S>>,
+    file: F,
+    remaining: u64,
+    chunk: Vec<u8>,
+}
+
+/// A future that writes request body from an `AsyncRead` and yields
+/// `EncoderDone`
+///
+/// This future is created by `Encoder::reader_body(reader, length)`
+pub struct ReaderBody<S, R> {
+    encoder: Option<Encoder<S>>,
+    reader: R,
+    remaining: Option<u64>,
+    chunk: Vec<u8>,
+}
+
+impl<S> EncoderDone<S> {
+    /// Returns a reason why connection will not be reused after this
+    /// request, if any
+    ///
+    /// Note: connection may still be closed because of the response
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        self.close_reason
+    }
+    /// Returns encoder for the next request reusing the buffer and the
+    /// request state of this one
+    ///
+    /// This is for code that drives the connection itself and pipelines
+    /// many requests, so it doesn't need to allocate a new state for each
+    /// request. Request state is reset, so whoever tracks this request must
+    /// have finished with it. Codecs should return `EncoderDone` to the
+    /// protocol instead.
+    ///
+    /// # Panics
+    ///
+    /// When connection can't be reused after this request (i.e. when
+    /// `close_reason()` is not `None`).
+    pub fn reset(self) -> Encoder<S> {
+        assert!(self.close_reason.is_none(),
+            "connection can't be reused: {:?}", self.close_reason);
+        self.state.store(RequestState::Empty as usize, Ordering::SeqCst);
+        let start = self.buf.out_buf.len();
+        Encoder {
+            message: MessageState::RequestStart,
+            buf: self.buf,
+            state: self.state,
+            close_signal: self.close_signal,
+            close_reason: None,
+            header_hook: self.header_hook,
+            headers_start: Some(start),
+        }
+    }
+}
+
 pub fn get_inner<S>(e: EncoderDone<S>) -> WriteBuf<S> {
     e.buf
 }
@@ -52,14 +140,21 @@ impl<S> Encoder<S> {
     /// This puts request line into a buffer immediately. If you don't
     /// continue with request it will be sent to the network shortly.
     ///
+    /// Method may be either a `Method` or a string.
+    ///
     /// # Panics
     ///
     /// When request line is already written. It's expected that your request
     /// handler state machine will never call the method twice.
-    pub fn request_line(&mut self, method: &str, path: &str, version: Version)
+    pub fn request_line<M>(&mut self, method: M, path: &str, version: Version)
+        where M: AsRef<str>
     {
+        let method = method.as_ref();
         self.message.request_line(&mut self.buf.out_buf,
             method, path, version);
+        if version == Version::Http10 {
+            self.set_close_reason(CloseReason::Http10);
+        }
         let nstatus = if method.eq_ignore_ascii_case("HEAD") {
             RequestState::StartedHead as usize
         } else {
@@ -95,7 +190,9 @@ impl<S> Encoder<S> {
     {
         if name.eq_ignore_ascii_case("Connection") && is_close(value.as_ref())
         {
-            self.close_signal.store(true, Ordering::SeqCst);
+            self.set_close_reason(CloseReason::ConnectionHeader);
+        } else if name.eq_ignore_ascii_case("Upgrade") {
+            self.set_close_reason(CloseReason::Upgrade);
         }
         self.message.add_header(&mut self.buf.out_buf, name, value.as_ref())
     }
@@ -114,6 +211,75 @@ impl<S> Encoder<S> {
         self.message.format_header(&mut self.buf.out_buf, name, value)
     }
 
+    /// Add multiple headers to the message
+    ///
+    /// All headers are validated before writing, so either all of them are
+    /// added or none of them (and the error is returned). Same rules as for
+    /// `add_header` apply to each header.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `add_headers` is called in the wrong state.
+    pub fn add_headers<N, V>(&mut self, headers: &[(N, V)])
+        -> Result<(), HeaderError>
+        where N: AsRef<str>,
+              V: AsRef<[u8]>,
+    {
+        validate_headers(headers)?;
+        for &(ref name, ref value) in headers {
+            self.add_header(name.as_ref(), value)?;
+        }
+        Ok(())
+    }
+
+    /// Write request line and headers from the `http::Request`
+    ///
+    /// This method is only available with `http_types` feature.
+    ///
+    /// Only path and query of the URI are put into request line. If URI
+    /// contains authority and there is no `Host` header, the header is
+    /// added. Note: headers are not finished, so you can add more headers,
+    /// and you must set body length with `add_length()` or `add_chunked()`
+    /// (specifying `Content-Length` or `Transfer-Encoding` in the request
+    /// is an error), and then call `done_headers()`.
+    ///
+    /// HTTP/2 request is sent as HTTP/1.1.
+    ///
+    /// # Panics
+    ///
+    /// When request line is already written.
+    #[cfg(feature="http_types")]
+    pub fn http_request_head<B>(&mut self, request: &::http::Request<B>)
+        -> Result<(), HeaderError>
+    {
+        let path = request.uri().path_and_query()
+            .map(|x| x.as_str()).unwrap_or("/");
+        self.request_line(request.method().as_str(), path,
+            Version::from_http(request.version()));
+        if !request.headers().contains_key(::http::header::HOST) {
+            if let Some(authority) = request.uri().authority_part() {
+                self.add_header("Host", authority.as_str())?;
+            }
+        }
+        for (name, value) in request.headers() {
+            self.add_header(name.as_str(), value.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Add `X-Request-Deadline` header with the time we are willing to wait
+    ///
+    /// Deadline is sent as a number of milliseconds. The server that
+    /// opted in (see `server::Config::respect_request_deadline`) cancels
+    /// request handler when the deadline is reached. Proxies should pass
+    /// the remaining part of `Head::request_deadline` here.
+    pub fn add_deadline(&mut self, timeout: Duration)
+        -> Result<(), HeaderError>
+    {
+        self.message.format_header(&mut self.buf.out_buf,
+            REQUEST_DEADLINE, deadline_millis(timeout))
+    }
+
     /// Add a content length to the message.
     ///
     /// The `Content-Length` header is written to the output buffer
@@ -121,6 +287,9 @@ impl<S> Encoder<S> {
     /// headers present in the message. When the body is send the length is
     /// validated.
     ///
+    /// Body is allowed for any method, including `GET` and `DELETE`
+    /// (although it's uncommon for them to have one).
+    ///
     /// # Panics
     ///
     /// Panics when `add_length` is called in the wrong state.
@@ -143,7 +312,12 @@ impl<S> Encoder<S> {
     {
         self.message.add_chunked(&mut self.buf.out_buf)
     }
-    /// Closes the HTTP header
+    /// Closes the HTTP header and returns `true` if entity body is expected
+    ///
+    /// Requests may always contain a body (without `add_length` or
+    /// `add_chunked` body is empty), so it's `true` for any valid request.
+    /// Body writes are checked against the same state, so a message that
+    /// can't have a body rejects them.
     ///
     /// Similarly to `add_header()` it's fine to `unwrap()` here, unless you're
     /// doing some proxying.
@@ -151,9 +325,13 @@ impl<S> Encoder<S> {
     /// # Panics
     ///
     /// Panics when the request is in a wrong state.
-    pub fn done_headers(&mut self) -> Result<(), HeaderError> {
+    pub fn done_headers(&mut self) -> Result<bool, HeaderError> {
+        if let Some(hook) = self.header_hook.take() {
+            let (added, _removed) = header_hook::run(&hook,
+                &mut self.buf.out_buf, self.headers_start);
+            self.add_headers(&added)?;
+        }
         self.message.done_headers(&mut self.buf.out_buf)
-        .map(|always_support_body| assert!(always_support_body))
     }
     /// Write a chunk of body
     ///
@@ -168,6 +346,18 @@ impl<S> Encoder<S> {
     pub fn write_body(&mut self, data: &[u8]) {
         self.message.write_body(&mut self.buf.out_buf, data)
     }
+    /// Write exactly one chunk of the chunked body
+    ///
+    /// Unlike `write_body`, which works for any body, this method asserts
+    /// that `add_chunked()` was used and puts the data as a single chunk.
+    /// Empty data is ignored, so it can't accidentally terminate the body.
+    ///
+    /// # Panics
+    ///
+    /// When request body is not chunked.
+    pub fn write_chunk(&mut self, data: &[u8]) {
+        self.message.write_chunk(&mut self.buf.out_buf, data)
+    }
     /// Finish writing request and return `EncoderDone` which can be moved to
     ///
     /// # Panics
@@ -175,7 +365,21 @@ impl<S> Encoder<S> {
     /// Panics when the request is in a wrong state.
     pub fn done(mut self) -> EncoderDone<S> {
         self.message.done(&mut self.buf.out_buf);
-        EncoderDone { buf: self.buf }
+        EncoderDone {
+            buf: self.buf,
+            close_reason: self.close_reason,
+            state: self.state,
+            close_signal: self.close_signal,
+            header_hook: self.header_hook,
+        }
+    }
+    /// Remembers the (first) reason and signals that connection must be
+    /// closed after this request
+    fn set_close_reason(&mut self, reason: CloseReason) {
+        self.close_signal.store(true, Ordering::SeqCst);
+        if self.close_reason.is_none() {
+            self.close_reason = Some(reason);
+        }
     }
 
     /// Flush the data to underlying socket
@@ -188,7 +392,11 @@ impl<S> Encoder<S> {
     pub fn flush(&mut self) -> Result<(), io::Error>
         where S: AsyncWrite
     {
-        self.buf.flush()
+        let before = self.buf.out_buf.len();
+        let result = self.buf.flush();
+        header_hook::flushed(&mut self.headers_start,
+                             before - self.buf.out_buf.len());
+        result
     }
     /// Returns bytes currently lying in the buffer
     ///
@@ -204,6 +412,153 @@ impl<S> Encoder<S> {
     pub fn wait_flush(self, watermark: usize) -> WaitFlush<S> {
         WaitFlush(Some(self), watermark)
     }
+    /// Returns future which yields encoder back when buffer is empty
+    ///
+    /// Unlike `flush()` which writes as much as the socket accepts right
+    /// now, and `wait_flush(x)` which allows up to `x - 1` bytes to remain
+    /// buffered, this resolves only when all data is written to the socket.
+    /// Useful to make sure everything is transmitted before proceeding,
+    /// for example before switching protocols.
+    pub fn flush_all(self) -> WaitFlush<S> {
+        // buffer is empty when `bytes_buffered()` < 1
+        self.wait_flush(1)
+    }
+
+    /// Send the request body from a file of known length
+    ///
+    /// This adds `Content-Length: length` and finishes headers, so it should
+    /// be called after request line and all other headers are written.
+    /// Returned future reads the file chunk by chunk, waiting for the buffer
+    /// to be flushed to the network before reading more, so the whole file
+    /// is never buffered. It resolves to `EncoderDone` which can be returned
+    /// from the `Codec::start_write` future.
+    ///
+    /// Future fails if file is shorter than `length`. Only `length` bytes
+    /// are sent even if the file is longer.
+    ///
+    /// Note: file is read synchronously, which is usually fine for local
+    /// files, but you may want to use a thread pool for slow storage.
+    pub fn file_body<F: Read>(mut self, file: F, length: u64)
+        -> Result<FileBody<S, F>, HeaderError>
+    {
+        self.add_length(length)?;
+        self.done_headers()?;
+        Ok(FileBody {
+            encoder: Some(self),
+            file: file,
+            remaining: length,
+            chunk: vec![0; min(length, FILE_CHUNK as u64) as usize],
+        })
+    }
+
+    /// Send the request body read from an asynchronous source
+    ///
+    /// This is like `file_body()` but works for any `AsyncRead`, like a
+    /// socket or a pipe. If `length` is known it's sent as `Content-Length`,
+    /// otherwise chunked encoding is used and the body ends when the reader
+    /// reaches end of stream. More data is read only when the output buffer
+    /// is flushed.
+    ///
+    /// Future fails if the stream ends before `length` bytes are read. Only
+    /// `length` bytes are sent even if the stream is longer.
+    pub fn reader_body<R: AsyncRead>(mut self, reader: R,
+        length: Option<u64>)
+        -> Result<ReaderBody<S, R>, HeaderError>
+    {
+        match length {
+            Some(length) => self.add_length(length)?,
+            None => self.add_chunked()?,
+        }
+        self.done_headers()?;
+        let chunk = length.map(|x| min(x, FILE_CHUNK as u64) as usize)
+            .unwrap_or(FILE_CHUNK);
+        Ok(ReaderBody {
+            encoder: Some(self),
+            reader: reader,
+            remaining: length,
+            chunk: vec![0; chunk],
+        })
+    }
+}
+
+impl<S: AsyncWrite, F: Read> Future for FileBody<S, F> {
+    type Item = EncoderDone<S>;
+    type Error = Error;
+    fn poll(&mut self) -> Result<Async<EncoderDone<S>>, Error> {
+        {
+            let enc = self.encoder.as_mut().expect("future is polled twice");
+            while self.remaining > 0 {
+                if enc.bytes_buffered() >= FILE_CHUNK {
+                    enc.flush().map_err(ErrorEnum::Io)?;
+                    if enc.bytes_buffered() >= FILE_CHUNK {
+                        return Ok(Async::NotReady);
+                    }
+                }
+                let max = min(self.remaining, self.chunk.len() as u64);
+                let chunk = &mut self.chunk[..max as usize];
+                let bytes = match self.file.read(chunk) {
+                    Ok(0) => {
+                        return Err(ErrorEnum::Io(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "file is shorter than declared length")).into());
+                    }
+                    Ok(bytes) => bytes,
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted
+                    => continue,
+                    Err(e) => return Err(ErrorEnum::Io(e).into()),
+                };
+                enc.write_body(&chunk[..bytes]);
+                self.remaining -= bytes as u64;
+            }
+        }
+        Ok(Async::Ready(self.encoder.take().unwrap().done()))
+    }
+}
+
+impl<S: AsyncWrite, R: AsyncRead> Future for ReaderBody<S, R> {
+    type Item = EncoderDone<S>;
+    type Error = Error;
+    fn poll(&mut self) -> Result<Async<EncoderDone<S>>, Error> {
+        {
+            let enc = self.encoder.as_mut().expect("future is polled twice");
+            while self.remaining != Some(0) {
+                if enc.bytes_buffered() >= FILE_CHUNK {
+                    enc.flush().map_err(ErrorEnum::Io)?;
+                    if enc.bytes_buffered() >= FILE_CHUNK {
+                        return Ok(Async::NotReady);
+                    }
+                }
+                let max = match self.remaining {
+                    Some(x) => min(x, self.chunk.len() as u64) as usize,
+                    None => self.chunk.len(),
+                };
+                let chunk = &mut self.chunk[..max];
+                let bytes = match self.reader.read(chunk) {
+                    Ok(0) if self.remaining.is_some() => {
+                        return Err(ErrorEnum::Io(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "stream is shorter than declared length")).into());
+                    }
+                    Ok(0) => break,
+                    Ok(bytes) => bytes,
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock
+                    => {
+                        // data already read must not wait for the next
+                        // chunk, as reader may never produce it until the
+                        // peer receives this one
+                        enc.flush().map_err(ErrorEnum::Io)?;
+                        return Ok(Async::NotReady);
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted
+                    => continue,
+                    Err(e) => return Err(ErrorEnum::Io(e).into()),
+                };
+                enc.write_body(&chunk[..bytes]);
+                self.remaining = self.remaining.map(|x| x - bytes as u64);
+            }
+        }
+        Ok(Async::Ready(self.encoder.take().unwrap().done()))
+    }
 }
 
 impl<S: AsyncWrite> Future for WaitFlush<S> {
@@ -227,11 +582,49 @@ pub fn new<S>(io: WriteBuf<S>,
     state: Arc<AtomicUsize>, close_signal: Arc<AtomicBool>)
     -> Encoder<S>
 {
+    let start = io.out_buf.len();
     Encoder {
         message: MessageState::RequestStart,
         buf: io,
         state: state,
         close_signal: close_signal,
+        close_reason: None,
+        header_hook: None,
+        headers_start: Some(start),
+    }
+}
+
+/// Sets a hook called in `done_headers`
+pub fn set_header_hook<S>(e: &mut Encoder<S>, hook: Option<HeaderHook>) {
+    e.header_hook = hook;
+}
+
+#[cfg(feature="testing")]
+impl Encoder<::tk_bufstream::MockData> {
+    /// Create an encoder over an in-memory buffer for tests
+    ///
+    /// Returns a mock which can be used to inspect produced bytes. Note
+    /// only flushed bytes are visible in the mock output:
+    ///
+    /// ```ignore
+    /// let (mut enc, mock) = Encoder::for_test();
+    /// enc.request_line("GET", "/", Version::Http11);
+    /// enc.add_header("Host", "example.com").unwrap();
+    /// enc.done_headers().unwrap();
+    /// enc.flush().unwrap();
+    /// assert_eq!(&mock.output(..)[..],
+    ///     &b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n"[..]);
+    /// ```
+    ///
+    /// This method is only available with `testing` feature.
+    pub fn for_test()
+        -> (Encoder<::tk_bufstream::MockData>, ::tk_bufstream::MockData)
+    {
+        use tk_bufstream::{IoBuf, MockData};
+        let mock = MockData::new();
+        let enc = new(IoBuf::new(mock.clone()).split().0,
+            Arc::new(AtomicUsize::new(0)), Arc::new(AtomicBool::new(false)));
+        (enc, mock)
     }
 }
 
@@ -246,3 +639,338 @@ impl<S> io::Write for Encoder<S> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{self, Read, Write};
+    use std::path::PathBuf;
+    use std::process;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, AtomicBool, Ordering};
+    use futures::{Future, task};
+    use tk_bufstream::{IoBuf, MockData};
+    use tokio_io::AsyncRead;
+
+    use enums::{Version, Method};
+    use super::RequestState;
+    #[cfg(feature="http_types")]
+    use base_serializer::HeaderError;
+    use super::{Encoder, EncoderDone, CloseReason, new, get_inner};
+
+    fn close_reason<F>(fun: F) -> (Option<CloseReason>, bool)
+        where F: FnOnce(&mut Encoder<MockData>)
+    {
+        let signal = Arc::new(AtomicBool::new(false));
+        let mut enc = new(IoBuf::new(MockData::new()).split().0,
+            Arc::new(AtomicUsize::new(0)), signal.clone());
+        fun(&mut enc);
+        enc.done_headers().unwrap();
+        let done: EncoderDone<MockData> = enc.done();
+        (done.close_reason(), signal.load(Ordering::SeqCst))
+    }
+
+    #[test]
+    fn done_headers_expects_body() {
+        let mock = MockData::new();
+        let mut enc = new(IoBuf::new(mock.clone()).split().0,
+            Arc::new(AtomicUsize::new(0)), Arc::new(AtomicBool::new(false)));
+        enc.request_line("HEAD", "/", Version::Http11);
+        assert!(enc.done_headers().unwrap());
+        get_inner(enc.done()).flush().unwrap();
+        let mut enc = new(IoBuf::new(mock.clone()).split().0,
+            Arc::new(AtomicUsize::new(0)), Arc::new(AtomicBool::new(false)));
+        enc.request_line("POST", "/", Version::Http11);
+        enc.add_chunked().unwrap();
+        assert!(enc.done_headers().unwrap());
+        enc.write_body(b"ok");
+        get_inner(enc.done()).flush().unwrap();
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "HEAD / HTTP/1.1\r\n\r\n\
+             POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+             2\r\nok\r\n0\r\n\r\n");
+    }
+
+    #[test]
+    fn typed_method() {
+        let mock = MockData::new();
+        let state = Arc::new(AtomicUsize::new(0));
+        let mut enc = new(IoBuf::new(mock.clone()).split().0,
+            state.clone(), Arc::new(AtomicBool::new(false)));
+        enc.request_line(Method::Head, "/", Version::Http11);
+        assert_eq!(state.load(Ordering::SeqCst),
+                   RequestState::StartedHead as usize);
+        enc.done_headers().unwrap();
+        get_inner(enc.done()).flush().unwrap();
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "HEAD / HTTP/1.1\r\n\r\n");
+    }
+
+    #[test]
+    fn reset() {
+        let mock = MockData::new();
+        let state = Arc::new(AtomicUsize::new(0));
+        let mut enc = new(IoBuf::new(mock.clone()).split().0,
+            state.clone(), Arc::new(AtomicBool::new(false)));
+        enc.request_line("HEAD", "/first", Version::Http11);
+        enc.done_headers().unwrap();
+        let mut enc = enc.done().reset();
+        assert_eq!(state.load(Ordering::SeqCst), 0);
+        enc.request_line("GET", "/second", Version::Http11);
+        enc.add_header("Host", "example.com").unwrap();
+        enc.done_headers().unwrap();
+        let done = enc.done();
+        assert_eq!(state.load(Ordering::SeqCst), 2);
+        get_inner(done).flush().unwrap();
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "HEAD /first HTTP/1.1\r\n\r\n\
+             GET /second HTTP/1.1\r\nHost: example.com\r\n\r\n");
+    }
+
+    #[test]
+    #[should_panic(expected="connection can't be reused")]
+    fn reset_after_close() {
+        let mut enc = new(IoBuf::new(MockData::new()).split().0,
+            Arc::new(AtomicUsize::new(0)), Arc::new(AtomicBool::new(false)));
+        enc.request_line("GET", "/", Version::Http11);
+        enc.add_header("Connection", "close").unwrap();
+        enc.done_headers().unwrap();
+        enc.done().reset();
+    }
+
+    #[test]
+    fn keep_alive() {
+        assert_eq!(close_reason(|enc| {
+            enc.request_line("GET", "/", Version::Http11);
+            enc.add_header("Connection", "keep-alive").unwrap();
+        }), (None, false));
+    }
+
+    #[test]
+    fn close_header() {
+        assert_eq!(close_reason(|enc| {
+            enc.request_line("GET", "/", Version::Http11);
+            enc.add_header("Connection", "close").unwrap();
+        }), (Some(CloseReason::ConnectionHeader), true));
+    }
+
+    #[test]
+    fn http10() {
+        assert_eq!(close_reason(|enc| {
+            enc.request_line("GET", "/", Version::Http10);
+            enc.add_header("Connection", "close").unwrap();
+        }), (Some(CloseReason::Http10), true));
+    }
+
+    #[test]
+    fn upgrade() {
+        assert_eq!(close_reason(|enc| {
+            enc.request_line("GET", "/chat", Version::Http11);
+            enc.add_header("Connection", "upgrade").unwrap();
+            enc.add_header("Upgrade", "websocket").unwrap();
+        }), (Some(CloseReason::Upgrade), true));
+    }
+
+    #[test]
+    #[cfg(feature="http_types")]
+    fn http_request_head() {
+        let mock = MockData::new();
+        let mut enc = new(IoBuf::new(mock.clone()).split().0,
+            Arc::new(AtomicUsize::new(0)), Arc::new(AtomicBool::new(false)));
+        let req = ::http::Request::builder()
+            .method("POST")
+            .uri("http://example.com/hello?x=1")
+            .header("Accept", "*/*")
+            .body(()).unwrap();
+        enc.http_request_head(&req).unwrap();
+        enc.add_length(0).unwrap();
+        enc.done_headers().unwrap();
+        enc.flush().unwrap();
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "POST /hello?x=1 HTTP/1.1\r\nHost: example.com\r\n\
+             accept: */*\r\nContent-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    #[cfg(feature="http_types")]
+    fn http_request_body_length() {
+        let mut enc = new(IoBuf::new(MockData::new()).split().0,
+            Arc::new(AtomicUsize::new(0)), Arc::new(AtomicBool::new(false)));
+        let req = ::http::Request::builder()
+            .uri("/")
+            .header("Content-Length", "10")
+            .body(()).unwrap();
+        assert_matches!(enc.http_request_head(&req),
+                        Err(HeaderError::BodyLengthHeader));
+    }
+
+    #[test]
+    #[cfg(feature="testing")]
+    fn for_test() {
+        let (mut enc, mock) = Encoder::for_test();
+        enc.request_line("GET", "/", Version::Http11);
+        enc.add_header("Host", "example.com").unwrap();
+        enc.done_headers().unwrap();
+        enc.flush().unwrap();
+        assert_eq!(&mock.output(..)[..],
+            &b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n"[..]);
+    }
+
+    fn request_str<F>(fun: F) -> String
+        where F: FnOnce(&mut Encoder<MockData>)
+    {
+        let mock = MockData::new();
+        let mut enc = new(IoBuf::new(mock.clone()).split().0,
+            Arc::new(AtomicUsize::new(0)), Arc::new(AtomicBool::new(false)));
+        fun(&mut enc);
+        get_inner(enc.done()).flush().unwrap();
+        String::from_utf8_lossy(&mock.output(..)).to_string()
+    }
+
+    #[test]
+    fn delete_with_body() {
+        let body = br#"{"ids": [1, 2]}"#;
+        assert_eq!(request_str(|enc| {
+            enc.request_line("DELETE", "/items", Version::Http11);
+            enc.add_header("Content-Type", "application/json").unwrap();
+            enc.add_length(body.len() as u64).unwrap();
+            enc.done_headers().unwrap();
+            enc.write_body(body);
+        }), "DELETE /items HTTP/1.1\r\nContent-Type: application/json\r\n\
+             Content-Length: 15\r\n\r\n{\"ids\": [1, 2]}");
+    }
+
+    #[test]
+    fn get_with_chunked_body() {
+        assert_eq!(request_str(|enc| {
+            enc.request_line("GET", "/search", Version::Http11);
+            enc.add_chunked().unwrap();
+            enc.done_headers().unwrap();
+            enc.write_body(b"{}");
+        }), "GET /search HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+             2\r\n{}\r\n0\r\n\r\n");
+    }
+
+    static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+    /// Creates a file with the unique name, so tests may run concurrently
+    fn temp_file(name: &str, data: &[u8]) -> PathBuf {
+        let path = env::temp_dir().join(format!("tk-http-test-{}-{}-{}",
+            name, process::id(), TEMP_FILES.fetch_add(1, Ordering::SeqCst)));
+        File::create(&path).unwrap().write_all(data).unwrap();
+        path
+    }
+
+    #[test]
+    fn file_body() {
+        let data = (0..200000).map(|x| (x % 251) as u8).collect::<Vec<_>>();
+        let path = temp_file("upload", &data);
+        let mock = MockData::new();
+        let mut enc = new(IoBuf::new(mock.clone()).split().0,
+            Arc::new(AtomicUsize::new(0)), Arc::new(AtomicBool::new(false)));
+        enc.request_line("PUT", "/upload", Version::Http11);
+        let done = enc.file_body(File::open(&path).unwrap(),
+            data.len() as u64).unwrap().wait().unwrap();
+        get_inner(done).flush().unwrap();
+        fs::remove_file(&path).unwrap();
+        let output = mock.output(..);
+        let head = b"PUT /upload HTTP/1.1\r\nContent-Length: 200000\r\n\r\n";
+        assert_eq!(&output[..head.len()], &head[..]);
+        assert!(&output[head.len()..] == &data[..]);
+    }
+
+    /// Yields chunks one by one, returning `WouldBlock` in between
+    struct Trickle {
+        chunks: Vec<&'static [u8]>,
+        blocked: bool,
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.blocked = !self.blocked;
+            if self.blocked {
+                task::current().notify();
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            if self.chunks.len() == 0 {
+                return Ok(0);
+            }
+            let chunk = self.chunks.remove(0);
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    impl AsyncRead for Trickle {}
+
+    fn reader_body(length: Option<u64>) -> String {
+        let mock = MockData::new();
+        let mut enc = new(IoBuf::new(mock.clone()).split().0,
+            Arc::new(AtomicUsize::new(0)), Arc::new(AtomicBool::new(false)));
+        enc.request_line("POST", "/", Version::Http11);
+        let reader = Trickle {
+            chunks: vec![b"hello", b" ", b"world"],
+            blocked: false,
+        };
+        let done = enc.reader_body(reader, length).unwrap().wait().unwrap();
+        get_inner(done).flush().unwrap();
+        String::from_utf8_lossy(&mock.output(..)).to_string()
+    }
+
+    #[test]
+    fn reader_body_chunked() {
+        assert_eq!(reader_body(None),
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+             5\r\nhello\r\n1\r\n \r\n5\r\nworld\r\n0\r\n\r\n");
+    }
+
+    #[test]
+    fn reader_body_length() {
+        assert_eq!(reader_body(Some(11)),
+            "POST / HTTP/1.1\r\nContent-Length: 11\r\n\r\nhello world");
+    }
+
+    /// Returns a short chunk and then blocks, like an idle pipe
+    struct Stalled(Option<&'static [u8]>);
+
+    impl Read for Stalled {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.take() {
+                Some(chunk) => {
+                    buf[..chunk.len()].copy_from_slice(chunk);
+                    Ok(chunk.len())
+                }
+                None => Err(io::ErrorKind::WouldBlock.into()),
+            }
+        }
+    }
+
+    impl AsyncRead for Stalled {}
+
+    #[test]
+    fn reader_body_flushes_when_blocked() {
+        let mock = MockData::new();
+        let mut enc = new(IoBuf::new(mock.clone()).split().0,
+            Arc::new(AtomicUsize::new(0)), Arc::new(AtomicBool::new(false)));
+        enc.request_line("POST", "/", Version::Http11);
+        let mut body = enc.reader_body(Stalled(Some(b"hello")), None)
+            .unwrap();
+        assert!(body.poll().unwrap().is_not_ready());
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+             5\r\nhello\r\n");
+    }
+
+    #[test]
+    fn file_body_too_short() {
+        let path = temp_file("short", b"hello");
+        let mut enc = new(IoBuf::new(MockData::new()).split().0,
+            Arc::new(AtomicUsize::new(0)), Arc::new(AtomicBool::new(false)));
+        enc.request_line("PUT", "/upload", Version::Http11);
+        let result = enc.file_body(File::open(&path).unwrap(), 10)
+            .unwrap().wait();
+        fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+}
@@ -84,6 +84,23 @@ quick_error! {
         ConnectionInvalid {
             description("invalid connection header in response")
         }
+        /// Status line of the response is longer than configured limit
+        StatusLineTooLong {
+            description("status line of the response is too long")
+        }
+        /// HTTP/1.1 response has no body length and config rejects such
+        UnframedResponse {
+            description("response has neither Content-Length \
+                         nor chunked encoding")
+        }
+        /// Unexpected data received after a complete response
+        UnexpectedData {
+            description("unexpected data after the end of response")
+        }
+        /// Server sent more interim (1xx) responses than configured limit
+        TooManyInterimResponses {
+            description("too many interim (1xx) responses")
+        }
         /// Unsupported status returned by server
         ///
         /// You have to write your own Codec to handle unsupported status codes
@@ -137,6 +154,57 @@ impl Error {
             _ => false,
         }
     }
+
+    /// Returns true if connection was reset or closed by server prematurely
+    ///
+    /// This is true for the connection closed in the middle of response
+    /// headers or body and for I/O errors caused by peer.
+    ///
+    /// Note: `tk_bufstream` reports `ConnectionReset` and `BrokenPipe` as a
+    /// closed connection on both reads and writes, so a reset by peer is
+    /// seen as the connection closed prematurely rather than an I/O error.
+    /// Only the rest (e.g. `ConnectionAborted`) is matched by I/O kind.
+    pub fn is_connection_reset(&self) -> bool {
+        use std::io::ErrorKind::*;
+        match self.0 {
+            ErrorEnum::ResetOnResponseHeaders => true,
+            ErrorEnum::ResetOnResponseBody => true,
+            ErrorEnum::Io(ref e) => match e.kind() {
+                ConnectionReset | ConnectionAborted | BrokenPipe
+                | UnexpectedEof => true,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Returns the kind of the error
+    #[cfg(test)]
+    pub(crate) fn kind(&self) -> &ErrorEnum {
+        &self.0
+    }
+
+    /// Returns true if server sent a malformed or unsupported response
+    pub fn is_protocol_error(&self) -> bool {
+        use self::ErrorEnum::*;
+        match self.0 {
+            Header(..) | ChunkSize(..) | BadContentLength
+            | DuplicateContentLength | PrematureResponseHeaders
+            | ConnectionInvalid | StatusLineTooLong | UnframedResponse
+            | TooManyInterimResponses | InvalidStatus
+            | UnexpectedData => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true if error is a request or keep-alive timeout
+    pub fn is_timeout(&self) -> bool {
+        match self.0 {
+            ErrorEnum::RequestTimeout => true,
+            ErrorEnum::KeepAliveTimeout => true,
+            _ => false,
+        }
+    }
 }
 
 #[test]
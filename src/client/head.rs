@@ -1,11 +1,14 @@
 #[allow(unused_imports)]
 use std::ascii::AsciiExt;
 use std::slice::Iter as SliceIter;
+use std::str::from_utf8;
 
 use httparse::Header;
 
-use enums::{Status};
+use enums::{Status, Version};
+use headers::{self, ConnectionTokens};
 use client::Head;
+use client::client::BodyKind;
 
 
 /// Iterator over all meaningful headers for the response
@@ -36,6 +39,35 @@ impl<'a> Head<'a> {
     pub fn raw_status(&self) -> (u16, &'a str) {
         (self.code, self.reason)
     }
+    /// Returns `true` if the response is `101 Switching Protocols`
+    ///
+    /// Return `RecvMode::hijack()` from `Codec::headers_received` to get
+    /// the underlying stream (and the bytes received after the headers) in
+    /// `Codec::hijack`. Otherwise connection is closed.
+    pub fn is_switching_protocols(&self) -> bool {
+        self.code == 101
+    }
+    /// Returns the value of the `Upgrade` header if it exists
+    ///
+    /// This is the protocol server switches to in `101 Switching Protocols`
+    /// response. Note `Upgrade` is a hop-by-hop header, so it's not
+    /// returned by `headers()`.
+    pub fn upgrade(&self) -> Option<&'a str> {
+        self.headers.iter()
+            .find(|h| h.name.eq_ignore_ascii_case("Upgrade"))
+            .and_then(|h| from_utf8(h.value).ok())
+            .map(|x| x.trim())
+    }
+    /// Returns HTTP version of the response
+    pub fn version(&self) -> Version {
+        self.version
+    }
+    /// Returns `true` if connection is closed after this response
+    ///
+    /// This is the case for `Connection: close` and for HTTP/1.0 responses.
+    pub fn connection_close(&self) -> bool {
+        self.connection_close
+    }
     /// Iterator over the headers of HTTP request
     ///
     /// This iterator strips the following kinds of headers:
@@ -57,6 +89,27 @@ impl<'a> Head<'a> {
             iter: self.headers.iter(),
         }
     }
+    /// Returns length of the response body if it's known from the headers
+    ///
+    /// This is `Some(x)` for responses with `Content-Length` and for the
+    /// ones that can't have a body (responses to `HEAD` requests, 1xx, 204
+    /// and 304) where it's `Some(0)`. It's `None` for chunked responses and
+    /// responses delimited by closing the connection. Useful to preallocate
+    /// a buffer for the body.
+    pub fn body_length(&self) -> Option<u64> {
+        match self.body_kind {
+            BodyKind::Fixed(x) => Some(x),
+            BodyKind::Chunked | BodyKind::Eof => None,
+        }
+    }
+    /// Iterator over the tokens of the `Connection` header
+    ///
+    /// Headers named by these tokens are hop-by-hop and are skipped by
+    /// `headers()`.
+    pub fn connection_tokens(&self) -> ConnectionTokens {
+        headers::connection_tokens(
+            self.connection_header.as_ref().map(|x| &x[..]))
+    }
     /// All headers of HTTP request
     ///
     /// Unlike `self.headers()` this does include hop-by-hop headers. This
@@ -64,6 +117,39 @@ impl<'a> Head<'a> {
     pub fn all_headers(&self) -> &'a [Header<'a>] {
         self.headers
     }
+    /// Number of bytes the status line and headers occupied in the input
+    ///
+    /// This includes the empty line terminating the headers, so the body
+    /// starts at this offset from the start of the response. Interim
+    /// (`1xx`) responses received before this one are not counted.
+    pub fn head_length(&self) -> usize {
+        self.head_length
+    }
+    /// Convert response head into `http::Response` without a body
+    ///
+    /// This method is only available with `http_types` feature.
+    ///
+    /// The conversion is lossy in the following ways:
+    ///
+    /// 1. Reason phrase is lost
+    /// 2. Header names are lowercased and headers are grouped by name
+    ///    (relative order of the headers with different names is lost)
+    /// 3. All headers are converted including hop-by-hop ones (i.e. it's
+    ///    `all_headers()` not `headers()`)
+    ///
+    /// Error is returned if status code or any header can't be represented
+    /// by the `http` crate.
+    #[cfg(feature="http_types")]
+    pub fn to_http_response(&self)
+        -> Result<::http::Response<()>, ::http::Error>
+    {
+        let mut builder = ::http::Response::builder();
+        builder.status(self.code).version(self.version.into());
+        for header in self.headers {
+            builder.header(header.name, header.value);
+        }
+        builder.body(())
+    }
 }
 
 
@@ -78,14 +164,109 @@ impl<'a> Iterator for HeaderIter<'a> {
                 continue;
             }
 
-            if let Some(ref conn) = self.head.connection_header {
-                let mut conn_headers = conn.split(',').map(|x| x.trim());
-                if conn_headers.any(|x| x.eq_ignore_ascii_case(header.name)) {
-                    continue;
-                }
+            if self.head.connection_tokens()
+                .any(|x| x.eq_ignore_ascii_case(header.name))
+            {
+                continue;
             }
             return Some((header.name, header.value));
         }
         return None;
     }
 }
+
+#[cfg(test)]
+mod test {
+    use httparse::Header;
+
+    use client::Head;
+    use client::client::BodyKind;
+    use enums::Version;
+
+    fn head<'a>(headers: &'a [Header<'a>], body_kind: BodyKind) -> Head<'a> {
+        Head {
+            version: Version::Http11,
+            code: 200,
+            reason: "OK",
+            headers: headers,
+            body_kind: body_kind,
+            connection_header: None,
+            connection_close: false,
+            head_length: 0,
+        }
+    }
+
+    #[test]
+    fn version() {
+        let head = head(&[], BodyKind::Fixed(0));
+        assert_eq!(head.version(), Version::Http11);
+        assert!(!head.connection_close());
+    }
+
+    #[test]
+    fn connection_tokens() {
+        let headers = [
+            Header { name: "Connection", value: b"keep-alive, Foo" },
+            Header { name: "Foo", value: b"bar" },
+            Header { name: "Content-Type", value: b"text/plain" },
+        ];
+        let head = Head {
+            version: Version::Http11,
+            code: 200,
+            reason: "OK",
+            headers: &headers,
+            body_kind: BodyKind::Eof,
+            connection_header: Some("keep-alive, Foo".into()),
+            connection_close: false,
+            head_length: 0,
+        };
+        assert_eq!(head.connection_tokens().collect::<Vec<_>>(),
+                   vec!["keep-alive", "Foo"]);
+        assert_eq!(head.headers().collect::<Vec<_>>(),
+                   vec![("Content-Type", &b"text/plain"[..])]);
+    }
+
+    #[test]
+    #[cfg(feature="http_types")]
+    fn to_http_response() {
+        let headers = [
+            Header { name: "Content-Type", value: b"text/plain" },
+            Header { name: "Set-Cookie", value: b"a=b" },
+            Header { name: "Set-Cookie", value: b"c=d" },
+        ];
+        let head = Head {
+            version: Version::Http10,
+            code: 404,
+            reason: "Not Here",
+            headers: &headers,
+            body_kind: BodyKind::Eof,
+            connection_header: None,
+            connection_close: true,
+            head_length: 0,
+        };
+        let resp = head.to_http_response().unwrap();
+        assert_eq!(resp.status(), ::http::StatusCode::NOT_FOUND);
+        assert_eq!(resp.version(), ::http::Version::HTTP_10);
+        assert_eq!(resp.headers()["content-type"], "text/plain");
+        let cookies = resp.headers().get_all("set-cookie").iter()
+            .collect::<Vec<_>>();
+        assert_eq!(cookies, vec!["a=b", "c=d"]);
+    }
+
+    #[test]
+    #[cfg(feature="http_types")]
+    fn invalid_header() {
+        let headers = [Header { name: "X-Bad", value: b"a\x01b" }];
+        let head = Head {
+            version: Version::Http11,
+            code: 200,
+            reason: "OK",
+            headers: &headers,
+            body_kind: BodyKind::Fixed(0),
+            connection_header: None,
+            connection_close: false,
+            head_length: 0,
+        };
+        assert!(head.to_http_response().is_err());
+    }
+}
@@ -2,9 +2,11 @@
 //!
 mod client;
 mod config;
+mod counters;
 mod encoder;
 mod errors;
 mod head;
+mod multipart;
 mod parser;
 mod proto;
 mod recv_mode;
@@ -12,16 +14,22 @@ pub mod buffered;
 
 pub use self::errors::Error;
 pub use self::client::{Client, Codec};
-pub use self::encoder::{Encoder, EncoderDone, WaitFlush};
+pub use self::encoder::{Encoder, EncoderDone, WaitFlush, FileBody};
+pub use self::encoder::{ReaderBody};
+pub use self::encoder::{CloseReason};
 pub use self::proto::{Proto};
+pub use self::counters::ReuseCounters;
+pub use self::multipart::Multipart;
 
 use std::borrow::Cow;
+use std::sync::Arc;
 use std::time::Duration;
 
 use httparse::Header;
 
 use self::client::BodyKind;
 use {Version};
+use header_hook::HeaderHook;
 
 /// Fine-grained configuration of the HTTP connection
 #[derive(Debug, Clone)]
@@ -31,6 +39,46 @@ pub struct Config {
     keep_alive_timeout: Duration,
     safe_pipeline_timeout: Duration,
     max_request_timeout: Duration,
+    reuse_counters: Option<Arc<ReuseCounters>>,
+    max_status_line_length: usize,
+    header_hook: Option<HeaderHook>,
+    lenient_chunk_size: bool,
+    max_interim_responses: usize,
+    unframed_body: UnframedBody,
+    trailing_data: TrailingData,
+}
+
+/// What to do with HTTP/1.1 response that has no body framing
+///
+/// When response has neither `Content-Length` nor chunked encoding (and
+/// it's not the one that can't have a body), its body is delimited by
+/// closing the connection. This is valid by the spec, but for HTTP/1.1 it's
+/// usually a sign of the misbehaving server or a proxy. HTTP/1.0 responses
+/// are always read until end of stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnframedBody {
+    /// Read body until connection is closed (default)
+    ReadToEof,
+    /// Assume response has empty body, and close connection after it
+    AssumeEmpty,
+    /// Fail request with `UnframedResponse` error
+    Reject,
+}
+
+/// What to do with unexpected bytes after a complete response
+///
+/// When a fixed-length or chunked response is complete, the only thing
+/// that may follow it on a keep-alive connection is the next response
+/// (and only if a request was sent for it). Anything else means that
+/// framing of the response is broken, either by a buggy server or as a
+/// response splitting attempt, so reusing connection is not safe.
+/// Empty lines between responses are always allowed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrailingData {
+    /// Close connection with `UnexpectedData` error (default)
+    Reject,
+    /// Don't check, the data is parsed as the next response
+    Ignore,
 }
 
 /// A borrowed structure that represents response headers
@@ -47,6 +95,7 @@ pub struct Head<'a> {
     body_kind: BodyKind,
     connection_header: Option<Cow<'a, str>>,
     connection_close: bool,
+    head_length: usize,
 }
 
 /// This type is returned from `headers_received` handler of either
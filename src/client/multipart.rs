@@ -0,0 +1,236 @@
+use std::fmt::Write;
+
+use rand::{Rng, thread_rng};
+
+use client::Encoder;
+use negotiation::valid_token;
+
+
+const BOUNDARY_LEN: usize = 32;
+
+/// A writer of `multipart/form-data` body (RFC 7578)
+///
+/// The writer doesn't own the encoder, so you can add parts incrementally
+/// and write file contents with `Encoder::write_body` (or wait for
+/// the buffer to be flushed in between). Example:
+///
+/// ```ignore
+/// let mut form = Multipart::new();
+/// enc.request_line("POST", "/upload", Version::Http11);
+/// enc.add_header("Content-Type", form.content_type()).unwrap();
+/// enc.add_chunked().unwrap();
+/// enc.done_headers().unwrap();
+/// form.write_field(&mut enc, "title", b"Hello");
+/// form.start_file(&mut enc, "file", "hello.txt", "text/plain");
+/// enc.write_body(b"Hello world");
+/// form.finish(&mut enc);
+/// ```
+///
+/// If sizes of all parts are known in advance, you can use
+/// `add_length(x)` instead of chunked encoding, where `x` is a sum of
+/// `field_size()` and `file_size()` for each part and the `end_size()`.
+#[derive(Debug)]
+pub struct Multipart {
+    boundary: String,
+    in_part: bool,
+}
+
+/// Writes quoted parameter value, escaping the same way as browsers do
+fn write_quoted(buf: &mut String, value: &str) {
+    buf.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => buf.push_str("%22"),
+            '\r' => buf.push_str("%0D"),
+            '\n' => buf.push_str("%0A"),
+            c => buf.push(c),
+        }
+    }
+    buf.push('"');
+}
+
+impl Multipart {
+    /// Create a writer with a random boundary
+    pub fn new() -> Multipart {
+        let boundary: String = thread_rng().gen_ascii_chars()
+            .take(BOUNDARY_LEN).collect();
+        Multipart::with_boundary(&boundary)
+    }
+    /// Create a writer with specified boundary
+    ///
+    /// It's up to the caller to ensure that boundary doesn't occur in
+    /// any part of the body.
+    ///
+    /// # Panics
+    ///
+    /// If boundary is empty, longer than 70 characters, or contains
+    /// characters that are not allowed (RFC 2046 allows only a subset
+    /// of printable ascii).
+    pub fn with_boundary(boundary: &str) -> Multipart {
+        assert!(boundary.len() > 0 && boundary.len() <= 70,
+            "boundary must be 1 to 70 characters long");
+        assert!(boundary.bytes().all(|c| c.is_ascii_alphanumeric() ||
+            b"'()+_,-./:=?".contains(&c)),
+            "boundary contains invalid characters");
+        Multipart {
+            boundary: boundary.to_string(),
+            in_part: false,
+        }
+    }
+    /// Boundary that separates parts
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+    /// Value for the `Content-Type` header of the request
+    ///
+    /// Boundary is quoted if it contains characters not allowed in a token.
+    pub fn content_type(&self) -> String {
+        if valid_token(&self.boundary) {
+            format!("multipart/form-data; boundary={}", self.boundary)
+        } else {
+            format!("multipart/form-data; boundary=\"{}\"", self.boundary)
+        }
+    }
+    fn part_head(&self, name: &str, file: Option<(&str, &str)>) -> String {
+        let mut head = String::with_capacity(128);
+        write!(head, "--{}\r\nContent-Disposition: form-data; name=",
+            self.boundary).unwrap();
+        write_quoted(&mut head, name);
+        if let Some((filename, content_type)) = file {
+            head.push_str("; filename=");
+            write_quoted(&mut head, filename);
+            head.push_str("\r\nContent-Type: ");
+            // content type is a header value so it must not break headers
+            head.push_str(&content_type.replace(|c| c == '\r' || c == '\n',
+                                                " "));
+        }
+        head.push_str("\r\n\r\n");
+        head
+    }
+    fn start_part<S>(&mut self, enc: &mut Encoder<S>, head: &str) {
+        if self.in_part {
+            enc.write_body(b"\r\n");
+        }
+        enc.write_body(head.as_bytes());
+        self.in_part = true;
+    }
+    /// Number of bytes `write_field` produces
+    pub fn field_size(&self, name: &str, value_len: u64) -> u64 {
+        self.part_head(name, None).len() as u64 + value_len + 2
+    }
+    /// Number of bytes `start_file` produces along with the file data
+    pub fn file_size(&self, name: &str, filename: &str, content_type: &str,
+        file_len: u64)
+        -> u64
+    {
+        self.part_head(name, Some((filename, content_type))).len() as u64
+            + file_len + 2
+    }
+    /// Number of bytes `finish` produces
+    pub fn end_size(&self) -> u64 {
+        self.boundary.len() as u64 + 6
+    }
+    /// Write a simple form field
+    pub fn write_field<S>(&mut self, enc: &mut Encoder<S>,
+        name: &str, value: &[u8])
+    {
+        let head = self.part_head(name, None);
+        self.start_part(enc, &head);
+        enc.write_body(value);
+    }
+    /// Write headers of a file part
+    ///
+    /// File data should be written with `enc.write_body()` after this call,
+    /// until next part is started or `finish()` is called.
+    pub fn start_file<S>(&mut self, enc: &mut Encoder<S>,
+        name: &str, filename: &str, content_type: &str)
+    {
+        let head = self.part_head(name, Some((filename, content_type)));
+        self.start_part(enc, &head);
+    }
+    /// Write the final boundary
+    ///
+    /// You still need to call `enc.done()` after this.
+    pub fn finish<S>(&mut self, enc: &mut Encoder<S>) {
+        if self.in_part {
+            enc.write_body(b"\r\n");
+            self.in_part = false;
+        }
+        enc.write_body(b"--");
+        enc.write_body(self.boundary.as_bytes());
+        enc.write_body(b"--\r\n");
+    }
+}
+
+impl Default for Multipart {
+    fn default() -> Multipart {
+        Multipart::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, AtomicBool};
+    use tk_bufstream::{IoBuf, MockData};
+
+    use enums::Version;
+    use client::encoder::{new, get_inner};
+    use super::Multipart;
+
+    #[test]
+    fn random_boundary() {
+        let a = Multipart::new();
+        let b = Multipart::new();
+        assert_eq!(a.boundary().len(), 32);
+        assert!(a.boundary() != b.boundary());
+    }
+
+    #[test]
+    #[should_panic(expected="invalid characters")]
+    fn invalid_boundary() {
+        Multipart::with_boundary("a\r\nb");
+    }
+
+    #[test]
+    fn quoted_boundary() {
+        assert_eq!(Multipart::with_boundary("a-b_c.d'e+f").content_type(),
+                   "multipart/form-data; boundary=a-b_c.d'e+f");
+        assert_eq!(Multipart::with_boundary("a(b)c,d/e:f=g?").content_type(),
+                   "multipart/form-data; boundary=\"a(b)c,d/e:f=g?\"");
+    }
+
+    #[test]
+    fn two_parts() {
+        let mock = MockData::new();
+        let mut enc = new(IoBuf::new(mock.clone()).split().0,
+            Arc::new(AtomicUsize::new(0)), Arc::new(AtomicBool::new(false)));
+        let mut form = Multipart::with_boundary("xyz");
+        let length = form.field_size("title", 5)
+            + form.file_size("file", "a \"b\".txt", "text/plain", 11)
+            + form.end_size();
+        enc.request_line("POST", "/upload", Version::Http11);
+        enc.add_header("Content-Type", form.content_type()).unwrap();
+        enc.add_length(length).unwrap();
+        enc.done_headers().unwrap();
+        form.write_field(&mut enc, "title", b"Hello");
+        form.start_file(&mut enc, "file", "a \"b\".txt", "text/plain");
+        enc.write_body(b"Hello ");
+        enc.write_body(b"world");
+        form.finish(&mut enc);
+        get_inner(enc.done()).flush().unwrap();
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "POST /upload HTTP/1.1\r\n\
+             Content-Type: multipart/form-data; boundary=xyz\r\n\
+             Content-Length: 190\r\n\r\n\
+             --xyz\r\n\
+             Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+             Hello\r\n\
+             --xyz\r\n\
+             Content-Disposition: form-data; name=\"file\"; \
+                filename=\"a %22b%22.txt\"\r\n\
+             Content-Type: text/plain\r\n\r\n\
+             Hello world\r\n\
+             --xyz--\r\n");
+    }
+}
@@ -1,3 +1,4 @@
+use std::cmp::min;
 use std::sync::Arc;
 use std::borrow::Cow;
 use std::sync::atomic::{AtomicUsize, AtomicBool, Ordering};
@@ -18,7 +19,7 @@ use headers;
 use chunked;
 use body_parser::BodyProgress;
 use client::encoder::RequestState;
-use client::{Codec, Error, Head};
+use client::{Codec, Config, Error, Head, UnframedBody};
 
 
 /// Number of headers to allocate on a stack
@@ -32,6 +33,7 @@ enum State {
     Headers {
         request_state: Arc<AtomicUsize>,
         close_signal: Arc<AtomicBool>,
+        config: Arc<Config>,
     },
     Body {
         mode: Mode,
@@ -44,6 +46,23 @@ pub struct Parser<S, C: Codec<S>> {
     codec: C,
     close: bool,
     state: State,
+    /// Number of interim (1xx) responses skipped
+    interim: usize,
+}
+
+enum Parsed {
+    /// Interim (1xx) response that is skipped
+    Interim,
+    /// Final response
+    Final(State, bool),
+}
+
+/// Returns true for 1xx responses that are followed by the final response
+///
+/// `101 Switching Protocols` is final as the connection is passed to the
+/// codec after it.
+fn is_interim(code: u16) -> bool {
+    code >= 100 && code < 200 && code != 101
 }
 
 
@@ -124,7 +143,7 @@ fn scan_headers<'x>(is_head: bool, code: u16, headers: &'x [httparse::Header])
     Ok((result, connection, close))
 }
 
-fn new_body(mode: BodyKind, recv_mode: Mode)
+fn new_body(mode: BodyKind, recv_mode: Mode, cfg: &Config)
     -> Result<BodyProgress, ErrorEnum>
 {
     use super::client::BodyKind as B;
@@ -132,21 +151,38 @@ fn new_body(mode: BodyKind, recv_mode: Mode)
     use client::errors::ErrorEnum::*;
     use body_parser::BodyProgress as P;
     match (mode, recv_mode) {
-        // TODO(tailhook) check size < usize
+        // body (if any) belongs to the tunnelled stream
+        (_, M::Hijack) => Ok(P::Fixed(0)),
         (B::Fixed(x), M::Buffered(b)) if x > b as u64 => {
             Err(ResponseBodyTooLong)
         }
-        (B::Fixed(x), _)  => Ok(P::Fixed(x as usize)),
-        (B::Chunked, _) => Ok(P::Chunked(chunked::State::new())),
+        (B::Fixed(x), _)  => Ok(P::Fixed(x)),
+        (B::Chunked, _) => Ok(P::Chunked(chunked::State::new()
+            .lenient(cfg.lenient_chunk_size))),
         (B::Eof, _) => Ok(P::Eof),
     }
 }
 
+/// Checks length of the status line, even if it is not fully received yet
+fn check_status_line(buffer: &Buf, limit: usize) -> Result<(), ErrorEnum> {
+    // no need to look further than the limit (plus line ending)
+    let buf = &buffer[..min(buffer.len(), limit.saturating_add(2))];
+    let line = match buf.iter().position(|&c| c == b'\n') {
+        Some(end) => &buf[..end],
+        None => buf,
+    };
+    let len = if line.ends_with(b"\r") { line.len() - 1 } else { line.len() };
+    if len > limit {
+        return Err(ErrorEnum::StatusLineTooLong);
+    }
+    Ok(())
+}
+
 fn parse_headers<S, C: Codec<S>>(
-    buffer: &mut Buf, codec: &mut C, is_head: bool)
-    -> Result<Option<(State, bool)>, Error>
+    buffer: &mut Buf, codec: &mut C, is_head: bool, cfg: &Config)
+    -> Result<Option<Parsed>, Error>
 {
-    let (mode, body, close, bytes) = {
+    let (parsed, bytes) = {
         let mut vec;
         let mut headers = [httparse::EMPTY_HEADER; MIN_HEADERS];
         let (ver, code, reason, headers, bytes) = {
@@ -166,35 +202,58 @@ fn parse_headers<S, C: Codec<S>>(
                 _ => return Ok(None),
             }
         };
-        let (body, conn, close) = try!(scan_headers(is_head, code, &headers));
-        let head = Head {
-            version: if ver == 1
-                { Version::Http11 } else { Version::Http10 },
-            code: code,
-            reason: reason,
-            headers: headers,
-            body_kind: body,
-            connection_header: conn,
-            // For HTTP/1.0 we could implement Connection: Keep-Alive
-            // but hopefully it's rare enough to ignore nowadays
-            connection_close: close || ver == 0,
-        };
-        let mode = codec.headers_received(&head)?;
-        (mode, body, close, bytes)
+        if is_interim(code) {
+            debug!("Skipping interim response {} {:?}", code, reason);
+            (None, bytes)
+        } else {
+            let (body, conn, close) = try!(scan_headers(is_head, code,
+                                                        &headers));
+            let (body, close) = match (body, ver, cfg.unframed_body) {
+                (BodyKind::Eof, 1, UnframedBody::AssumeEmpty) => {
+                    (BodyKind::Fixed(0), true)
+                }
+                (BodyKind::Eof, 1, UnframedBody::Reject) => {
+                    return Err(ErrorEnum::UnframedResponse.into());
+                }
+                (body, _, _) => (body, close),
+            };
+            let head = Head {
+                version: if ver == 1
+                    { Version::Http11 } else { Version::Http10 },
+                code: code,
+                reason: reason,
+                headers: headers,
+                body_kind: body,
+                connection_header: conn,
+                // For HTTP/1.0 we could implement Connection: Keep-Alive
+                // but hopefully it's rare enough to ignore nowadays
+                connection_close: close || ver == 0,
+                head_length: bytes,
+            };
+            let mode = codec.headers_received(&head)?;
+            // after `101 Switching Protocols` the stream is not HTTP anymore,
+            // so if codec doesn't take it over, the connection is closed
+            let close = close || code == 101 && mode.mode != Mode::Hijack;
+            (Some((mode, body, close)), bytes)
+        }
     };
     buffer.consume(bytes);
-    Ok(Some((
-        State::Body {
-            mode: mode.mode,
-            progress: new_body(body, mode.mode)?,
-        },
-        close,
-    )))
+    match parsed {
+        Some((mode, body, close)) => Ok(Some(Parsed::Final(
+            State::Body {
+                mode: mode.mode,
+                progress: new_body(body, mode.mode, cfg)?,
+            },
+            close,
+        ))),
+        None => Ok(Some(Parsed::Interim)),
+    }
 }
 
 impl<S, C: Codec<S>> Parser<S, C> {
     pub fn new(io: ReadBuf<S>, codec: C,
-        request_state: Arc<AtomicUsize>, close_signal: Arc<AtomicBool>)
+        request_state: Arc<AtomicUsize>, close_signal: Arc<AtomicBool>,
+        config: &Arc<Config>)
         -> Parser<S, C>
     {
         Parser {
@@ -204,9 +263,18 @@ impl<S, C: Codec<S>> Parser<S, C> {
             state: State::Headers {
                 request_state: request_state,
                 close_signal: close_signal,
+                config: config.clone(),
             },
+            interim: 0,
         }
     }
+    /// Returns true if codec requested to hijack the connection
+    pub fn is_hijacked(&self) -> bool {
+        matches!(self.state, State::Body { mode: Mode::Hijack, .. })
+    }
+    pub fn into_codec(self) -> C {
+        self.codec
+    }
     fn read_and_parse(&mut self) -> Poll<(), Error>
         where S: AsyncRead
     {
@@ -216,10 +284,11 @@ impl<S, C: Codec<S>> Parser<S, C> {
         self.state = if let Headers {
                 ref request_state,
                 ref close_signal,
+                ref config,
             } = self.state
         {
             let state;
-            loop {
+            'read: loop {
                 if io.read().map_err(ErrorEnum::Io)? == 0 {
                     if io.done() {
                         return Err(ErrorEnum::ResetOnResponseHeaders.into());
@@ -232,16 +301,30 @@ impl<S, C: Codec<S>> Parser<S, C> {
                     return Err(ErrorEnum::PrematureResponseHeaders.into());
                 }
                 let is_head = reqs == RequestState::StartedHead as usize;
-                match parse_headers(&mut io.in_buf, &mut self.codec, is_head)? {
-                    None => continue,
-                    Some((body, close)) => {
-                        if close {
-                            close_signal.store(true, Ordering::SeqCst);
-                            self.close = true;
+                loop {
+                    check_status_line(&io.in_buf,
+                                      config.max_status_line_length)?;
+                    match parse_headers(&mut io.in_buf, &mut self.codec,
+                                        is_head, config)?
+                    {
+                        None => continue 'read,
+                        Some(Parsed::Interim) => {
+                            self.interim += 1;
+                            if self.interim > config.max_interim_responses {
+                                return Err(
+                                    ErrorEnum::TooManyInterimResponses.into());
+                            }
+                            // final response may be in the buffer already
                         }
-                        state = body;
-                        break
-                    },
+                        Some(Parsed::Final(body, close)) => {
+                            if close {
+                                close_signal.store(true, Ordering::SeqCst);
+                                self.close = true;
+                            }
+                            state = body;
+                            break 'read;
+                        },
+                    }
                 }
             };
             state
@@ -252,6 +335,7 @@ impl<S, C: Codec<S>> Parser<S, C> {
         loop {
             match self.state {
                 Headers {..} => unreachable!(),
+                Body { mode: Hijack, .. } => return Ok(Async::Ready(())),
                 Body { ref mode, ref mut progress } => {
                     progress.parse(&mut io).map_err(ErrorEnum::ChunkSize)?;
                     let (bytes, done) = progress.check_buf(&io);
@@ -276,9 +360,8 @@ impl<S, C: Codec<S>> Parser<S, C> {
                             }
                         }
                         Some(Async::NotReady) => {
-                            if matches!(*mode, Progressive(x) if x > bytes) {
-                                return Ok(Async::NotReady);
-                            }
+                            // codec wakes up the task when it's ready
+                            return Ok(Async::NotReady);
                         }
                         None => {} // Read more
                     }
@@ -298,12 +381,13 @@ impl<S, C: Codec<S>> Parser<S, C> {
 impl<S: AsyncRead, C: Codec<S>> Future for Parser<S, C> {
     type Item = Option<ReadBuf<S>>;
     type Error = Error;
-    /// Returns None if response contains `Connection: close`
+    /// Returns None if response contains `Connection: close` (unless
+    /// connection is hijacked)
     fn poll(&mut self) -> Poll<Option<ReadBuf<S>>, Error> {
         match self.read_and_parse()? {
             Async::Ready(()) => {
                 let io = self.io.take().expect("buffer still here");
-                if self.close {
+                if self.close && !self.is_hijacked() {
                     Ok(Async::Ready(None))
                 } else {
                     Ok(Async::Ready(Some(io)))
@@ -1,12 +1,12 @@
 use std::collections::VecDeque;
-use std::cmp::max;
+use std::cmp::{max, min};
 use std::mem;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, AtomicBool, Ordering};
 use std::time::Instant;
 
-use tk_bufstream::{IoBuf, WriteBuf, ReadBuf};
+use tk_bufstream::{IoBuf, WriteBuf, ReadBuf, Buf};
 use tokio_core::net::TcpStream;
 use tokio_core::reactor::{Handle, Timeout};
 use tokio_io::{AsyncRead, AsyncWrite};
@@ -15,18 +15,23 @@ use futures::{Future, AsyncSink, Async, Sink, StartSend, Poll};
 use client::parser::Parser;
 use client::encoder::{self, get_inner};
 use client::errors::ErrorEnum;
-use client::{Codec, Error, Config};
+use client::{Codec, Error, Config, TrailingData};
+use client::counters::Decision;
 
 
 enum OutState<S, F> {
     Idle(WriteBuf<S>, Instant),
     Write(F, Instant),
+    Hijacked,
     Void,
 }
 
 enum InState<S, C: Codec<S>> {
     Idle(ReadBuf<S>, Instant),
     Read(Parser<S, C>, Instant),
+    /// Waiting for request to be written to hand over the connection
+    Hijack(C, ReadBuf<S>, Instant),
+    Hijacked,
     Void,
 }
 
@@ -42,6 +47,8 @@ pub struct PureProto<S, C: Codec<S>> {
     reading: InState<S, C>,
     close: Arc<AtomicBool>,
     config: Arc<Config>,
+    /// Whether reason of connection close is already counted
+    close_recorded: bool,
 }
 
 /// A low-level HTTP/1.x client protocol handler
@@ -55,6 +62,24 @@ pub struct Proto<S, C: Codec<S>> {
 }
 
 
+/// Checks data left in the buffer after a complete response
+///
+/// It may only be the beginning of the next response, if there is a
+/// request waiting for it.
+fn check_trailing_data(buf: &Buf, pipelined: bool) -> Result<(), ErrorEnum> {
+    let start = buf[..].iter().position(|&c| c != b'\r' && c != b'\n')
+        .unwrap_or(buf.len());
+    let data = &buf[start..];
+    if data.len() == 0 {
+        return Ok(());
+    }
+    let prefix = min(data.len(), 5);
+    if pipelined && data[..prefix] == b"HTTP/"[..prefix] {
+        return Ok(());
+    }
+    Err(ErrorEnum::UnexpectedData)
+}
+
 impl<S, C: Codec<S>> Proto<S, C> {
     /// Create a new protocol implementation from a TCP connection and a config
     ///
@@ -71,6 +96,7 @@ impl<S, C: Codec<S>> Proto<S, C> {
                 reading: InState::Idle(cin, Instant::now()),
                 close: Arc::new(AtomicBool::new(false)),
                 config: cfg.clone(),
+                close_recorded: false,
             },
             handle: handle.clone(),
             timeout: Timeout::new(cfg.keep_alive_timeout, &handle)
@@ -109,11 +135,15 @@ impl<S: AsyncRead + AsyncWrite, C: Codec<S>> PureProto<S, C> {
                 }
                 OutState::Idle(io, time)
             }
+            OutState::Hijacked => OutState::Hijacked,
             // Note we break connection if serializer errored, because
             // we don't actually know if connection can be reused
             // safefully in this case
             OutState::Write(mut fut, start) => match fut.poll()? {
                 Async::Ready(done) => {
+                    if let Some(reason) = done.close_reason() {
+                        debug!("Connection will not be reused: {:?}", reason);
+                    }
                     let mut io = get_inner(done);
                     io.flush().map_err(ErrorEnum::Io)?;
                     progress = true;
@@ -132,7 +162,7 @@ impl<S: AsyncRead + AsyncWrite, C: Codec<S>> PureProto<S, C> {
                     if let Some(w) = self.waiting.pop_front() {
                         let Waiting { codec: nr, state, queued_at } = w;
                         let parser = Parser::new(io, nr,
-                            state, self.close.clone());
+                            state, self.close.clone(), &self.config);
                         (InState::Read(parser, queued_at), true)
                     } else {
                         // This serves for two purposes:
@@ -155,7 +185,17 @@ impl<S: AsyncRead + AsyncWrite, C: Codec<S>> PureProto<S, C> {
                         Async::NotReady => {
                             (InState::Read(parser, time), false)
                         }
+                        Async::Ready(Some(io)) if parser.is_hijacked() => {
+                            (InState::Hijack(parser.into_codec(), io, time),
+                             true)
+                        }
                         Async::Ready(Some(io)) => {
+                            if self.config.trailing_data ==
+                                TrailingData::Reject
+                            {
+                                check_trailing_data(&io.in_buf,
+                                    self.waiting.len() > 0)?;
+                            }
                             // after request is done, rearm keep-alive
                             // timeout
                             match self.writing {
@@ -164,6 +204,12 @@ impl<S: AsyncRead + AsyncWrite, C: Codec<S>> PureProto<S, C> {
                                 }
                                 _ => {}
                             }
+                            // if server closes connection we get `None`
+                            if self.close.load(Ordering::SeqCst) {
+                                self.record(Decision::ClosedByClient);
+                            } else {
+                                self.record(Decision::Reused);
+                            }
                             (InState::Idle(io, Instant::now()), true)
                         }
                         Async::Ready(None) => {
@@ -171,6 +217,25 @@ impl<S: AsyncRead + AsyncWrite, C: Codec<S>> PureProto<S, C> {
                         }
                     }
                 }
+                InState::Hijack(mut codec, io, time) => {
+                    if matches!(self.writing, OutState::Idle(..)) {
+                        let out = match mem::replace(&mut self.writing,
+                                                     OutState::Hijacked)
+                        {
+                            OutState::Idle(out, _) => out,
+                            _ => unreachable!(),
+                        };
+                        // requests pipelined after this one are never
+                        // going to be answered
+                        self.waiting.clear();
+                        self.record(Decision::ClosedByClient);
+                        codec.hijack(out, io);
+                        (InState::Hijacked, true)
+                    } else {
+                        (InState::Hijack(codec, io, time), false)
+                    }
+                }
+                InState::Hijacked => (InState::Hijacked, false),
                 InState::Void => unreachable!(),
             };
         self.reading = state;
@@ -181,9 +246,27 @@ impl<S: AsyncRead + AsyncWrite, C: Codec<S>> PureProto<S, C> {
 impl<S: AsyncRead + AsyncWrite, C: Codec<S>> Sink for Proto<S, C> {
     type SinkItem = C;
     type SinkError = Error;
-    fn start_send(&mut self, mut item: Self::SinkItem)
+    fn start_send(&mut self, item: Self::SinkItem)
         -> StartSend<Self::SinkItem, Self::SinkError>
     {
+        match self.do_start_send(item) {
+            Ok(x) => Ok(x),
+            Err(e) => Err(self.proto.record_error(e)),
+        }
+    }
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        match self.do_poll_complete() {
+            Ok(x) => Ok(x),
+            Err(e) => Err(self.proto.record_error(e)),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite, C: Codec<S>> Proto<S, C> {
+    fn do_start_send(&mut self, mut item: C) -> StartSend<C, Error> {
+        if self.proto.is_hijacked() {
+            return Err(ErrorEnum::Closed.into());
+        }
         let old_timeout = self.proto.get_timeout();
         let res = loop {
             item = match self.proto.start_send(item)? {
@@ -196,6 +279,9 @@ impl<S: AsyncRead + AsyncWrite, C: Codec<S>> Sink for Proto<S, C> {
                 break AsyncSink::NotReady(item);
             }
         };
+        if self.proto.is_hijacked() {
+            return Ok(res);
+        }
         let new_timeout = self.proto.get_timeout();
         let now = Instant::now();
         if new_timeout < now {
@@ -224,9 +310,15 @@ impl<S: AsyncRead + AsyncWrite, C: Codec<S>> Sink for Proto<S, C> {
         }
         Ok(res)
     }
-    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+    fn do_poll_complete(&mut self) -> Poll<(), Error> {
+        if self.proto.is_hijacked() {
+            return Ok(Async::Ready(()));
+        }
         let old_timeout = self.proto.get_timeout();
         let res = self.proto.poll_complete()?;
+        if self.proto.is_hijacked() {
+            return Ok(res);
+        }
         let new_timeout = self.proto.get_timeout();
         let now = Instant::now();
         if new_timeout < now {
@@ -250,6 +342,30 @@ impl<S: AsyncRead + AsyncWrite, C: Codec<S>> Sink for Proto<S, C> {
 }
 
 impl<S, C: Codec<S>> PureProto<S, C> {
+    fn record(&mut self, decision: Decision) {
+        if let Some(ref counters) = self.config.reuse_counters {
+            if decision == Decision::Reused {
+                counters.record(decision);
+            } else if !self.close_recorded {
+                self.close_recorded = true;
+                counters.record(decision);
+            }
+        }
+    }
+    fn record_error(&mut self, err: Error) -> Error {
+        if err.is_timeout() {
+            self.record(Decision::ClosedDueToTimeout);
+        } else if err.is_graceful() {
+            // only `Closed` is graceful among remaining errors
+            self.record(Decision::ClosedByServer);
+        } else {
+            self.record(Decision::ClosedDueToError);
+        }
+        err
+    }
+    fn is_hijacked(&self) -> bool {
+        matches!(self.reading, InState::Hijacked)
+    }
     fn get_timeout(&self) -> Instant {
         match self.writing {
             OutState::Idle(_, time) => {
@@ -259,9 +375,11 @@ impl<S, C: Codec<S>> PureProto<S, C> {
                             return max(time, rtime) +
                                 self.config.keep_alive_timeout;
                         }
-                        InState::Read(_, time) => {
+                        InState::Read(_, time) |
+                        InState::Hijack(_, _, time) => {
                             return time + self.config.max_request_timeout;
                         }
+                        InState::Hijacked => unreachable!(),
                         InState::Void => unreachable!(),
                     }
                 } else {
@@ -272,6 +390,7 @@ impl<S, C: Codec<S>> PureProto<S, C> {
             OutState::Write(_, time) => {
                 return time + self.config.max_request_timeout;
             }
+            OutState::Hijacked => unreachable!(),
             OutState::Void => unreachable!(),
         }
     }
@@ -283,6 +402,11 @@ impl<S: AsyncRead + AsyncWrite, C: Codec<S>> Sink for PureProto<S, C> {
     fn start_send(&mut self, mut item: Self::SinkItem)
         -> StartSend<Self::SinkItem, Self::SinkError>
     {
+        match self.reading {
+            InState::Hijack(..) => return Ok(AsyncSink::NotReady(item)),
+            InState::Hijacked => return Err(ErrorEnum::Closed.into()),
+            _ => {}
+        }
         if self.waiting.len() > 0 {
             if self.waiting.len() > self.config.inflight_request_limit {
                 // Return right away if limit reached
@@ -325,8 +449,10 @@ impl<S: AsyncRead + AsyncWrite, C: Codec<S>> Sink for PureProto<S, C> {
                         (AsyncSink::NotReady(item), OutState::Idle(io, time))
                     } else {
                         let state = Arc::new(AtomicUsize::new(0));
-                        let e = encoder::new(io,
+                        let mut e = encoder::new(io,
                                 state.clone(), self.close.clone());
+                        encoder::set_header_hook(&mut e,
+                            self.config.header_hook.clone());
                         let fut = item.start_write(e);
                         self.waiting.push_back(Waiting {
                             codec: item,
@@ -345,6 +471,7 @@ impl<S: AsyncRead + AsyncWrite, C: Codec<S>> Sink for PureProto<S, C> {
                 // * Dropping future
                 (AsyncSink::NotReady(item), OutState::Write(fut, start))
             }
+            OutState::Hijacked => unreachable!(),
             OutState::Void => unreachable!(),
         };
         self.writing = st;
@@ -360,7 +487,7 @@ impl<S: AsyncRead + AsyncWrite, C: Codec<S>> Sink for PureProto<S, C> {
         }
         // Basically we return Ready when there are no in-flight requests,
         // which means we can shutdown connection safefully.
-        if self.waiting.len() == 0 &&
+        if self.is_hijacked() || self.waiting.len() == 0 &&
                 matches!(self.writing, OutState::Idle(..)) &&
                 matches!(self.reading, InState::Idle(..))
         {
@@ -370,3 +497,565 @@ impl<S: AsyncRead + AsyncWrite, C: Codec<S>> Sink for PureProto<S, C> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::mem;
+    use std::sync::{Arc, Mutex};
+
+    use futures::{Async, Sink};
+    use futures::future::{FutureResult, ok, lazy};
+    use tk_bufstream::{MockData, ReadBuf, WriteBuf};
+    use tokio_core::reactor::Core;
+
+    use client::{Codec, Config, Encoder, EncoderDone, Error, Head, RecvMode};
+    use client::{ReuseCounters, UnframedBody, TrailingData};
+    use client::errors::ErrorEnum;
+    use enums::Version;
+    use test_util::Reset;
+    use super::Proto;
+
+    struct Get {
+        close: bool,
+    }
+
+    impl<S> Codec<S> for Get {
+        type Future = FutureResult<EncoderDone<S>, Error>;
+        fn start_write(&mut self, mut e: Encoder<S>) -> Self::Future {
+            e.request_line("GET", "/", Version::Http11);
+            if self.close {
+                e.add_header("Connection", "close").unwrap();
+            }
+            e.done_headers().unwrap();
+            ok(e.done())
+        }
+        fn headers_received(&mut self, _headers: &Head)
+            -> Result<RecvMode, Error>
+        {
+            Ok(RecvMode::buffered(1024))
+        }
+        fn data_received(&mut self, data: &[u8], end: bool)
+            -> Result<Async<usize>, Error>
+        {
+            assert!(end);
+            Ok(Async::Ready(data.len()))
+        }
+    }
+
+    fn request(close: bool, response: &str)
+        -> (Result<Async<()>, Error>, Arc<ReuseCounters>)
+    {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let counters = Arc::new(ReuseCounters::new());
+        let mock = MockData::new();
+        let mut proto = Proto::new(mock.clone(), &handle,
+            &Config::new().reuse_counters(&counters).done());
+        // timeouts must be polled within a task
+        let res = core.run(lazy(|| {
+            assert!(proto.start_send(Get { close: close })
+                .unwrap().is_ready());
+            proto.poll_complete().unwrap();
+            mock.add_input(response);
+            Ok::<_, ()>(proto.poll_complete())
+        })).unwrap();
+        (res, counters)
+    }
+
+    #[test]
+    fn reused() {
+        let (res, counters) = request(false,
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+        assert!(res.unwrap().is_ready());
+        assert_eq!(counters.reused(), 1);
+        assert_eq!(counters.closed_by_server(), 0);
+    }
+
+    #[test]
+    fn closed_by_server() {
+        let (res, counters) = request(false,
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\
+             Connection: close\r\n\r\n");
+        assert!(res.is_err());
+        assert_eq!(counters.reused(), 0);
+        assert_eq!(counters.closed_by_server(), 1);
+    }
+
+    #[test]
+    fn closed_by_client() {
+        let (res, counters) = request(true,
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+        assert!(res.unwrap().is_ready());
+        assert_eq!(counters.reused(), 0);
+        assert_eq!(counters.closed_by_client(), 1);
+    }
+
+    #[test]
+    fn closed_due_to_error() {
+        let (res, counters) = request(false, "HTTP/1.1 200 OK\r\n\
+             Content-Length: 1\r\nContent-Length: 2\r\n\r\n");
+        assert!(res.is_err());
+        assert_eq!(counters.closed_due_to_error(), 1);
+        assert_eq!(counters.closed_by_server(), 0);
+    }
+
+    #[test]
+    fn reset_mid_headers() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let mut proto = Proto::new(
+            Reset(Some(b"HTTP/1.1 200 OK\r\nContent-Le")),
+            &handle, &Config::new().done());
+        let err = core.run(lazy(|| {
+            assert!(proto.start_send(Get { close: false })
+                .unwrap().is_ready());
+            Ok::<_, ()>(proto.poll_complete())
+        })).unwrap().unwrap_err();
+        assert!(matches!(*err.kind(), ErrorEnum::ResetOnResponseHeaders));
+        assert!(err.is_connection_reset());
+        assert!(!err.is_protocol_error());
+    }
+
+    #[test]
+    fn truncated_chunked_body() {
+        for &input in &[
+            // mid-chunk
+            &b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+               5\r\nhel"[..],
+            // between chunks
+            &b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+               5\r\nhello\r\n"[..],
+            // last chunk without the final empty line
+            &b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+               5\r\nhello\r\n0\r\n"[..],
+        ] {
+            let mut core = Core::new().unwrap();
+            let handle = core.handle();
+            let mut proto = Proto::new(Reset(Some(input)),
+                &handle, &Config::new().done());
+            let err = core.run(lazy(|| {
+                assert!(proto.start_send(Get { close: false })
+                    .unwrap().is_ready());
+                Ok::<_, ()>(proto.poll_complete())
+            })).unwrap().unwrap_err();
+            assert!(matches!(*err.kind(), ErrorEnum::ResetOnResponseBody));
+            assert!(err.is_connection_reset());
+        }
+    }
+
+    #[test]
+    fn malformed_headers() {
+        let (res, _) = request(false,
+            "HTTP/1.1 200 OK\r\nContent-Length 0\r\n\r\n");
+        let err = res.unwrap_err();
+        assert!(err.is_protocol_error());
+        assert!(!err.is_connection_reset());
+    }
+
+    #[test]
+    fn status_line_too_long() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let mock = MockData::new();
+        let mut proto = Proto::new(mock.clone(), &handle,
+            &Config::new().max_status_line_length(256).done());
+        let res = core.run(lazy(|| {
+            assert!(proto.start_send(Get { close: false })
+                .unwrap().is_ready());
+            proto.poll_complete().unwrap();
+            mock.add_input("HTTP/1.1 200 ");
+            mock.add_input(vec![b'x'; 1 << 20]);
+            Ok::<_, ()>(proto.poll_complete())
+        })).unwrap();
+        assert!(matches!(*res.unwrap_err().kind(),
+                         ErrorEnum::StatusLineTooLong));
+    }
+
+    /// Logs response head and drains the body
+    struct Inspect {
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Codec<MockData> for Inspect {
+        type Future = FutureResult<EncoderDone<MockData>, Error>;
+        fn start_write(&mut self, mut e: Encoder<MockData>) -> Self::Future {
+            e.request_line("GET", "/", Version::Http11);
+            e.done_headers().unwrap();
+            ok(e.done())
+        }
+        fn headers_received(&mut self, headers: &Head)
+            -> Result<RecvMode, Error>
+        {
+            let route = headers.headers()
+                .find(|&(name, _)| name == "X-Route")
+                .map(|(_, value)| String::from_utf8_lossy(value).to_string());
+            self.log.lock().unwrap().push(format!("{} {} {:?}",
+                headers.version(), headers.raw_status().0, route));
+            Ok(RecvMode::progressive(1))
+        }
+        fn data_received(&mut self, data: &[u8], end: bool)
+            -> Result<Async<usize>, Error>
+        {
+            self.log.lock().unwrap().push(format!("{} {}", data.len(), end));
+            Ok(Async::Ready(data.len()))
+        }
+    }
+
+    /// Records a property of every response head received
+    struct RecordHead<T>(fn(&Head) -> T, Arc<Mutex<Vec<T>>>);
+
+    impl<S, T> Codec<S> for RecordHead<T> {
+        type Future = FutureResult<EncoderDone<S>, Error>;
+        fn start_write(&mut self, mut e: Encoder<S>) -> Self::Future {
+            e.request_line("GET", "/", Version::Http11);
+            e.done_headers().unwrap();
+            ok(e.done())
+        }
+        fn headers_received(&mut self, headers: &Head)
+            -> Result<RecvMode, Error>
+        {
+            self.1.lock().unwrap().push((self.0)(headers));
+            Ok(RecvMode::buffered(1024))
+        }
+        fn data_received(&mut self, data: &[u8], end: bool)
+            -> Result<Async<usize>, Error>
+        {
+            assert!(end);
+            Ok(Async::Ready(data.len()))
+        }
+    }
+
+    /// Sends a request, feeds the response and returns what `f` returned
+    /// for its head
+    fn record_head<T>(f: fn(&Head) -> T, response: &str) -> Vec<T> {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let mock = MockData::new();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut proto = Proto::new(mock.clone(), &handle,
+            &Config::new().done());
+        core.run(lazy(|| {
+            assert!(proto.start_send(RecordHead(f, log.clone()))
+                .unwrap().is_ready());
+            proto.poll_complete().unwrap();
+            mock.add_input(response);
+            Ok::<_, ()>(proto.poll_complete())
+        })).unwrap().unwrap();
+        let result = mem::replace(&mut *log.lock().unwrap(), Vec::new());
+        result
+    }
+
+    #[test]
+    fn head_length() {
+        let head = "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\
+                    Content-Length: 5\r\n\r\n";
+        assert_eq!(record_head(|h| h.head_length(),
+            &format!("HTTP/1.1 100 Continue\r\n\r\n{}hello", head)),
+            vec![head.len()]);
+    }
+
+    #[test]
+    fn body_length() {
+        assert_eq!(record_head(|h| h.body_length(),
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello"),
+            vec![Some(5)]);
+        assert_eq!(record_head(|h| h.body_length(),
+            "HTTP/1.1 204 No Content\r\n\r\n"),
+            vec![Some(0)]);
+        assert_eq!(record_head(|h| h.body_length(),
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+             5\r\nhello\r\n0\r\n\r\n"),
+            vec![None]);
+        assert_eq!(record_head(|h| h.body_length(),
+            "HTTP/1.0 200 OK\r\n\r\nhello"),
+            vec![None]);
+    }
+
+    #[test]
+    fn head_before_body() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let mock = MockData::new();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut proto = Proto::new(mock.clone(), &handle,
+            &Config::new().done());
+        let res = core.run(lazy(|| {
+            assert!(proto.start_send(Inspect { log: log.clone() })
+                .unwrap().is_ready());
+            proto.poll_complete().unwrap();
+            mock.add_input("HTTP/1.1 200 OK\r\nX-Route: a\r\n\
+                            Content-Length: 10\r\n\r\n");
+            assert!(proto.poll_complete().unwrap().is_not_ready());
+            // head is available before any body is received
+            assert_eq!(*log.lock().unwrap(),
+                       vec!["HTTP/1.1 200 Some(\"a\")".to_string()]);
+            mock.add_input("0123456789");
+            Ok::<_, ()>(proto.poll_complete())
+        })).unwrap();
+        assert!(res.unwrap().is_ready());
+        assert_eq!(log.lock().unwrap().last().unwrap(), "10 true");
+    }
+
+    #[test]
+    fn huge_content_length() {
+        // 2^32 + 5 bytes, used to be truncated to 5 on 32-bit platforms
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let mock = MockData::new();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut proto = Proto::new(mock.clone(), &handle,
+            &Config::new().done());
+        let res = core.run(lazy(|| {
+            assert!(proto.start_send(Inspect { log: log.clone() })
+                .unwrap().is_ready());
+            proto.poll_complete().unwrap();
+            mock.add_input("HTTP/1.1 200 OK\r\n\
+                            Content-Length: 4294967301\r\n\r\nhello");
+            Ok::<_, ()>(proto.poll_complete())
+        })).unwrap();
+        assert!(res.unwrap().is_not_ready());
+        assert_eq!(log.lock().unwrap().last().unwrap(), "5 false");
+    }
+
+    fn unframed(policy: UnframedBody) -> Result<Async<()>, Error> {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let mock = MockData::new();
+        let mut proto = Proto::new(mock.clone(), &handle,
+            &Config::new().unframed_body(policy).done());
+        core.run(lazy(|| {
+            assert!(proto.start_send(Get { close: false })
+                .unwrap().is_ready());
+            proto.poll_complete().unwrap();
+            mock.add_input("HTTP/1.1 200 OK\r\n\r\nhello");
+            Ok::<_, ()>(proto.poll_complete())
+        })).unwrap()
+    }
+
+    #[test]
+    fn unframed_read_to_eof() {
+        // waiting for the connection to be closed
+        assert!(unframed(UnframedBody::ReadToEof).unwrap().is_not_ready());
+    }
+
+    #[test]
+    fn unframed_assume_empty() {
+        // response is complete, but connection is closed
+        let err = unframed(UnframedBody::AssumeEmpty).unwrap_err();
+        assert!(matches!(*err.kind(), ErrorEnum::Closed));
+    }
+
+    #[test]
+    fn unframed_reject() {
+        let err = unframed(UnframedBody::Reject).unwrap_err();
+        assert!(matches!(*err.kind(), ErrorEnum::UnframedResponse));
+    }
+
+    fn trailing(policy: TrailingData, response: &str)
+        -> Result<Async<()>, Error>
+    {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let mock = MockData::new();
+        let mut proto = Proto::new(mock.clone(), &handle,
+            &Config::new().trailing_data(policy).done());
+        core.run(lazy(|| {
+            assert!(proto.start_send(Get { close: false })
+                .unwrap().is_ready());
+            proto.poll_complete().unwrap();
+            mock.add_input(response);
+            Ok::<_, ()>(proto.poll_complete())
+        })).unwrap()
+    }
+
+    #[test]
+    fn trailing_junk() {
+        let err = trailing(TrailingData::Reject,
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello junk")
+            .unwrap_err();
+        assert!(matches!(*err.kind(), ErrorEnum::UnexpectedData));
+        assert!(err.is_protocol_error());
+        assert!(matches!(*trailing(TrailingData::Reject,
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+             5\r\nhello\r\n0\r\n\r\nHTTP/1.1 200 OK\r\n\r\n")
+            .unwrap_err().kind(), ErrorEnum::UnexpectedData));
+        assert!(trailing(TrailingData::Ignore,
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello junk")
+            .unwrap().is_ready());
+    }
+
+    #[test]
+    fn trailing_empty_lines() {
+        assert!(trailing(TrailingData::Reject,
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello\r\n")
+            .unwrap().is_ready());
+    }
+
+    #[test]
+    fn interim_responses() {
+        let (res, counters) = request(false,
+            "HTTP/1.1 100 Continue\r\n\r\n\
+             HTTP/1.1 103 Early Hints\r\nLink: </style.css>\r\n\r\n\
+             HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+        assert!(res.unwrap().is_ready());
+        assert_eq!(counters.reused(), 1);
+    }
+
+    #[test]
+    fn too_many_interim_responses() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let mock = MockData::new();
+        let mut proto = Proto::new(mock.clone(), &handle,
+            &Config::new().max_interim_responses(5).done());
+        let res = core.run(lazy(|| {
+            assert!(proto.start_send(Get { close: false })
+                .unwrap().is_ready());
+            proto.poll_complete().unwrap();
+            for _ in 0..100 {
+                mock.add_input("HTTP/1.1 100 Continue\r\n\r\n");
+            }
+            Ok::<_, ()>(proto.poll_complete())
+        })).unwrap();
+        assert!(matches!(*res.unwrap_err().kind(),
+                         ErrorEnum::TooManyInterimResponses));
+    }
+
+    struct Connect {
+        tunnel: Arc<Mutex<Option<Vec<u8>>>>,
+    }
+
+    impl Codec<MockData> for Connect {
+        type Future = FutureResult<EncoderDone<MockData>, Error>;
+        fn start_write(&mut self, mut e: Encoder<MockData>) -> Self::Future {
+            e.request_line("CONNECT", "example.com:443", Version::Http11);
+            e.add_header("Host", "example.com:443").unwrap();
+            e.done_headers().unwrap();
+            ok(e.done())
+        }
+        fn headers_received(&mut self, headers: &Head)
+            -> Result<RecvMode, Error>
+        {
+            assert_eq!(headers.raw_status().0, 200);
+            Ok(RecvMode::hijack())
+        }
+        fn data_received(&mut self, _data: &[u8], _end: bool)
+            -> Result<Async<usize>, Error>
+        {
+            unreachable!();
+        }
+        fn hijack(&mut self, mut output: WriteBuf<MockData>,
+            input: ReadBuf<MockData>)
+        {
+            output.out_buf.extend(b"ping");
+            output.flush().unwrap();
+            *self.tunnel.lock().unwrap() = Some(input.in_buf[..].to_vec());
+        }
+    }
+
+    #[test]
+    fn connect_tunnel() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let counters = Arc::new(ReuseCounters::new());
+        let tunnel = Arc::new(Mutex::new(None));
+        let mock = MockData::new();
+        let mut proto = Proto::new(mock.clone(), &handle,
+            &Config::new().reuse_counters(&counters).done());
+        core.run(lazy(|| {
+            assert!(proto.start_send(Connect { tunnel: tunnel.clone() })
+                .unwrap().is_ready());
+            proto.poll_complete().unwrap();
+            mock.add_input("HTTP/1.1 200 Connection established\r\n\r\n\
+                            hello");
+            assert!(proto.poll_complete().unwrap().is_ready());
+            // no more requests over the tunnel
+            assert!(proto.start_send(Connect { tunnel: tunnel.clone() })
+                .is_err());
+            Ok::<_, ()>(())
+        })).unwrap();
+        assert_eq!(tunnel.lock().unwrap().as_ref().map(|x| &x[..]),
+                   Some(&b"hello"[..]));
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "CONNECT example.com:443 HTTP/1.1\r\n\
+             Host: example.com:443\r\n\r\nping");
+        assert_eq!(counters.closed_by_client(), 1);
+        assert_eq!(counters.reused(), 0);
+    }
+
+    struct Upgrade {
+        stream: Arc<Mutex<Option<Vec<u8>>>>,
+    }
+
+    impl Codec<MockData> for Upgrade {
+        type Future = FutureResult<EncoderDone<MockData>, Error>;
+        fn start_write(&mut self, mut e: Encoder<MockData>) -> Self::Future {
+            e.request_line("GET", "/chat", Version::Http11);
+            e.add_header("Connection", "upgrade").unwrap();
+            e.add_header("Upgrade", "websocket").unwrap();
+            e.done_headers().unwrap();
+            ok(e.done())
+        }
+        fn headers_received(&mut self, headers: &Head)
+            -> Result<RecvMode, Error>
+        {
+            if headers.is_switching_protocols() {
+                assert_eq!(headers.upgrade(), Some("websocket"));
+                Ok(RecvMode::hijack())
+            } else {
+                Ok(RecvMode::buffered(1024))
+            }
+        }
+        fn data_received(&mut self, data: &[u8], end: bool)
+            -> Result<Async<usize>, Error>
+        {
+            assert!(end);
+            Ok(Async::Ready(data.len()))
+        }
+        fn hijack(&mut self, _output: WriteBuf<MockData>,
+            input: ReadBuf<MockData>)
+        {
+            *self.stream.lock().unwrap() = Some(input.in_buf[..].to_vec());
+        }
+    }
+
+    #[test]
+    fn switching_protocols() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let stream = Arc::new(Mutex::new(None));
+        let mock = MockData::new();
+        let mut proto = Proto::new(mock.clone(), &handle,
+            &Config::new().done());
+        core.run(lazy(|| {
+            assert!(proto.start_send(Upgrade { stream: stream.clone() })
+                .unwrap().is_ready());
+            proto.poll_complete().unwrap();
+            mock.add_input("HTTP/1.1 101 Switching Protocols\r\n\
+                            Upgrade: websocket\r\n\
+                            Connection: Upgrade\r\n\r\nframe");
+            assert!(proto.poll_complete().unwrap().is_ready());
+            Ok::<_, ()>(())
+        })).unwrap();
+        assert_eq!(stream.lock().unwrap().as_ref().map(|x| &x[..]),
+                   Some(&b"frame"[..]));
+    }
+
+    #[test]
+    fn header_hook() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let mock = MockData::new();
+        let mut proto = Proto::new(mock.clone(), &handle,
+            &Config::new().header_hook(|h| h.add("X-Request-Id", "42"))
+            .done());
+        core.run(lazy(|| {
+            assert!(proto.start_send(Get { close: false })
+                .unwrap().is_ready());
+            proto.poll_complete().unwrap();
+            Ok::<_, ()>(())
+        })).unwrap();
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "GET / HTTP/1.1\r\nX-Request-Id: 42\r\n\r\n");
+    }
+}
@@ -5,6 +5,7 @@ use client::RecvMode;
 pub enum Mode {
     Buffered(usize),
     Progressive(usize),
+    Hijack,
 }
 
 
@@ -36,4 +37,20 @@ impl RecvMode {
             mode: Mode::Progressive(min_bytes_hint),
         }
     }
+    /// Don't read response body and hijack connection after response
+    /// headers are received. Useful for connection upgrades and for
+    /// `CONNECT` method.
+    ///
+    /// The connection is handed over to `Codec::hijack` as soon as request
+    /// is fully written, along with any bytes already received past the
+    /// response headers. No more requests are sent over the connection
+    /// after that.
+    ///
+    /// Note: `data_received` method of Codec is never called for `Hijack`d
+    /// connection.
+    pub fn hijack() -> RecvMode {
+        RecvMode {
+            mode: Mode::Hijack,
+        }
+    }
 }
@@ -0,0 +1,154 @@
+#[allow(unused_imports)]
+use std::ascii::AsciiExt;
+
+
+quick_error! {
+    /// Error parsing `Content-Range` header
+    #[derive(Debug, PartialEq, Eq)]
+    pub enum ContentRangeError {
+        /// Range unit is not `bytes`
+        UnsupportedUnit {
+            description("unsupported range unit")
+        }
+        /// Header denotes unsatisfied range (`bytes */length`)
+        ///
+        /// This form is used in `416 Range Not Satisfiable` responses, the
+        /// complete length of the representation is in the error.
+        Unsatisfied(total: u64) {
+            description("range not satisfiable")
+        }
+        /// Last byte is before the first one or after the complete length
+        InvalidRange {
+            description("invalid byte range")
+        }
+        /// Header value can't be parsed
+        Syntax {
+            description("invalid syntax of the Content-Range header")
+        }
+    }
+}
+
+/// A value of the `Content-Range` header of the response
+///
+/// See [RFC 7233](https://tools.ietf.org/html/rfc7233#section-4.2) for the
+/// description of the header. Only `bytes` unit is supported. Useful for
+/// resuming downloads, i.e. to check which part of the file is served.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentRange {
+    start: u64,
+    end: u64,
+    total: Option<u64>,
+}
+
+fn parse_number(value: &str) -> Result<u64, ContentRangeError> {
+    if value.len() == 0 || !value.bytes().all(|c| c >= b'0' && c <= b'9') {
+        return Err(ContentRangeError::Syntax);
+    }
+    value.parse().map_err(|_| ContentRangeError::Syntax)
+}
+
+impl ContentRange {
+    /// Parse the value of the header, e.g. `bytes 200-1000/1200`
+    ///
+    /// Complete length may be `*` if it's unknown to the server.
+    pub fn parse(value: &str) -> Result<ContentRange, ContentRangeError> {
+        use self::ContentRangeError::*;
+
+        let value = value.trim_matches(|c| c == ' ' || c == '\t');
+        let space = value.find(' ').ok_or(Syntax)?;
+        if !value[..space].eq_ignore_ascii_case("bytes") {
+            return Err(UnsupportedUnit);
+        }
+        let value = value[space+1..].trim_start_matches(' ');
+        let slash = value.find('/').ok_or(Syntax)?;
+        let (range, total) = (&value[..slash], &value[slash+1..]);
+        let total = if total == "*" {
+            None
+        } else {
+            Some(parse_number(total)?)
+        };
+        if range == "*" {
+            return Err(total.map(Unsatisfied).unwrap_or(Syntax));
+        }
+        let dash = range.find('-').ok_or(Syntax)?;
+        let start = parse_number(&range[..dash])?;
+        let end = parse_number(&range[dash+1..])?;
+        if end < start || total.map(|t| end >= t).unwrap_or(false) {
+            return Err(InvalidRange);
+        }
+        Ok(ContentRange {
+            start: start,
+            end: end,
+            total: total,
+        })
+    }
+    /// Offset of the first byte of the range
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+    /// Offset of the last byte of the range (inclusive)
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+    /// Complete length of the representation, `None` if unknown (`*`)
+    pub fn total(&self) -> Option<u64> {
+        self.total
+    }
+    /// Number of bytes in the range (i.e. the expected body length)
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+    /// Always `false` as a range has at least one byte (unsatisfied ranges
+    /// are reported as `ContentRangeError::Unsatisfied`)
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Returns `(start, end, total)` tuple
+    pub fn as_tuple(&self) -> (u64, u64, Option<u64>) {
+        (self.start, self.end, self.total)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ContentRange, ContentRangeError};
+
+    #[test]
+    fn known_total() {
+        let range = ContentRange::parse("bytes 200-1000/1200").unwrap();
+        assert_eq!(range.as_tuple(), (200, 1000, Some(1200)));
+        assert_eq!(range.len(), 801);
+        assert!(!range.is_empty());
+        assert_eq!(ContentRange::parse("Bytes 0-0/1").unwrap().as_tuple(),
+                   (0, 0, Some(1)));
+    }
+
+    #[test]
+    fn unknown_total() {
+        let range = ContentRange::parse("bytes 42-1233/*").unwrap();
+        assert_eq!(range.as_tuple(), (42, 1233, None));
+        assert_eq!(range.total(), None);
+    }
+
+    #[test]
+    fn unsatisfied() {
+        assert_eq!(ContentRange::parse("bytes */1234"),
+                   Err(ContentRangeError::Unsatisfied(1234)));
+        assert_eq!(ContentRange::parse("bytes */*"),
+                   Err(ContentRangeError::Syntax));
+    }
+
+    #[test]
+    fn malformed() {
+        use super::ContentRangeError::*;
+        assert_eq!(ContentRange::parse("items 1-2/3"), Err(UnsupportedUnit));
+        assert_eq!(ContentRange::parse("bytes 1-2"), Err(Syntax));
+        assert_eq!(ContentRange::parse("bytes=1-2/3"), Err(Syntax));
+        assert_eq!(ContentRange::parse("bytes -2/3"), Err(Syntax));
+        assert_eq!(ContentRange::parse("bytes +1-2/3"), Err(Syntax));
+        assert_eq!(ContentRange::parse("bytes 1-x/3"), Err(Syntax));
+        assert_eq!(ContentRange::parse(""), Err(Syntax));
+        assert_eq!(ContentRange::parse("bytes 5-2/10"), Err(InvalidRange));
+        assert_eq!(ContentRange::parse("bytes 0-10/10"), Err(InvalidRange));
+    }
+}
@@ -0,0 +1,151 @@
+use std::fmt;
+
+use negotiation::valid_token;
+
+
+/// Enum representing HTTP request method
+///
+/// Methods are case-sensitive, so `get` is an extension method rather than
+/// `Get`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Method {
+    /// `OPTIONS` method
+    Options,
+    /// `GET` method
+    Get,
+    /// `HEAD` method
+    Head,
+    /// `POST` method
+    Post,
+    /// `PUT` method
+    Put,
+    /// `DELETE` method
+    Delete,
+    /// `TRACE` method
+    Trace,
+    /// `CONNECT` method
+    Connect,
+    /// `PATCH` method (RFC 5789)
+    Patch,
+    /// Any other method
+    Extension(String),
+}
+
+impl Method {
+    /// Parse method from the request line
+    ///
+    /// Returns `None` if the value is not a valid token.
+    pub fn parse(value: &str) -> Option<Method> {
+        use self::Method::*;
+        let method = match value {
+            "OPTIONS" => Options,
+            "GET" => Get,
+            "HEAD" => Head,
+            "POST" => Post,
+            "PUT" => Put,
+            "DELETE" => Delete,
+            "TRACE" => Trace,
+            "CONNECT" => Connect,
+            "PATCH" => Patch,
+            _ if valid_token(value) => Extension(value.to_string()),
+            _ => return None,
+        };
+        Some(method)
+    }
+    /// Returns the method as it is written in the request line
+    pub fn as_str(&self) -> &str {
+        use self::Method::*;
+        match *self {
+            Options => "OPTIONS",
+            Get => "GET",
+            Head => "HEAD",
+            Post => "POST",
+            Put => "PUT",
+            Delete => "DELETE",
+            Trace => "TRACE",
+            Connect => "CONNECT",
+            Patch => "PATCH",
+            Extension(ref value) => value,
+        }
+    }
+    /// Returns true if the method is safe (read-only), RFC 7231 section 4.2.1
+    ///
+    /// Extension methods are never considered safe.
+    pub fn is_safe(&self) -> bool {
+        use self::Method::*;
+        matches!(*self, Get | Head | Options | Trace)
+    }
+    /// Returns true if repeating the request has the same effect as sending
+    /// it once, RFC 7231 section 4.2.2
+    ///
+    /// This is true for safe methods and also for `PUT` and `DELETE`.
+    /// Extension methods are never considered idempotent.
+    pub fn is_idempotent(&self) -> bool {
+        use self::Method::*;
+        self.is_safe() || matches!(*self, Put | Delete)
+    }
+}
+
+impl AsRef<str> for Method {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Method;
+
+    #[test]
+    fn standard() {
+        for name in &["OPTIONS", "GET", "HEAD", "POST", "PUT", "DELETE",
+                      "TRACE", "CONNECT", "PATCH"]
+        {
+            let method = Method::parse(name).unwrap();
+            assert!(!matches!(method, Method::Extension(..)));
+            assert_eq!(method.to_string(), *name);
+        }
+        assert_eq!(Method::parse("GET"), Some(Method::Get));
+    }
+
+    #[test]
+    fn extension() {
+        assert_eq!(Method::parse("PROPFIND"),
+                   Some(Method::Extension("PROPFIND".into())));
+        assert_eq!(Method::parse("get"),
+                   Some(Method::Extension("get".into())));
+        assert_eq!(Method::parse("PROPFIND").unwrap().as_str(), "PROPFIND");
+        assert_eq!(Method::parse(""), None);
+        assert_eq!(Method::parse("GET /"), None);
+        assert_eq!(Method::parse("M(1)"), None);
+    }
+
+    #[test]
+    fn safety() {
+        // (method, safe, idempotent)
+        for &(name, safe, idempotent) in &[
+            ("GET", true, true),
+            ("HEAD", true, true),
+            ("OPTIONS", true, true),
+            ("TRACE", true, true),
+            ("PUT", false, true),
+            ("DELETE", false, true),
+            ("POST", false, false),
+            ("PATCH", false, false),
+            ("CONNECT", false, false),
+            // extension methods are neither safe nor idempotent
+            ("PROPFIND", false, false),
+            ("get", false, false),
+        ] {
+            let method = Method::parse(name).unwrap();
+            assert_eq!(method.is_safe(), safe, "{}", name);
+            assert_eq!(method.is_idempotent(), idempotent, "{}", name);
+        }
+    }
+}
@@ -1,5 +1,7 @@
+mod method;
 mod status;
 mod version;
 
+pub use self::method::*;
 pub use self::status::*;
 pub use self::version::*;
@@ -18,3 +18,31 @@ impl fmt::Display for Version {
     }
 }
 
+
+#[cfg(feature="http_types")]
+impl From<Version> for ::http::Version {
+    fn from(version: Version) -> ::http::Version {
+        match version {
+            Version::Http10 => ::http::Version::HTTP_10,
+            Version::Http11 => ::http::Version::HTTP_11,
+        }
+    }
+}
+
+#[cfg(feature="http_types")]
+impl Version {
+    /// Convert version of the `http` crate
+    ///
+    /// Note: this is lossy, i.e. HTTP/0.9 is treated as HTTP/1.0 and
+    /// HTTP/2 is treated as HTTP/1.1, as these are only versions we can
+    /// send.
+    pub fn from_http(version: ::http::Version) -> Version {
+        if version == ::http::Version::HTTP_09 ||
+            version == ::http::Version::HTTP_10
+        {
+            Version::Http10
+        } else {
+            Version::Http11
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use std::fmt;
+use std::slice::Iter as SliceIter;
+use std::str::from_utf8;
+use std::sync::Arc;
+#[allow(unused_imports)]
+use std::ascii::AsciiExt;
+
+use tk_bufstream::Buf;
+
+
+/// A function that observes and modifies headers of every outgoing message
+///
+/// The hook is set in the client or server `Config` and is called by
+/// `Encoder::done_headers()` right before the end of the header block
+/// is written, so it's useful for cross-cutting concerns like adding
+/// request ids or signing requests. Headers added by the hook are
+/// validated just like the ones added via `Encoder::add_header` (in
+/// particular they can't set body length).
+#[derive(Clone)]
+pub struct HeaderHook(Arc<Fn(&mut OutgoingHeaders) + Send + Sync>);
+
+/// Headers of the message being written, passed to the `HeaderHook`
+///
+/// Note: headers are serialized into the output buffer as soon as they
+/// are added, so if you flush the encoder before `done_headers()` the
+/// headers sent already are neither visible to the hook nor removable.
+#[derive(Debug)]
+pub struct OutgoingHeaders<'a> {
+    existing: Vec<(&'a str, &'a [u8])>,
+    added: Vec<(String, Vec<u8>)>,
+    removed: Vec<String>,
+}
+
+impl HeaderHook {
+    /// Create a hook from a function
+    pub fn new<F>(f: F) -> HeaderHook
+        where F: Fn(&mut OutgoingHeaders) + Send + Sync + 'static
+    {
+        HeaderHook(Arc::new(f))
+    }
+}
+
+impl fmt::Debug for HeaderHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("HeaderHook")
+    }
+}
+
+/// Headers that define how message is delimited, they are already accounted
+/// for by the time hook is called
+fn is_framing(name: &str) -> bool {
+    name.eq_ignore_ascii_case("Content-Length") ||
+        name.eq_ignore_ascii_case("Transfer-Encoding") ||
+        name.eq_ignore_ascii_case("Connection")
+}
+
+/// Parses header lines of the message (skipping the first line)
+fn parse_lines(block: &[u8]) -> Vec<(&str, &[u8])> {
+    block.split(|&c| c == b'\n').skip(1)
+        .filter_map(|line| {
+            let colon = line.iter().position(|&c| c == b':')?;
+            let name = from_utf8(&line[..colon]).ok()?;
+            let mut value = &line[colon+1..];
+            if value.ends_with(b"\r") {
+                value = &value[..value.len()-1];
+            }
+            if value.starts_with(b" ") {
+                value = &value[1..];
+            }
+            Some((name, value))
+        })
+        .collect()
+}
+
+impl<'a> OutgoingHeaders<'a> {
+    /// Iterator over headers added so far (in order they are written)
+    pub fn iter<'x>(&'x self) -> SliceIter<'x, (&'a str, &'a [u8])> {
+        self.existing.iter()
+    }
+    /// Returns the first value of the header (name is case-insensitive)
+    pub fn get(&self, name: &str) -> Option<&'a [u8]> {
+        self.existing.iter()
+            .find(|&&(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, v)| v)
+    }
+    /// Add a header to the message
+    ///
+    /// If the header is invalid, `done_headers()` returns an error.
+    pub fn add<V: AsRef<[u8]>>(&mut self, name: &str, value: V) {
+        self.added.push((name.to_string(), value.as_ref().to_vec()));
+    }
+    /// Remove all headers with this name (case-insensitive)
+    ///
+    /// Returns false if there is no such header. `Content-Length`,
+    /// `Transfer-Encoding` and `Connection` can't be removed, as they
+    /// influence how message is delimited, false is also returned for them.
+    /// Headers added by the hook itself are not affected.
+    pub fn remove(&mut self, name: &str) -> bool {
+        if is_framing(name) || self.get(name).is_none() {
+            return false;
+        }
+        self.existing.retain(|&(n, _)| !n.eq_ignore_ascii_case(name));
+        self.removed.push(name.to_string());
+        return true;
+    }
+}
+
+/// Runs hook over the message starting at `start` of the buffer
+///
+/// Removed headers are removed from the buffer right away, added ones are
+/// returned so that encoder can validate them. Names of removed headers are
+/// returned too, so encoder can forget it has seen them.
+pub fn run(hook: &HeaderHook, buf: &mut Buf, start: Option<usize>)
+    -> (Vec<(String, Vec<u8>)>, Vec<String>)
+{
+    let (added, removed) = {
+        let mut headers = OutgoingHeaders {
+            existing: match start {
+                Some(start) => parse_lines(&buf[start..]),
+                None => Vec::new(),
+            },
+            added: Vec::new(),
+            removed: Vec::new(),
+        };
+        (hook.0)(&mut headers);
+        (headers.added, headers.removed)
+    };
+    if let Some(start) = start {
+        if removed.len() > 0 {
+            remove_lines(buf, start, &removed);
+        }
+    }
+    return (added, removed);
+}
+
+/// Updates start of the message after `consumed` bytes are flushed
+pub fn flushed(start: &mut Option<usize>, consumed: usize) {
+    *start = match *start {
+        Some(x) if x >= consumed => Some(x - consumed),
+        _ => None,
+    };
+}
+
+fn remove_lines(buf: &mut Buf, start: usize, names: &[String]) {
+    let mut ranges = Vec::new();
+    {
+        let block = &buf[start..];
+        let mut offset = 0;
+        for (idx, line) in block.split(|&c| c == b'\n').enumerate() {
+            let end = offset + line.len() + 1;
+            let name = line.iter().position(|&c| c == b':')
+                .and_then(|colon| from_utf8(&line[..colon]).ok());
+            match name {
+                Some(name) if idx > 0 &&
+                    names.iter().any(|n| n.eq_ignore_ascii_case(name))
+                => ranges.push((start + offset, start + end)),
+                _ => {}
+            }
+            offset = end;
+        }
+    }
+    for &(begin, end) in ranges.iter().rev() {
+        buf.remove_range(begin..end);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tk_bufstream::Buf;
+    use super::{HeaderHook, run};
+
+    #[test]
+    fn remove_and_add() {
+        let mut buf = Buf::new();
+        buf.extend(b"HTTP/1.1 204 No Content\r\n");
+        let start = buf.len();
+        buf.extend(b"HTTP/1.1 200 OK\r\nServer: a\r\nContent-Length: 0\r\n\
+                     X-Keep: 1\r\nserver: b\r\n");
+        let hook = HeaderHook::new(|h| {
+            assert_eq!(h.iter().count(), 4);
+            assert!(h.remove("Server"));
+            assert!(!h.remove("Server"));
+            assert!(!h.remove("Content-Length"));
+            h.add("X-Added", "2");
+        });
+        let (added, removed) = run(&hook, &mut buf, Some(start));
+        assert_eq!(added, vec![("X-Added".to_string(), b"2".to_vec())]);
+        assert_eq!(removed, vec!["Server".to_string()]);
+        assert_eq!(String::from_utf8_lossy(&buf[..]),
+            "HTTP/1.1 204 No Content\r\n\
+             HTTP/1.1 200 OK\r\nContent-Length: 0\r\nX-Keep: 1\r\n");
+    }
+}
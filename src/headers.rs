@@ -1,6 +1,79 @@
+use std::borrow::Cow;
+use std::str::{from_utf8, Split};
+use std::time::Duration;
 #[allow(unused_imports)]
 use std::ascii::AsciiExt;
 
+/// A header that contains the number of milliseconds the client is willing
+/// to wait for the response
+pub const REQUEST_DEADLINE: &'static str = "X-Request-Deadline";
+
+/// Well-known header names in canonical and lowercase spelling
+///
+/// Hop-by-hop and framing headers (`Connection`, `Content-Length`, `Host`
+/// and similar) are not here, as `headers()` of the parsed head skips them.
+const KNOWN_NAMES: &'static [(&'static str, &'static str)] = &[
+    ("Accept", "accept"),
+    ("Accept-Charset", "accept-charset"),
+    ("Accept-Encoding", "accept-encoding"),
+    ("Accept-Language", "accept-language"),
+    ("Accept-Ranges", "accept-ranges"),
+    ("Access-Control-Allow-Origin", "access-control-allow-origin"),
+    ("Age", "age"),
+    ("Allow", "allow"),
+    ("Authorization", "authorization"),
+    ("Cache-Control", "cache-control"),
+    ("Content-Disposition", "content-disposition"),
+    ("Content-Encoding", "content-encoding"),
+    ("Content-Language", "content-language"),
+    ("Content-Range", "content-range"),
+    ("Content-Type", "content-type"),
+    ("Cookie", "cookie"),
+    ("Date", "date"),
+    ("ETag", "etag"),
+    ("Expect", "expect"),
+    ("Expires", "expires"),
+    ("If-Match", "if-match"),
+    ("If-Modified-Since", "if-modified-since"),
+    ("If-None-Match", "if-none-match"),
+    ("If-Range", "if-range"),
+    ("If-Unmodified-Since", "if-unmodified-since"),
+    ("Last-Modified", "last-modified"),
+    ("Link", "link"),
+    ("Location", "location"),
+    ("Origin", "origin"),
+    ("Pragma", "pragma"),
+    ("Range", "range"),
+    ("Referer", "referer"),
+    ("Server", "server"),
+    ("Set-Cookie", "set-cookie"),
+    ("User-Agent", "user-agent"),
+    ("Vary", "vary"),
+    ("Via", "via"),
+    ("WWW-Authenticate", "www-authenticate"),
+    ("X-Forwarded-For", "x-forwarded-for"),
+    ("X-Requested-With", "x-requested-with"),
+];
+
+/// Returns a static string for well-known header names
+///
+/// Only canonical (`Content-Type`) and lowercase (`content-type`) spellings
+/// are interned, so the name is returned exactly as it was received.
+/// Unknown names are copied to the heap.
+pub fn intern_name(name: &str) -> Cow<'static, str> {
+    for &(canonical, lower) in KNOWN_NAMES {
+        if canonical.len() != name.len() {
+            continue;
+        }
+        if name == canonical {
+            return Cow::Borrowed(canonical);
+        } else if name == lower {
+            return Cow::Borrowed(lower);
+        }
+    }
+    Cow::Owned(name.to_string())
+}
+
 // header value is byte sequence
 // we need case insensitive comparison and strip out of the whitespace
 pub fn is_close(val: &[u8]) -> bool {
@@ -97,9 +170,68 @@ pub fn is_continue(val: &[u8]) -> bool {
     return true;
 }
 
+// value of `X-Request-Deadline`, invalid values are ignored
+pub fn parse_deadline(val: &[u8]) -> Option<Duration> {
+    from_utf8(val).ok()
+        .and_then(|x| x.trim().parse().ok())
+        .map(Duration::from_millis)
+}
+
+pub fn deadline_millis(timeout: Duration) -> u64 {
+    timeout.as_secs() * 1000 + timeout.subsec_millis() as u64
+}
+
+/// Iterator over the tokens of the `Connection` header
+///
+/// Tokens are split on commas, optional whitespace around them is stripped
+/// and empty list elements are skipped. This iterator is created by
+/// `Head::connection_tokens()` of either client or server.
+#[derive(Debug, Clone)]
+pub struct ConnectionTokens<'a> {
+    iter: Split<'a, char>,
+}
+
+pub fn connection_tokens<'a>(value: Option<&'a str>) -> ConnectionTokens<'a> {
+    ConnectionTokens {
+        iter: value.unwrap_or("").split(','),
+    }
+}
+
+impl<'a> Iterator for ConnectionTokens<'a> {
+    type Item = &'a str;
+    fn next(&mut self) -> Option<&'a str> {
+        while let Some(token) = self.iter.next() {
+            let token = token.trim_matches(|c| c == ' ' || c == '\t');
+            if token.len() > 0 {
+                return Some(token);
+            }
+        }
+        return None;
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::{is_chunked, is_close, is_continue};
+    use std::borrow::Cow;
+    use std::time::Duration;
+    use super::{is_chunked, is_close, is_continue, intern_name, KNOWN_NAMES};
+    use super::{parse_deadline, deadline_millis, connection_tokens};
+
+    #[test]
+    fn interned_names() {
+        for &(canonical, lower) in KNOWN_NAMES {
+            assert_eq!(canonical.to_lowercase(), lower);
+            assert!(matches!(intern_name(canonical), Cow::Borrowed(x)
+                             if x == canonical));
+            assert!(matches!(intern_name(lower), Cow::Borrowed(x)
+                             if x == lower));
+        }
+        // mixed case is kept as is
+        assert!(matches!(intern_name("CONTENT-TYPE"), Cow::Owned(ref x)
+                         if x == "CONTENT-TYPE"));
+        assert!(matches!(intern_name("X-Custom"), Cow::Owned(ref x)
+                         if x == "X-Custom"));
+    }
 
     #[test]
     fn test_chunked() {
@@ -137,4 +269,24 @@ mod test {
         assert!(!is_continue(b"100-continue y  "));
         assert!(!is_continue(b"100-coztinue   "));
     }
+
+    #[test]
+    fn test_deadline() {
+        assert_eq!(parse_deadline(b"1500"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_deadline(b" 0 "), Some(Duration::from_millis(0)));
+        assert_eq!(parse_deadline(b"-1"), None);
+        assert_eq!(parse_deadline(b"1.5"), None);
+        assert_eq!(parse_deadline(b""), None);
+        assert_eq!(deadline_millis(Duration::new(2, 500_999_999)), 2500);
+    }
+
+    #[test]
+    fn test_connection_tokens() {
+        assert_eq!(connection_tokens(Some("keep-alive, Foo"))
+            .collect::<Vec<_>>(), vec!["keep-alive", "Foo"]);
+        assert_eq!(connection_tokens(Some(" close ,,\tUpgrade\t, "))
+            .collect::<Vec<_>>(), vec!["close", "Upgrade"]);
+        assert_eq!(connection_tokens(Some("")).count(), 0);
+        assert_eq!(connection_tokens(None).count(), 0);
+    }
 }
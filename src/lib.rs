@@ -24,6 +24,8 @@ extern crate byteorder;
 #[macro_use] extern crate matches;
 #[macro_use] extern crate log;
 #[cfg(feature="date_header")]extern crate httpdate;
+#[cfg(feature="tower")] extern crate tower_service;
+#[cfg(feature="http_types")] extern crate http;
 
 pub mod server;
 pub mod client;
@@ -33,5 +35,18 @@ mod headers;
 mod base_serializer;
 mod chunked;
 mod body_parser;
+mod warning;
+mod negotiation;
+mod content_range;
+mod header_hook;
+#[cfg(test)] mod test_util;
 
-pub use enums::{Version, Status};
+pub use enums::{Version, Status, Method};
+pub use warning::{Warning, WarningError};
+pub use negotiation::{Accept, MediaType, MediaRange, NegotiationError};
+pub use negotiation::{AcceptLanguage, LanguageRange};
+pub use negotiation::{Prefer, Preference, preference_applied};
+pub use content_range::{ContentRange, ContentRangeError};
+pub use base_serializer::{HeaderError, validate_headers};
+pub use headers::ConnectionTokens;
+pub use header_hook::OutgoingHeaders;
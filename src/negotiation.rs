@@ -0,0 +1,799 @@
+use std::fmt;
+use std::time::Duration;
+#[allow(unused_imports)]
+use std::ascii::AsciiExt;
+
+
+quick_error! {
+    /// Error parsing negotiation headers
+    #[derive(Debug, PartialEq, Eq)]
+    pub enum NegotiationError {
+        /// Media type is invalid (e.g. `*/html`)
+        InvalidMediaType {
+            description("invalid media type")
+        }
+        /// Quality value is not a number in range `0..1` with at most three
+        /// digits after the dot
+        InvalidQuality {
+            description("invalid quality value")
+        }
+        /// Language range is invalid
+        InvalidLanguage {
+            description("invalid language range")
+        }
+        /// Header value can't be parsed
+        Syntax {
+            description("invalid syntax of the header")
+        }
+    }
+}
+
+/// A media type, e.g. `text/html; charset=utf-8`
+///
+/// Type, subtype and parameter names are lowercased, parameter values are
+/// kept as is. `Display` formats the value suitable for `Content-Type`
+/// header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaType {
+    main_type: String,
+    subtype: String,
+    params: Vec<(String, String)>,
+}
+
+/// A single media range of the `Accept` header with its quality
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaRange {
+    media: MediaType,
+    quality: u16,
+}
+
+/// A parsed value of the `Accept` header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Accept {
+    ranges: Vec<MediaRange>,
+}
+
+/// A single language range of the `Accept-Language` header with its quality
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LanguageRange {
+    range: String,
+    quality: u16,
+}
+
+/// A parsed value of the `Accept-Language` header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AcceptLanguage {
+    ranges: Vec<LanguageRange>,
+}
+
+/// A single preference of the `Prefer` header, e.g. `wait=10`
+///
+/// Names (of the preference and its parameters) are lowercased, values are
+/// kept as is. Empty value is the same as no value (RFC 7240, section 2).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Preference {
+    name: String,
+    value: Option<String>,
+    params: Vec<(String, Option<String>)>,
+}
+
+/// A parsed value of the `Prefer` header (RFC 7240)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Prefer {
+    preferences: Vec<Preference>,
+}
+
+fn is_tchar(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+}
+
+/// Returns true if value is a non-empty token (e.g. a content coding)
+pub fn valid_token(value: &str) -> bool {
+    value.len() > 0 && value.chars().all(is_tchar)
+}
+
+fn skip_ws(value: &str) -> &str {
+    value.trim_start_matches(|c| c == ' ' || c == '\t')
+}
+
+/// Splits a (possibly empty) token at the start of the value
+fn token(value: &str) -> (&str, &str) {
+    let end = value.find(|c| !is_tchar(c)).unwrap_or(value.len());
+    (&value[..end], &value[end..])
+}
+
+fn parse_quoted(value: &str) -> Result<(String, &str), NegotiationError> {
+    debug_assert!(value.starts_with('"'));
+    let mut result = String::new();
+    let mut chars = value.char_indices().skip(1);
+    while let Some((idx, c)) = chars.next() {
+        match c {
+            '"' => return Ok((result, &value[idx+1..])),
+            '\\' => match chars.next() {
+                Some((_, c)) => result.push(c),
+                None => break,
+            },
+            c if c != '\t' && c.is_control() => break,
+            c => result.push(c),
+        }
+    }
+    Err(NegotiationError::Syntax)
+}
+
+/// Parses quality value into thousandths
+fn parse_quality(value: &str) -> Result<u16, NegotiationError> {
+    let (int, frac) = match value.find('.') {
+        Some(idx) => (&value[..idx], &value[idx+1..]),
+        None => (value, ""),
+    };
+    if frac.len() > 3 || !frac.chars().all(|c| c.is_ascii_digit()) {
+        return Err(NegotiationError::InvalidQuality);
+    }
+    let frac = frac.chars().chain("000".chars()).take(3)
+        .fold(0, |acc, c| acc*10 + (c as u16 - b'0' as u16));
+    match int {
+        "0" => Ok(frac),
+        "1" if frac == 0 => Ok(1000),
+        _ => Err(NegotiationError::InvalidQuality),
+    }
+}
+
+/// Parses `;name=value` parameters of the list element
+///
+/// Returns parameters before the `q` one, quality (if specified) and the rest
+/// of the value. Parameters after `q` (accept-ext) are ignored.
+fn parse_params(mut rest: &str)
+    -> Result<(Vec<(String, String)>, Option<u16>, &str), NegotiationError>
+{
+    let mut params = Vec::new();
+    let mut quality = None;
+    loop {
+        rest = skip_ws(rest);
+        if !rest.starts_with(';') {
+            return Ok((params, quality, rest));
+        }
+        let (name, tail) = token(skip_ws(&rest[1..]));
+        if name.len() == 0 || !tail.starts_with('=') {
+            return Err(NegotiationError::Syntax);
+        }
+        let (value, tail) = if tail[1..].starts_with('"') {
+            parse_quoted(&tail[1..])?
+        } else {
+            match token(&tail[1..]) {
+                ("", _) => return Err(NegotiationError::Syntax),
+                (value, tail) => (value.to_string(), tail),
+            }
+        };
+        rest = tail;
+        if name.eq_ignore_ascii_case("q") {
+            quality = Some(parse_quality(&value)?);
+        } else if quality.is_none() {
+            params.push((name.to_ascii_lowercase(), value));
+        }
+    }
+}
+
+fn parse_media(value: &str)
+    -> Result<(MediaType, Option<u16>, &str), NegotiationError>
+{
+    let (main_type, rest) = token(value);
+    if main_type.len() == 0 || !rest.starts_with('/') {
+        return Err(NegotiationError::Syntax);
+    }
+    let (subtype, rest) = token(&rest[1..]);
+    if subtype.len() == 0 {
+        return Err(NegotiationError::Syntax);
+    }
+    if main_type == "*" && subtype != "*" {
+        return Err(NegotiationError::InvalidMediaType);
+    }
+    let (params, quality, rest) = parse_params(rest)?;
+    Ok((MediaType {
+        main_type: main_type.to_ascii_lowercase(),
+        subtype: subtype.to_ascii_lowercase(),
+        params: params,
+    }, quality, rest))
+}
+
+impl MediaType {
+    /// Create a media type without parameters
+    ///
+    /// # Panics
+    ///
+    /// If type or subtype is empty or is not a valid token
+    pub fn new(main_type: &str, subtype: &str) -> MediaType {
+        assert!(main_type.len() > 0 && main_type.chars().all(is_tchar),
+            "invalid media type");
+        assert!(subtype.len() > 0 && subtype.chars().all(is_tchar),
+            "invalid media subtype");
+        MediaType {
+            main_type: main_type.to_ascii_lowercase(),
+            subtype: subtype.to_ascii_lowercase(),
+            params: Vec::new(),
+        }
+    }
+    /// Add a parameter to media type
+    ///
+    /// # Panics
+    ///
+    /// If name is not a valid token or value contains control characters
+    pub fn with_param(mut self, name: &str, value: &str) -> MediaType {
+        assert!(name.len() > 0 && name.chars().all(is_tchar),
+            "invalid parameter name");
+        assert!(value.chars().all(|c| c == '\t' || !c.is_control()),
+            "invalid parameter value");
+        self.params.push((name.to_ascii_lowercase(), value.to_string()));
+        self
+    }
+    /// Parse a media type (i.e. a value of the `Content-Type` header)
+    pub fn parse(value: &str) -> Result<MediaType, NegotiationError> {
+        match parse_media(skip_ws(value))? {
+            (media, None, rest) if skip_ws(rest).len() == 0 => Ok(media),
+            _ => Err(NegotiationError::Syntax),
+        }
+    }
+    /// Type of the media (i.e. `text` in `text/html`)
+    pub fn main_type(&self) -> &str {
+        &self.main_type
+    }
+    /// Subtype of the media (i.e. `html` in `text/html`)
+    pub fn subtype(&self) -> &str {
+        &self.subtype
+    }
+    /// Parameters of the media type, names are lowercase
+    pub fn params(&self) -> &[(String, String)] {
+        &self.params
+    }
+    /// Get the value of the parameter by (case-insensitive) name
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.iter()
+            .find(|&&(ref n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, ref v)| &v[..])
+    }
+}
+
+impl MediaRange {
+    /// Media range (may contain `*` as type and subtype)
+    pub fn media_type(&self) -> &MediaType {
+        &self.media
+    }
+    /// Quality of the range from `0.0` to `1.0`
+    pub fn quality(&self) -> f32 {
+        self.quality as f32 / 1000.0
+    }
+    /// Returns specificity of the match, if range matches media type
+    fn matches(&self, media: &MediaType) -> Option<usize> {
+        let range = &self.media;
+        if range.main_type == "*" {
+            return Some(0);
+        }
+        if range.main_type != media.main_type {
+            return None;
+        }
+        if range.subtype == "*" {
+            return Some(1);
+        }
+        if range.subtype != media.subtype {
+            return None;
+        }
+        for &(ref name, ref value) in &range.params {
+            if media.param(name) != Some(&value[..]) {
+                return None;
+            }
+        }
+        Some(2 + range.params.len())
+    }
+}
+
+impl Accept {
+    /// Parse a value of the `Accept` header
+    ///
+    /// Note: if there is no `Accept` header in the request, any media type
+    /// is acceptable. If there are multiple headers, join them with a comma.
+    pub fn parse(value: &str) -> Result<Accept, NegotiationError> {
+        let mut ranges = Vec::new();
+        let mut rest = value;
+        loop {
+            rest = rest.trim_start_matches(
+                |c| c == ' ' || c == '\t' || c == ',');
+            if rest.len() == 0 {
+                return Ok(Accept { ranges: ranges });
+            }
+            let (media, quality, tail) = parse_media(rest)?;
+            if tail.len() > 0 && !tail.starts_with(',') {
+                return Err(NegotiationError::Syntax);
+            }
+            rest = tail;
+            ranges.push(MediaRange {
+                media: media,
+                quality: quality.unwrap_or(1000),
+            });
+        }
+    }
+    /// Media ranges in the order of appearance in the header
+    pub fn ranges(&self) -> &[MediaRange] {
+        &self.ranges
+    }
+    fn quality_of(&self, media: &MediaType) -> u16 {
+        let mut best = None;
+        for range in &self.ranges {
+            if let Some(spec) = range.matches(media) {
+                match best {
+                    Some((bspec, _)) if bspec >= spec => {}
+                    _ => best = Some((spec, range.quality)),
+                }
+            }
+        }
+        best.map(|(_, q)| q).unwrap_or(0)
+    }
+    /// Quality of the media type according to the most specific range that
+    /// matches it, or `0.0` if media type is not acceptable
+    pub fn quality(&self, media: &MediaType) -> f32 {
+        self.quality_of(media) as f32 / 1000.0
+    }
+    /// Find the most acceptable media type among offered ones
+    ///
+    /// Media types with zero quality are never returned. If multiple media
+    /// types have the same quality, the first one is returned, so `offered`
+    /// should be sorted by server preference.
+    pub fn best_match(&self, offered: &[MediaType]) -> Option<MediaType> {
+        let mut best: Option<(u16, &MediaType)> = None;
+        for media in offered {
+            let quality = self.quality_of(media);
+            match best {
+                Some((bq, _)) if bq >= quality => {}
+                _ if quality == 0 => {}
+                _ => best = Some((quality, media)),
+            }
+        }
+        best.map(|(_, media)| media.clone())
+    }
+}
+
+fn valid_language_range(range: &str) -> bool {
+    range == "*" || valid_language_tag(range)
+}
+
+/// Checks syntax of the language tag (e.g. `en-US`), as used in the
+/// `Content-Language` header
+pub fn valid_language_tag(tag: &str) -> bool {
+    tag.split('-').enumerate().all(|(idx, part)| {
+        part.len() > 0 && part.len() <= 8 && if idx == 0 {
+            part.chars().all(|c| c.is_ascii_alphabetic())
+        } else {
+            part.chars().all(|c| c.is_ascii_alphanumeric())
+        }
+    })
+}
+
+impl LanguageRange {
+    /// Language range (lowercase), e.g. `en-us` or `*`
+    pub fn range(&self) -> &str {
+        &self.range
+    }
+    /// Quality of the range from `0.0` to `1.0`
+    pub fn quality(&self) -> f32 {
+        self.quality as f32 / 1000.0
+    }
+    /// Returns specificity of the match, if range matches language tag
+    ///
+    /// This is a basic filtering of RFC 4647: a range matches a tag if it's
+    /// equal to the tag or is a prefix of it followed by `-`.
+    fn matches(&self, tag: &str) -> Option<usize> {
+        if self.range == "*" {
+            return Some(0);
+        }
+        let (tag, len) = (tag.as_bytes(), self.range.len());
+        if tag.len() >= len &&
+            tag[..len].eq_ignore_ascii_case(self.range.as_bytes()) &&
+            (tag.len() == len || tag[len] == b'-')
+        {
+            Some(len)
+        } else {
+            None
+        }
+    }
+}
+
+impl AcceptLanguage {
+    /// Parse a value of the `Accept-Language` header
+    ///
+    /// Note: if there is no `Accept-Language` header in the request, any
+    /// language is acceptable.
+    pub fn parse(value: &str) -> Result<AcceptLanguage, NegotiationError> {
+        let mut ranges = Vec::new();
+        let mut rest = value;
+        loop {
+            rest = rest.trim_start_matches(
+                |c| c == ' ' || c == '\t' || c == ',');
+            if rest.len() == 0 {
+                return Ok(AcceptLanguage { ranges: ranges });
+            }
+            let (range, tail) = token(rest);
+            if !valid_language_range(range) {
+                return Err(NegotiationError::InvalidLanguage);
+            }
+            let (params, quality, tail) = parse_params(tail)?;
+            if params.len() > 0 || tail.len() > 0 && !tail.starts_with(',') {
+                return Err(NegotiationError::Syntax);
+            }
+            rest = tail;
+            ranges.push(LanguageRange {
+                range: range.to_ascii_lowercase(),
+                quality: quality.unwrap_or(1000),
+            });
+        }
+    }
+    /// Language ranges in the order of appearance in the header
+    pub fn ranges(&self) -> &[LanguageRange] {
+        &self.ranges
+    }
+    fn quality_of(&self, tag: &str) -> u16 {
+        let mut best = None;
+        for range in &self.ranges {
+            if let Some(spec) = range.matches(tag) {
+                match best {
+                    Some((bspec, _)) if bspec >= spec => {}
+                    _ => best = Some((spec, range.quality)),
+                }
+            }
+        }
+        best.map(|(_, q)| q).unwrap_or(0)
+    }
+    /// Quality of the language tag according to the longest range that
+    /// matches it, or `0.0` if language is not acceptable
+    pub fn quality(&self, tag: &str) -> f32 {
+        self.quality_of(tag) as f32 / 1000.0
+    }
+    /// Find the most acceptable language among available ones
+    ///
+    /// Range `en` matches both `en` and `en-US`, but range `en-US` doesn't
+    /// match `en`, so if you want fallback to a generic language, you
+    /// should check it separately. Languages with zero quality are never
+    /// returned. If multiple languages have the same quality, the first one
+    /// is returned.
+    pub fn best_match<'x>(&self, available: &[&'x str]) -> Option<&'x str> {
+        let mut best: Option<(u16, &'x str)> = None;
+        for &tag in available {
+            let quality = self.quality_of(tag);
+            match best {
+                Some((bq, _)) if bq >= quality => {}
+                _ if quality == 0 => {}
+                _ => best = Some((quality, tag)),
+            }
+        }
+        best.map(|(_, tag)| tag)
+    }
+}
+
+/// Parses `name[=value]` pair of the preference or its parameter
+fn parse_pair(value: &str)
+    -> Result<(String, Option<String>, &str), NegotiationError>
+{
+    let (name, rest) = token(value);
+    if name.len() == 0 {
+        return Err(NegotiationError::Syntax);
+    }
+    let name = name.to_ascii_lowercase();
+    let tail = skip_ws(rest);
+    if !tail.starts_with('=') {
+        return Ok((name, None, rest));
+    }
+    let tail = skip_ws(&tail[1..]);
+    let (value, rest) = if tail.starts_with('"') {
+        parse_quoted(tail)?
+    } else {
+        match token(tail) {
+            ("", _) => return Err(NegotiationError::Syntax),
+            (value, rest) => (value.to_string(), rest),
+        }
+    };
+    let value = if value.len() > 0 { Some(value) } else { None };
+    Ok((name, value, rest))
+}
+
+impl Preference {
+    /// Preference name (lowercase), e.g. `return` or `respond-async`
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// Preference value, e.g. `minimal` for `return=minimal`
+    pub fn value(&self) -> Option<&str> {
+        self.value.as_ref().map(|x| &x[..])
+    }
+    /// Parameters of the preference, in the order of appearance
+    pub fn params(&self) -> &[(String, Option<String>)] {
+        &self.params
+    }
+    /// Returns value of the parameter (name must be lowercase)
+    ///
+    /// Returns `None` both if parameter is absent and if it has no value.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.iter().find(|&&(ref n, _)| n == name)
+            .and_then(|&(_, ref v)| v.as_ref().map(|x| &x[..]))
+    }
+}
+
+impl Prefer {
+    /// Parse a value of the `Prefer` header
+    ///
+    /// If there are multiple headers, join them with a comma.
+    pub fn parse(value: &str) -> Result<Prefer, NegotiationError> {
+        let mut preferences = Vec::new();
+        let mut rest = value;
+        loop {
+            rest = rest.trim_start_matches(
+                |c| c == ' ' || c == '\t' || c == ',');
+            if rest.len() == 0 {
+                return Ok(Prefer { preferences: preferences });
+            }
+            let (name, value, mut tail) = parse_pair(rest)?;
+            let mut params = Vec::new();
+            loop {
+                tail = skip_ws(tail);
+                if !tail.starts_with(';') {
+                    break;
+                }
+                tail = skip_ws(&tail[1..]);
+                // empty parameters (i.e. `a;;b`) are allowed by grammar
+                if tail.starts_with(is_tchar) {
+                    let (name, value, t) = parse_pair(tail)?;
+                    params.push((name, value));
+                    tail = t;
+                }
+            }
+            if tail.len() > 0 && !tail.starts_with(',') {
+                return Err(NegotiationError::Syntax);
+            }
+            rest = tail;
+            preferences.push(Preference {
+                name: name,
+                value: value,
+                params: params,
+            });
+        }
+    }
+    /// Preferences in the order of appearance in the header
+    pub fn preferences(&self) -> &[Preference] {
+        &self.preferences
+    }
+    /// Returns preference by name (must be lowercase)
+    ///
+    /// If preference is specified multiple times, the first one is
+    /// returned, as RFC 7240 requires.
+    pub fn get(&self, name: &str) -> Option<&Preference> {
+        self.preferences.iter().find(|p| p.name == name)
+    }
+    /// Returns value of the `return` preference
+    /// (`minimal` or `representation`)
+    pub fn return_value(&self) -> Option<&str> {
+        self.get("return").and_then(|p| p.value())
+    }
+    /// Returns time client is willing to wait for the response (`wait`)
+    pub fn wait(&self) -> Option<Duration> {
+        self.get("wait").and_then(|p| p.value())
+            .and_then(|x| x.parse().ok())
+            .map(Duration::from_secs)
+    }
+    /// Returns true if the `respond-async` preference is present
+    pub fn respond_async(&self) -> bool {
+        self.get("respond-async").is_some()
+    }
+}
+
+/// Formats a value of the `Preference-Applied` header
+///
+/// Only names and values of the preferences are written, as parameters
+/// are not allowed in this header:
+///
+/// ```ignore
+/// let prefer = Prefer::parse("return=minimal; foo=bar, wait=10")?;
+/// let ret = prefer.get("return").unwrap();
+/// // ... respond with a minimal representation ...
+/// e.add_header("Preference-Applied", preference_applied(&[ret]))?;
+/// // -> Preference-Applied: return=minimal
+/// ```
+pub fn preference_applied(applied: &[&Preference]) -> String {
+    let mut result = String::new();
+    for pref in applied {
+        if result.len() > 0 {
+            result.push_str(", ");
+        }
+        result.push_str(&pref.name);
+        if let Some(ref value) = pref.value {
+            result.push_str(&format!("={}", Param(value)));
+        }
+    }
+    result
+}
+
+struct Param<'a>(&'a str);
+
+impl<'a> fmt::Display for Param<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.len() > 0 && self.0.chars().all(is_tchar) {
+            return f.write_str(self.0);
+        }
+        f.write_str("\"")?;
+        for c in self.0.chars() {
+            if c == '"' || c == '\\' {
+                f.write_str("\\")?;
+            }
+            write!(f, "{}", c)?;
+        }
+        f.write_str("\"")
+    }
+}
+
+impl fmt::Display for MediaType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.main_type, self.subtype)?;
+        for &(ref name, ref value) in &self.params {
+            write!(f, "; {}={}", name, Param(value))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use super::{Accept, AcceptLanguage, MediaType, NegotiationError};
+    use super::{Prefer, preference_applied};
+
+    fn mt(value: &str) -> MediaType {
+        MediaType::parse(value).unwrap()
+    }
+
+    #[test]
+    fn parse_media_type() {
+        let m = mt("Text/HTML; Charset=\"utf-8\"; level=1");
+        assert_eq!(m.main_type(), "text");
+        assert_eq!(m.subtype(), "html");
+        assert_eq!(m.param("charset"), Some("utf-8"));
+        assert_eq!(m.to_string(), "text/html; charset=utf-8; level=1");
+        assert_eq!(MediaType::new("text", "plain")
+            .with_param("title", "a b").to_string(),
+            r#"text/plain; title="a b""#);
+        assert_eq!(MediaType::parse("text"), Err(NegotiationError::Syntax));
+        assert_eq!(MediaType::parse("*/html"),
+                   Err(NegotiationError::InvalidMediaType));
+    }
+
+    #[test]
+    fn quality() {
+        assert_eq!(Accept::parse("a/b;q=0.5").unwrap().ranges()[0].quality(),
+                   0.5);
+        assert_eq!(Accept::parse("a/b;q=1.000").unwrap().ranges()[0]
+                   .quality(), 1.0);
+        assert_eq!(Accept::parse("a/b;q=1.5"),
+                   Err(NegotiationError::InvalidQuality));
+        assert_eq!(Accept::parse("a/b;q=0.1234"),
+                   Err(NegotiationError::InvalidQuality));
+        assert_eq!(Accept::parse("a/b;q="), Err(NegotiationError::Syntax));
+    }
+
+    #[test]
+    fn rfc_audio() {
+        let a = Accept::parse("audio/*; q=0.2, audio/basic").unwrap();
+        assert_eq!(a.best_match(&[mt("audio/mpeg"), mt("audio/basic")]),
+                   Some(mt("audio/basic")));
+        assert_eq!(a.best_match(&[mt("audio/mpeg")]), Some(mt("audio/mpeg")));
+        assert_eq!(a.best_match(&[mt("video/mp4")]), None);
+    }
+
+    #[test]
+    fn rfc_text() {
+        let a = Accept::parse("text/plain; q=0.5, text/html,\
+                               text/x-dvi; q=0.8, text/x-c").unwrap();
+        assert_eq!(a.best_match(&[mt("text/plain"), mt("text/x-dvi"),
+                                  mt("text/html")]),
+                   Some(mt("text/html")));
+        assert_eq!(a.best_match(&[mt("text/plain"), mt("text/x-dvi")]),
+                   Some(mt("text/x-dvi")));
+        // equal quality, first one wins
+        assert_eq!(a.best_match(&[mt("text/x-c"), mt("text/html")]),
+                   Some(mt("text/x-c")));
+    }
+
+    #[test]
+    fn rfc_precedence() {
+        let a = Accept::parse("text/*;q=0.3, text/html;q=0.7, \
+            text/html;level=1, text/html;level=2;q=0.4, */*;q=0.5").unwrap();
+        assert_eq!(a.quality(&mt("text/html;level=1")), 1.0);
+        assert_eq!(a.quality(&mt("text/html")), 0.7);
+        assert_eq!(a.quality(&mt("text/plain")), 0.3);
+        assert_eq!(a.quality(&mt("image/jpeg")), 0.5);
+        assert_eq!(a.quality(&mt("text/html;level=2")), 0.4);
+        assert_eq!(a.quality(&mt("text/html;level=3")), 0.7);
+    }
+
+    #[test]
+    fn not_acceptable() {
+        let a = Accept::parse("*/*, text/plain;q=0").unwrap();
+        assert_eq!(a.best_match(&[mt("text/plain")]), None);
+        assert_eq!(a.best_match(&[mt("text/plain"), mt("text/html")]),
+                   Some(mt("text/html")));
+        assert_eq!(Accept::parse("").unwrap().best_match(&[mt("a/b")]), None);
+    }
+
+    #[test]
+    fn language_prefix() {
+        let a = AcceptLanguage::parse("da, en-GB;q=0.8, en;q=0.7").unwrap();
+        assert_eq!(a.quality("da"), 1.0);
+        assert_eq!(a.quality("en-GB"), 0.8);
+        assert_eq!(a.quality("en-US"), 0.7);
+        assert_eq!(a.quality("en"), 0.7);
+        assert_eq!(a.quality("english"), 0.0);
+        assert_eq!(a.best_match(&["en-US", "en-gb"]), Some("en-gb"));
+        assert_eq!(a.best_match(&["de", "en-US"]), Some("en-US"));
+        assert_eq!(a.best_match(&["de", "fr"]), None);
+    }
+
+    #[test]
+    fn language_wildcard() {
+        let a = AcceptLanguage::parse("fr-CH, fr;q=0.9, *;q=0.5, de;q=0")
+            .unwrap();
+        assert_eq!(a.best_match(&["de", "ru"]), Some("ru"));
+        assert_eq!(a.best_match(&["de"]), None);
+        assert_eq!(a.best_match(&["ru", "fr-FR"]), Some("fr-FR"));
+        assert_eq!(AcceptLanguage::parse("en_US"),
+                   Err(NegotiationError::InvalidLanguage));
+        assert_eq!(AcceptLanguage::parse("en;level=1"),
+                   Err(NegotiationError::Syntax));
+    }
+
+    #[test]
+    fn prefer_return() {
+        let p = Prefer::parse("return=representation").unwrap();
+        assert_eq!(p.return_value(), Some("representation"));
+        assert_eq!(p.wait(), None);
+        assert!(!p.respond_async());
+        let p = Prefer::parse("Return = \"minimal\"").unwrap();
+        assert_eq!(p.return_value(), Some("minimal"));
+    }
+
+    #[test]
+    fn prefer_wait() {
+        let p = Prefer::parse("respond-async, wait=10").unwrap();
+        assert_eq!(p.wait(), Some(Duration::new(10, 0)));
+        assert!(p.respond_async());
+        assert_eq!(p.get("respond-async").unwrap().value(), None);
+        assert_eq!(Prefer::parse("wait=soon").unwrap().wait(), None);
+    }
+
+    #[test]
+    fn prefer_params() {
+        let p = Prefer::parse("foo; bar, baz=\"a, b\";; q=1;x=\"\", \
+                               return=minimal, return=representation")
+            .unwrap();
+        let names = p.preferences().iter().map(|x| x.name())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["foo", "baz", "return", "return"]);
+        let foo = p.get("foo").unwrap();
+        assert_eq!(foo.params(), &[("bar".to_string(), None)]);
+        let baz = p.get("baz").unwrap();
+        assert_eq!(baz.value(), Some("a, b"));
+        assert_eq!(baz.param("q"), Some("1"));
+        assert_eq!(baz.param("x"), None);
+        assert_eq!(baz.params().len(), 2);
+        // first one wins
+        assert_eq!(p.return_value(), Some("minimal"));
+        assert_eq!(Prefer::parse(""), Ok(Prefer { preferences: vec![] }));
+        assert_eq!(Prefer::parse("wait="), Err(NegotiationError::Syntax));
+        assert_eq!(Prefer::parse("=1"), Err(NegotiationError::Syntax));
+        assert_eq!(Prefer::parse("a b"), Err(NegotiationError::Syntax));
+    }
+
+    #[test]
+    fn preference_applied_header() {
+        let p = Prefer::parse("return=minimal; foo=bar, respond-async, \
+                               x=\"a b\"").unwrap();
+        assert_eq!(preference_applied(&[p.get("return").unwrap()]),
+                   "return=minimal");
+        assert_eq!(preference_applied(&p.preferences().iter()
+                                      .collect::<Vec<_>>()),
+                   "return=minimal, respond-async, x=\"a b\"");
+        assert_eq!(preference_applied(&[]), "");
+    }
+}
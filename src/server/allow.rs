@@ -0,0 +1,170 @@
+use enums::Status;
+use negotiation::valid_token;
+use super::{Encoder, EncoderDone};
+
+
+/// A set of methods supported by a resource
+///
+/// It replies to `OPTIONS` requests and rejects unsupported methods with
+/// `405 Method Not Allowed`, both with the `Allow` header. Example:
+///
+/// ```ignore
+/// let allow = Allow::new(&[Method::Get, Method::Head, Method::Put]);
+/// let mut e = match allow.check(req.method(), e) {
+///     Ok(e) => e,
+///     Err(done) => return ok(done),
+/// };
+/// // serve GET, HEAD or PUT request
+/// ```
+#[derive(Debug, Clone)]
+pub struct Allow {
+    methods: Vec<String>,
+    header: String,
+}
+
+/// Validates methods and removes duplicates (keeping the original order)
+pub fn unique<M: AsRef<str>>(methods: &[M]) -> Vec<String> {
+    let mut list: Vec<String> = Vec::with_capacity(methods.len() + 1);
+    for method in methods {
+        let method = method.as_ref();
+        assert!(valid_token(method), "invalid method {:?}", method);
+        if !list.iter().any(|x| x == method) {
+            list.push(method.to_string());
+        }
+    }
+    list
+}
+
+/// Formats a set of methods as a value of the `Allow` header
+///
+/// Duplicates are removed, the order of first occurrences is preserved.
+/// Unlike `Allow::new` this doesn't add `OPTIONS` to the list. Methods are
+/// case-sensitive. Accepts both `Method` values and strings.
+///
+/// # Panics
+///
+/// If method is not a valid token
+pub fn allow_header<M: AsRef<str>>(methods: &[M]) -> String {
+    unique(methods).join(", ")
+}
+
+impl Allow {
+    /// Create a set of supported methods
+    ///
+    /// `OPTIONS` is always supported, it's added to the list if not
+    /// specified. Methods are case-sensitive. Accepts both `Method` values
+    /// and strings.
+    ///
+    /// # Panics
+    ///
+    /// If method is not a valid token
+    pub fn new<M: AsRef<str>>(methods: &[M]) -> Allow {
+        let mut list = unique(methods);
+        if !list.iter().any(|x| x == "OPTIONS") {
+            list.push("OPTIONS".to_string());
+        }
+        Allow {
+            header: allow_header(&list),
+            methods: list,
+        }
+    }
+    /// Value of the `Allow` header
+    pub fn header_value(&self) -> &str {
+        &self.header
+    }
+    /// Returns true if method is supported
+    pub fn is_allowed(&self, method: &str) -> bool {
+        self.methods.iter().any(|x| x == method)
+    }
+    /// Reply to `OPTIONS` request or to unsupported method
+    ///
+    /// For `OPTIONS` request `200 OK` with empty body is sent, for methods
+    /// that are not supported it's `405 Method Not Allowed`. In both cases
+    /// response is finished and returned as `Err` so you can return it from
+    /// the handler. Otherwise encoder is returned back untouched.
+    pub fn check<S>(&self, method: &str, mut e: Encoder<S>)
+        -> Result<Encoder<S>, EncoderDone<S>>
+    {
+        if method == "OPTIONS" {
+            e.status(Status::Ok);
+        } else if !self.is_allowed(method) {
+            e.status(Status::MethodNotAllowed);
+        } else {
+            return Ok(e);
+        }
+        e.add_header("Allow", &self.header)
+            .expect("methods are validated");
+        e.add_length(0).expect("no content length is set");
+        e.done_headers().expect("headers are valid");
+        Err(e.done())
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use enums::Method;
+    use server::encoder::{for_test, get_inner};
+    use super::{Allow, allow_header};
+
+    fn check(allow: &Allow, method: &str) -> Option<String> {
+        let (e, mock) = for_test(method == "HEAD");
+        match allow.check(method, e) {
+            Ok(_) => None,
+            Err(done) => {
+                get_inner(done).flush().unwrap();
+                Some(String::from_utf8_lossy(&mock.output(..)).to_string())
+            }
+        }
+    }
+
+    #[test]
+    fn options() {
+        let allow = Allow::new(&["GET", "HEAD", "GET"]);
+        assert_eq!(allow.header_value(), "GET, HEAD, OPTIONS");
+        assert_eq!(check(&allow, "OPTIONS").unwrap(),
+            "HTTP/1.1 200 OK\r\nAllow: GET, HEAD, OPTIONS\r\n\
+             Content-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn allowed() {
+        let allow = Allow::new(&["GET", "HEAD"]);
+        assert!(check(&allow, "GET").is_none());
+        assert!(check(&allow, "HEAD").is_none());
+    }
+
+    #[test]
+    fn method_not_allowed() {
+        let allow = Allow::new(&["GET", "PUT", "OPTIONS"]);
+        assert_eq!(check(&allow, "DELETE").unwrap(),
+            "HTTP/1.1 405 Method Not Allowed\r\nAllow: GET, PUT, OPTIONS\r\n\
+             Content-Length: 0\r\n\r\n");
+        // methods are case-sensitive
+        assert!(check(&allow, "get").is_some());
+    }
+
+    #[test]
+    fn header_value() {
+        assert_eq!(allow_header(&["GET", "PUT", "GET", "get", "DELETE",
+                                  "PUT", "OPTIONS", "GET"]),
+                   "GET, PUT, get, DELETE, OPTIONS");
+        assert_eq!(allow_header::<&str>(&[]), "");
+    }
+
+    #[test]
+    fn typed_methods() {
+        let allow = Allow::new(&[Method::Get, Method::Head,
+                                 Method::Extension("PURGE".into())]);
+        assert_eq!(allow.header_value(), "GET, HEAD, PURGE, OPTIONS");
+        assert!(check(&allow, "PURGE").is_none());
+        assert_eq!(allow_header(&[Method::Put, Method::Delete, Method::Put]),
+                   "PUT, DELETE");
+    }
+
+    #[test]
+    #[should_panic(expected="invalid method")]
+    fn invalid_method() {
+        Allow::new(&["GET POST"]);
+    }
+}
@@ -0,0 +1,124 @@
+use std::sync::Mutex;
+
+
+/// A pool of reusable body buffers
+///
+/// Buffers are drawn from the pool by `BufferedDispatcher` when request
+/// body is received and returned back when `Request` is dropped. If pool
+/// is empty a fresh buffer is allocated. The pool is shared by all
+/// connections (so put it into an `Arc`).
+#[derive(Debug)]
+pub struct BufferPool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    buffer_size: usize,
+    max_buffers: usize,
+}
+
+impl BufferPool {
+    /// Create a pool of at most `max_buffers` idle buffers
+    ///
+    /// Fresh buffers are allocated with `buffer_size` capacity, so it should
+    /// be set to a typical size of the payload. Buffers that grew larger than
+    /// four times `buffer_size` are not returned to the pool.
+    pub fn new(buffer_size: usize, max_buffers: usize) -> BufferPool {
+        BufferPool {
+            buffers: Mutex::new(Vec::with_capacity(max_buffers)),
+            buffer_size: buffer_size,
+            max_buffers: max_buffers,
+        }
+    }
+    /// Get an empty buffer from the pool or allocate a new one
+    pub fn get(&self) -> Vec<u8> {
+        self.buffers.lock().expect("buffer pool is not poisoned")
+            .pop()
+            .unwrap_or_else(|| Vec::with_capacity(self.buffer_size))
+    }
+    /// Return buffer to the pool
+    ///
+    /// Buffer is dropped if pool is full or buffer is too large.
+    pub fn put(&self, mut buf: Vec<u8>) {
+        if buf.capacity() == 0 ||
+            buf.capacity() > self.buffer_size.saturating_mul(4)
+        {
+            return;
+        }
+        buf.clear();
+        let mut buffers = self.buffers.lock()
+            .expect("buffer pool is not poisoned");
+        if buffers.len() < self.max_buffers {
+            buffers.push(buf);
+        }
+    }
+    /// Number of idle buffers in the pool
+    pub fn idle_buffers(&self) -> usize {
+        self.buffers.lock().expect("buffer pool is not poisoned").len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use futures::future::ok;
+    use tk_bufstream::MockData;
+    use tokio_core::reactor::Core;
+
+    use server::{Config, Encoder};
+    use server::buffered::{Request, BufferedDispatcher};
+    use server::proto::PureProto;
+    use super::BufferPool;
+
+    #[test]
+    fn recycle() {
+        let pool = BufferPool::new(1024, 2);
+        let mut buf = pool.get();
+        assert_eq!(buf.capacity(), 1024);
+        buf.extend(b"hello");
+        let ptr = buf.as_ptr();
+        pool.put(buf);
+        assert_eq!(pool.idle_buffers(), 1);
+        let buf = pool.get();
+        assert_eq!(buf.as_ptr(), ptr);
+        assert_eq!(buf.len(), 0);
+        assert_eq!(pool.idle_buffers(), 0);
+    }
+
+    #[test]
+    fn limits() {
+        let pool = BufferPool::new(16, 1);
+        pool.put(Vec::with_capacity(1024));
+        assert_eq!(pool.idle_buffers(), 0);
+        pool.put(Vec::with_capacity(16));
+        pool.put(Vec::with_capacity(16));
+        assert_eq!(pool.idle_buffers(), 1);
+    }
+
+    #[test]
+    fn request_body() {
+        let core = Core::new().unwrap();
+        let mock = MockData::new();
+        let pool = Arc::new(BufferPool::new(1024, 10));
+        let mut disp = BufferedDispatcher::new(
+            "127.0.0.1:1".parse().unwrap(), &core.handle(),
+            || |req: Request, mut e: Encoder<MockData>| {
+                e.status(::Status::Ok);
+                e.add_length(req.body().len() as u64).unwrap();
+                e.done_headers().unwrap();
+                e.write_body(req.body());
+                ok(e.done())
+            });
+        disp.buffer_pool(&pool);
+        let mut proto = PureProto::new(mock.clone(),
+            &Config::new().done(), disp);
+        mock.add_input("POST / HTTP/1.1\r\nHost: example.com\r\n\
+                        Content-Length: 5\r\n\r\nhello");
+        proto.process().unwrap();
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello");
+        assert_eq!(pool.idle_buffers(), 1);
+        mock.add_input("POST / HTTP/1.1\r\nHost: example.com\r\n\
+                        Content-Length: 3\r\n\r\nabc");
+        proto.process().unwrap();
+        assert_eq!(pool.idle_buffers(), 1);
+    }
+}
@@ -1,8 +1,11 @@
 //! Higher-level interface for serving fully buffered requests
 //!
+use std::borrow::Cow;
+use std::slice;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::marker::PhantomData;
+use std::mem;
 
 use futures::{Async, Future, IntoFuture};
 use futures::future::FutureResult;
@@ -11,7 +14,9 @@ use tk_bufstream::{ReadBuf, WriteBuf, ReadFramed, WriteFramed};
 
 use websocket::{ServerCodec as WebsocketCodec};
 use super::{Error, Encoder, EncoderDone, Dispatcher, Codec, Head, RecvMode};
-use super::{WebsocketHandshake};
+use super::{WebsocketHandshake, BufferPool, ConnectionData};
+use super::request_target;
+use headers::intern_name;
 use {Version};
 
 /// Buffered request struct
@@ -25,9 +30,11 @@ pub struct Request {
     path: String,
     host: Option<String>,
     version: Version,
-    headers: Vec<(String, Vec<u8>)>,
+    headers: Vec<(Cow<'static, str>, Vec<u8>)>,
     body: Vec<u8>,
     websocket_handshake: Option<WebsocketHandshake>,
+    pool: Option<Arc<BufferPool>>,
+    connection_data: ConnectionData,
 }
 
 /// A dispatcher that allows to process request and return response using
@@ -35,6 +42,8 @@ pub struct Request {
 pub struct BufferedDispatcher<S, N: NewService<S>> {
     addr: SocketAddr,
     max_request_length: usize,
+    buffer_pool: Option<Arc<BufferPool>>,
+    connection_data: ConnectionData,
     service: N,
     handle: Handle,
     phantom: PhantomData<S>,
@@ -133,6 +142,19 @@ impl<S, H, I, T, U> Service<S> for WebsocketService<H, I, T, U>
     }
 }
 
+/// Iterator over the headers of the buffered request
+#[derive(Debug)]
+pub struct HeaderIter<'a> {
+    iter: slice::Iter<'a, (Cow<'static, str>, Vec<u8>)>,
+}
+
+impl<'a> Iterator for HeaderIter<'a> {
+    type Item = (&'a str, &'a [u8]);
+    fn next(&mut self) -> Option<(&'a str, &'a [u8])> {
+        self.iter.next().map(|&(ref name, ref value)| (&name[..], &value[..]))
+    }
+}
+
 impl Request {
     /// Returns peer address that initiated HTTP connection
     pub fn peer_addr(&self) -> SocketAddr {
@@ -146,6 +168,10 @@ impl Request {
     pub fn path(&self) -> &str {
         &self.path
     }
+    /// Returns query string of a request (after the first `?`), not decoded
+    pub fn raw_query(&self) -> Option<&str> {
+        request_target::raw_query(&self.path)
+    }
     /// Returns the host header of a request
     pub fn host(&self) -> Option<&str> {
         self.host.as_ref().map(|s| s.as_ref())
@@ -155,7 +181,18 @@ impl Request {
         self.version
     }
     /// Returns request headers
-    pub fn headers(&self) -> &[(String, Vec<u8>)] {
+    ///
+    /// Note: since names of well-known headers are interned, this is an
+    /// iterator rather than a slice of owned strings, use
+    /// `interned_headers()` to get the underlying slice.
+    pub fn headers(&self) -> HeaderIter {
+        HeaderIter { iter: self.headers.iter() }
+    }
+    /// Returns request headers with well-known names being static strings
+    ///
+    /// Names of well-known headers are static strings, so they don't take
+    /// memory for every request.
+    pub fn interned_headers(&self) -> &[(Cow<'static, str>, Vec<u8>)] {
         &self.headers
     }
     /// Returns request body
@@ -166,6 +203,18 @@ impl Request {
     pub fn websocket_handshake(&self) -> Option<&WebsocketHandshake> {
         self.websocket_handshake.as_ref()
     }
+    /// Returns data shared by all requests of this connection
+    pub fn connection_data(&self) -> &ConnectionData {
+        &self.connection_data
+    }
+}
+
+impl Drop for Request {
+    fn drop(&mut self) {
+        if let Some(ref pool) = self.pool {
+            pool.put(mem::replace(&mut self.body, Vec::new()));
+        }
+    }
 }
 
 impl<S, T, R> NewService<S> for T
@@ -207,6 +256,8 @@ impl<S, N: NewService<S>> BufferedDispatcher<S, N> {
         BufferedDispatcher {
             addr: addr,
             max_request_length: 10_485_760,
+            buffer_pool: None,
+            connection_data: ConnectionData::new(),
             service: service,
             handle: handle.clone(),
             phantom: PhantomData,
@@ -216,6 +267,12 @@ impl<S, N: NewService<S>> BufferedDispatcher<S, N> {
     pub fn max_request_length(&mut self, value: usize) {
         self.max_request_length = value;
     }
+    /// Sets a pool to draw request body buffers from
+    ///
+    /// Buffer is returned to the pool when `Request` is dropped.
+    pub fn buffer_pool(&mut self, pool: &Arc<BufferPool>) {
+        self.buffer_pool = Some(pool.clone());
+    }
 }
 
 impl<S, H, I, T, U> BufferedDispatcher<S, WebsocketFactory<H, I>>
@@ -234,6 +291,8 @@ impl<S, H, I, T, U> BufferedDispatcher<S, WebsocketFactory<H, I>>
         BufferedDispatcher {
             addr: addr,
             max_request_length: 10_485_760,
+            buffer_pool: None,
+            connection_data: ConnectionData::new(),
             service: WebsocketFactory {
                 service: Arc::new(http),
                 websockets: Arc::new(websockets),
@@ -263,10 +322,12 @@ impl<S, N: NewService<S>> Dispatcher<S> for BufferedDispatcher<S, N> {
                 host: headers.host().map(|x| x.to_string()),
                 version: headers.version(),
                 headers: headers.headers().map(|(name, value)| {
-                    (name.to_string(), value.to_vec())
+                    (intern_name(name), value.to_vec())
                 }).collect(),
                 body: Vec::new(),
                 websocket_handshake: up.unwrap_or(None),
+                pool: self.buffer_pool.clone(),
+                connection_data: self.connection_data.clone(),
             }),
             handle: self.handle.clone(),
         })
@@ -286,7 +347,15 @@ impl<S, R: Service<S>> Codec<S> for BufferedCodec<R> {
         -> Result<Async<usize>, Error>
     {
         assert!(end);
-        self.request.as_mut().unwrap().body = data.to_vec();
+        let req = self.request.as_mut().unwrap();
+        req.body = match req.pool {
+            Some(ref pool) => {
+                let mut body = pool.get();
+                body.extend_from_slice(data);
+                body
+            }
+            None => data.to_vec(),
+        };
         Ok(Async::Ready(data.len()))
     }
     fn start_response(&mut self, e: Encoder<S>) -> R::Future {
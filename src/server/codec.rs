@@ -5,10 +5,14 @@ use super::{Error, Encoder, EncoderDone, Head};
 use super::RecvMode;
 
 
+/// Framing of the message body
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum BodyKind {
+    /// Body of the fixed length (`Content-Length`), zero if there is no body
     Fixed(u64),
+    /// Chunked body (`Transfer-Encoding: chunked`)
     Chunked,
+    /// Body that we can't read (`CONNECT` requests)
     Unsupported,
 }
 
@@ -64,6 +68,11 @@ pub trait Codec<S> {
     /// might complete on response completion without spawning another ones,
     /// but note that next response can't start writing in the meantime).
     ///
+    /// Method may return `Async::NotReady` to apply backpressure: no more
+    /// data is read from the connection until the task is woken up, so
+    /// codec must make sure it is (e.g. by flushing the buffer it writes
+    /// data to).
+    ///
     /// Protocol panics if returned number of bytes larger than `data.len()`.
     ///
     fn data_received(&mut self, data: &[u8], end: bool)
@@ -0,0 +1,248 @@
+use std::str::from_utf8;
+use std::time::{SystemTime, UNIX_EPOCH};
+#[allow(unused_imports)]
+use std::ascii::AsciiExt;
+
+use httpdate::parse_http_date;
+
+
+/// Result of the evaluation of request preconditions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precondition {
+    /// All preconditions passed (or there were none), process the request
+    Passed,
+    /// Reply with `304 Not Modified` (only for `GET` and `HEAD`)
+    NotModified,
+    /// Reply with `412 Precondition Failed`
+    Failed,
+}
+
+/// Conditional headers of the request (RFC 7232)
+///
+/// Example:
+///
+/// ```ignore
+/// let cond = Preconditions::from_headers(head.headers());
+/// match cond.evaluate(head.method(), Some("\"v1\""), Some(mtime)) {
+///     Precondition::Passed => { /* serve request */ }
+///     Precondition::NotModified => e.status(Status::NotModified),
+///     Precondition::Failed => e.status(Status::PreconditionFailed),
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Preconditions<'a> {
+    if_match: Vec<&'a str>,
+    if_none_match: Vec<&'a str>,
+    if_modified_since: Option<SystemTime>,
+    if_unmodified_since: Option<SystemTime>,
+}
+
+/// Splits entity tag into weakness flag and opaque tag
+fn parse_etag(tag: &str) -> Option<(bool, &str)> {
+    let (weak, tag) = if tag.starts_with("W/") {
+        (true, &tag[2..])
+    } else {
+        (false, tag)
+    };
+    if tag.len() >= 2 && tag.starts_with('"') && tag.ends_with('"') {
+        Some((weak, &tag[1..tag.len()-1]))
+    } else {
+        None
+    }
+}
+
+/// Returns true if any tag in the list matches `etag`
+///
+/// List may contain `*` which matches any existing representation.
+fn list_matches(list: &[&str], etag: Option<&str>, weak: bool) -> bool {
+    let etag = match etag.and_then(parse_etag) {
+        Some(etag) => etag,
+        None => return false,
+    };
+    for value in list {
+        for item in value.split(',') {
+            let item = item.trim();
+            if item == "*" {
+                return true;
+            }
+            match parse_etag(item) {
+                Some((iweak, tag)) if tag == etag.1 => {
+                    if weak || !iweak && !etag.0 {
+                        return true;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    false
+}
+
+/// Returns true if `time` is later than `date`, with second precision
+fn modified_since(time: SystemTime, date: SystemTime) -> bool {
+    let secs = |t: SystemTime| t.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs()).unwrap_or(0);
+    secs(time) > secs(date)
+}
+
+impl<'a> Preconditions<'a> {
+    /// Collect conditional headers from the request headers
+    ///
+    /// Headers with invalid dates are ignored, as RFC requires.
+    pub fn from_headers<I>(headers: I) -> Preconditions<'a>
+        where I: IntoIterator<Item=(&'a str, &'a [u8])>
+    {
+        let mut result = Preconditions {
+            if_match: Vec::new(),
+            if_none_match: Vec::new(),
+            if_modified_since: None,
+            if_unmodified_since: None,
+        };
+        for (name, value) in headers {
+            let value = match from_utf8(value) {
+                Ok(value) => value.trim(),
+                Err(_) => continue,
+            };
+            if name.eq_ignore_ascii_case("If-Match") {
+                result.if_match.push(value);
+            } else if name.eq_ignore_ascii_case("If-None-Match") {
+                result.if_none_match.push(value);
+            } else if name.eq_ignore_ascii_case("If-Modified-Since") {
+                result.if_modified_since = parse_http_date(value).ok();
+            } else if name.eq_ignore_ascii_case("If-Unmodified-Since") {
+                result.if_unmodified_since = parse_http_date(value).ok();
+            }
+        }
+        return result;
+    }
+    /// Returns true if there are no conditional headers
+    pub fn is_empty(&self) -> bool {
+        self.if_match.len() == 0 && self.if_none_match.len() == 0 &&
+            self.if_modified_since.is_none() &&
+            self.if_unmodified_since.is_none()
+    }
+    /// Evaluate `If-Match` and `If-Unmodified-Since`
+    ///
+    /// Returns false if the request must be rejected with `412 Precondition
+    /// Failed`. This is what is needed for optimistic concurrency of `PUT`
+    /// and `DELETE` requests. `etag` is a current entity tag of the resource
+    /// (including quotes), it's `None` if resource doesn't exist.
+    ///
+    /// `If-Unmodified-Since` is only evaluated when there is no `If-Match`.
+    pub fn write_allowed(&self, etag: Option<&str>,
+        last_modified: Option<SystemTime>)
+        -> bool
+    {
+        if self.if_match.len() > 0 {
+            return list_matches(&self.if_match, etag, false);
+        }
+        match (self.if_unmodified_since, last_modified) {
+            (Some(date), Some(time)) => !modified_since(time, date),
+            _ => true,
+        }
+    }
+    /// Evaluate all preconditions in the order defined by RFC 7232 (section 6)
+    ///
+    /// `If-None-Match` takes precedence over `If-Modified-Since` and the
+    /// latter is only evaluated for `GET` and `HEAD` requests.
+    pub fn evaluate(&self, method: &str, etag: Option<&str>,
+        last_modified: Option<SystemTime>)
+        -> Precondition
+    {
+        if !self.write_allowed(etag, last_modified) {
+            return Precondition::Failed;
+        }
+        let read = method == "GET" || method == "HEAD";
+        if self.if_none_match.len() > 0 {
+            if list_matches(&self.if_none_match, etag, true) {
+                if read {
+                    return Precondition::NotModified;
+                } else {
+                    return Precondition::Failed;
+                }
+            }
+        } else if read {
+            match (self.if_modified_since, last_modified) {
+                (Some(date), Some(time)) if !modified_since(time, date) => {
+                    return Precondition::NotModified;
+                }
+                _ => {}
+            }
+        }
+        Precondition::Passed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+    use super::{Preconditions, Precondition};
+
+    fn cond<'a>(headers: &'a [(&'a str, &'a str)]) -> Preconditions<'a> {
+        Preconditions::from_headers(
+            headers.iter().map(|&(n, v)| (n, v.as_bytes())))
+    }
+
+    #[test]
+    fn if_match() {
+        let c = cond(&[("If-Match", r#""a", "b""#)]);
+        assert!(c.write_allowed(Some(r#""b""#), None));
+        assert!(!c.write_allowed(Some(r#""c""#), None));
+        // strong comparison
+        assert!(!c.write_allowed(Some(r#"W/"b""#), None));
+        // resource doesn't exist
+        assert!(!c.write_allowed(None, None));
+        let c = cond(&[("If-Match", "*")]);
+        assert!(c.write_allowed(Some(r#""x""#), None));
+        assert!(!c.write_allowed(None, None));
+        assert_eq!(c.evaluate("DELETE", None, None), Precondition::Failed);
+    }
+
+    #[test]
+    fn if_unmodified_since() {
+        let c = cond(&[("If-Unmodified-Since",
+                        "Sun, 06 Nov 1994 08:49:37 GMT")]);
+        let date = UNIX_EPOCH + Duration::new(784111777, 0);
+        assert!(c.write_allowed(None, Some(date)));
+        assert!(c.write_allowed(None, Some(date + Duration::from_millis(5))));
+        assert!(!c.write_allowed(None, Some(date + Duration::new(1, 0))));
+        assert_eq!(c.evaluate("PUT", None, Some(date + Duration::new(1, 0))),
+                   Precondition::Failed);
+        // If-Match takes precedence
+        let c = cond(&[("If-Match", r#""a""#),
+                       ("If-Unmodified-Since",
+                        "Sun, 06 Nov 1994 08:49:37 GMT")]);
+        assert!(c.write_allowed(Some(r#""a""#),
+                                Some(date + Duration::new(1, 0))));
+    }
+
+    #[test]
+    fn if_none_match() {
+        let c = cond(&[("If-None-Match", r#"W/"a""#),
+                       ("If-Modified-Since",
+                        "Sun, 06 Nov 1994 08:49:37 GMT")]);
+        let date = UNIX_EPOCH + Duration::new(784111777, 0);
+        assert_eq!(c.evaluate("GET", Some(r#""a""#), None),
+                   Precondition::NotModified);
+        assert_eq!(c.evaluate("PUT", Some(r#""a""#), None),
+                   Precondition::Failed);
+        // If-Modified-Since is ignored when If-None-Match is present
+        assert_eq!(c.evaluate("GET", Some(r#""b""#), Some(date)),
+                   Precondition::Passed);
+        let c = cond(&[("If-Modified-Since",
+                        "Sun, 06 Nov 1994 08:49:37 GMT")]);
+        assert_eq!(c.evaluate("HEAD", None, Some(date)),
+                   Precondition::NotModified);
+        assert_eq!(c.evaluate("POST", None, Some(date)),
+                   Precondition::Passed);
+        assert_eq!(c.evaluate("GET", None, Some(date + Duration::new(1, 0))),
+                   Precondition::Passed);
+    }
+
+    #[test]
+    fn no_conditions() {
+        let c = cond(&[("If-Modified-Since", "yesterday")]);
+        assert!(c.is_empty());
+        assert_eq!(c.evaluate("GET", None, None), Precondition::Passed);
+    }
+}
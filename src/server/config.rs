@@ -1,7 +1,10 @@
+use std::usize;
 use std::time::Duration;
 use std::sync::Arc;
 
 use server::{Config};
+use header_hook::{HeaderHook, OutgoingHeaders};
+use {Status};
 
 impl Config {
     /// Create a config with defaults
@@ -16,10 +19,49 @@ impl Config {
             input_body_whole_timeout: Duration::new(3600, 0),
             output_body_byte_timeout: Duration::new(15, 0),
             output_body_whole_timeout: Duration::new(3600, 0),
+            no_delay_on_last_write: true,
+            handler_timeout: None,
+            handler_timeout_status: Status::ServiceUnavailable,
+            disable_pipelining: false,
+            respect_request_deadline: false,
+            normalize_path: false,
+            max_chunk_size: usize::MAX,
+            lenient_request_line: false,
+            reject_unknown_expect: true,
+            default_content_type: None,
+            max_header_value_size: usize::MAX,
+            keep_alive_header: false,
+            date_header: false,
+            header_hook: None,
+            lenient_chunk_size: false,
+            max_requests_per_connection: None,
+            reject_conflicting_host: false,
+            request_timeout: None,
+            body_read_idle_timeout: None,
+            reply_to_trace: false,
+            max_body_drain: 65536,
         }
     }
     /// A number of inflight requests until we stop reading more requests
+    ///
+    /// This is the maximum number of requests on a single connection that
+    /// are processed at once: codecs of pipelined requests are created and
+    /// receive the request body before previous responses are sent, so a
+    /// handler may start its work in `headers_received`/`data_received`.
+    /// Responses are still written in the order of requests:
+    /// `start_response` is called only when the previous response is done.
+    /// Next request is not read until one of the responses is finished.
+    /// Default is `2`.
+    ///
+    /// Note: the response being written counts towards the limit. Earlier
+    /// versions didn't count it, so effectively allowed one more request
+    /// (i.e. `3` by default).
+    ///
+    /// # Panics
+    ///
+    /// Panics if value is zero, as no request could be processed then
     pub fn inflight_request_limit(&mut self, value: usize) -> &mut Self {
+        assert!(value >= 1, "inflight_request_limit must be at least 1");
         self.inflight_request_limit = value;
         self
     }
@@ -58,6 +100,50 @@ impl Config {
         self.input_body_byte_timeout = value;
         self
     }
+    /// Maximum delay between two reads while request body is received
+    ///
+    /// This timeout is reset on each successful read, so it detects clients
+    /// that send part of the body and then stall (clients that trickle a
+    /// byte once in a while are caught by `input_body_whole_timeout` or
+    /// `request_timeout`). Connection is closed on expiry. By default there
+    /// is no such timeout.
+    pub fn body_read_idle_timeout(&mut self, value: Duration) -> &mut Self {
+        self.body_read_idle_timeout = Some(value);
+        self
+    }
+    /// Timeout of receiving the whole request, both headers and body
+    ///
+    /// This timeout starts when first byte of headers is received, and
+    /// unlike `headers_timeout` and `input_body_whole_timeout` it's not
+    /// restarted between headers and body, so it caps the total time
+    /// request may hold the connection even if client sends data slowly
+    /// but steadily. On expiry `408 Request Timeout` is sent (after the
+    /// responses to previous pipelined requests) and connection is closed.
+    /// By default there is no such timeout.
+    pub fn request_timeout(&mut self, value: Duration) -> &mut Self {
+        self.request_timeout = Some(value);
+        self
+    }
+    /// Reply to `TRACE` requests by reflecting the request
+    ///
+    /// When enabled, `TRACE` requests don't reach the dispatcher, instead
+    /// `200 OK` is sent with the request head as a `message/http` body
+    /// (see `Head::trace_message`), and connection is closed. Disabled by
+    /// default, as `TRACE` may expose headers added by proxies.
+    pub fn reply_to_trace(&mut self, value: bool) -> &mut Self {
+        self.reply_to_trace = value;
+        self
+    }
+    /// Maximum size of the unread body that is discarded
+    ///
+    /// When `Codec::recv_mode` returns `RecvMode::ignore_body()` the body
+    /// of up to this size is discarded so the connection can be reused,
+    /// larger bodies make protocol close the connection after the response
+    /// instead. Default is 64KiB.
+    pub fn max_body_drain(&mut self, value: usize) -> &mut Self {
+        self.max_body_drain = value;
+        self
+    }
     /// Timeout of whole request body received
     ///
     /// This timeout might be adjusted on per-request basis in
@@ -83,4 +169,211 @@ impl Config {
         self.output_body_whole_timeout = value;
         self
     }
+    /// Flush the response as soon as it is finished
+    ///
+    /// By default (`true`) when response future resolves we flush the
+    /// buffer immediately, even if there are more pipelined requests whose
+    /// responses could be appended to the same buffer. This minimizes
+    /// latency regardless of `TCP_NODELAY` setting.
+    ///
+    /// When set to `false` the final bytes of a response are not flushed
+    /// when the response future resolves, so they may be sent in a single
+    /// write with an error reply generated by the protocol (e.g. for a
+    /// malformed pipelined request). The buffer is always flushed before
+    /// the next handler starts its response, so a slow handler never
+    /// delays a response which is already finished.
+    pub fn no_delay_on_last_write(&mut self, value: bool) -> &mut Self {
+        self.no_delay_on_last_write = value;
+        self
+    }
+    /// Maximum time request handler may work before starting a response
+    ///
+    /// This timeout starts when `Codec::start_response` is called. If the
+    /// response future is not finished in time it is dropped. Then, if
+    /// the handler has not written the status line yet, we reply with
+    /// `handler_timeout_status` and close the connection. Otherwise, the
+    /// connection is closed right away (as we don't know how to finish
+    /// the response).
+    ///
+    /// By default there is no timeout (only `output_body_whole_timeout`
+    /// applies).
+    pub fn handler_timeout(&mut self, value: Duration) -> &mut Self {
+        self.handler_timeout = Some(value);
+        self
+    }
+    /// Status code to reply with when `handler_timeout` is reached
+    ///
+    /// Default is `503 Service Unavailable`, but `504 Gateway Timeout` is
+    /// more appropriate for proxies.
+    pub fn handler_timeout_status(&mut self, value: Status) -> &mut Self {
+        self.handler_timeout_status = value;
+        self
+    }
+    /// Disable request pipelining
+    ///
+    /// When set to `true`, next request is not read (and `headers_received`
+    /// is not called for it) until the response to the previous one is
+    /// fully flushed to the socket. This is useful if handlers have side
+    /// effects that must be ordered by response.
+    ///
+    /// Default is `false`. Note `inflight_request_limit` is effectively
+    /// ignored when pipelining is disabled.
+    pub fn disable_pipelining(&mut self, value: bool) -> &mut Self {
+        self.disable_pipelining = value;
+        self
+    }
+    /// Enforce deadline sent by the client as a handler timeout
+    ///
+    /// When enabled, and request contains `X-Request-Deadline` header
+    /// (number of milliseconds client is willing to wait), the deadline
+    /// works exactly like `handler_timeout` (the smaller of the two is
+    /// used).
+    ///
+    /// Default is `false`. The parsed value is available as
+    /// `Head::request_deadline` regardless of this setting.
+    pub fn respect_request_deadline(&mut self, value: bool) -> &mut Self {
+        self.respect_request_deadline = value;
+        self
+    }
+    /// Normalize request path before passing request to the dispatcher
+    ///
+    /// When enabled `Head::path()` returns the path with `.` and `..`
+    /// segments resolved and duplicate slashes collapsed (see
+    /// `server::normalize_path`), the original is available as
+    /// `Head::raw_path()`. Requests with path going above the root are
+    /// replied with `400 Bad Request` and the connection is closed.
+    ///
+    /// Default is `false`.
+    pub fn normalize_path(&mut self, value: bool) -> &mut Self {
+        self.normalize_path = value;
+        self
+    }
+    /// Maximum size of a single chunk in chunked request body
+    ///
+    /// Larger chunk sizes are rejected with `ChunkParseError`. By default
+    /// any size that fits `usize` is accepted.
+    pub fn max_chunk_size(&mut self, value: usize) -> &mut Self {
+        self.max_chunk_size = value;
+        self
+    }
+    /// Accept multiple spaces and tabs between request line tokens
+    ///
+    /// By default request line must have exactly single spaces between
+    /// method, request-target and version (as RFC 7230 requires), as
+    /// different interpretation of the request line by proxy and backend
+    /// may be used for request smuggling. Enable this only if you need to
+    /// support broken clients.
+    pub fn lenient_request_line(&mut self, value: bool) -> &mut Self {
+        self.lenient_request_line = value;
+        self
+    }
+    /// Reply `417 Expectation Failed` to requests with unknown `Expect`
+    ///
+    /// When enabled (default), request with `Expect` header containing
+    /// anything other than `100-continue` is not passed to the dispatcher,
+    /// instead `417 Expectation Failed` is sent (after the responses to
+    /// previous pipelined requests) and connection is closed.
+    pub fn reject_unknown_expect(&mut self, value: bool) -> &mut Self {
+        self.reject_unknown_expect = value;
+        self
+    }
+    /// Set `Content-Type` for responses that have a body but no content type
+    ///
+    /// The header is added in `Encoder::done_headers` if handler didn't add
+    /// `Content-Type` itself and response has non-empty body (either
+    /// non-zero `Content-Length` or chunked encoding). Common values are
+    /// `application/octet-stream` and `text/plain; charset=utf-8`. By
+    /// default no content type is added.
+    ///
+    /// # Panics
+    ///
+    /// Panics if value contains a newline
+    pub fn default_content_type(&mut self, value: &str) -> &mut Self {
+        assert!(!value.contains(|c| c == '\r' || c == '\n'),
+            "content type must not contain newlines");
+        self.default_content_type = Some(value.into());
+        self
+    }
+    /// Maximum size of a single header value in request
+    ///
+    /// Request is rejected as soon as the value being received exceeds the
+    /// limit, so we don't buffer the whole value. Note: this is a check
+    /// additional to the limit on number of headers. Default is no limit.
+    pub fn max_header_value_size(&mut self, value: usize) -> &mut Self {
+        self.max_header_value_size = value;
+        self
+    }
+    /// Add `Connection: keep-alive` header to HTTP/1.1 responses
+    ///
+    /// Keep-alive is a default in HTTP/1.1 so the header is not needed, but
+    /// some intermediaries behave better with an explicit one. The header is
+    /// only added when connection is kept alive after the response (i.e.
+    /// never along with `Connection: close`) and when handler didn't add
+    /// `Connection` header itself. Default is `false`.
+    pub fn keep_alive_header(&mut self, value: bool) -> &mut Self {
+        self.keep_alive_header = value;
+        self
+    }
+    /// Add `Date` header with the current time to every response
+    ///
+    /// The header is added in `Encoder::done_headers` unless handler has
+    /// added `Date` itself (e.g. with `Encoder::add_date`). RFC 7231
+    /// requires origin servers having a clock to send the header, so it's
+    /// a good idea to enable this for internet-facing servers. Default is
+    /// `false`, so systems without a reliable clock don't send the header
+    /// unless a handler adds one.
+    #[cfg(feature="date_header")]
+    pub fn date_header(&mut self, value: bool) -> &mut Self {
+        self.date_header = value;
+        self
+    }
+    /// Call a function right before response headers are finished
+    ///
+    /// The function receives headers added by the handler and may add or
+    /// remove headers. It's called in `Encoder::done_headers()`, so added
+    /// headers are validated the usual way and an error is returned from
+    /// `done_headers()` if they are invalid. Useful for adding headers that
+    /// every response must have (e.g. a request id). Default is no hook.
+    pub fn header_hook<F>(&mut self, f: F) -> &mut Self
+        where F: Fn(&mut OutgoingHeaders) + Send + Sync + 'static
+    {
+        self.header_hook = Some(HeaderHook::new(f));
+        self
+    }
+    /// Accept whitespace after the chunk size in chunked requests
+    ///
+    /// By default chunk size lines like `5 \r\n` are rejected, as the
+    /// grammar doesn't allow whitespace there. Whitespace before chunk
+    /// extensions (`5 ;ext`) is always allowed.
+    pub fn lenient_chunk_size(&mut self, value: bool) -> &mut Self {
+        self.lenient_chunk_size = value;
+        self
+    }
+    /// Maximum number of requests served over a single connection
+    ///
+    /// The last allowed request is replied with `Connection: close` and
+    /// the connection is closed after the response. Handler can find out
+    /// how many requests are left with `Head::requests_remaining()`.
+    /// Default is unlimited.
+    pub fn max_requests_per_connection(&mut self, value: usize) -> &mut Self {
+        self.max_requests_per_connection = Some(value);
+        self
+    }
+    /// Reject requests where `Host` header differs from host in request-target
+    ///
+    /// When request-target is in absolute form and also has a `Host`
+    /// header, the header must be ignored (RFC 7230, section 5.4), but
+    /// a mismatch usually means that some proxy in the chain interprets
+    /// the request differently, which may be used for request smuggling.
+    /// By default such request is passed to the dispatcher and
+    /// `Head::has_conflicting_host()` returns true, when enabled
+    /// `400 Bad Request` is sent and connection is closed instead.
+    ///
+    /// Host names are compared case-insensitively and the default port
+    /// of the scheme (e.g. `http://example.com:80/`) is the same as no
+    /// port.
+    pub fn reject_conflicting_host(&mut self, value: bool) -> &mut Self {
+        self.reject_conflicting_host = value;
+        self
+    }
 }
@@ -0,0 +1,118 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+
+/// Typed values that persist for the whole lifetime of a connection
+///
+/// All `buffered::Request`s received on the same connection share the same
+/// data, so it can be used to keep state like an authenticated session
+/// across (possibly pipelined) requests. Values are keyed by their type, so
+/// wrap them into a newtype to store multiple values of the same type.
+///
+/// ```ignore
+/// if req.connection_data().get::<Session>().is_none() {
+///     req.connection_data().insert(Session::authenticate(&req)?);
+/// }
+/// ```
+#[derive(Clone, Default)]
+pub struct ConnectionData {
+    values: Arc<Mutex<HashMap<TypeId, Box<Any + Send>>>>,
+}
+
+impl ConnectionData {
+    /// Create an empty storage
+    pub fn new() -> ConnectionData {
+        ConnectionData::default()
+    }
+    /// Returns a copy of the value of type `T` if it's stored
+    pub fn get<T: Any + Send + Clone>(&self) -> Option<T> {
+        self.values.lock().expect("connection data is not poisoned")
+            .get(&TypeId::of::<T>())
+            .and_then(|x| x.downcast_ref::<T>())
+            .cloned()
+    }
+    /// Store a value, returns the previous value of the same type
+    pub fn insert<T: Any + Send>(&self, value: T) -> Option<T> {
+        self.values.lock().expect("connection data is not poisoned")
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|x| x.downcast::<T>().ok())
+            .map(|x| *x)
+    }
+    /// Remove the value of type `T` and return it
+    pub fn remove<T: Any + Send>(&self) -> Option<T> {
+        self.values.lock().expect("connection data is not poisoned")
+            .remove(&TypeId::of::<T>())
+            .and_then(|x| x.downcast::<T>().ok())
+            .map(|x| *x)
+    }
+}
+
+impl fmt::Debug for ConnectionData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.values.lock() {
+            Ok(values) => write!(f, "ConnectionData({} values)", values.len()),
+            Err(_) => f.write_str("ConnectionData(poisoned)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::future::ok;
+    use tk_bufstream::MockData;
+    use tokio_core::reactor::Core;
+
+    use server::{Config, Encoder};
+    use server::buffered::{Request, BufferedDispatcher};
+    use server::proto::PureProto;
+    use super::ConnectionData;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Session(String);
+
+    #[test]
+    fn typed_values() {
+        let data = ConnectionData::new();
+        assert_eq!(data.get::<Session>(), None);
+        assert_eq!(data.insert(Session("a".into())), None);
+        assert_eq!(data.insert(7u32), None);
+        assert_eq!(data.insert(Session("b".into())),
+                   Some(Session("a".into())));
+        assert_eq!(data.clone().get::<Session>(), Some(Session("b".into())));
+        assert_eq!(data.remove::<u32>(), Some(7));
+        assert_eq!(data.get::<u32>(), None);
+    }
+
+    #[test]
+    fn state_across_requests() {
+        let core = Core::new().unwrap();
+        let mock = MockData::new();
+        let disp = BufferedDispatcher::new(
+            "127.0.0.1:1".parse().unwrap(), &core.handle(),
+            || |req: Request, mut e: Encoder<MockData>| {
+                let data = req.connection_data();
+                let user = match data.get::<Session>() {
+                    Some(Session(user)) => user,
+                    None => {
+                        data.insert(Session(req.path()[1..].to_string()));
+                        "anonymous".to_string()
+                    }
+                };
+                e.status(::Status::Ok);
+                e.add_length(user.len() as u64).unwrap();
+                e.done_headers().unwrap();
+                e.write_body(user.as_bytes());
+                ok(e.done())
+            });
+        let mut proto = PureProto::new(mock.clone(),
+            &Config::new().done(), disp);
+        mock.add_input("GET /alice HTTP/1.1\r\nHost: example.com\r\n\r\n\
+                        GET /bob HTTP/1.1\r\nHost: example.com\r\n\r\n");
+        proto.process().unwrap();
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\nanonymous\
+             HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nalice");
+    }
+}
@@ -0,0 +1,482 @@
+use std::str::from_utf8;
+use std::time::Duration;
+#[allow(unused_imports)]
+use std::ascii::AsciiExt;
+
+use enums::Status;
+use negotiation::valid_token;
+use super::allow::unique;
+use super::{Encoder, EncoderDone};
+
+
+/// Value of the `Origin` request header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin<'a> {
+    /// The `null` origin
+    ///
+    /// It's sent by sandboxed documents, pages loaded from `file:` URLs
+    /// and after some cross-origin redirects, so it's shared by unrelated
+    /// pages.
+    Null,
+    /// An origin in the `scheme://host[:port]` form
+    Host {
+        /// Scheme, i.e. `https`
+        scheme: &'a str,
+        /// Host name or IP address (IPv6 address is in square brackets)
+        host: &'a str,
+        /// Port if specified explicitly
+        port: Option<u16>,
+    },
+}
+
+fn valid_scheme(scheme: &str) -> bool {
+    scheme.chars().next().map_or(false, |c| c.is_ascii_alphabetic()) &&
+        scheme.chars().all(|c| {
+            c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.'
+        })
+}
+
+fn valid_host(host: &str) -> bool {
+    if host.starts_with('[') {
+        return host.len() > 2 && host.ends_with(']') &&
+            host[1..host.len()-1].chars().all(|c| {
+                c.is_ascii_hexdigit() || c == ':' || c == '.'
+            });
+    }
+    host.len() > 0 && host.chars().all(|c| {
+        c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == '_'
+    })
+}
+
+fn default_port(scheme: &str) -> Option<u16> {
+    if scheme.eq_ignore_ascii_case("http") ||
+        scheme.eq_ignore_ascii_case("ws")
+    {
+        Some(80)
+    } else if scheme.eq_ignore_ascii_case("https") ||
+        scheme.eq_ignore_ascii_case("wss")
+    {
+        Some(443)
+    } else {
+        None
+    }
+}
+
+impl<'a> Origin<'a> {
+    /// Parse the value of the `Origin` header
+    ///
+    /// Returns `None` if value is invalid: it has a path, user info, or
+    /// contains a list of origins (browsers never send a list).
+    pub fn parse(value: &'a [u8]) -> Option<Origin<'a>> {
+        let value = from_utf8(value).ok()?.trim_matches(' ');
+        if value == "null" {
+            return Some(Origin::Null);
+        }
+        let delim = value.find("://")?;
+        let scheme = &value[..delim];
+        let rest = &value[delim+3..];
+        let host_end = if rest.starts_with('[') {
+            rest.find(']')? + 1
+        } else {
+            rest.find(':').unwrap_or(rest.len())
+        };
+        let (host, port) = rest.split_at(host_end);
+        if !valid_scheme(scheme) || !valid_host(host) {
+            return None;
+        }
+        let port = if port.len() == 0 {
+            None
+        } else if port.starts_with(':') && port.len() > 1 &&
+            port[1..].chars().all(|c| c.is_ascii_digit())
+        {
+            Some(port[1..].parse().ok()?)
+        } else {
+            return None;
+        };
+        Some(Origin::Host { scheme: scheme, host: host, port: port })
+    }
+    /// Returns true if both are the same origin
+    ///
+    /// Scheme and host are compared case-insensitively, and default port
+    /// of the scheme is the same as no port. The `null` origin is never
+    /// the same as any other origin (including `null`).
+    pub fn same_origin(&self, other: &Origin) -> bool {
+        match (*self, *other) {
+            (Origin::Host { scheme: s1, host: h1, port: p1 },
+             Origin::Host { scheme: s2, host: h2, port: p2 })
+            => {
+                s1.eq_ignore_ascii_case(s2) && h1.eq_ignore_ascii_case(h2) &&
+                    p1.or(default_port(s1)) == p2.or(default_port(s2))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Cross-origin resource sharing policy of a resource
+///
+/// It replies to preflight requests and adds `Access-Control-*` headers
+/// to responses to requests from allowed origins. Example:
+///
+/// ```ignore
+/// let mut cors = Cors::new(&["https://example.com"]);
+/// cors.allow_methods(&["GET", "PUT"]).allow_headers(&["Content-Type"]);
+/// let mut e = match cors.preflight(req.method(), req.headers(), e) {
+///     Ok(e) => e,
+///     Err(done) => return ok(done),
+/// };
+/// e.status(Status::Ok);
+/// cors.add_headers(req.headers(), &mut e);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Cors {
+    origins: Vec<String>,
+    allow_null: bool,
+    methods: Vec<String>,
+    headers: Vec<String>,
+    credentials: bool,
+    max_age: Option<Duration>,
+}
+
+/// Headers of the request that are relevant for CORS
+struct CorsHeaders<'a> {
+    origin: Option<&'a [u8]>,
+    request_method: Option<&'a str>,
+    request_headers: Vec<&'a str>,
+}
+
+fn cors_headers<'a, I>(headers: I) -> CorsHeaders<'a>
+    where I: IntoIterator<Item=(&'a str, &'a [u8])>
+{
+    let mut result = CorsHeaders {
+        origin: None,
+        request_method: None,
+        request_headers: Vec::new(),
+    };
+    for (name, value) in headers {
+        if name.eq_ignore_ascii_case("Origin") {
+            result.origin = Some(value);
+        } else if name.eq_ignore_ascii_case("Access-Control-Request-Method") {
+            result.request_method = from_utf8(value).ok()
+                .map(|x| x.trim_matches(' '));
+        } else if
+            name.eq_ignore_ascii_case("Access-Control-Request-Headers")
+        {
+            if let Ok(value) = from_utf8(value) {
+                result.request_headers.extend(value.split(',')
+                    .map(|x| x.trim_matches(|c| c == ' ' || c == '\t'))
+                    .filter(|x| x.len() > 0));
+            }
+        }
+    }
+    result
+}
+
+impl Cors {
+    /// Create a policy allowing requests from the specified origins
+    ///
+    /// By default only `GET`, `HEAD` and `POST` methods and no custom
+    /// headers are allowed, and credentials are not allowed.
+    ///
+    /// # Panics
+    ///
+    /// If origin is not a valid `scheme://host[:port]` origin
+    pub fn new(origins: &[&str]) -> Cors {
+        for origin in origins {
+            match Origin::parse(origin.as_bytes()) {
+                Some(Origin::Host { .. }) => {}
+                _ => panic!("invalid origin {:?}", origin),
+            }
+        }
+        Cors {
+            origins: origins.iter().map(|x| x.to_string()).collect(),
+            allow_null: false,
+            methods: unique(&["GET", "HEAD", "POST"]),
+            headers: Vec::new(),
+            credentials: false,
+            max_age: None,
+        }
+    }
+    /// Allow requests from the `null` origin
+    ///
+    /// Note: any sandboxed page can send requests with `null` origin, so
+    /// this is almost the same as allowing any origin.
+    pub fn allow_null(&mut self, value: bool) -> &mut Self {
+        self.allow_null = value;
+        self
+    }
+    /// Methods allowed in cross-origin requests (case-sensitive)
+    ///
+    /// # Panics
+    ///
+    /// If method is not a valid token
+    pub fn allow_methods(&mut self, methods: &[&str]) -> &mut Self {
+        self.methods = unique(methods);
+        self
+    }
+    /// Request headers allowed in cross-origin requests
+    ///
+    /// # Panics
+    ///
+    /// If header name is not a valid token
+    pub fn allow_headers(&mut self, headers: &[&str]) -> &mut Self {
+        for name in headers {
+            assert!(valid_token(name), "invalid header name {:?}", name);
+        }
+        self.headers = headers.iter().map(|x| x.to_string()).collect();
+        self
+    }
+    /// Allow requests with credentials (cookies, authorization)
+    pub fn allow_credentials(&mut self, value: bool) -> &mut Self {
+        self.credentials = value;
+        self
+    }
+    /// Time that client may cache result of the preflight request
+    pub fn max_age(&mut self, value: Duration) -> &mut Self {
+        self.max_age = Some(value);
+        self
+    }
+    /// Returns true if requests from the origin are allowed
+    pub fn is_allowed(&self, origin: &Origin) -> bool {
+        match *origin {
+            Origin::Null => self.allow_null,
+            Origin::Host { .. } => self.origins.iter().any(|x| {
+                Origin::parse(x.as_bytes())
+                    .map_or(false, |x| x.same_origin(origin))
+            }),
+        }
+    }
+    /// Returns the origin if it's allowed
+    fn allowed_origin<'a>(&self, value: Option<&'a [u8]>)
+        -> Option<&'a [u8]>
+    {
+        value.and_then(|value| match Origin::parse(value) {
+            Some(ref origin) if self.is_allowed(origin) => Some(value),
+            _ => None,
+        })
+    }
+    /// Writes headers common for preflight and actual responses
+    fn write_origin<S>(&self, origin: Option<&[u8]>, e: &mut Encoder<S>) {
+        if let Some(origin) = origin {
+            e.add_header("Access-Control-Allow-Origin", origin)
+                .expect("origin is validated");
+            if self.credentials {
+                e.add_header("Access-Control-Allow-Credentials", "true")
+                    .expect("header is valid");
+            }
+        }
+        // response depends on the origin, so caches must take it into
+        // account (even if the origin is not allowed)
+        e.add_header("Vary", "Origin").expect("header is valid");
+    }
+    /// Reply to a preflight request
+    ///
+    /// Preflight request is an `OPTIONS` request with `Origin` and
+    /// `Access-Control-Request-Method` headers. If origin, method and all
+    /// request headers are allowed, `204 No Content` is sent with the
+    /// `Access-Control-*` headers, otherwise it's `403 Forbidden`. In both
+    /// cases response is finished and returned as `Err` so you can return
+    /// it from the handler. Otherwise encoder is returned back untouched.
+    pub fn preflight<'a, S, I>(&self, method: &str, headers: I,
+        mut e: Encoder<S>)
+        -> Result<Encoder<S>, EncoderDone<S>>
+        where I: IntoIterator<Item=(&'a str, &'a [u8])>
+    {
+        let req = cors_headers(headers);
+        let request_method = match req.request_method {
+            Some(m) if method == "OPTIONS" && req.origin.is_some() => m,
+            _ => return Ok(e),
+        };
+        let origin = self.allowed_origin(req.origin);
+        let allowed = origin.is_some() &&
+            self.methods.iter().any(|x| x == request_method) &&
+            req.request_headers.iter().all(|name| {
+                self.headers.iter().any(|x| x.eq_ignore_ascii_case(name))
+            });
+        if !allowed {
+            e.status(Status::Forbidden);
+            self.write_origin(None, &mut e);
+            e.add_length(0).expect("no content length is set");
+            e.done_headers().expect("headers are valid");
+            return Err(e.done());
+        }
+        e.status(Status::NoContent);
+        self.write_origin(origin, &mut e);
+        e.add_header("Access-Control-Allow-Methods", self.methods.join(", "))
+            .expect("methods are validated");
+        if self.headers.len() > 0 {
+            e.add_header("Access-Control-Allow-Headers",
+                         self.headers.join(", "))
+                .expect("headers are validated");
+        }
+        if let Some(max_age) = self.max_age {
+            e.format_header("Access-Control-Max-Age", max_age.as_secs())
+                .expect("header is valid");
+        }
+        e.done_headers().expect("headers are valid");
+        Err(e.done())
+    }
+    /// Add CORS headers to the response of the actual request
+    ///
+    /// Must be called after the status is written. Returns true if the
+    /// request has an allowed origin (i.e. `Access-Control-Allow-Origin`
+    /// is added). Note: the request is served anyway, it's the client who
+    /// doesn't expose the response to the page if origin is not allowed.
+    pub fn add_headers<'a, S, I>(&self, headers: I, e: &mut Encoder<S>)
+        -> bool
+        where I: IntoIterator<Item=(&'a str, &'a [u8])>
+    {
+        let origin = self.allowed_origin(cors_headers(headers).origin);
+        self.write_origin(origin, e);
+        origin.is_some()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use enums::Status;
+    use server::encoder::{for_test, get_inner};
+    use super::{Cors, Origin};
+
+    fn headers<'a>(list: &'a [(&'a str, &'a str)])
+        -> Vec<(&'a str, &'a [u8])>
+    {
+        list.iter().map(|&(n, v)| (n, v.as_bytes())).collect()
+    }
+
+    fn respond(cors: &Cors, method: &str, list: &[(&str, &str)]) -> String {
+        let (e, mock) = for_test(false);
+        let done = match cors.preflight(method, headers(list), e) {
+            Ok(mut e) => {
+                e.status(Status::Ok);
+                cors.add_headers(headers(list), &mut e);
+                e.add_length(0).unwrap();
+                e.done_headers().unwrap();
+                e.done()
+            }
+            Err(done) => done,
+        };
+        get_inner(done).flush().unwrap();
+        String::from_utf8_lossy(&mock.output(..)).to_string()
+    }
+
+    #[test]
+    fn parse_origin() {
+        assert_eq!(Origin::parse(b"null"), Some(Origin::Null));
+        assert_eq!(Origin::parse(b"https://example.com"), Some(Origin::Host {
+            scheme: "https", host: "example.com", port: None }));
+        assert_eq!(Origin::parse(b"http://[::1]:8080"), Some(Origin::Host {
+            scheme: "http", host: "[::1]", port: Some(8080) }));
+        for value in &["", "example.com", "https://", "https://example.com/",
+                       "https://a@example.com", "https://example.com:",
+                       "https://example.com:99999", "1http://example.com",
+                       "https://a.com https://b.com", "https://[::1"]
+        {
+            assert_eq!(Origin::parse(value.as_bytes()), None, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn same_origin() {
+        let a = Origin::parse(b"https://Example.com").unwrap();
+        assert!(a.same_origin(&Origin::parse(b"HTTPS://example.com:443")
+                              .unwrap()));
+        assert!(!a.same_origin(&Origin::parse(b"http://example.com")
+                               .unwrap()));
+        assert!(!a.same_origin(&Origin::parse(b"https://example.com:8443")
+                               .unwrap()));
+        assert!(!Origin::Null.same_origin(&Origin::Null));
+    }
+
+    #[test]
+    fn allowed_origin() {
+        let mut cors = Cors::new(&["https://example.com"]);
+        cors.allow_credentials(true);
+        assert_eq!(respond(&cors, "GET", &[("Origin", "https://example.com")]),
+            "HTTP/1.1 200 OK\r\n\
+             Access-Control-Allow-Origin: https://example.com\r\n\
+             Access-Control-Allow-Credentials: true\r\n\
+             Vary: Origin\r\nContent-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn disallowed_origin() {
+        let cors = Cors::new(&["https://example.com"]);
+        let plain = "HTTP/1.1 200 OK\r\nVary: Origin\r\n\
+                     Content-Length: 0\r\n\r\n";
+        assert_eq!(respond(&cors, "GET", &[("Origin", "https://evil.com")]),
+                   plain);
+        assert_eq!(respond(&cors, "GET",
+                           &[("Origin", "https://example.com.evil.com")]),
+                   plain);
+        assert_eq!(respond(&cors, "GET", &[("Origin", "http://example.com")]),
+                   plain);
+        assert_eq!(respond(&cors, "GET", &[]), plain);
+    }
+
+    #[test]
+    fn null_origin() {
+        let mut cors = Cors::new(&["https://example.com"]);
+        assert_eq!(respond(&cors, "GET", &[("Origin", "null")]),
+            "HTTP/1.1 200 OK\r\nVary: Origin\r\nContent-Length: 0\r\n\r\n");
+        cors.allow_null(true);
+        assert_eq!(respond(&cors, "GET", &[("Origin", "null")]),
+            "HTTP/1.1 200 OK\r\nAccess-Control-Allow-Origin: null\r\n\
+             Vary: Origin\r\nContent-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn preflight() {
+        let mut cors = Cors::new(&["https://example.com"]);
+        cors.allow_methods(&["GET", "PUT"])
+            .allow_headers(&["Content-Type", "X-Token"])
+            .max_age(Duration::new(600, 0));
+        assert_eq!(respond(&cors, "OPTIONS", &[
+                ("Origin", "https://example.com"),
+                ("Access-Control-Request-Method", "PUT"),
+                ("Access-Control-Request-Headers", "x-token, content-type"),
+            ]),
+            "HTTP/1.1 204 No Content\r\n\
+             Access-Control-Allow-Origin: https://example.com\r\n\
+             Vary: Origin\r\n\
+             Access-Control-Allow-Methods: GET, PUT\r\n\
+             Access-Control-Allow-Headers: Content-Type, X-Token\r\n\
+             Access-Control-Max-Age: 600\r\n\r\n");
+    }
+
+    #[test]
+    fn preflight_rejected() {
+        let mut cors = Cors::new(&["https://example.com"]);
+        cors.allow_headers(&["Content-Type"]);
+        let forbidden = "HTTP/1.1 403 Forbidden\r\nVary: Origin\r\n\
+                         Content-Length: 0\r\n\r\n";
+        assert_eq!(respond(&cors, "OPTIONS", &[
+                ("Origin", "https://evil.com"),
+                ("Access-Control-Request-Method", "GET"),
+            ]), forbidden);
+        assert_eq!(respond(&cors, "OPTIONS", &[
+                ("Origin", "https://example.com"),
+                ("Access-Control-Request-Method", "DELETE"),
+            ]), forbidden);
+        assert_eq!(respond(&cors, "OPTIONS", &[
+                ("Origin", "https://example.com"),
+                ("Access-Control-Request-Method", "POST"),
+                ("Access-Control-Request-Headers", "Content-Type, X-Other"),
+            ]), forbidden);
+        // not a preflight request
+        assert_eq!(respond(&cors, "OPTIONS", &[
+                ("Origin", "https://example.com"),
+            ]),
+            "HTTP/1.1 200 OK\r\n\
+             Access-Control-Allow-Origin: https://example.com\r\n\
+             Vary: Origin\r\nContent-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    #[should_panic(expected="invalid origin")]
+    fn invalid_origin() {
+        Cors::new(&["https://example.com/"]);
+    }
+}
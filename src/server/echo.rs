@@ -0,0 +1,267 @@
+use std::fmt;
+#[allow(unused_imports)]
+use std::ascii::AsciiExt;
+
+use futures::{Async, Future, Poll};
+use futures::sync::oneshot::{channel, Sender, Receiver};
+use tokio_io::AsyncWrite;
+
+use Status;
+use server::error::ErrorEnum;
+use super::{Error, Encoder, EncoderDone, Dispatcher, Codec, Head, RecvMode};
+
+
+/// Number of bytes in the output buffer that pauses reading the request
+const WATERMARK: usize = 65536;
+
+/// A dispatcher that replies with the body of the request
+///
+/// This is useful for integration tests (of the library itself or of load
+/// balancers and proxies in front of the server). Framing of the response
+/// matches the request: chunked body is sent back chunked, body with
+/// `Content-Length` is sent with the same length. `Content-Type` of the
+/// request is also sent back.
+///
+/// The body is streamed: every chunk received is written to the response
+/// right away. While more than 64KiB of the response is waiting to be
+/// sent, no more of the request is read, so a client that doesn't read
+/// the response doesn't make the server buffer the body.
+#[derive(Debug, Clone)]
+pub struct Echo {
+    _private: (),
+}
+
+/// A codec of the `Echo` dispatcher
+pub struct EchoCodec<S> {
+    length: Option<u64>,
+    content_type: Option<Vec<u8>>,
+    encoder: Option<Encoder<S>>,
+    sender: Option<Sender<EncoderDone<S>>>,
+}
+
+/// A future returned from `EchoCodec::start_response`
+///
+/// Resolves when the whole request body is written to the response.
+pub struct EchoResponse<S>(Receiver<EncoderDone<S>>);
+
+impl Echo {
+    /// Create a dispatcher
+    pub fn new() -> Echo {
+        Echo { _private: () }
+    }
+}
+
+impl Default for Echo {
+    fn default() -> Echo {
+        Echo::new()
+    }
+}
+
+impl<S: AsyncWrite> Dispatcher<S> for Echo {
+    type Codec = EchoCodec<S>;
+    fn headers_received(&mut self, headers: &Head)
+        -> Result<Self::Codec, Error>
+    {
+        Ok(EchoCodec {
+            length: headers.body_length(),
+            content_type: headers.headers()
+                .find(|&(name, _)| name.eq_ignore_ascii_case("Content-Type"))
+                .map(|(_, value)| value.to_vec()),
+            encoder: None,
+            sender: None,
+        })
+    }
+}
+
+impl<S: AsyncWrite> Codec<S> for EchoCodec<S> {
+    type ResponseFuture = EchoResponse<S>;
+    fn recv_mode(&mut self) -> RecvMode {
+        RecvMode::progressive(1)
+    }
+    fn data_received(&mut self, data: &[u8], end: bool)
+        -> Result<Async<usize>, Error>
+    {
+        {
+            let e = match self.encoder.as_mut() {
+                Some(e) => e,
+                // protocol polls us again when the response is started
+                None => return Ok(Async::NotReady),
+            };
+            if data.len() > 0 {
+                if e.bytes_buffered() >= WATERMARK {
+                    // schedules a wakeup when output is writable
+                    e.flush().map_err(ErrorEnum::Io)?;
+                    if e.bytes_buffered() >= WATERMARK {
+                        return Ok(Async::NotReady);
+                    }
+                }
+                e.write_body(data);
+            }
+        }
+        if end {
+            let done = self.encoder.take()
+                .expect("encoder is not done").done();
+            self.sender.take().expect("response is started").send(done)
+                .map_err(|_| debug!("Unused echo response")).ok();
+        }
+        Ok(Async::Ready(data.len()))
+    }
+    fn start_response(&mut self, mut e: Encoder<S>) -> EchoResponse<S> {
+        e.status(Status::Ok);
+        if let Some(ref ctype) = self.content_type {
+            e.add_header("Content-Type", ctype)
+                .expect("content type is valid");
+        }
+        match self.length {
+            Some(length) => e.add_length(length).expect("can add length"),
+            None => e.add_chunked().expect("can add chunked"),
+        }
+        e.done_headers().expect("can finish headers");
+        let (tx, rx) = channel();
+        self.encoder = Some(e);
+        self.sender = Some(tx);
+        EchoResponse(rx)
+    }
+}
+
+impl<S> Drop for EchoCodec<S> {
+    fn drop(&mut self) {
+        // request body is not fully received (i.e. connection is closed)
+        if let Some(e) = self.encoder.take() {
+            self.sender.take().map(|tx| tx.send(e.abort_body()).ok());
+        }
+    }
+}
+
+impl<S> fmt::Debug for EchoCodec<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EchoCodec")
+            .field("length", &self.length)
+            .field("started", &self.encoder.is_some())
+            .finish()
+    }
+}
+
+impl<S> fmt::Debug for EchoResponse<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("EchoResponse")
+    }
+}
+
+impl<S> Future for EchoResponse<S> {
+    type Item = EncoderDone<S>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<EncoderDone<S>, Error> {
+        self.0.poll().map_err(Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::future::lazy;
+    use netbuf::Buf;
+    use tk_bufstream::MockData;
+    use tokio_core::reactor::Core;
+
+    use chunked;
+    use server::Config;
+    use server::proto::PureProto;
+    use test_util::Conn;
+    use super::Echo;
+
+    fn body() -> Vec<u8> {
+        (0..1_000_000).map(|x| (x % 251) as u8).collect()
+    }
+
+    fn echo(input: &[u8]) -> Vec<u8> {
+        let mut core = Core::new().unwrap();
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(), &Config::new().done(),
+                                       Echo::new());
+        mock.add_input(input);
+        core.run(lazy(|| {
+            // body is read and written in pieces, so process until idle
+            let mut written = 0;
+            loop {
+                assert!(proto.process().unwrap());
+                if mock.output(..).len() == written && written > 0 {
+                    break;
+                }
+                written = mock.output(..).len();
+            }
+            Ok::<_, ()>(())
+        })).unwrap();
+        mock.output(..).to_vec()
+    }
+
+    fn split_head(output: &[u8]) -> (String, &[u8]) {
+        let end = output.windows(4).position(|x| x == b"\r\n\r\n")
+            .unwrap() + 4;
+        (String::from_utf8_lossy(&output[..end]).to_string(), &output[end..])
+    }
+
+    #[test]
+    fn fixed() {
+        let body = body();
+        let mut input = b"POST / HTTP/1.1\r\nContent-Type: a/b\r\n\
+            Content-Length: 1000000\r\n\r\n".to_vec();
+        input.extend(&body);
+        let output = echo(&input);
+        let (head, rest) = split_head(&output);
+        assert_eq!(head, "HTTP/1.1 200 OK\r\nContent-Type: a/b\r\n\
+                          Content-Length: 1000000\r\n\r\n");
+        assert!(rest == &body[..]);
+    }
+
+    #[test]
+    fn chunked() {
+        let body = body();
+        let mut input = b"POST / HTTP/1.1\r\n\
+            Transfer-Encoding: chunked\r\n\r\n".to_vec();
+        for chunk in body.chunks(10000) {
+            input.extend(format!("{:x}\r\n", chunk.len()).as_bytes());
+            input.extend(chunk);
+            input.extend(b"\r\n");
+        }
+        input.extend(b"0\r\n\r\n");
+        let output = echo(&input);
+        let (head, rest) = split_head(&output);
+        assert_eq!(head, "HTTP/1.1 200 OK\r\n\
+                          Transfer-Encoding: chunked\r\n\r\n");
+        let mut buf = Buf::new();
+        buf.extend(rest);
+        let mut state = chunked::State::new();
+        state.parse(&mut buf).unwrap();
+        assert!(state.is_done());
+        assert_eq!(buf.len(), state.buffered());
+        assert!(&buf[..] == &body[..]);
+    }
+
+    #[test]
+    fn backpressure() {
+        let mut core = Core::new().unwrap();
+        let conn = Conn::new();
+        conn.blocked.set(true);
+        let mut proto = PureProto::new(conn.clone(), &Config::new().done(),
+                                       Echo::new());
+        let body = body();
+        let head = "HTTP/1.1 200 OK\r\nContent-Length: 1000000\r\n\r\n";
+        conn.mock.add_input("POST / HTTP/1.1\r\n\
+                             Content-Length: 1000000\r\n\r\n");
+        conn.mock.add_input(&body);
+        core.run(lazy(|| {
+            for _ in 0..10 {
+                proto.process().unwrap();
+            }
+            // request is not read while the response can't be sent
+            assert!(conn.read.get() < body.len() / 2);
+            conn.blocked.set(false);
+            while conn.mock.output(..).len() < head.len() + body.len() {
+                proto.process().unwrap();
+            }
+            Ok::<_, ()>(())
+        })).unwrap();
+        let output = conn.mock.output(..);
+        assert_eq!(split_head(&output), (head.to_string(), &body[..]));
+    }
+}
@@ -1,13 +1,20 @@
 use std::io;
 use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use futures::{Future, Poll, Async};
-use tk_bufstream::{WriteBuf, WriteRaw, FutureWriteRaw};
+use tk_bufstream::{Buf, WriteBuf, WriteRaw, FutureWriteRaw};
 use tokio_io::AsyncWrite;
 
-use base_serializer::{MessageState, HeaderError};
-use enums::{Version, Status};
+use base_serializer::{MessageState, HeaderError, Body, validate_headers};
+use enums::{Version, Status, Method};
+use warning::Warning;
+use header_hook::{self, HeaderHook};
+use headers;
+use negotiation::{valid_language_tag, valid_token};
 use super::headers::Head;
+use super::codec::BodyKind;
 
 
 /// This a response writer that you receive in `Codec`
@@ -16,13 +23,61 @@ use super::headers::Head;
 /// is consistent and valid protocol
 pub struct Encoder<S> {
     state: MessageState,
-    io: WriteBuf<S>,
+    // it's optional only to be able to return the buffer on drop
+    io: Option<WriteBuf<S>>,
+    reclaim: Option<Reclaim<S>>,
+    deferred: Option<Deferred>,
+    default_content_type: Option<Arc<str>>,
+    content_type_set: bool,
+    keep_alive_header: bool,
+    connection_set: bool,
+    date_header: bool,
+    date_set: bool,
+    header_hook: Option<HeaderHook>,
+    /// Offset of the response in the output buffer, `None` if it is
+    /// partially flushed
+    headers_start: Option<usize>,
+    is_head: bool,
+    /// Connection is closed after the response
+    close: bool,
+    /// Protocol has sent `100 Continue` for this request already
+    continue_sent: bool,
+    stats: Stats,
 }
 
+/// Counters behind `Encoder::bytes_written()` and similar methods
+#[derive(Debug, Clone, Copy, Default)]
+struct Stats {
+    /// Bytes in the output buffer before the response was started
+    start: usize,
+    /// Bytes flushed to the socket (including ones of previous responses)
+    flushed: u64,
+    headers: u64,
+    body: u64,
+    flushes: u64,
+}
+
+/// Body buffered until we decide between `Content-Length` and chunked
+/// encoding (see `Encoder::add_deferred_length`)
+struct Deferred {
+    threshold: usize,
+    headers_done: bool,
+    body: Vec<u8>,
+}
+
+/// A slot where dropped encoder puts its buffer (and whether response
+/// has been started)
+///
+/// This is used by the protocol to reply with an error when handler timed
+/// out and its future (owning the encoder) is dropped.
+pub type Reclaim<S> = Arc<Mutex<Option<(WriteBuf<S>, bool)>>>;
+
 /// This structure returned from `Encoder::done` and works as a continuation
 /// that should be returned from the future that writes request.
 pub struct EncoderDone<S> {
     buf: WriteBuf<S>,
+    aborted: bool,
+    close: bool,
 }
 
 /// This structure contains all needed info to start response of the request
@@ -37,12 +92,16 @@ pub struct ResponseConfig {
     pub do_close: bool,
     /// Version of HTTP request
     pub version: Version,
+    /// Time client is willing to wait for the response
+    pub deadline: Option<Duration>,
+    /// Protocol has sent `100 Continue` before reading the request body
+    pub continue_sent: bool,
 }
 
 /// A future that yields `RawBody` after buffer is empty
 ///
 /// This future is created by `Encoder::raw_body()``
-pub struct FutureRawBody<S>(FutureWriteRaw<S>);
+pub struct FutureRawBody<S>(FutureWriteRaw<S>, bool);
 
 /// A future that yields `Encoder` again after buffer has less bytes
 ///
@@ -60,6 +119,7 @@ pub struct WaitFlush<S>(Option<Encoder<S>>, usize);
 /// reconstruct original object, `EncoderDone` in this case.
 pub struct RawBody<S> {
     io: WriteRaw<S>,
+    close: bool,
 }
 
 
@@ -70,12 +130,42 @@ impl<S> Encoder<S> {
     /// A server should respond with the 100 status code if it receives a
     /// 100-continue expectation.
     ///
+    /// Note: protocol sends `100 Continue` itself before reading the body
+    /// of such request. This method does nothing if it was already sent,
+    /// so a single interim response is sent in any case.
+    ///
     /// # Panics
     ///
     /// When the response is already started. It's expected that your response
     /// handler state machine will never call the method twice.
     pub fn response_continue(&mut self) {
-        self.state.response_continue(&mut self.io.out_buf)
+        if self.continue_sent {
+            return;
+        }
+        self.state.response_continue(out_buf(&mut self.io));
+        if self.headers_start.is_some() {
+            // interim response is not the part of the final headers
+            self.headers_start = Some(out_buf(&mut self.io).len());
+        }
+    }
+
+    /// Write a 103 (Early Hints) response with `Link` headers
+    ///
+    /// Lets client start preloading resources while the final response is
+    /// being prepared, e.g. `early_hints(&["</style.css>; rel=preload"])`.
+    /// May be called multiple times, but only before the final status line.
+    /// Nothing is sent if request is HTTP/1.0.
+    ///
+    /// # Panics
+    ///
+    /// When status line is already written.
+    pub fn early_hints(&mut self, links: &[&str]) -> Result<(), HeaderError> {
+        self.state.response_early_hints(out_buf(&mut self.io), links)?;
+        if self.headers_start.is_some() {
+            // hints are not the part of the final response headers
+            self.headers_start = Some(out_buf(&mut self.io).len());
+        }
+        Ok(())
     }
 
     /// Write status line using `Status` enum
@@ -91,7 +181,7 @@ impl<S> Encoder<S> {
     /// When the status code is 100 (Continue). 100 is not allowed
     /// as a final status code.
     pub fn status(&mut self, status: Status) {
-        self.state.response_status(&mut self.io.out_buf,
+        self.state.response_status(out_buf(&mut self.io),
             status.code(), status.reason())
     }
 
@@ -105,7 +195,80 @@ impl<S> Encoder<S> {
     /// When the status code is 100 (Continue). 100 is not allowed
     /// as a final status code.
     pub fn custom_status(&mut self, code: u16, reason: &str) {
-        self.state.response_status(&mut self.io.out_buf, code, reason)
+        self.state.response_status(out_buf(&mut self.io), code, reason)
+    }
+
+    /// Replace status line written by `status()`
+    ///
+    /// Status line and headers are kept in the buffer until `done_headers()`
+    /// (unless you flush the encoder explicitly), so the status may be
+    /// changed late, e.g. after part of the request body is processed.
+    /// Headers added so far are kept. `done_headers()` is the point of no
+    /// return.
+    ///
+    /// Returns `RequireBodyless` if the new status doesn't allow a body
+    /// (1xx, 204, 304) but body length is already set.
+    ///
+    /// # Panics
+    ///
+    /// When status line is not written yet, or headers are already done
+    /// or (partially) flushed. When the status code is 100 (Continue).
+    pub fn replace_status(&mut self, status: Status)
+        -> Result<(), HeaderError>
+    {
+        self.replace_custom_status(status.code(), status.reason())
+    }
+
+    /// Replace status line with a custom one (see `replace_status()`)
+    ///
+    /// # Panics
+    ///
+    /// Same as for `replace_status()`
+    pub fn replace_custom_status(&mut self, code: u16, reason: &str)
+        -> Result<(), HeaderError>
+    {
+        let bodyless = (code >= 100 && code < 200) ||
+            code == 204 || code == 304;
+        match self.deferred {
+            Some(ref deferred) => {
+                assert!(!deferred.headers_done,
+                    "replace_status called after done_headers");
+                if bodyless {
+                    return Err(HeaderError::RequireBodyless);
+                }
+            }
+            None => {}
+        }
+        let start = self.headers_start
+            .expect("status line is not flushed yet");
+        self.state.replace_status(out_buf(&mut self.io), start,
+            self.is_head, code, reason)
+    }
+
+    /// Write status line and headers from the `http::Response`
+    ///
+    /// This method is only available with `http_types` feature.
+    ///
+    /// Response version is ignored (it's the same as in request). Headers
+    /// are not finished, so you can add more headers, and you must set body
+    /// length with `add_length()` or `add_chunked()` (specifying
+    /// `Content-Length` or `Transfer-Encoding` in the response is an error),
+    /// and then call `done_headers()`.
+    ///
+    /// # Panics
+    ///
+    /// When status line is already written or status is 100 (Continue).
+    #[cfg(feature="http_types")]
+    pub fn http_response_head<B>(&mut self, response: &::http::Response<B>)
+        -> Result<(), HeaderError>
+    {
+        let status = response.status();
+        self.custom_status(status.as_u16(),
+            status.canonical_reason().unwrap_or("Unknown"));
+        for (name, value) in response.headers() {
+            self.add_header(name.as_str(), value.as_bytes())?;
+        }
+        Ok(())
     }
 
     /// Add a header to the message.
@@ -124,13 +287,42 @@ impl<S> Encoder<S> {
     /// application handler it's okay to unwrap the result and to get
     /// a meaningful panic (that is basically an assertion).
     ///
+    /// Returns `HeadersDone` when called after `done_headers()` while body
+    /// length is deferred (see `add_deferred_length`).
+    ///
     /// # Panics
     ///
     /// Panics when `add_header` is called in the wrong state.
     pub fn add_header<V: AsRef<[u8]>>(&mut self, name: &str, value: V)
         -> Result<(), HeaderError>
     {
-        self.state.add_header(&mut self.io.out_buf, name, value.as_ref())
+        if self.deferred_headers_done() {
+            return Err(HeaderError::HeadersDone);
+        }
+        self.state.add_header(out_buf(&mut self.io), name, value.as_ref())?;
+        self.check_header(name, value.as_ref());
+        Ok(())
+    }
+
+    /// Add multiple headers to the message
+    ///
+    /// All headers are validated before writing, so either all of them are
+    /// added or none of them (and the error is returned). Same rules as for
+    /// `add_header` apply to each header.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `add_headers` is called in the wrong state.
+    pub fn add_headers<N, V>(&mut self, headers: &[(N, V)])
+        -> Result<(), HeaderError>
+        where N: AsRef<str>,
+              V: AsRef<[u8]>,
+    {
+        validate_headers(headers)?;
+        for &(ref name, ref value) in headers {
+            self.add_header(name.as_ref(), value)?;
+        }
+        Ok(())
     }
 
     /// Same as `add_header` but allows value to be formatted directly into
@@ -141,7 +333,138 @@ impl<S> Encoder<S> {
     pub fn format_header<D: Display>(&mut self, name: &str, value: D)
         -> Result<(), HeaderError>
     {
-        self.state.format_header(&mut self.io.out_buf, name, value)
+        if self.deferred_headers_done() {
+            return Err(HeaderError::HeadersDone);
+        }
+        let start = out_buf(&mut self.io).len();
+        self.state.format_header(out_buf(&mut self.io), name, value)?;
+        // only `Connection` needs the value, so don't copy it otherwise
+        let value = if name.eq_ignore_ascii_case("Connection") {
+            let buf = out_buf(&mut self.io);
+            // the line is `name: value\r\n`
+            buf[start + name.len() + 2..buf.len() - 2].to_vec()
+        } else {
+            Vec::new()
+        };
+        self.check_header(name, &value);
+        Ok(())
+    }
+    /// Returns true if `done_headers()` is called but the headers are not
+    /// written yet because body length is deferred
+    fn deferred_headers_done(&self) -> bool {
+        self.deferred.as_ref().map(|d| d.headers_done).unwrap_or(false)
+    }
+    fn check_header(&mut self, name: &str, value: &[u8]) {
+        if name.eq_ignore_ascii_case("Content-Type") {
+            self.content_type_set = true;
+        } else if name.eq_ignore_ascii_case("Connection") {
+            self.connection_set = true;
+            if value.split(|&x| x == b',').any(headers::is_close) {
+                self.close = true;
+            }
+        } else if name.eq_ignore_ascii_case("Date") {
+            self.date_set = true;
+        }
+    }
+    /// Reverts `check_header` for a header removed by the hook
+    ///
+    /// `Connection` can't be removed, so it's not here.
+    fn uncheck_header(&mut self, name: &str) {
+        if name.eq_ignore_ascii_case("Content-Type") {
+            self.content_type_set = false;
+        } else if name.eq_ignore_ascii_case("Date") {
+            self.date_set = false;
+        }
+    }
+    /// Writes default `Content-Type` if configured and not set by handler
+    fn add_default_content_type(&mut self) {
+        if self.content_type_set {
+            return;
+        }
+        if let Some(ctype) = self.default_content_type.take() {
+            self.state.add_header(out_buf(&mut self.io),
+                "Content-Type", ctype.as_bytes())
+                .expect("default content type is valid");
+        }
+    }
+
+    /// Writes `Date` if configured and not set by handler
+    fn add_auto_date(&mut self) {
+        if !self.date_header || self.date_set {
+            return;
+        }
+        #[cfg(feature="date_header")]
+        self.add_date();
+    }
+
+    /// Writes `Connection: keep-alive` if configured and connection is
+    /// going to be kept alive after this response
+    fn add_keep_alive(&mut self) {
+        use base_serializer::MessageState::*;
+        if !self.keep_alive_header || self.connection_set {
+            return;
+        }
+        if matches!(self.state,
+                    Headers { close: false, .. } |
+                    FixedHeaders { close: false, .. } |
+                    ChunkedHeaders { close: false, .. }) {
+            self.state.add_header(out_buf(&mut self.io),
+                "Connection", b"keep-alive")
+                .expect("keep-alive header is valid");
+        }
+    }
+
+    /// Add a `Warning` header
+    ///
+    /// May be called multiple times to add multiple warnings
+    pub fn add_warning(&mut self, warning: &Warning)
+        -> Result<(), HeaderError>
+    {
+        self.format_header("Warning", warning)
+    }
+
+    /// Add a `Content-Location` header
+    ///
+    /// This is the URL of the negotiated representation (or the canonical
+    /// URL of the resource). Relative references are allowed. Returns
+    /// `InvalidHeaderValue` if value is empty or contains whitespace or
+    /// control characters.
+    pub fn add_content_location(&mut self, url: &str)
+        -> Result<(), HeaderError>
+    {
+        if url.len() == 0 ||
+            url.chars().any(|c| c.is_whitespace() || c.is_control())
+        {
+            return Err(HeaderError::InvalidHeaderValue);
+        }
+        self.add_header("Content-Location", url)
+    }
+
+    /// Add a `Content-Language` header with one or more language tags
+    ///
+    /// E.g. `add_content_language(&["en-US"])`, tags are validated to be
+    /// syntactically correct (`InvalidHeaderValue` is returned otherwise).
+    pub fn add_content_language(&mut self, tags: &[&str])
+        -> Result<(), HeaderError>
+    {
+        if tags.len() == 0 || !tags.iter().all(|t| valid_language_tag(t)) {
+            return Err(HeaderError::InvalidHeaderValue);
+        }
+        self.add_header("Content-Language", tags.join(", "))
+    }
+
+    /// Add a `Content-Encoding` header, codings are in order they applied
+    ///
+    /// E.g. `add_content_encoding(&["gzip"])`. Note: encoder doesn't
+    /// compress the body, you should write already encoded data. Returns
+    /// `InvalidHeaderValue` if any coding is not a valid token.
+    pub fn add_content_encoding(&mut self, codings: &[&str])
+        -> Result<(), HeaderError>
+    {
+        if codings.len() == 0 || !codings.iter().all(|c| valid_token(c)) {
+            return Err(HeaderError::InvalidHeaderValue);
+        }
+        self.add_header("Content-Encoding", codings.join(", "))
     }
 
     /// Add a content length to the message.
@@ -156,7 +479,10 @@ impl<S> Encoder<S> {
     pub fn add_length(&mut self, n: u64)
         -> Result<(), HeaderError>
     {
-        self.state.add_length(&mut self.io.out_buf, n)
+        if self.deferred.is_some() {
+            return Err(HeaderError::DuplicateContentLength);
+        }
+        self.state.add_length(out_buf(&mut self.io), n)
     }
     /// Sets the transfer encoding to chunked.
     ///
@@ -170,7 +496,76 @@ impl<S> Encoder<S> {
     pub fn add_chunked(&mut self)
         -> Result<(), HeaderError>
     {
-        self.state.add_chunked(&mut self.io.out_buf)
+        if self.deferred.is_some() {
+            return Err(HeaderError::TransferEncodingAfterContentLength);
+        }
+        self.state.add_chunked(out_buf(&mut self.io))
+    }
+    /// Defer the choice between `Content-Length` and chunked encoding
+    ///
+    /// Instead of writing body length header immediately, the body is
+    /// buffered (and headers are not finished) until either:
+    ///
+    /// 1. The `done()` is called with less than `threshold` bytes written,
+    ///    then `Content-Length` is sent along with the whole body
+    /// 2. More than `threshold` bytes were written, then
+    ///    `Transfer-Encoding: chunked` is used for the rest of the response
+    ///
+    /// This is useful for dynamic handlers that may produce either a tiny
+    /// or a huge response. Note: `raw_body()` is not allowed until body
+    /// length is decided, and in response to `HEAD` request you may need
+    /// to write full body to get correct `Content-Length`. As HTTP/1.0 has
+    /// no chunked encoding, response to such request is always buffered.
+    ///
+    /// # Panics
+    ///
+    /// Panics when called in the wrong state (before `status()` or after
+    /// `done_headers()`)
+    pub fn add_deferred_length(&mut self, threshold: usize)
+        -> Result<(), HeaderError>
+    {
+        match self.state {
+            MessageState::Headers { body: Body::Denied, .. }
+            => return Err(HeaderError::RequireBodyless),
+            MessageState::Headers { .. } if self.deferred.is_none() => {}
+            MessageState::Headers { .. } | MessageState::FixedHeaders { .. }
+            => return Err(HeaderError::DuplicateContentLength),
+            MessageState::ChunkedHeaders { .. }
+            => return Err(HeaderError::ContentLengthAfterTransferEncoding),
+            ref state => {
+                panic!("Called add_deferred_length() method on response \
+                    in state {:?}", state)
+            }
+        }
+        self.deferred = Some(Deferred {
+            threshold: threshold,
+            headers_done: false,
+            body: Vec::new(),
+        });
+        Ok(())
+    }
+    /// Writes body size headers and the buffered body if length was deferred
+    fn commit_deferred(&mut self, chunked: bool) {
+        let chunked = chunked && self.state.chunked_allowed();
+        if let Some(deferred) = self.deferred.take() {
+            {
+                self.add_auto_date();
+                if chunked || deferred.body.len() > 0 {
+                    self.add_default_content_type();
+                }
+                self.add_keep_alive();
+                let buf = out_buf(&mut self.io);
+                if chunked {
+                    self.state.add_chunked(buf)
+                } else {
+                    self.state.add_length(buf, deferred.body.len() as u64)
+                }.expect("body length is validated in add_deferred_length");
+                self.state.done_headers(buf)
+                    .expect("headers are valid");
+            }
+            self.stats.headers = self.bytes_written();
+            self.state.write_body(out_buf(&mut self.io), &deferred.body);
+        }
     }
 
     /// Add a date header with the current date
@@ -194,6 +589,45 @@ impl<S> Encoder<S> {
     pub fn is_started(&self) -> bool {
         self.state.is_started()
     }
+    /// Returns true if response body is going to be sent
+    ///
+    /// This is `false` when replying to a `HEAD` request or when status is
+    /// 1xx, 204 or 304. Before `status()` is called only request method is
+    /// taken into account. After `done_headers()` this is the same value as
+    /// returned from it.
+    ///
+    /// Note: you still may call `write_body()` in response to a `HEAD`
+    /// request, the data is just not sent.
+    pub fn response_allows_body(&self) -> bool {
+        self.state.allows_body()
+    }
+    /// Returns true if connection is going to be reused after the response
+    ///
+    /// This is false for HTTP/1.0 requests, requests with
+    /// `Connection: close` and the last request allowed by
+    /// `Config::max_requests_per_connection`. It also becomes false when
+    /// `Connection: close` is added with `add_header()`, in this case
+    /// the protocol closes the connection after this response too (and
+    /// pipelined requests received after this one are not served).
+    pub fn will_keep_alive(&self) -> bool {
+        !self.close
+    }
+    /// Returns framing of the response body chosen by `done_headers()`
+    ///
+    /// Bodyless responses (1xx, 204, 304) report `Fixed(0)`. Responses to
+    /// `HEAD` requests report the framing declared by the headers even
+    /// though the body is not sent. Returns `None` until framing is known,
+    /// i.e. before `done_headers()` or, with `add_deferred_length()`,
+    /// until the buffered body exceeds the threshold.
+    pub fn chosen_framing(&self) -> Option<BodyKind> {
+        match self.state {
+            MessageState::FixedBody { content_length, .. }
+            => Some(BodyKind::Fixed(content_length)),
+            MessageState::ChunkedBody { .. } => Some(BodyKind::Chunked),
+            MessageState::Bodyless => Some(BodyKind::Fixed(0)),
+            _ => None,
+        }
+    }
     /// Closes the HTTP header and returns `true` if entity body is expected.
     ///
     /// Specifically `false` is returned when status is 1xx, 204, 304 or in
@@ -206,7 +640,35 @@ impl<S> Encoder<S> {
     ///
     /// Panics when the response is in a wrong state.
     pub fn done_headers(&mut self) -> Result<bool, HeaderError> {
-        self.state.done_headers(&mut self.io.out_buf)
+        if let Some(hook) = self.header_hook.take() {
+            let (added, removed) = header_hook::run(&hook,
+                out_buf(&mut self.io), self.headers_start);
+            for name in &removed {
+                self.uncheck_header(name);
+            }
+            self.add_headers(&added)?;
+        }
+        if let Some(ref mut deferred) = self.deferred {
+            // headers are written when body length is known
+            assert!(!deferred.headers_done, "done_headers called twice");
+            deferred.headers_done = true;
+            return Ok(!matches!(self.state,
+                MessageState::Headers { body: Body::Head, .. }));
+        }
+        self.add_auto_date();
+        let has_body = match self.state {
+            MessageState::FixedHeaders { content_length, .. }
+            => content_length > 0,
+            MessageState::ChunkedHeaders { .. } => true,
+            _ => false,
+        };
+        if has_body {
+            self.add_default_content_type();
+        }
+        self.add_keep_alive();
+        let has_body = self.state.done_headers(out_buf(&mut self.io))?;
+        self.stats.headers = self.bytes_written();
+        Ok(has_body)
     }
     /// Write a chunk of the message body.
     ///
@@ -229,7 +691,36 @@ impl<S> Encoder<S> {
     /// determine response body length (either Content-Length or
     /// Transfer-Encoding).
     pub fn write_body(&mut self, data: &[u8]) {
-        self.state.write_body(&mut self.io.out_buf, data)
+        self.stats.body += data.len() as u64;
+        let overflow = match self.deferred {
+            Some(ref mut deferred) => {
+                assert!(deferred.headers_done,
+                    "Called write_body() before done_headers()");
+                deferred.body.extend_from_slice(data);
+                deferred.body.len() > deferred.threshold
+            }
+            None => {
+                return self.state.write_body(out_buf(&mut self.io), data);
+            }
+        };
+        if overflow && self.state.chunked_allowed() {
+            self.commit_deferred(true);
+        }
+    }
+    /// Write exactly one chunk of the chunked body
+    ///
+    /// Unlike `write_body`, which works for any body, this method asserts
+    /// that `add_chunked()` was used (or `add_deferred_length` had switched
+    /// to chunked encoding) and puts the data as a single chunk. Empty data
+    /// is ignored, so it can't accidentally terminate the body.
+    ///
+    /// # Panics
+    ///
+    /// When response body is not chunked.
+    pub fn write_chunk(&mut self, data: &[u8]) {
+        assert!(self.deferred.is_none(),
+            "Called write_chunk() before body length is decided");
+        self.state.write_chunk(out_buf(&mut self.io), data)
     }
     /// Returns true if `done()` method is already called and everything
     /// was okay.
@@ -245,8 +736,64 @@ impl<S> Encoder<S> {
     ///
     /// When the response is in the wrong state.
     pub fn done(mut self) -> EncoderDone<S> {
-        self.state.done(&mut self.io.out_buf);
-        EncoderDone { buf: self.io }
+        self.commit_deferred(false);
+        self.state.done(out_buf(&mut self.io));
+        EncoderDone {
+            buf: self.io.take().expect("encoder is not done"),
+            aborted: false,
+            close: self.close,
+        }
+    }
+    /// Abort the response in the middle of the body
+    ///
+    /// Unlike `done()` this doesn't write the final chunk of the chunked
+    /// body (and doesn't validate that the whole fixed-size body is written).
+    /// The connection is closed right after the data already buffered is
+    /// sent, so client may detect that response is truncated rather than
+    /// treat it as a complete one.
+    ///
+    /// Use this when an error occurs while streaming the body and it's too
+    /// late to send an error page.
+    pub fn abort_body(mut self) -> EncoderDone<S> {
+        // if length is not decided yet, use chunked encoding to make
+        // truncation visible to the client
+        self.commit_deferred(true);
+        self.state = MessageState::Done;
+        EncoderDone {
+            buf: self.io.take().expect("encoder is not done"),
+            aborted: true,
+            close: true,
+        }
+    }
+    /// Writes precomputed bytes as the whole response
+    ///
+    /// This is a low-level interface for load-testing and replay tools:
+    /// `data` is copied into the output buffer as is, bypassing status
+    /// line, header and body encoding. Connection lifecycle is still
+    /// handled by the protocol, i.e. connection is closed after this
+    /// response if the request asked so.
+    ///
+    /// **The caller is responsible for the correctness of the framing.**
+    /// Nothing is validated: if `data` is not exactly one response with
+    /// correct body length, `Connection` header matching the request and
+    /// no body for `HEAD` requests (or if it contains several recorded
+    /// responses that are not matched by pipelined requests), the client
+    /// will misinterpret the rest of the connection.
+    ///
+    /// # Panics
+    ///
+    /// When anything has been written to the response already.
+    #[cfg(feature="unsafe_raw")]
+    pub fn raw_response(mut self, data: &[u8]) -> EncoderDone<S> {
+        assert!(!self.state.is_started(),
+            "raw_response called after the response is started");
+        out_buf(&mut self.io).extend(data);
+        self.state = MessageState::Done;
+        EncoderDone {
+            buf: self.io.take().expect("encoder is not done"),
+            aborted: false,
+            close: self.close,
+        }
     }
     /// Returns a raw body for zero-copy writing techniques
     ///
@@ -267,9 +814,10 @@ impl<S> Encoder<S> {
     /// # Panics
     ///
     /// This method panics if it's called when headers are not written yet.
-    pub fn raw_body(self) -> FutureRawBody<S> {
+    pub fn raw_body(mut self) -> FutureRawBody<S> {
         assert!(self.state.is_after_headers());
-        FutureRawBody(self.io.borrow_raw())
+        FutureRawBody(self.io.take().expect("encoder is not done")
+            .borrow_raw(), self.close)
     }
 
     /// Flush the data to underlying socket
@@ -279,17 +827,69 @@ impl<S> Encoder<S> {
     ///
     /// You can find out how many bytes are left using `bytes_buffered()`
     /// method
+    ///
+    /// Don't flush small responses: when the response future is resolved,
+    /// protocol sends the head and the body in a single write.
+    ///
+    /// If body length is deferred (see `add_deferred_length`) and some
+    /// body is already written, flushing switches to the chunked encoding
+    /// (unless request is HTTP/1.0, then the body is sent on `done()`).
     pub fn flush(&mut self) -> Result<(), io::Error>
         where S: AsyncWrite
     {
-        self.io.flush()
+        let pending = self.deferred.as_ref()
+            .map(|d| d.headers_done && d.body.len() > 0).unwrap_or(false);
+        if pending && self.state.chunked_allowed() {
+            self.commit_deferred(true);
+        }
+        let io = self.io.as_mut().expect("encoder is not done");
+        let before = io.out_buf.len();
+        let result = io.flush();
+        let consumed = before - io.out_buf.len();
+        header_hook::flushed(&mut self.headers_start, consumed);
+        self.stats.flushed += consumed as u64;
+        self.stats.flushes += 1;
+        result
     }
     /// Returns bytes currently lying in the buffer
     ///
     /// It's possible that these bytes are left from the previous request if
-    /// pipelining is enabled.
+    /// pipelining is enabled. Body buffered while its length is deferred
+    /// is included.
     pub fn bytes_buffered(&mut self) -> usize {
-        self.io.out_buf.len()
+        let deferred = self.deferred.as_ref().map(|d| d.body.len())
+            .unwrap_or(0);
+        out_buf(&mut self.io).len() + deferred
+    }
+    /// Returns total number of bytes of this response put into the buffer
+    ///
+    /// This includes headers, body and chunked encoding overhead, both
+    /// already flushed and still buffered. Bytes left from the previous
+    /// response are not counted.
+    pub fn bytes_written(&self) -> u64 {
+        let buffered = self.io.as_ref().map(|io| io.out_buf.len())
+            .unwrap_or(0);
+        self.stats.flushed + buffered as u64 - self.stats.start as u64
+    }
+    /// Returns number of bytes of the status line and headers
+    ///
+    /// Interim responses (`100 Continue`, `103 Early Hints`) are included.
+    /// This is zero until headers are written into the buffer (note that
+    /// with `add_deferred_length` it may happen later than `done_headers`).
+    pub fn header_bytes(&self) -> u64 {
+        self.stats.headers
+    }
+    /// Returns number of body bytes passed to `write_body`
+    ///
+    /// Chunked encoding overhead is not included. For `HEAD` requests the
+    /// body is counted even though it's not sent.
+    pub fn body_bytes(&self) -> u64 {
+        self.stats.body
+    }
+    /// Returns how many times `flush()` was called (including implicit
+    /// calls by `wait_flush` and `flush_all` futures)
+    pub fn flushes(&self) -> u64 {
+        self.stats.flushes
     }
 
     /// Returns future which yield encoder back when buffer is flushed
@@ -298,13 +898,42 @@ impl<S> Encoder<S> {
     pub fn wait_flush(self, watermark: usize) -> WaitFlush<S> {
         WaitFlush(Some(self), watermark)
     }
+    /// Returns future which yields encoder back when buffer is empty
+    ///
+    /// Unlike `flush()` which writes as much as the socket accepts right
+    /// now, and `wait_flush(x)` which allows up to `x - 1` bytes to remain
+    /// buffered, this resolves only when all data is written to the socket.
+    /// Useful to make sure everything is transmitted before proceeding,
+    /// for example before switching protocols.
+    pub fn flush_all(self) -> WaitFlush<S> {
+        // buffer is empty when `bytes_buffered()` < 1
+        self.wait_flush(1)
+    }
 }
 
 impl<S> RawBody<S> {
     /// Returns `EncoderDone` object that might be passed back to the HTTP
     /// protocol
     pub fn done(self) -> EncoderDone<S> {
-        EncoderDone { buf: self.io.into_buf() }
+        EncoderDone {
+            buf: self.io.into_buf(),
+            aborted: false,
+            close: self.close,
+        }
+    }
+    /// Returns `EncoderDone` which closes the connection after the response
+    ///
+    /// This is a raw counterpart of `Encoder::abort_body`: nothing is
+    /// written after the data you've already written, in particular no
+    /// final chunk of the chunked body, so client can detect that response
+    /// is truncated. Use it when writing the body fails midway (e.g.
+    /// sendfile error).
+    pub fn abort(self) -> EncoderDone<S> {
+        EncoderDone {
+            buf: self.io.into_buf(),
+            aborted: true,
+            close: true,
+        }
     }
 }
 
@@ -341,22 +970,152 @@ impl<S: AsyncWrite> AsyncWrite for RawBody<S> {
     }
 }
 
+impl<S> Drop for Encoder<S> {
+    fn drop(&mut self) {
+        if let Some(ref reclaim) = self.reclaim {
+            if let Some(io) = self.io.take() {
+                *reclaim.lock().expect("reclaim lock is not poisoned") =
+                    Some((io, self.state.is_started()));
+            }
+        }
+    }
+}
+
+fn out_buf<S>(io: &mut Option<WriteBuf<S>>) -> &mut Buf {
+    &mut io.as_mut().expect("encoder is not done").out_buf
+}
+
+/// Returns true if response was finished by `Encoder::abort_body`
+pub fn is_aborted<S>(e: &EncoderDone<S>) -> bool {
+    e.aborted
+}
+
+/// Returns true if connection must be closed after the response
+pub fn will_close<S>(e: &EncoderDone<S>) -> bool {
+    e.close
+}
+
 pub fn get_inner<S>(e: EncoderDone<S>) -> WriteBuf<S> {
     e.buf
 }
 
+/// Sets `Content-Type` written in `done_headers` if handler didn't set one
+pub fn set_default_content_type<S>(e: &mut Encoder<S>,
+    value: Option<Arc<str>>)
+{
+    e.default_content_type = value;
+}
+
+/// Enables `Connection: keep-alive` header on persistent connections
+pub fn set_keep_alive_header<S>(e: &mut Encoder<S>, value: bool) {
+    e.keep_alive_header = value;
+}
+
+/// Enables `Date` header written in `done_headers` if handler didn't set one
+pub fn set_date_header<S>(e: &mut Encoder<S>, value: bool) {
+    e.date_header = value;
+}
+
+/// Sets a hook called in `done_headers`
+pub fn set_header_hook<S>(e: &mut Encoder<S>, hook: Option<HeaderHook>) {
+    e.header_hook = hook;
+}
+
+/// Same as `new` but returns buffer to `reclaim` slot if dropped unfinished
+pub fn new_reclaimable<S>(io: WriteBuf<S>, cfg: ResponseConfig,
+    reclaim: &Reclaim<S>)
+    -> Encoder<S>
+{
+    let mut enc = new(io, cfg);
+    enc.reclaim = Some(reclaim.clone());
+    enc
+}
+
 pub fn new<S>(io: WriteBuf<S>, cfg: ResponseConfig) -> Encoder<S> {
     use base_serializer::Body::*;
 
     // TODO(tailhook) implement Connection: Close,
     // (including explicit one in HTTP/1.0) and maybe others
+    let start = io.out_buf.len();
     Encoder {
         state: MessageState::ResponseStart {
             body: if cfg.is_head { Head } else { Normal },
             version: cfg.version,
             close: cfg.do_close || cfg.version == Version::Http10,
         },
-        io: io,
+        io: Some(io),
+        reclaim: None,
+        deferred: None,
+        default_content_type: None,
+        content_type_set: false,
+        keep_alive_header: false,
+        connection_set: false,
+        date_header: false,
+        date_set: false,
+        header_hook: None,
+        headers_start: Some(start),
+        is_head: cfg.is_head,
+        close: cfg.do_close || cfg.version == Version::Http10,
+        continue_sent: cfg.continue_sent,
+        stats: Stats { start: start, ..Stats::default() },
+    }
+}
+
+/// Creates an encoder of the response to an HTTP/1.1 request for tests
+#[cfg(test)]
+pub fn for_test(is_head: bool)
+    -> (Encoder<::tk_bufstream::MockData>, ::tk_bufstream::MockData)
+{
+    let mock = ::tk_bufstream::MockData::new();
+    (for_test_io(mock.clone(), is_head), mock)
+}
+
+/// Same as `for_test` but writes into the specified transport
+#[cfg(test)]
+pub fn for_test_io<S>(io: S, is_head: bool) -> Encoder<S>
+    where S: ::tokio_io::AsyncRead + AsyncWrite,
+{
+    use tk_bufstream::IoBuf;
+    new(IoBuf::new(io).split().0, ResponseConfig {
+        is_head: is_head,
+        do_close: false,
+        version: Version::Http11,
+        deadline: None,
+        continue_sent: false,
+    })
+}
+
+#[cfg(feature="testing")]
+impl Encoder<::tk_bufstream::MockData> {
+    /// Create an encoder over an in-memory buffer for tests
+    ///
+    /// Encoder is created as if for a `GET` request over HTTP/1.1. Returns
+    /// a mock which can be used to inspect produced bytes. Note only
+    /// flushed bytes are visible in the mock output:
+    ///
+    /// ```ignore
+    /// let (mut enc, mock) = Encoder::for_test();
+    /// enc.status(Status::NoContent);
+    /// enc.done_headers().unwrap();
+    /// enc.flush().unwrap();
+    /// assert_eq!(&mock.output(..)[..],
+    ///     &b"HTTP/1.1 204 No Content\r\n\r\n"[..]);
+    /// ```
+    ///
+    /// This method is only available with `testing` feature.
+    pub fn for_test()
+        -> (Encoder<::tk_bufstream::MockData>, ::tk_bufstream::MockData)
+    {
+        use tk_bufstream::{IoBuf, MockData};
+        let mock = MockData::new();
+        let enc = new(IoBuf::new(mock.clone()).split().0, ResponseConfig {
+            is_head: false,
+            do_close: false,
+            version: Version::Http11,
+            deadline: None,
+            continue_sent: false,
+        });
+        (enc, mock)
     }
 }
 
@@ -364,8 +1123,10 @@ impl ResponseConfig {
     pub fn from(req: &Head) -> ResponseConfig {
         ResponseConfig {
             version: req.version(),
-            is_head: req.method() == "HEAD",
+            is_head: *req.typed_method() == Method::Head,
             do_close: req.connection_close(),
+            deadline: req.request_deadline(),
+            continue_sent: false,
         }
     }
 }
@@ -374,7 +1135,8 @@ impl<S: AsyncWrite> Future for FutureRawBody<S> {
     type Item = RawBody<S>;
     type Error = io::Error;
     fn poll(&mut self) -> Poll<RawBody<S>, io::Error> {
-        self.0.poll().map(|x| x.map(|y| RawBody { io: y }))
+        let close = self.1;
+        self.0.poll().map(|x| x.map(|y| RawBody { io: y, close: close }))
     }
 }
 
@@ -385,7 +1147,7 @@ impl<S: AsyncWrite> Future for WaitFlush<S> {
         let bytes_left = {
             let enc = self.0.as_mut().expect("future is polled twice");
             enc.flush()?;
-            enc.io.out_buf.len()
+            enc.bytes_buffered()
         };
         if bytes_left < self.1 {
             Ok(Async::Ready(self.0.take().unwrap()))
@@ -419,12 +1181,18 @@ mod sendfile {
 
 #[cfg(test)]
 mod test {
+    use futures::Future;
     use tk_bufstream::{MockData, IoBuf};
-    use {Status};
+    use {Status, Warning};
 
-    use base_serializer::{MessageState, Body};
-    use super::{Encoder, EncoderDone};
+    use base_serializer::{MessageState, Body, HeaderError};
+    use super::{Encoder, EncoderDone, ResponseConfig, new, get_inner};
+    use super::for_test;
+    use super::{set_default_content_type, set_keep_alive_header, will_close};
+    use super::{set_date_header, set_header_hook};
     use enums::Version;
+    use header_hook::HeaderHook;
+    use server::BodyKind;
 
     fn do_response11_str<F>(fun: F) -> String
         where F: FnOnce(Encoder<MockData>) -> EncoderDone<MockData>
@@ -436,12 +1204,72 @@ mod test {
                     version: Version::Http11,
                     close: false,
                 },
-                io: IoBuf::new(mock.clone()).split().0,
+                io: Some(IoBuf::new(mock.clone()).split().0),
+                reclaim: None,
+                deferred: None,
+                default_content_type: None,
+                content_type_set: false,
+                keep_alive_header: false,
+                connection_set: false,
+                date_header: false,
+                date_set: false,
+                header_hook: None,
+                headers_start: Some(0),
+                is_head: false,
+                close: false,
+                continue_sent: false,
+                stats: Default::default(),
             });
         {done}.buf.flush().unwrap();
         String::from_utf8_lossy(&mock.output(..)).to_string()
     }
 
+    #[test]
+    fn early_hints() {
+        assert_eq!(do_response11_str(|mut enc| {
+                enc.early_hints(&["</style.css>; rel=preload; as=style",
+                                  "</app.js>; rel=preload; as=script"])
+                    .unwrap();
+                enc.early_hints(&["</font.woff2>; rel=preload"]).unwrap();
+                enc.status(Status::Ok);
+                enc.add_length(0).unwrap();
+                enc.done_headers().unwrap();
+                enc.done()
+            }),
+            "HTTP/1.1 103 Early Hints\r\n\
+             Link: </style.css>; rel=preload; as=style\r\n\
+             Link: </app.js>; rel=preload; as=script\r\n\r\n\
+             HTTP/1.1 103 Early Hints\r\n\
+             Link: </font.woff2>; rel=preload\r\n\r\n\
+             HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    #[should_panic(expected="early_hints")]
+    fn early_hints_after_status() {
+        do_response11_str(|mut enc| {
+            enc.status(Status::Ok);
+            enc.early_hints(&["</style.css>; rel=preload"]).unwrap();
+            enc.done()
+        });
+    }
+
+    #[test]
+    #[should_panic(expected="Called done() before done_headers()")]
+    fn done_without_headers() {
+        do_response11_str(|mut enc| {
+            enc.status(Status::Ok);
+            enc.add_length(0).unwrap();
+            enc.done()
+        });
+    }
+
+    #[test]
+    #[should_panic(expected="Called done() before the status line")]
+    fn done_without_status() {
+        do_response11_str(|enc| enc.done());
+    }
+
     #[test]
     fn date_header() {
         assert!(do_response11_str(|mut enc| {
@@ -452,4 +1280,684 @@ mod test {
                 enc.done()
             }).starts_with("HTTP/1.1 200 OK\r\nDate: "));
     }
+
+    #[test]
+    #[cfg(feature="date_header")]
+    fn auto_date_header() {
+        let out = do_response11_str(|mut enc| {
+                set_date_header(&mut enc, true);
+                enc.status(Status::Ok);
+                enc.add_length(0).unwrap();
+                enc.done_headers().unwrap();
+                enc.done()
+            });
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\
+                                 Date: "));
+        // handler added its own date
+        let out = do_response11_str(|mut enc| {
+                set_date_header(&mut enc, true);
+                enc.status(Status::Ok);
+                enc.add_header("Date", "Thu, 01 Jan 1970 00:00:00 GMT")
+                    .unwrap();
+                enc.add_length(0).unwrap();
+                enc.done_headers().unwrap();
+                enc.done()
+            });
+        assert_eq!(out, "HTTP/1.1 200 OK\r\n\
+                         Date: Thu, 01 Jan 1970 00:00:00 GMT\r\n\
+                         Content-Length: 0\r\n\r\n");
+        // deferred length
+        let out = do_response11_str(|mut enc| {
+                set_date_header(&mut enc, true);
+                enc.status(Status::Ok);
+                enc.add_deferred_length(100).unwrap();
+                enc.done_headers().unwrap();
+                enc.write_body(b"ok");
+                enc.done()
+            });
+        assert_eq!(out.matches("\r\nDate: ").count(), 1);
+    }
+
+    #[test]
+    fn default_content_type() {
+        assert_eq!(do_response11_str(|mut enc| {
+                set_default_content_type(&mut enc, Some("text/plain".into()));
+                enc.status(Status::Ok);
+                enc.add_length(2).unwrap();
+                enc.done_headers().unwrap();
+                enc.write_body(b"ok");
+                enc.done()
+            }), "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\
+                 Content-Type: text/plain\r\n\r\nok");
+        assert_eq!(do_response11_str(|mut enc| {
+                set_default_content_type(&mut enc, Some("text/plain".into()));
+                enc.status(Status::Ok);
+                enc.add_deferred_length(100).unwrap();
+                enc.done_headers().unwrap();
+                enc.write_body(b"ok");
+                enc.done()
+            }), "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\
+                 Content-Length: 2\r\n\r\nok");
+    }
+
+    #[test]
+    fn explicit_content_type() {
+        assert_eq!(do_response11_str(|mut enc| {
+                set_default_content_type(&mut enc, Some("text/plain".into()));
+                enc.status(Status::Ok);
+                enc.add_header("content-type", "text/html").unwrap();
+                enc.add_chunked().unwrap();
+                enc.done_headers().unwrap();
+                enc.write_body(b"ok");
+                enc.done()
+            }), "HTTP/1.1 200 OK\r\ncontent-type: text/html\r\n\
+                 Transfer-Encoding: chunked\r\n\r\n2\r\nok\r\n0\r\n\r\n");
+    }
+
+    #[test]
+    fn hook_removes_content_type() {
+        assert_eq!(do_response11_str(|mut enc| {
+                set_default_content_type(&mut enc, Some("text/plain".into()));
+                set_header_hook(&mut enc, Some(HeaderHook::new(|h| {
+                    assert!(h.remove("Content-Type"));
+                })));
+                enc.status(Status::Ok);
+                enc.add_header("Content-Type", "text/html").unwrap();
+                enc.add_length(2).unwrap();
+                enc.done_headers().unwrap();
+                enc.write_body(b"ok");
+                enc.done()
+            }), "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\
+                 Content-Type: text/plain\r\n\r\nok");
+    }
+
+    #[test]
+    fn hook_after_continue() {
+        assert_eq!(do_response11_str(|mut enc| {
+                set_header_hook(&mut enc, Some(HeaderHook::new(|h| {
+                    assert_eq!(h.iter().count(), 2);
+                    assert!(h.remove("Server"));
+                })));
+                enc.response_continue();
+                enc.status(Status::Ok);
+                enc.add_header("Server", "x").unwrap();
+                enc.add_length(0).unwrap();
+                enc.done_headers().unwrap();
+                enc.done()
+            }), "HTTP/1.1 100 Continue\r\n\r\n\
+                 HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn no_content_type_without_body() {
+        assert_eq!(do_response11_str(|mut enc| {
+                set_default_content_type(&mut enc, Some("text/plain".into()));
+                enc.status(Status::Ok);
+                enc.add_length(0).unwrap();
+                enc.done_headers().unwrap();
+                enc.done()
+            }), "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn add_headers() {
+        assert_eq!(do_response11_str(|mut enc| {
+                enc.status(Status::Ok);
+                enc.add_headers(&[("X-A", &b"1"[..]), ("X-B", &b"2"[..])])
+                    .unwrap();
+                enc.add_length(0).unwrap();
+                enc.done_headers().unwrap();
+                enc.done()
+            }), "HTTP/1.1 200 OK\r\nX-A: 1\r\nX-B: 2\r\n\
+                 Content-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn add_headers_invalid() {
+        assert_eq!(do_response11_str(|mut enc| {
+                enc.status(Status::Ok);
+                assert_matches!(enc.add_headers(&[
+                        ("X-A", "1"),
+                        ("X-B", "2\r\nX-Evil: 3"),
+                    ]),
+                    Err(HeaderError::InvalidHeaderValue));
+                assert_matches!(enc.add_headers(&[
+                        ("X-A", "1"),
+                        ("Content-Length", "2"),
+                    ]),
+                    Err(HeaderError::BodyLengthHeader));
+                enc.add_length(0).unwrap();
+                enc.done_headers().unwrap();
+                enc.done()
+            }), "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn keep_alive_header() {
+        assert_eq!(do_response11_str(|mut enc| {
+                set_keep_alive_header(&mut enc, true);
+                enc.status(Status::Ok);
+                enc.add_length(0).unwrap();
+                enc.done_headers().unwrap();
+                enc.done()
+            }), "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\
+                 Connection: keep-alive\r\n\r\n");
+        assert_eq!(do_response11_str(|mut enc| {
+                set_keep_alive_header(&mut enc, true);
+                enc.status(Status::Ok);
+                enc.add_deferred_length(100).unwrap();
+                enc.done_headers().unwrap();
+                enc.write_body(b"ok");
+                enc.done()
+            }), "HTTP/1.1 200 OK\r\nConnection: keep-alive\r\n\
+                 Content-Length: 2\r\n\r\nok");
+        // not configured
+        assert_eq!(do_response11_str(|mut enc| {
+                enc.status(Status::Ok);
+                enc.add_length(0).unwrap();
+                enc.done_headers().unwrap();
+                enc.done()
+            }), "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+        // handler sets connection header itself
+        assert_eq!(do_response11_str(|mut enc| {
+                set_keep_alive_header(&mut enc, true);
+                enc.status(Status::SwitchingProtocol);
+                enc.add_header("Connection", "upgrade").unwrap();
+                enc.done_headers().unwrap();
+                enc.done()
+            }), "HTTP/1.1 101 Switching Protocol\r\n\
+                 Connection: upgrade\r\n\r\n");
+    }
+
+    #[test]
+    fn head_content_length() {
+        let (mut enc, mock) = for_test(true);
+        enc.status(Status::Ok);
+        enc.add_length(1000).unwrap();
+        assert!(!enc.done_headers().unwrap());
+        // the body of the equivalent GET may be written, it's discarded
+        enc.write_body(b"hello");
+        get_inner(enc.done()).flush().unwrap();
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "HTTP/1.1 200 OK\r\nContent-Length: 1000\r\n\r\n");
+    }
+
+    #[test]
+    fn response_allows_body() {
+        let (mut enc, _) = for_test(true);
+        assert!(!enc.response_allows_body());
+        enc.status(Status::Ok);
+        assert!(!enc.response_allows_body());
+        enc.add_length(10).unwrap();
+        assert!(!enc.done_headers().unwrap());
+        assert!(!enc.response_allows_body());
+        do_response11_str(|mut enc| {
+            assert!(enc.response_allows_body());
+            enc.status(Status::NoContent);
+            assert!(!enc.response_allows_body());
+            assert!(!enc.done_headers().unwrap());
+            assert!(!enc.response_allows_body());
+            enc.done()
+        });
+        do_response11_str(|mut enc| {
+            enc.status(Status::Ok);
+            assert!(enc.response_allows_body());
+            enc.add_chunked().unwrap();
+            assert!(enc.done_headers().unwrap());
+            assert!(enc.response_allows_body());
+            enc.done()
+        });
+    }
+
+    fn keep_alive<F>(do_close: bool, version: Version, f: F) -> bool
+        where F: FnOnce(&mut Encoder<MockData>)
+    {
+        let mock = MockData::new();
+        let mut enc = new(IoBuf::new(mock.clone()).split().0, ResponseConfig {
+            is_head: false,
+            do_close: do_close,
+            version: version,
+            deadline: None,
+            continue_sent: false,
+        });
+        enc.status(Status::Ok);
+        f(&mut enc);
+        let result = enc.will_keep_alive();
+        enc.add_length(0).unwrap();
+        enc.done_headers().unwrap();
+        assert_eq!(will_close(&enc.done()), !result);
+        result
+    }
+
+    #[test]
+    fn will_keep_alive() {
+        assert!(keep_alive(false, Version::Http11, |_| {}));
+        assert!(!keep_alive(true, Version::Http11, |_| {}));
+        assert!(!keep_alive(false, Version::Http10, |_| {}));
+        assert!(keep_alive(false, Version::Http11, |e| {
+            e.add_header("Connection", "keep-alive").unwrap();
+        }));
+        assert!(!keep_alive(false, Version::Http11, |e| {
+            e.add_header("Connection", "keep-alive, Close").unwrap();
+        }));
+        assert!(!keep_alive(false, Version::Http11, |e| {
+            e.format_header("Connection", "close").unwrap();
+        }));
+    }
+
+    #[test]
+    fn hook_adds_close() {
+        let (mut enc, _) = for_test(false);
+        set_header_hook(&mut enc, Some(HeaderHook::new(|h| {
+            h.add("Connection", "close");
+        })));
+        enc.status(Status::Ok);
+        enc.add_length(0).unwrap();
+        enc.done_headers().unwrap();
+        assert!(!enc.will_keep_alive());
+        assert!(will_close(&enc.done()));
+    }
+
+    #[test]
+    fn explicit_close() {
+        let (mut enc, mock) = for_test(false);
+        set_keep_alive_header(&mut enc, true);
+        enc.status(Status::Ok);
+        assert!(enc.will_keep_alive());
+        enc.add_header("Connection", "close").unwrap();
+        assert!(!enc.will_keep_alive());
+        enc.add_length(0).unwrap();
+        enc.done_headers().unwrap();
+        get_inner(enc.done()).flush().unwrap();
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "HTTP/1.1 200 OK\r\nConnection: close\r\n\
+             Content-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn no_keep_alive_header_on_close() {
+        let mock = MockData::new();
+        let mut enc = new(IoBuf::new(mock.clone()).split().0, ResponseConfig {
+            is_head: false,
+            do_close: true,
+            version: Version::Http11,
+            deadline: None,
+            continue_sent: false,
+        });
+        set_keep_alive_header(&mut enc, true);
+        enc.status(Status::Ok);
+        enc.add_length(0).unwrap();
+        enc.done_headers().unwrap();
+        {enc.done()}.buf.flush().unwrap();
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\
+             Connection: close\r\n\r\n");
+    }
+
+    #[test]
+    fn multiple_warnings() {
+        assert_eq!(do_response11_str(|mut enc| {
+                enc.status(Status::Ok);
+                enc.add_warning(&Warning::new(110, "-", "Response is Stale")
+                    .unwrap()).unwrap();
+                enc.add_warning(&Warning::new(214, "proxy", "Transformed")
+                    .unwrap()).unwrap();
+                enc.add_length(0).unwrap();
+                enc.done_headers().unwrap();
+                enc.done()
+            }), "HTTP/1.1 200 OK\r\n\
+                 Warning: 110 - \"Response is Stale\"\r\n\
+                 Warning: 214 proxy \"Transformed\"\r\n\
+                 Content-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn negotiated_headers() {
+        assert_eq!(do_response11_str(|mut enc| {
+                enc.status(Status::Ok);
+                enc.add_content_location("/docs/index.en.html").unwrap();
+                enc.add_content_language(&["en-US", "en"]).unwrap();
+                enc.add_content_encoding(&["gzip"]).unwrap();
+                enc.add_length(0).unwrap();
+                enc.done_headers().unwrap();
+                enc.done()
+            }), "HTTP/1.1 200 OK\r\n\
+                 Content-Location: /docs/index.en.html\r\n\
+                 Content-Language: en-US, en\r\n\
+                 Content-Encoding: gzip\r\n\
+                 Content-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn invalid_negotiated_headers() {
+        do_response11_str(|mut enc| {
+            enc.status(Status::Ok);
+            assert_matches!(enc.add_content_location("/a b"),
+                Err(HeaderError::InvalidHeaderValue));
+            assert_matches!(enc.add_content_location(""),
+                Err(HeaderError::InvalidHeaderValue));
+            assert_matches!(enc.add_content_language(&["en_US"]),
+                Err(HeaderError::InvalidHeaderValue));
+            assert_matches!(enc.add_content_language(&["*"]),
+                Err(HeaderError::InvalidHeaderValue));
+            assert_matches!(enc.add_content_language(&["toolonglanguage"]),
+                Err(HeaderError::InvalidHeaderValue));
+            assert_matches!(enc.add_content_encoding(&["gzip", "x y"]),
+                Err(HeaderError::InvalidHeaderValue));
+            assert_matches!(enc.add_content_encoding(&[]),
+                Err(HeaderError::InvalidHeaderValue));
+            enc.add_length(0).unwrap();
+            enc.done_headers().unwrap();
+            enc.done()
+        });
+    }
+
+    #[test]
+    #[cfg(feature="testing")]
+    fn testing_feature() {
+        let (mut enc, mock) = Encoder::for_test();
+        enc.status(Status::NoContent);
+        enc.done_headers().unwrap();
+        enc.flush().unwrap();
+        assert_eq!(&mock.output(..)[..],
+                   &b"HTTP/1.1 204 No Content\r\n\r\n"[..]);
+    }
+
+    #[test]
+    #[cfg(feature="http_types")]
+    fn http_response_head() {
+        let resp = ::http::Response::builder()
+            .status(404)
+            .header("Content-Type", "text/plain")
+            .header("X-Hello", "world")
+            .body(()).unwrap();
+        assert_eq!(do_response11_str(|mut enc| {
+                enc.http_response_head(&resp).unwrap();
+                enc.add_length(0).unwrap();
+                enc.done_headers().unwrap();
+                enc.done()
+            }), "HTTP/1.1 404 Not Found\r\ncontent-type: text/plain\r\n\
+                 x-hello: world\r\nContent-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    #[cfg(feature="http_types")]
+    fn http_response_body_length() {
+        let resp = ::http::Response::builder()
+            .header("Transfer-Encoding", "chunked")
+            .body(()).unwrap();
+        do_response11_str(|mut enc| {
+            assert_matches!(enc.http_response_head(&resp),
+                            Err(HeaderError::BodyLengthHeader));
+            enc.add_length(0).unwrap();
+            enc.done_headers().unwrap();
+            enc.done()
+        });
+    }
+
+    #[test]
+    fn deferred_small() {
+        assert_eq!(do_response11_str(|mut enc| {
+                enc.status(Status::Ok);
+                enc.add_deferred_length(10).unwrap();
+                assert!(enc.done_headers().unwrap());
+                enc.write_body(b"hello");
+                enc.write_body(b"world");
+                enc.done()
+            }), "HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhelloworld");
+    }
+
+    #[test]
+    fn deferred_large() {
+        assert_eq!(do_response11_str(|mut enc| {
+                enc.status(Status::Ok);
+                enc.add_deferred_length(6).unwrap();
+                assert!(enc.done_headers().unwrap());
+                enc.write_body(b"hello");
+                enc.write_body(b"world");
+                enc.write_body(b"!");
+                enc.done()
+            }), "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                 a\r\nhelloworld\r\n1\r\n!\r\n0\r\n\r\n");
+    }
+
+    #[test]
+    fn deferred_header_after_done() {
+        assert_eq!(do_response11_str(|mut enc| {
+                enc.status(Status::Ok);
+                enc.add_deferred_length(10).unwrap();
+                enc.done_headers().unwrap();
+                assert!(matches!(enc.add_header("X-Late", "1"),
+                                 Err(HeaderError::HeadersDone)));
+                assert!(matches!(enc.format_header("X-Late", 1),
+                                 Err(HeaderError::HeadersDone)));
+                enc.write_body(b"hello");
+                enc.done()
+            }), "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello");
+    }
+
+    #[test]
+    fn deferred_flush() {
+        let (mut enc, mock) = for_test(false);
+        enc.status(Status::Ok);
+        enc.add_deferred_length(100).unwrap();
+        enc.done_headers().unwrap();
+        enc.write_body(b"hello");
+        // status line and the deferred body
+        assert_eq!(enc.bytes_buffered(), 17 + 5);
+        let mut enc = enc.flush_all().wait().unwrap();
+        assert_eq!(enc.bytes_buffered(), 0);
+        enc.write_body(b"world");
+        get_inner(enc.done()).flush().unwrap();
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+                   "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                    5\r\nhello\r\n5\r\nworld\r\n0\r\n\r\n");
+    }
+
+    #[test]
+    fn deferred_http10() {
+        let mock = MockData::new();
+        let mut enc = new(IoBuf::new(mock.clone()).split().0, ResponseConfig {
+            is_head: false,
+            do_close: false,
+            version: Version::Http10,
+            deadline: None,
+            continue_sent: false,
+        });
+        enc.status(Status::Ok);
+        enc.add_deferred_length(6).unwrap();
+        assert!(enc.done_headers().unwrap());
+        enc.write_body(b"hello");
+        enc.write_body(b"world");
+        get_inner(enc.done()).flush().unwrap();
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "HTTP/1.0 200 OK\r\nContent-Length: 10\r\n\
+             Connection: close\r\n\r\nhelloworld");
+    }
+
+    #[test]
+    fn chosen_framing() {
+        do_response11_str(|mut enc| {
+            enc.status(Status::Ok);
+            enc.add_length(5).unwrap();
+            assert_eq!(enc.chosen_framing(), None);
+            enc.done_headers().unwrap();
+            assert_eq!(enc.chosen_framing(), Some(BodyKind::Fixed(5)));
+            enc.write_body(b"hello");
+            enc.done()
+        });
+        do_response11_str(|mut enc| {
+            enc.status(Status::Ok);
+            enc.add_chunked().unwrap();
+            enc.done_headers().unwrap();
+            assert_eq!(enc.chosen_framing(), Some(BodyKind::Chunked));
+            enc.done()
+        });
+        do_response11_str(|mut enc| {
+            enc.status(Status::NoContent);
+            enc.done_headers().unwrap();
+            assert_eq!(enc.chosen_framing(), Some(BodyKind::Fixed(0)));
+            enc.done()
+        });
+        do_response11_str(|mut enc| {
+            enc.status(Status::Ok);
+            enc.add_deferred_length(6).unwrap();
+            enc.done_headers().unwrap();
+            enc.write_body(b"hello");
+            assert_eq!(enc.chosen_framing(), None);
+            enc.write_body(b"world");
+            assert_eq!(enc.chosen_framing(), Some(BodyKind::Chunked));
+            enc.done()
+        });
+    }
+
+    #[test]
+    fn chosen_framing_head() {
+        let (mut enc, _) = for_test(true);
+        enc.status(Status::Ok);
+        enc.add_length(100).unwrap();
+        assert!(!enc.done_headers().unwrap());
+        assert_eq!(enc.chosen_framing(), Some(BodyKind::Fixed(100)));
+    }
+
+    #[test]
+    fn replace_status() {
+        assert_eq!(do_response11_str(|mut enc| {
+                enc.status(Status::Ok);
+                enc.add_header("X-Request-Id", "1").unwrap();
+                enc.add_length(9).unwrap();
+                // e.g. request body turned out to be invalid
+                enc.replace_status(Status::BadRequest).unwrap();
+                enc.replace_custom_status(422, "Invalid Data").unwrap();
+                enc.done_headers().unwrap();
+                enc.write_body(b"try again");
+                enc.done()
+            }),
+            "HTTP/1.1 422 Invalid Data\r\nX-Request-Id: 1\r\n\
+             Content-Length: 9\r\n\r\ntry again");
+        assert_eq!(do_response11_str(|mut enc| {
+                enc.status(Status::Ok);
+                enc.replace_status(Status::NoContent).unwrap();
+                assert_matches!(enc.add_length(0),
+                                Err(HeaderError::RequireBodyless));
+                enc.replace_status(Status::Created).unwrap();
+                enc.add_length(0).unwrap();
+                enc.done_headers().unwrap();
+                enc.done()
+            }),
+            "HTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn replace_status_after_continue() {
+        assert_eq!(do_response11_str(|mut enc| {
+                enc.response_continue();
+                enc.status(Status::Ok);
+                enc.replace_status(Status::BadRequest).unwrap();
+                enc.add_length(0).unwrap();
+                enc.done_headers().unwrap();
+                enc.done()
+            }),
+            "HTTP/1.1 100 Continue\r\n\r\n\
+             HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn replace_status_bodyless() {
+        do_response11_str(|mut enc| {
+            enc.status(Status::Ok);
+            enc.add_chunked().unwrap();
+            assert_matches!(enc.replace_status(Status::NotModified),
+                            Err(HeaderError::RequireBodyless));
+            enc.done_headers().unwrap();
+            enc.done()
+        });
+    }
+
+    #[test]
+    #[should_panic(expected="replace_status")]
+    fn replace_status_after_headers() {
+        do_response11_str(|mut enc| {
+            enc.status(Status::Ok);
+            enc.add_length(0).unwrap();
+            enc.done_headers().unwrap();
+            enc.replace_status(Status::NotFound).unwrap();
+            enc.done()
+        });
+    }
+
+    #[test]
+    fn write_stats() {
+        let (mut enc, mock) = for_test(false);
+        enc.status(Status::Ok);
+        enc.add_chunked().unwrap();
+        assert_eq!(enc.header_bytes(), 0);
+        enc.done_headers().unwrap();
+        assert_eq!(enc.header_bytes(), 47);
+        enc.write_body(b"hello");
+        enc.flush().unwrap();
+        enc.write_body(b"");
+        enc.write_body(b" world");
+        assert_eq!(enc.body_bytes(), 11);
+        assert_eq!(enc.flushes(), 1);
+        enc.flush().unwrap();
+        assert_eq!(enc.flushes(), 2);
+        assert_eq!(enc.bytes_written(), mock.output(..).len() as u64);
+        let done = enc.done();
+        get_inner(done).flush().unwrap();
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+             5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n");
+    }
+
+    #[test]
+    fn deferred_headers_not_written() {
+        let mock = MockData::new();
+        let mut enc = Encoder {
+            state: MessageState::ResponseStart {
+                body: Body::Normal,
+                version: Version::Http11,
+                close: false,
+            },
+            io: Some(IoBuf::new(mock.clone()).split().0),
+            reclaim: None,
+            deferred: None,
+            default_content_type: None,
+            content_type_set: false,
+            keep_alive_header: false,
+            connection_set: false,
+            date_header: false,
+            date_set: false,
+            header_hook: None,
+            headers_start: Some(0),
+            is_head: false,
+            close: false,
+            continue_sent: false,
+            stats: Default::default(),
+        };
+        enc.status(Status::Ok);
+        enc.add_deferred_length(100).unwrap();
+        assert_matches!(enc.add_length(5),
+                        Err(HeaderError::DuplicateContentLength));
+        enc.done_headers().unwrap();
+        enc.write_body(b"hello");
+        assert!(!enc.state.is_after_headers());
+        {enc.done()}.buf.flush().unwrap();
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+                   "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello");
+    }
+
+    #[test]
+    fn flush_all() {
+        let (mut enc, mock) = for_test(false);
+        enc.status(Status::Ok);
+        enc.add_length(5).unwrap();
+        enc.done_headers().unwrap();
+        enc.write_body(b"hello");
+        assert!(enc.bytes_buffered() > 0);
+        let mut enc = enc.flush_all().wait().unwrap();
+        assert_eq!(enc.bytes_buffered(), 0);
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+                   "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello");
+    }
 }
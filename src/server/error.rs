@@ -25,7 +25,7 @@ quick_error! {
             description("chunk size parse error")
             from()
         }
-        /// Connection reset
+        /// Connection is reset or closed by peer in the middle of a request
         ConnectionReset {
             description("connection reset")
         }
@@ -49,6 +49,16 @@ quick_error! {
         ContentLengthInvalid {
             description("invalid content-length header")
         }
+        /// Value of some header is larger than
+        /// `Config::max_header_value_size`
+        HeaderValueTooLong {
+            description("header value is too long")
+        }
+        /// Transfer-encoding header is invalid (non-utf-8, or `chunked` is
+        /// not the last coding or specified twice)
+        TransferEncodingInvalid {
+            description("invalid transfer-encoding header")
+        }
         /// Duplicate content-length header, this is prohibited due to security
         DuplicateContentLength {
             description("duplicate content length header")
@@ -67,6 +77,11 @@ quick_error! {
         Timeout {
             description("timeout while reading or writing request")
         }
+        /// Request handler has not finished the response in time, after
+        /// it started writing the response
+        HandlerTimeout {
+            description("request handler timed out")
+        }
         Custom(err: Box<::std::error::Error + Send + Sync>) {
             description("custom error")
             display("custom error: {}", err)
@@ -82,6 +97,48 @@ impl Error {
     {
         Error(ErrorEnum::Custom(err.into()))
     }
+
+    /// Returns true if connection was reset or closed by client prematurely
+    ///
+    /// This is true for the connection closed in the middle of request
+    /// headers or body and for I/O errors caused by peer, which are usually
+    /// not worth logging.
+    ///
+    /// Note: `tk_bufstream` reports `ConnectionReset` and `BrokenPipe` as a
+    /// closed connection on both reads and writes, so a reset by peer is
+    /// seen as the connection closed prematurely rather than an I/O error.
+    /// Only the rest (e.g. `ConnectionAborted`) is matched by I/O kind.
+    pub fn is_connection_reset(&self) -> bool {
+        use std::io::ErrorKind::*;
+        match self.0 {
+            ErrorEnum::ConnectionReset => true,
+            ErrorEnum::Io(ref e) => match e.kind() {
+                ConnectionReset | ConnectionAborted | BrokenPipe
+                | UnexpectedEof => true,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Returns the kind of the error
+    #[cfg(test)]
+    pub(crate) fn kind(&self) -> &ErrorEnum {
+        &self.0
+    }
+
+    /// Returns true if client sent a malformed or unsupported request
+    pub fn is_protocol_error(&self) -> bool {
+        use self::ErrorEnum::*;
+        match self.0 {
+            ParseError(..) | ChunkParseError(..) | BadRequestTarget
+            | HostInvalid | DuplicateHost | ConnectionInvalid
+            | ContentLengthInvalid | HeaderValueTooLong
+            | TransferEncodingInvalid | DuplicateContentLength
+            | UnsupportedBody | RequestTooLong => true,
+            _ => false,
+        }
+    }
 }
 
 impl From<io::Error> for Error {
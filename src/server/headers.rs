@@ -3,18 +3,20 @@ use std::slice::Iter as SliceIter;
 #[allow(unused_imports)]
 use std::ascii::AsciiExt;
 use std::borrow::Cow;
+use std::time::Duration;
 
 use httparse::{self, EMPTY_HEADER, Request, Header};
 use tk_bufstream::Buf;
 
 use server::error::{Error, ErrorEnum};
-use super::{RequestTarget, Dispatcher};
+use super::{RequestTarget, Dispatcher, Config};
 use super::codec::BodyKind;
 use super::encoder::ResponseConfig;
 use super::websocket::{self, WebsocketHandshake};
-use super::request_target;
-use headers;
-use {Version};
+use super::request_target::{self, normalize_path};
+use headers::{self, ConnectionTokens};
+use negotiation::valid_token;
+use {Version, Status, Method};
 
 
 /// Number of headers to allocate on a stack
@@ -25,8 +27,9 @@ const MAX_HEADERS: usize = 1024;
 
 struct RequestConfig<'a> {
     body: BodyKind,
-    #[allow(dead_code)] // TODO(tailhook) implement Expect support
     expect_continue: bool,
+    /// `Expect` header contains something other than `100-continue`
+    unknown_expect: bool,
     connection_close: bool,
     connection: Option<Cow<'a, str>>,
     host: Option<&'a str>,
@@ -36,6 +39,8 @@ struct RequestConfig<'a> {
     /// the header value by proxy in this case. But you might consider
     /// returning 400 Bad Request.
     conflicting_host: bool,
+    deadline: Option<Duration>,
+    transfer_encoding: Vec<&'a str>,
 }
 
 /// A borrowed structure that represents request headers
@@ -45,6 +50,7 @@ struct RequestConfig<'a> {
 #[derive(Debug)]
 pub struct Head<'a> {
     method: &'a str,
+    typed_method: Method,
     raw_target: &'a str,
     target: RequestTarget<'a>,
     host: Option<&'a str>,
@@ -54,6 +60,12 @@ pub struct Head<'a> {
     body_kind: BodyKind,
     connection_close: bool,
     connection_header: Option<Cow<'a, str>>,
+    request_deadline: Option<Duration>,
+    normalized_path: Option<Cow<'a, str>>,
+    transfer_encoding: Vec<&'a str>,
+    requests_remaining: Option<usize>,
+    expect_continue: bool,
+    head_length: usize,
 }
 
 /// Iterator over all meaningful headers for the request
@@ -72,6 +84,12 @@ impl<'a> Head<'a> {
     pub fn method(&self) -> &str {
         self.method
     }
+    /// Returns a HTTP method as an enum
+    ///
+    /// Methods other than the standard ones are `Method::Extension`
+    pub fn typed_method(&self) -> &Method {
+        &self.typed_method
+    }
     /// Request-target (the middle part of the first line of request)
     pub fn request_target(&self) -> &RequestTarget<'a> {
         &self.target
@@ -83,18 +101,31 @@ impl<'a> Head<'a> {
     /// Returns path portion of request uri
     ///
     /// Note: this may return something not starting from a slash when
-    /// full uri is used as request-target
+    /// full uri is used as request-target (unless `Config::normalize_path`
+    /// is enabled, in which case normalized path is returned)
     ///
     /// If the request target is in asterisk form this returns None
     pub fn path(&self) -> Option<&str> {
-        use super::RequestTarget::*;
-        match self.target {
-            Origin(x) => Some(x),
-            Absolute { path, .. } => Some(path),
-            Authority(..) => None,
-            Asterisk => None,
+        match self.normalized_path {
+            Some(ref path) => Some(path),
+            None => self.raw_path(),
         }
     }
+    /// Returns path portion of request uri as sent by the client
+    ///
+    /// This is the same as `path()` unless `Config::normalize_path` is
+    /// enabled.
+    pub fn raw_path(&self) -> Option<&str> {
+        target_path(&self.target)
+    }
+    /// Returns query string of request uri (the part after the first `?`)
+    ///
+    /// The query is returned as sent by the client, no decoding is done.
+    /// Returns `None` if there is no `?` in the request target. Note that
+    /// `path()` also includes the query string.
+    pub fn raw_query(&self) -> Option<&str> {
+        self.raw_path().and_then(request_target::raw_query)
+    }
     /// Return host of a request
     ///
     /// Note: this might be extracted from request-target portion of
@@ -153,14 +184,78 @@ impl<'a> Head<'a> {
     pub fn all_headers(&self) -> &'a [Header<'a>] {
         self.headers
     }
+    /// Number of bytes the request line and headers occupied in the input
+    ///
+    /// This includes the empty line terminating the headers, so the body
+    /// starts at this offset from the start of the request. Note: with
+    /// `Config::lenient_request_line` this is the length after the request
+    /// line is normalized.
+    pub fn head_length(&self) -> usize {
+        self.head_length
+    }
+    /// Returns the request head serialized as `message/http`
+    ///
+    /// This is the body of the reply to a `TRACE` request (RFC 7231,
+    /// section 4.3.8). `Authorization`, `Proxy-Authorization` and `Cookie`
+    /// headers are excluded, as they may contain sensitive data.
+    pub fn trace_message(&self) -> Vec<u8> {
+        let mut buf = format!("{} {} {}\r\n",
+            self.method, self.raw_target, self.version).into_bytes();
+        for header in self.headers {
+            if header.name.eq_ignore_ascii_case("Authorization") ||
+                header.name.eq_ignore_ascii_case("Proxy-Authorization") ||
+                header.name.eq_ignore_ascii_case("Cookie")
+            {
+                continue;
+            }
+            buf.extend(header.name.as_bytes());
+            buf.extend(b": ");
+            buf.extend(header.value);
+            buf.extend(b"\r\n");
+        }
+        buf.extend(b"\r\n");
+        buf
+    }
     /// Return `true` if `Connection: close` header exists
+    ///
+    /// This is also `true` for HTTP/1.0 requests and for the last request
+    /// allowed by `Config::max_requests_per_connection`, i.e. whenever
+    /// connection is closed after the response.
     pub fn connection_close(&self) -> bool {
         self.connection_close
     }
+    /// Number of requests that may be sent on this connection after this one
+    ///
+    /// Returns `None` unless `Config::max_requests_per_connection` is set.
+    pub fn requests_remaining(&self) -> Option<usize> {
+        self.requests_remaining
+    }
+    /// Returns `true` if this is the last request allowed on the connection
+    pub fn is_last_request(&self) -> bool {
+        self.requests_remaining == Some(0)
+    }
+    /// Returns `true` if request contains `Expect: 100-continue`
+    ///
+    /// Client waits for `100 Continue` before sending the body of such
+    /// request. It's sent by the protocol when body is about to be read,
+    /// so to reject the request without receiving the body return
+    /// `RecvMode::reject_body()` or `RecvMode::ignore_body()` from
+    /// `Codec::recv_mode`.
+    pub fn expect_continue(&self) -> bool {
+        self.expect_continue
+    }
     /// Returns the value of the `Connection` header (all of them, if multiple)
     pub fn connection_header(&'a self) -> Option<&'a str> {
         self.connection_header.as_ref().map(|x| &x[..])
     }
+    /// Iterator over the tokens of the `Connection` header
+    ///
+    /// Headers named by these tokens are hop-by-hop and are skipped by
+    /// `headers()`.
+    pub fn connection_tokens(&self) -> ConnectionTokens {
+        headers::connection_tokens(
+            self.connection_header.as_ref().map(|x| &x[..]))
+    }
 
     /// Returns true if there was transfer-encoding or content-length != 0
     ///
@@ -182,6 +277,54 @@ impl<'a> Head<'a> {
             _ => None,
         }
     }
+    /// Returns the time client is willing to wait for the response
+    ///
+    /// This is parsed from `X-Request-Deadline` header, which contains
+    /// number of milliseconds. Invalid values are ignored. If
+    /// `Config::respect_request_deadline` is enabled the deadline is also
+    /// enforced as a handler timeout. Proxies should propagate the
+    /// remaining time to the backend via `client::Encoder::add_deadline`.
+    pub fn request_deadline(&self) -> Option<Duration> {
+        self.request_deadline
+    }
+    /// Returns transfer codings of the request body in order of application
+    ///
+    /// For example `["gzip", "chunked"]` for `Transfer-Encoding: gzip,
+    /// chunked`. If the list is not empty it always ends with `chunked`
+    /// (otherwise request is rejected). Only `chunked` is decoded by the
+    /// library, so any other codings must be decoded by the handler
+    /// (or the request should be replied with `501 Not Implemented`).
+    pub fn transfer_encoding(&self) -> &[&'a str] {
+        &self.transfer_encoding
+    }
+    /// Convert request head into `http::Request` without a body
+    ///
+    /// This method is only available with `http_types` feature.
+    ///
+    /// The conversion is lossy in the following ways:
+    ///
+    /// 1. Header names are lowercased and headers are grouped by name
+    ///    (relative order of the headers with different names is lost)
+    /// 2. All headers are converted including hop-by-hop ones (i.e. it's
+    ///    `all_headers()` not `headers()`)
+    /// 3. `normalize_path` setting is not applied, raw request-target is
+    ///    used as an URI
+    ///
+    /// Error is returned if either method, request-target or any header
+    /// can't be represented by the `http` crate.
+    #[cfg(feature="http_types")]
+    pub fn to_http_request(&self)
+        -> Result<::http::Request<()>, ::http::Error>
+    {
+        let mut builder = ::http::Request::builder();
+        builder.method(self.method)
+            .uri(self.raw_target)
+            .version(self.version.into());
+        for header in self.headers {
+            builder.header(header.name, header.value);
+        }
+        builder.body(())
+    }
     /// Check if connection is a websocket and return hanshake info
     ///
     /// `Err(())` is returned when there was handshake but where was something
@@ -199,7 +342,70 @@ impl<'a> Head<'a> {
     }
 }
 
-fn scan_headers<'x>(raw_request: &'x Request)
+fn target_path<'a>(target: &RequestTarget<'a>) -> Option<&'a str> {
+    use super::RequestTarget::*;
+    match *target {
+        Origin(x) => Some(x),
+        Absolute { path, .. } => Some(path),
+        Authority(..) => None,
+        Asterisk => None,
+    }
+}
+
+/// Returns true if the header value is surrounded by spaces only
+///
+/// Parser strips both spaces and tabs around the value, so this looks at
+/// the raw `buffer` the header is parsed from.
+fn plain_padding(buffer: &[u8], header: &Header) -> bool {
+    let base = buffer.as_ptr() as usize;
+    let value = header.value.as_ptr() as usize;
+    let name_end = header.name.as_ptr() as usize + header.name.len() + 1;
+    if header.value.len() == 0 || name_end < base || value < name_end ||
+        value + header.value.len() > base + buffer.len()
+    {
+        return true;
+    }
+    let start = name_end - base;
+    let end = value - base + header.value.len();
+    let line_end = buffer[end..].iter()
+        .position(|&c| c == b'\r' || c == b'\n')
+        .map_or(buffer.len(), |x| end + x);
+    buffer[start..value - base].iter().all(|&c| c == b' ') &&
+        buffer[end..line_end].iter().all(|&c| c == b' ')
+}
+
+/// Strictly validates a transfer coding
+///
+/// Obfuscated codings (containing tabs or control characters, quoted,
+/// `chunked` with parameters) and `identity` which has no meaning in
+/// HTTP/1.1 are rejected, as other servers may interpret them differently
+/// which is a request smuggling vector.
+fn valid_coding(coding: &str) -> bool {
+    let mut parts = coding.splitn(2, ';');
+    let name = parts.next().unwrap_or("").trim_end_matches(' ');
+    let has_params = parts.next().is_some();
+    valid_token(name) && !name.eq_ignore_ascii_case("identity") &&
+        !(has_params && name.eq_ignore_ascii_case("chunked"))
+}
+
+/// Compares two `host[:port]` values
+///
+/// Host names are case-insensitive and `default_port` of the scheme is the
+/// same as no port at all.
+fn same_host(a: &str, b: &str, default_port: &str) -> bool {
+    fn strip<'x>(host: &'x str, port: &str) -> &'x str {
+        if host.ends_with(port) {
+            let prefix = &host[..host.len() - port.len()];
+            if prefix.ends_with(':') {
+                return &prefix[..prefix.len()-1];
+            }
+        }
+        host
+    }
+    strip(a, default_port).eq_ignore_ascii_case(strip(b, default_port))
+}
+
+fn scan_headers<'x>(raw_request: &'x Request, buffer: &[u8])
     -> Result<RequestConfig<'x>, ErrorEnum>
 {
     // Implements the body length algorithm for requests:
@@ -221,15 +427,18 @@ fn scan_headers<'x>(raw_request: &'x Request)
     use super::codec::BodyKind::*;
     use server::error::ErrorEnum::*;
 
-    let mut has_content_length = false;
+    let mut content_length = None;
+    let mut transfer_encoding = Vec::new();
     let mut close = raw_request.version.unwrap() == 0;
     let mut expect_continue = false;
+    let mut unknown_expect = false;
     let mut body = Fixed(0);
     let mut connection = None::<Cow<_>>;
     let mut host_header = false;
     let target = request_target::parse(raw_request.path.unwrap())
         .ok_or(BadRequestTarget)?;
     let mut conflicting_host = false;
+    let mut deadline = None;
     let mut host = match target {
         RequestTarget::Authority(x) => Some(x),
         RequestTarget::Absolute { authority, .. } => Some(authority),
@@ -237,30 +446,34 @@ fn scan_headers<'x>(raw_request: &'x Request)
     };
     for header in raw_request.headers.iter() {
         if header.name.eq_ignore_ascii_case("Transfer-Encoding") {
-            if let Some(enc) = header.value.split(|&x| x == b',').last() {
-                if headers::is_chunked(enc) {
-                    if has_content_length {
-                        // override but don't allow keep-alive
-                        close = true;
-                    }
-                    body = Chunked;
+            // multiple headers are the same as a comma-separated list
+            let value = from_utf8(header.value)
+                .map_err(|_| TransferEncodingInvalid)?;
+            if !plain_padding(buffer, header) {
+                // i.e. `Transfer-Encoding:\tchunked`
+                return Err(TransferEncodingInvalid);
+            }
+            for coding in value.split(',').map(|x| x.trim_matches(' ')) {
+                if coding.len() == 0 {
+                    continue;
+                }
+                if !valid_coding(coding) {
+                    return Err(TransferEncodingInvalid);
                 }
+                if transfer_encoding.last()
+                    .map_or(false, |x: &&str| x.eq_ignore_ascii_case("chunked"))
+                {
+                    // chunked must be applied only once and the last one
+                    return Err(TransferEncodingInvalid);
+                }
+                transfer_encoding.push(coding);
             }
         } else if header.name.eq_ignore_ascii_case("Content-Length") {
-            if has_content_length {
+            if content_length.is_some() {
                 // duplicate content_length
                 return Err(DuplicateContentLength);
             }
-            has_content_length = true;
-            if body != Chunked {
-                let s = from_utf8(header.value)
-                    .map_err(|_| ContentLengthInvalid)?;
-                let len = s.parse().map_err(|_| ContentLengthInvalid)?;
-                body = Fixed(len);
-            } else {
-                // transfer-encoding has preference and don't allow keep-alive
-                close = true;
-            }
+            content_length = Some(header.value);
         } else if header.name.eq_ignore_ascii_case("Connection") {
             let strconn = from_utf8(header.value)
                 .map_err(|_| ConnectionInvalid)?.trim();
@@ -278,37 +491,123 @@ fn scan_headers<'x>(raw_request: &'x Request)
             host_header = true;
             let strhost = from_utf8(header.value)
                 .map_err(|_| HostInvalid)?.trim();
-            if host.is_none() {  // if host is not in uri
+            match host {
                 // TODO(tailhook) additional validations for host
-                host = Some(strhost);
-            } else if host != Some(strhost) {
-                conflicting_host = true;
+                None => host = Some(strhost),  // if host is not in uri
+                Some(uri_host) => {
+                    let port = match target {
+                        RequestTarget::Absolute { scheme: "https", .. }
+                        => "443",
+                        _ => "80",
+                    };
+                    if !same_host(uri_host, strhost, port) {
+                        conflicting_host = true;
+                    }
+                }
             }
         } else if header.name.eq_ignore_ascii_case("Expect") {
             if headers::is_continue(header.value) {
                 expect_continue = true;
+            } else {
+                unknown_expect = true;
             }
+        } else if header.name.eq_ignore_ascii_case(headers::REQUEST_DEADLINE) {
+            deadline = headers::parse_deadline(header.value);
         }
     }
+    if let Some(last) = transfer_encoding.last() {
+        if !last.eq_ignore_ascii_case("chunked") {
+            // can't determine length of the body
+            return Err(TransferEncodingInvalid);
+        }
+        if content_length.is_some() {
+            // transfer-encoding has preference but don't allow keep-alive
+            close = true;
+        }
+        body = Chunked;
+    } else if let Some(value) = content_length {
+        let s = from_utf8(value).map_err(|_| ContentLengthInvalid)?;
+        let len = s.parse().map_err(|_| ContentLengthInvalid)?;
+        body = Fixed(len);
+    }
     if raw_request.method.unwrap() == "CONNECT" {
         body = Unsupported;
     }
     Ok(RequestConfig {
         body: body,
         expect_continue: expect_continue,
+        unknown_expect: unknown_expect,
         connection: connection,
         host: host,
         target: target,
         connection_close: close,
         conflicting_host: conflicting_host,
+        deadline: deadline,
+        transfer_encoding: transfer_encoding,
     })
 }
 
-pub fn parse_headers<S, D>(buffer: &mut Buf, disp: &mut D)
-    -> Result<Option<(BodyKind, D::Codec, ResponseConfig)>, Error>
+/// Result of parsing request headers
+pub enum Parsed<C> {
+    /// Request is accepted by dispatcher, last field is true if
+    /// `100 Continue` must be sent before reading the body
+    Request(BodyKind, C, ResponseConfig, bool),
+    /// Request is rejected before it reaches dispatcher, the status should
+    /// be sent and connection closed
+    Reject(Status, ResponseConfig),
+    /// `TRACE` request (when `Config::reply_to_trace` is enabled), the
+    /// message should be reflected and connection closed
+    Trace(Vec<u8>, ResponseConfig),
+}
+
+/// Returns size of the value of the last (incomplete) header line
+fn partial_value_size(buffer: &[u8]) -> usize {
+    let line = match buffer.iter().rposition(|&x| x == b'\n') {
+        Some(idx) => &buffer[idx+1..],
+        None => return 0,  // still in request line
+    };
+    match line.iter().position(|&x| x == b':') {
+        Some(idx) => line.len() - idx - 1,
+        None => 0,
+    }
+}
+
+/// Replaces runs of spaces and tabs in request line by a single space
+fn normalize_request_line(buffer: &mut Buf) {
+    let line_end = match buffer[..].iter().position(|&x| x == b'\n') {
+        Some(end) => end,
+        None => return,  // request line is not complete yet
+    };
+    let mut write = 0;
+    let mut prev_space = false;
+    for read in 0..line_end {
+        let ch = buffer[read];
+        if ch == b' ' || ch == b'\t' {
+            if prev_space {
+                continue;
+            }
+            prev_space = true;
+            buffer[write] = b' ';
+        } else {
+            prev_space = false;
+            buffer[write] = ch;
+        }
+        write += 1;
+    }
+    if write < line_end {
+        buffer.remove_range(write..line_end);
+    }
+}
+
+pub fn parse_headers<S, D>(buffer: &mut Buf, disp: &mut D, config: &Config,
+                           requests_remaining: Option<usize>)
+    -> Result<Option<Parsed<D::Codec>>, Error>
     where D: Dispatcher<S>,
 {
-    let (body_kind, codec, cfg, bytes) = {
+    if config.lenient_request_line {
+        normalize_request_line(buffer);
+    }
+    let (parsed, bytes) = {
         let mut vec;
         let mut headers = [EMPTY_HEADER; MIN_HEADERS];
 
@@ -321,10 +620,28 @@ pub fn parse_headers<S, D>(buffer: &mut Buf, disp: &mut D)
         }
         match result.map_err(ErrorEnum::ParseError)? {
             httparse::Status::Complete(bytes) => {
-                let cfg = scan_headers(&raw)?;
+                let limit = config.max_header_value_size;
+                if raw.headers.iter().any(|h| h.value.len() > limit) {
+                    return Err(ErrorEnum::HeaderValueTooLong.into());
+                }
+                let cfg = scan_headers(&raw, &buffer[..])?;
                 let ver = raw.version.unwrap();
+                let (normalized_path, escapes_root) =
+                    match target_path(&cfg.target) {
+                        Some(path) if config.normalize_path
+                        => match normalize_path(path) {
+                            Some(path) => (Some(path), false),
+                            None => (None, true),
+                        },
+                        _ => (None, false),
+                    };
                 let head = Head {
                     method: raw.method.unwrap(),
+                    // httparse before 1.8 doesn't check that method
+                    // is a token, so keep whatever bytes we've got
+                    typed_method: Method::parse(raw.method.unwrap())
+                        .unwrap_or_else(|| Method::Extension(
+                            raw.method.unwrap().to_string())),
                     raw_target: raw.path.unwrap(),
                     target: cfg.target,
                     version: if ver == 1
@@ -336,19 +653,60 @@ pub fn parse_headers<S, D>(buffer: &mut Buf, disp: &mut D)
                     // For HTTP/1.0 we could implement
                     // Connection: Keep-Alive but hopefully it's rare
                     // enough to ignore nowadays
-                    connection_close: cfg.connection_close || ver == 0,
+                    connection_close: cfg.connection_close || ver == 0
+                        || requests_remaining == Some(0),
                     connection_header: cfg.connection,
+                    request_deadline: cfg.deadline,
+                    normalized_path: normalized_path,
+                    transfer_encoding: cfg.transfer_encoding,
+                    requests_remaining: requests_remaining,
+                    expect_continue: cfg.expect_continue,
+                    head_length: bytes,
                 };
-                let codec = disp.headers_received(&head)?;
-                // TODO(tailhook) send 100-expect response headers
+                // HTTP/1.0 clients don't wait for `100 Continue`
+                let send_continue = cfg.expect_continue && ver == 1 &&
+                    cfg.body != BodyKind::Fixed(0);
                 let response_config = ResponseConfig::from(&head);
-                (cfg.body, codec, response_config, bytes)
+                if escapes_root {
+                    debug!("Rejecting request with path above the root");
+                    (Parsed::Reject(Status::BadRequest, response_config),
+                     bytes)
+                } else if head.conflicting_host &&
+                    config.reject_conflicting_host
+                {
+                    debug!("Rejecting request with conflicting host");
+                    (Parsed::Reject(Status::BadRequest, response_config),
+                     bytes)
+                } else if cfg.unknown_expect && config.reject_unknown_expect {
+                    debug!("Rejecting request with unknown expectation");
+                    (Parsed::Reject(Status::ExpectationFailed,
+                                    response_config),
+                     bytes)
+                } else if config.reply_to_trace &&
+                    head.typed_method == Method::Trace
+                {
+                    (Parsed::Trace(head.trace_message(), response_config),
+                     bytes)
+                } else {
+                    let codec = disp.headers_received(&head)?;
+                    (Parsed::Request(cfg.body, codec, response_config,
+                                     send_continue),
+                     bytes)
+                }
+            }
+            httparse::Status::Partial => {
+                // don't wait until the whole huge value is buffered
+                if partial_value_size(&buffer[..]) >
+                    config.max_header_value_size
+                {
+                    return Err(ErrorEnum::HeaderValueTooLong.into());
+                }
+                return Ok(None);
             }
-            _ => return Ok(None),
         }
     };
     buffer.consume(bytes);
-    Ok(Some((body_kind, codec, cfg)))
+    Ok(Some(parsed))
 }
 
 impl<'a> Iterator for HeaderIter<'a> {
@@ -364,11 +722,10 @@ impl<'a> Iterator for HeaderIter<'a> {
                 continue;
             }
 
-            if let Some(ref conn) = self.head.connection_header {
-                let mut conn_headers = conn.split(',').map(|x| x.trim());
-                if conn_headers.any(|x| x.eq_ignore_ascii_case(header.name)) {
-                    continue;
-                }
+            if self.head.connection_tokens()
+                .any(|x| x.eq_ignore_ascii_case(header.name))
+            {
+                continue;
             }
             return Some((header.name, header.value));
         }
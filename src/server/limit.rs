@@ -0,0 +1,350 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use futures::{Async, Poll, Stream};
+use futures::task::{self, Task};
+
+
+struct State {
+    active: usize,
+    max: usize,
+    waiters: Vec<Task>,
+}
+
+/// A semaphore that limits number of simultaneous connections
+///
+/// This is useful to prevent exhaustion of file descriptors. The limit is
+/// usually applied to the stream of accepted connections, new connections
+/// are not accepted while limit is reached (so they are queued in the
+/// listen backlog of the OS) and accepting is resumed as soon as any
+/// connection is closed:
+///
+/// ```ignore
+/// let limit = ConnectionLimit::new(10000);
+/// limit.limit(listener.incoming())
+///     .map(|((socket, addr), guard)| {
+///         Proto::new(socket, &cfg, dispatcher(addr), &handle)
+///         // connection slot is released when guard is dropped
+///         .then(move |_| { drop(guard); Ok(()) })
+///     })
+/// ```
+#[derive(Clone)]
+pub struct ConnectionLimit {
+    state: Arc<Mutex<State>>,
+}
+
+/// A slot taken from the `ConnectionLimit`, released on drop
+pub struct ConnectionGuard {
+    state: Arc<Mutex<State>>,
+}
+
+/// A stream that pairs each item with a `ConnectionGuard`
+///
+/// Created by `ConnectionLimit::limit`. Underlying stream is not polled
+/// while there are no free slots. The slot is taken only when the stream
+/// yields an item, so an idle listener doesn't hold one.
+pub struct Limited<S: Stream> {
+    stream: S,
+    limit: ConnectionLimit,
+    /// An item received when the slot was taken by someone else meanwhile
+    pending: Option<S::Item>,
+}
+
+/// A stream of accepted connections that skips rejected peers
+///
+/// Created by `filter_peers`.
+pub struct Filtered<S, F> {
+    stream: S,
+    check: F,
+}
+
+/// Check every accepted connection with a callback before it's served
+///
+/// The callback receives peer address and returns false to reject the
+/// connection. Rejected sockets are dropped (i.e. closed) immediately,
+/// before any byte is read, and are not yielded from the stream. This is
+/// a building block for basic DoS protection like per-IP connection
+/// limits or block lists:
+///
+/// ```ignore
+/// let incoming = filter_peers(listener.incoming(),
+///     |addr| !blocked.contains(&addr.ip()));
+/// limit.limit(incoming)
+///     .map(|((socket, addr), guard)| {
+///         // ...
+///     })
+/// ```
+///
+/// Filter is usually applied before `ConnectionLimit::limit` so that
+/// rejected peers don't wait for a free slot.
+pub fn filter_peers<S, C, F>(stream: S, check: F) -> Filtered<S, F>
+    where S: Stream<Item=(C, SocketAddr)>,
+          F: FnMut(&SocketAddr) -> bool,
+{
+    Filtered {
+        stream: stream,
+        check: check,
+    }
+}
+
+impl ConnectionLimit {
+    /// Create a limit of `max_connections` simultaneous connections
+    pub fn new(max_connections: usize) -> ConnectionLimit {
+        ConnectionLimit {
+            state: Arc::new(Mutex::new(State {
+                active: 0,
+                max: max_connections,
+                waiters: Vec::new(),
+            })),
+        }
+    }
+    /// Returns number of connections currently holding a slot
+    pub fn connections(&self) -> usize {
+        self.state.lock().expect("limit is not poisoned").active
+    }
+    /// Returns the maximum number of connections
+    pub fn max_connections(&self) -> usize {
+        self.state.lock().expect("limit is not poisoned").max
+    }
+    /// Take a free slot
+    ///
+    /// If limit is reached, returns `NotReady` and the current task is
+    /// notified when any slot is released.
+    pub fn poll_acquire(&self) -> Async<ConnectionGuard> {
+        let mut state = self.state.lock().expect("limit is not poisoned");
+        if state.active < state.max {
+            state.active += 1;
+            Async::Ready(ConnectionGuard { state: self.state.clone() })
+        } else {
+            state.park();
+            Async::NotReady
+        }
+    }
+    /// Returns `Ready` if there is a free slot, without taking it
+    ///
+    /// Otherwise current task is notified when any slot is released.
+    fn poll_free(&self) -> Async<()> {
+        let mut state = self.state.lock().expect("limit is not poisoned");
+        if state.active < state.max {
+            Async::Ready(())
+        } else {
+            state.park();
+            Async::NotReady
+        }
+    }
+    /// Apply the limit to a stream of connections
+    pub fn limit<S: Stream>(&self, stream: S) -> Limited<S> {
+        Limited {
+            stream: stream,
+            limit: self.clone(),
+            pending: None,
+        }
+    }
+}
+
+impl State {
+    /// Registers current task to be notified when a slot is released
+    fn park(&mut self) {
+        if !self.waiters.iter().any(|t| t.will_notify_current()) {
+            self.waiters.push(task::current());
+        }
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let waiters = {
+            let mut state = self.state.lock()
+                .expect("limit is not poisoned");
+            state.active -= 1;
+            ::std::mem::replace(&mut state.waiters, Vec::new())
+        };
+        for task in waiters {
+            task.notify();
+        }
+    }
+}
+
+impl<S: Stream> Stream for Limited<S> {
+    type Item = (S::Item, ConnectionGuard);
+    type Error = S::Error;
+    fn poll(&mut self) -> Poll<Option<Self::Item>, S::Error> {
+        if self.pending.is_none() {
+            if self.limit.poll_free().is_not_ready() {
+                return Ok(Async::NotReady);
+            }
+            match self.stream.poll()? {
+                Async::Ready(Some(item)) => self.pending = Some(item),
+                Async::Ready(None) => return Ok(Async::Ready(None)),
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+        // slot may be taken by another stream sharing the limit while we
+        // were accepting, then the item waits for the next free slot
+        match self.limit.poll_acquire() {
+            Async::Ready(guard) => {
+                let item = self.pending.take().expect("item is received");
+                Ok(Async::Ready(Some((item, guard))))
+            }
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl<S, C, F> Stream for Filtered<S, F>
+    where S: Stream<Item=(C, SocketAddr)>,
+          F: FnMut(&SocketAddr) -> bool,
+{
+    type Item = (C, SocketAddr);
+    type Error = S::Error;
+    fn poll(&mut self) -> Poll<Option<Self::Item>, S::Error> {
+        loop {
+            match self.stream.poll()? {
+                Async::Ready(Some((conn, addr))) => {
+                    if (self.check)(&addr) {
+                        return Ok(Async::Ready(Some((conn, addr))));
+                    }
+                    debug!("Connection from {} rejected", addr);
+                    drop(conn);
+                }
+                Async::Ready(None) => return Ok(Async::Ready(None)),
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+impl fmt::Debug for ConnectionLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.state.lock().expect("limit is not poisoned");
+        write!(f, "ConnectionLimit({}/{})", state.active, state.max)
+    }
+}
+
+impl fmt::Debug for ConnectionGuard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ConnectionGuard")
+    }
+}
+
+impl<S: fmt::Debug, F> fmt::Debug for Filtered<S, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Filtered")
+            .field("stream", &self.stream)
+            .finish()
+    }
+}
+
+impl<S: Stream + fmt::Debug> fmt::Debug for Limited<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Limited")
+            .field("stream", &self.stream)
+            .field("limit", &self.limit)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures::Async;
+    use futures::executor::{spawn, Notify};
+    use futures::stream::{iter_ok, poll_fn};
+    use super::{ConnectionLimit, filter_peers};
+
+    struct Counter(AtomicUsize);
+
+    impl Notify for Counter {
+        fn notify(&self, _id: usize) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// A connection that counts how many times it's closed
+    struct Conn(Arc<AtomicUsize>);
+
+    impl Drop for Conn {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn reject_peers() {
+        let closed = Arc::new(AtomicUsize::new(0));
+        let conns = vec!["10.0.0.1:1000", "10.0.0.2:1000", "10.0.0.1:2000"]
+            .into_iter()
+            .map(|a| (Conn(closed.clone()), a.parse().unwrap()))
+            .collect::<Vec<_>>();
+        let mut checked = Vec::new();
+        let mut stream = spawn(filter_peers(iter_ok::<_, ()>(conns),
+            |addr| {
+                checked.push(*addr);
+                addr.port() != 1000
+            }));
+        match stream.wait_stream() {
+            Some(Ok((_conn, addr))) => {
+                assert_eq!(addr, "10.0.0.1:2000".parse().unwrap());
+                // rejected connections are closed right away
+                assert_eq!(closed.load(Ordering::SeqCst), 2);
+            }
+            _ => panic!("third connection is accepted"),
+        }
+        assert_eq!(closed.load(Ordering::SeqCst), 3);
+        assert!(stream.wait_stream().is_none());
+        drop(stream);
+        assert_eq!(checked.len(), 3);
+    }
+
+    #[test]
+    fn limit_connections() {
+        let notify = Arc::new(Counter(AtomicUsize::new(0)));
+        let limit = ConnectionLimit::new(2);
+        let mut stream = spawn(limit.limit(iter_ok::<_, ()>(vec![1, 2, 3])));
+        let mut poll = || stream.poll_stream_notify(&notify, 0).unwrap();
+        let first = match poll() {
+            Async::Ready(Some((1, guard))) => guard,
+            _ => panic!("first connection is accepted"),
+        };
+        let _second = match poll() {
+            Async::Ready(Some((2, guard))) => guard,
+            _ => panic!("second connection is accepted"),
+        };
+        assert_eq!(limit.connections(), 2);
+        assert!(poll().is_not_ready());
+        // task is parked only once
+        assert!(poll().is_not_ready());
+        assert_eq!(notify.0.load(Ordering::SeqCst), 0);
+
+        drop(first);
+        assert_eq!(limit.connections(), 1);
+        assert_eq!(notify.0.load(Ordering::SeqCst), 1);
+        match poll() {
+            Async::Ready(Some((3, _guard))) => {
+                assert_eq!(limit.connections(), 2);
+            }
+            _ => panic!("third connection is accepted"),
+        }
+    }
+
+    #[test]
+    fn idle_listener() {
+        let notify = Arc::new(Counter(AtomicUsize::new(0)));
+        let limit = ConnectionLimit::new(1);
+        let mut stream = spawn(limit.limit(
+            poll_fn(|| Ok::<_, ()>(Async::NotReady::<Option<u32>>))));
+        assert!(stream.poll_stream_notify(&notify, 0).unwrap()
+                .is_not_ready());
+        // no slot is held while waiting for a connection
+        assert_eq!(limit.connections(), 0);
+        let guard = match limit.poll_acquire() {
+            Async::Ready(guard) => guard,
+            Async::NotReady => panic!("slot is free"),
+        };
+        assert_eq!(limit.connections(), 1);
+        drop(guard);
+    }
+}
@@ -9,19 +9,45 @@ mod request_target;
 mod headers;
 mod websocket;
 mod recv_mode;
+mod buffer_pool;
+mod allow;
+mod cors;
+mod connection_data;
+mod proxy;
+mod limit;
+mod echo;
+mod security;
+#[cfg(feature="date_header")] mod conditional;
 pub mod buffered;
+#[cfg(feature="tower")] pub mod tower;
 
 pub use self::error::Error;
+pub use self::buffer_pool::BufferPool;
+pub use self::allow::{Allow, allow_header};
+pub use self::cors::{Cors, Origin};
+pub use self::security::{SecurityHeaders, FrameOptions};
+pub use self::connection_data::ConnectionData;
+pub use self::proxy::{ResponsePump, PumpDone};
+pub use self::proxy::{RequestPump, RequestStart, RequestDone};
+pub use self::limit::{ConnectionLimit, ConnectionGuard, Limited};
+pub use self::limit::{Filtered, filter_peers};
+pub use self::echo::{Echo, EchoCodec, EchoResponse};
 pub use self::encoder::{Encoder, EncoderDone};
 pub use self::encoder::{WaitFlush, FutureRawBody, RawBody};
-pub use self::codec::{Codec, Dispatcher};
+pub use self::codec::{Codec, Dispatcher, BodyKind};
 pub use self::proto::Proto;
 pub use self::headers::{Head, HeaderIter};
-pub use self::request_target::RequestTarget;
+pub use self::request_target::{RequestTarget, normalize_path};
 pub use self::websocket::{WebsocketHandshake};
+#[cfg(feature="date_header")]
+pub use self::conditional::{Preconditions, Precondition};
 
+use std::sync::Arc;
 use std::time::Duration;
 
+use {Status};
+use header_hook::HeaderHook;
+
 
 /// Fine-grained configuration of the HTTP server
 #[derive(Debug, Clone)]
@@ -35,6 +61,27 @@ pub struct Config {
     input_body_whole_timeout: Duration,
     output_body_byte_timeout: Duration,
     output_body_whole_timeout: Duration,
+    no_delay_on_last_write: bool,
+    handler_timeout: Option<Duration>,
+    handler_timeout_status: Status,
+    disable_pipelining: bool,
+    respect_request_deadline: bool,
+    normalize_path: bool,
+    max_chunk_size: usize,
+    lenient_request_line: bool,
+    reject_unknown_expect: bool,
+    default_content_type: Option<Arc<str>>,
+    max_header_value_size: usize,
+    keep_alive_header: bool,
+    date_header: bool,
+    header_hook: Option<HeaderHook>,
+    lenient_chunk_size: bool,
+    max_requests_per_connection: Option<usize>,
+    reject_conflicting_host: bool,
+    request_timeout: Option<Duration>,
+    body_read_idle_timeout: Option<Duration>,
+    reply_to_trace: bool,
+    max_body_drain: usize,
 }
 
 /// This type is returned from `headers_received` handler of either
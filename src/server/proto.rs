@@ -1,19 +1,23 @@
 use std::mem;
 use std::sync::Arc;
 use std::collections::VecDeque;
-use std::time::Instant;
+use std::cmp::min;
+use std::time::{Duration, Instant};
+use std::sync::Mutex;
 
 use futures::{Future, Poll, Async};
 use tk_bufstream::{IoBuf, WriteBuf, ReadBuf};
 use tokio_io::{AsyncRead, AsyncWrite};
 use tokio_core::reactor::{Handle, Timeout};
 
-use super::encoder::{self, get_inner, ResponseConfig};
+use super::encoder::{self, get_inner, ResponseConfig, Reclaim};
+use super::Encoder;
 use super::{Dispatcher, Codec, Config};
-use super::headers::parse_headers;
+use super::headers::{parse_headers, Parsed};
 use super::codec::BodyKind;
 use server::error::{ErrorEnum, Error};
 use server::recv_mode::{Mode, get_mode};
+use enums::{Status, Version};
 use chunked;
 use body_parser::BodyProgress;
 
@@ -30,6 +34,22 @@ struct BodyState<C> {
     progress: BodyProgress,
     response_config: ResponseConfig,
     codec: C,
+    /// `100 Continue` is not sent yet, so body can't be read
+    continue_pending: bool,
+    /// Codec returned `NotReady`, so no more data is read until it
+    /// consumes some of the buffered body
+    paused: bool,
+    /// `start_response` is already called (progressive mode only), so the
+    /// codec is not queued when the body is received
+    response_started: bool,
+}
+
+/// A reply that protocol sends itself, after all pipelined responses
+enum Reply {
+    /// Error status, connection is closed after it
+    Reject(Status),
+    /// Reply to a `TRACE` request, the body is the request message
+    Trace(Vec<u8>),
 }
 
 enum InState<C> {
@@ -37,6 +57,9 @@ enum InState<C> {
     KeepAlive,
     Headers,
     Body(BodyState<C>),
+    /// Discarding body of the request with `RecvMode::ignore_body()`,
+    /// up to a number of bytes
+    Drain(BodyProgress, u64),
     Hijack,
     Closed,
 }
@@ -53,7 +76,17 @@ pub struct PureProto<S, D: Dispatcher<S>> {
     last_byte_written: Instant,
     /// Long-term deadline for reading (headers- or input body_whole- timeout)
     read_deadline: Instant,
+    /// Deadline of the whole request (if `request_timeout` is configured)
+    request_deadline: Option<Instant>,
     response_deadline: Instant,
+    /// Deadline of the handler (if `handler_timeout` is configured)
+    handler_deadline: Option<(Instant, ResponseConfig)>,
+    /// Slot where encoder returns the buffer when handler is dropped
+    reclaim: Option<Reclaim<S>>,
+    /// Response to send after all pipelined responses, before closing
+    reject: Option<(Reply, ResponseConfig)>,
+    /// Number of requests parsed on this connection
+    requests_received: usize,
 }
 
 /// A low-level HTTP/1.x server protocol handler
@@ -63,20 +96,22 @@ pub struct Proto<S, D: Dispatcher<S>> {
     timeout: Timeout,
 }
 
-fn new_body(mode: BodyKind, recv_mode: Mode)
+fn new_body(mode: BodyKind, recv_mode: Mode, cfg: &Config)
     -> Result<BodyProgress, ErrorEnum>
 {
     use super::codec::BodyKind as B;
     use super::recv_mode::Mode as M;
     use body_parser::BodyProgress as P;
     match (mode, recv_mode) {
-        // TODO(tailhook) check size < usize
         (B::Unsupported, _) => Err(ErrorEnum::UnsupportedBody),
         (B::Fixed(x), M::BufferedUpfront(b)) if x > b as u64 => {
             Err(ErrorEnum::RequestTooLong)
         }
-        (B::Fixed(x), _)  => Ok(P::Fixed(x as usize)),
-        (B::Chunked, _) => Ok(P::Chunked(chunked::State::new())),
+        (B::Fixed(x), _)  => Ok(P::Fixed(x)),
+        (B::Chunked, _) => Ok(P::Chunked(
+            chunked::State::with_max_chunk_size(cfg.max_chunk_size)
+            .max_trailer_value_size(cfg.max_header_value_size)
+            .lenient(cfg.lenient_chunk_size))),
     }
 }
 
@@ -97,6 +132,62 @@ impl<S: AsyncRead+AsyncWrite, D: Dispatcher<S>> Proto<S, D> {
     }
 }
 
+/// Returns true if connection is closed after response to this request
+fn will_close(rc: &ResponseConfig) -> bool {
+    rc.do_close || rc.version == Version::Http10
+}
+
+/// Writes an empty response with specified status and `Connection: close`
+fn reply_and_close<S>(io: WriteBuf<S>, rc: ResponseConfig, status: Status)
+    -> WriteBuf<S>
+{
+    let mut e = encoder::new(io, ResponseConfig { do_close: true, ..rc });
+    e.status(status);
+    e.add_length(0).expect("can add length");
+    e.done_headers().expect("can finish headers");
+    get_inner(e.done())
+}
+
+/// Writes a reply to `TRACE` request and `Connection: close`
+fn reply_trace<S>(io: WriteBuf<S>, rc: ResponseConfig, message: &[u8])
+    -> WriteBuf<S>
+{
+    let mut e = encoder::new(io, ResponseConfig { do_close: true, ..rc });
+    e.status(Status::Ok);
+    e.add_header("Content-Type", "message/http")
+        .expect("can add content type");
+    e.add_length(message.len() as u64).expect("can add length");
+    e.done_headers().expect("can finish headers");
+    e.write_body(message);
+    get_inner(e.done())
+}
+
+/// Creates an encoder for the response with the settings from the config
+fn response_encoder<S>(io: WriteBuf<S>, rc: ResponseConfig, config: &Config,
+    reclaim: &Option<Reclaim<S>>, hijack: bool)
+    -> Encoder<S>
+{
+    let mut e = match *reclaim {
+        Some(ref slot) => encoder::new_reclaimable(io, rc, slot),
+        None => encoder::new(io, rc),
+    };
+    encoder::set_default_content_type(&mut e,
+        config.default_content_type.clone());
+    encoder::set_date_header(&mut e, config.date_header);
+    encoder::set_header_hook(&mut e, config.header_hook.clone());
+    encoder::set_keep_alive_header(&mut e,
+        config.keep_alive_header && !hijack);
+    e
+}
+
+fn handler_timeout(cfg: &Config, rc: &ResponseConfig) -> Option<Duration> {
+    let client = if cfg.respect_request_deadline { rc.deadline } else { None };
+    match (cfg.handler_timeout, client) {
+        (Some(server), Some(client)) => Some(min(server, client)),
+        (server, client) => server.or(client),
+    }
+}
+
 impl<S, D: Dispatcher<S>> PureProto<S, D> {
     pub fn new(conn: S, cfg: &Arc<Config>, dispatcher: D)
         -> PureProto<S, D>
@@ -115,7 +206,18 @@ impl<S, D: Dispatcher<S>> PureProto<S, D> {
             last_byte_read: Instant::now(),
             last_byte_written: Instant::now(),
             read_deadline: Instant::now() + cfg.first_byte_timeout,
+            request_deadline: None,
             response_deadline: Instant::now(),  // irrelevant at start
+            handler_deadline: None,
+            reclaim: if cfg.handler_timeout.is_some() ||
+                        cfg.respect_request_deadline
+            {
+                Some(Arc::new(Mutex::new(None)))
+            } else {
+                None
+            },
+            reject: None,
+            requests_received: 0,
         }
     }
     /// Resturns Ok(true) if new data has been read
@@ -132,36 +234,119 @@ impl<S, D: Dispatcher<S>> PureProto<S, D> {
             return Ok(false);
         };
         loop {
+            // the request being read (or the next one) needs a slot
             let limit = match self.reading {
-                Headers| Connected | KeepAlive
+                // response being written belongs to the request being read
+                Body(BodyState { response_started: true, .. })
                 => self.config.inflight_request_limit,
-                Body(..) => self.config.inflight_request_limit-1,
+                Headers| Connected | KeepAlive | Body(..)
+                => self.config.inflight_request_limit.saturating_sub(1),
+                // request being drained is already in `waiting`
+                Drain(..) => self.config.inflight_request_limit,
                 Closed | Hijack => return Ok(changed),
             };
-            if self.waiting.len() <= limit {
+            // the response being written is not in `waiting` anymore
+            let writing = match self.writing {
+                OutState::Write(..) | OutState::Switch(..) => 1,
+                OutState::Idle(..) | OutState::Void => 0,
+            };
+            // don't parse next pipelined request if all slots are busy
+            let full = self.waiting.len() + writing >=
+                self.config.inflight_request_limit;
+            // when pipelining is disabled, next request is only read
+            // after previous response is fully flushed
+            let blocked = self.config.disable_pipelining &&
+                (self.waiting.len() > 0 ||
+                 !matches!(self.writing, OutState::Idle(ref io)
+                           if io.out_buf.len() == 0));
+            let paused = matches!(self.reading,
+                                  Body(BodyState { paused: true, .. }));
+            if self.waiting.len() + writing <= limit && !paused &&
+                !(blocked && matches!(self.reading, KeepAlive))
+            {
                 // TODO(tailhook) Do reads after parse_headers() [optimization]
                 if inbuf.read().map_err(ErrorEnum::Io)? > 0 {
                     self.last_byte_read = Instant::now();
                 }
             }
+            if matches!(self.reading,
+                        Body(BodyState { continue_pending: true, .. }))
+            {
+                // wait until `100 Continue` is sent by `do_writes`
+                break;
+            }
             let (next, cont) = match mem::replace(&mut self.reading, Closed) {
-                KeepAlive | Connected if inbuf.in_buf.len() > 0 => {
+                KeepAlive | Connected
+                if inbuf.in_buf.len() > 0 && !blocked && !full
+                => {
                     self.read_deadline = Instant::now()
                         + self.config.headers_timeout;
+                    self.request_deadline = self.config.request_timeout
+                        .map(|timeo| Instant::now() + timeo);
                     (Headers, true)
                 }
                 Connected => (Connected, false),
                 KeepAlive => (KeepAlive, false),
                 Headers => {
-                    match parse_headers(&mut inbuf.in_buf,
-                                        &mut self.dispatcher)?
-                    {
-                        Some((body, mut codec, cfg)) => {
+                    let received = self.requests_received;
+                    let remaining = self.config.max_requests_per_connection
+                        .map(|x| x.saturating_sub(received + 1));
+                    let parsed = parse_headers(&mut inbuf.in_buf,
+                                               &mut self.dispatcher,
+                                               &self.config, remaining)?;
+                    if parsed.is_some() {
+                        self.requests_received += 1;
+                    }
+                    match parsed {
+                        Some(Parsed::Reject(status, cfg)) => {
+                            changed = true;
+                            // Don't read anything else, as we don't know
+                            // whether client sends request body or not
+                            self.reject = Some((Reply::Reject(status), cfg));
+                            (Closed, false)
+                        }
+                        Some(Parsed::Trace(message, cfg)) => {
+                            changed = true;
+                            // Same as above, there might be a body
+                            self.reject = Some((Reply::Trace(message), cfg));
+                            (Closed, false)
+                        }
+                        Some(Parsed::Request(body, mut codec, cfg,
+                                             send_continue))
+                        => {
                             changed = true;
                             let mode = codec.recv_mode();
                             if get_mode(&mode) == Mode::Hijack {
                                 self.waiting.push_back((cfg, codec));
                                 (Hijack, true)
+                            } else if get_mode(&mode) == Mode::Reject {
+                                // Body is not read, so we can't continue
+                                // with the next request
+                                let cfg = ResponseConfig {
+                                    do_close: true, ..cfg };
+                                self.waiting.push_back((cfg, codec));
+                                (Closed, false)
+                            } else if get_mode(&mode) == Mode::Ignore {
+                                let progress = new_body(body, Mode::Ignore,
+                                                        &self.config)?;
+                                let limit = self.config.max_body_drain as u64;
+                                if will_close(&cfg) || send_continue ||
+                                    matches!(progress,
+                                             BodyProgress::Fixed(x)
+                                             if x > limit)
+                                {
+                                    // Body is not read, so we can't continue
+                                    // with the next request
+                                    let cfg = ResponseConfig {
+                                        do_close: true, ..cfg };
+                                    self.waiting.push_back((cfg, codec));
+                                    (Closed, false)
+                                } else {
+                                    self.waiting.push_back((cfg, codec));
+                                    self.read_deadline = Instant::now() +
+                                        self.config.input_body_whole_timeout;
+                                    (Drain(progress, limit), true)
+                                }
                             } else {
                                 let timeo = mode.timeout.unwrap_or(
                                     self.config.input_body_whole_timeout);
@@ -169,11 +354,29 @@ impl<S, D: Dispatcher<S>> PureProto<S, D> {
                                 (Body(BodyState {
                                     mode: get_mode(&mode),
                                     response_config: cfg,
-                                    progress: new_body(body, get_mode(&mode))?,
-                                    codec: codec }),
+                                    progress: new_body(body, get_mode(&mode),
+                                                      &self.config)?,
+                                    codec: codec,
+                                    continue_pending: send_continue,
+                                    paused: false,
+                                    response_started: false }),
                                  true)
                             }
                         }
+                        None if inbuf.done() => {
+                            if inbuf.in_buf[..].iter()
+                                .all(|&c| c == b'\r' || c == b'\n')
+                            {
+                                // only empty lines between requests, so
+                                // client closed an idle connection
+                                (Closed, false)
+                            } else {
+                                // connection is closed in the middle of
+                                // headers
+                                return Err(
+                                    ErrorEnum::ConnectionReset.into());
+                            }
+                        }
                         None => (Headers, false),
                     }
                 }
@@ -195,27 +398,54 @@ impl<S, D: Dispatcher<S>> PureProto<S, D> {
                     match operation {
                         Some(Async::Ready(consumed)) => {
                             body.progress.consume(inbuf, consumed);
+                            body.paused = false;
                             if done && consumed == bytes {
                                 changed = true;
-                                self.waiting.push_back(
-                                    (body.response_config, body.codec));
+                                let close = will_close(&body.response_config);
+                                if !body.response_started {
+                                    self.waiting.push_back(
+                                        (body.response_config, body.codec));
+                                }
                                 self.read_deadline = Instant::now()
                                     + self.config.keep_alive_timeout;
-                                (KeepAlive, true)
+                                if close {
+                                    // Connection is closed after response,
+                                    // so pipelined requests are not read
+                                    (Closed, false)
+                                } else {
+                                    (KeepAlive, true)
+                                }
                             } else {
                                 (Body(body), true) // TODO(tailhook) check
                             }
                         }
                         Some(Async::NotReady) => {
-                            if matches!(body.mode, Mode::Progressive(x) if x > bytes) {
-                                (Body(body), false)
-                            } else {
-                                (Body(body), true) // TODO(tailhook) check
-                            }
+                            // codec wakes up the task when it's ready
+                            body.paused = true;
+                            (Body(body), false)
                         }
                         None => (Body(body), false),
                     }
                 }
+                Drain(mut progress, limit) => {
+                    progress.parse(inbuf)
+                        .map_err(ErrorEnum::ChunkParseError)?;
+                    let (bytes, done) = progress.check_buf(inbuf);
+                    progress.consume(inbuf, bytes);
+                    if bytes as u64 > limit {
+                        debug!("Unread request body is too large, \
+                                closing connection");
+                        (Closed, false)
+                    } else if done {
+                        self.read_deadline = Instant::now()
+                            + self.config.keep_alive_timeout;
+                        (KeepAlive, true)
+                    } else if inbuf.done() {
+                        (Closed, false)
+                    } else {
+                        (Drain(progress, limit - bytes as u64), false)
+                    }
+                }
                 Hijack => (Hijack, false),
                 Closed => unreachable!(),
             };
@@ -226,6 +456,56 @@ impl<S, D: Dispatcher<S>> PureProto<S, D> {
         }
         Ok(changed)
     }
+    /// Replies with error status if handler hasn't started the response
+    ///
+    /// Must be called after handler's future is dropped
+    fn handler_timed_out(&mut self, rc: ResponseConfig)
+        -> Result<WriteBuf<S>, Error>
+    {
+        let reclaimed = self.reclaim.as_ref()
+            .and_then(|slot| slot.lock()
+                .expect("reclaim lock is not poisoned")
+                .take());
+        match reclaimed {
+            Some((io, false)) => {
+                debug!("Handler timed out, replying with {:?}",
+                    self.config.handler_timeout_status);
+                // Don't read and don't reply on any more requests
+                self.waiting.clear();
+                self.reject = None;
+                self.reading = InState::Closed;
+                Ok(reply_and_close(io, rc, self.config.handler_timeout_status))
+            }
+            _ => Err(ErrorEnum::HandlerTimeout.into()),
+        }
+    }
+    /// Replies `408 Request Timeout` if request is not received in time
+    fn check_request_timeout(&mut self) {
+        use self::InState::*;
+        if !matches!(self.reading, Headers | Body(..)) {
+            return;
+        }
+        match self.request_deadline {
+            Some(deadline) if Instant::now() >= deadline => {}
+            _ => return,
+        }
+        debug!("Request is not received in time, replying with 408");
+        let rc = match mem::replace(&mut self.reading, Closed) {
+            Body(body) => body.response_config,
+            _ => ResponseConfig {
+                is_head: false,
+                do_close: true,
+                version: Version::Http11,
+                deadline: None,
+                continue_sent: false,
+            },
+        };
+        self.reject = Some((Reply::Reject(Status::RequestTimeout), rc));
+        self.request_deadline = None;
+        // time to send the response
+        self.read_deadline = Instant::now()
+            + self.config.output_body_whole_timeout;
+    }
     fn do_writes(&mut self) -> Result<(), Error>
         where S: AsyncWrite
     {
@@ -236,7 +516,13 @@ impl<S, D: Dispatcher<S>> PureProto<S, D> {
             let (next, cont) = match mem::replace(&mut self.writing, Void) {
                 Idle(mut io) => {
                     let old_len = io.out_buf.len();
-                    if old_len > 0 {
+                    // a handler may take a while to produce the next
+                    // response, so finished responses never wait for it,
+                    // only replies generated by the protocol itself may
+                    // share a write with them
+                    let coalesce = !self.config.no_delay_on_last_write
+                        && self.waiting.len() == 0 && self.reject.is_some();
+                    if old_len > 0 && !coalesce {
                         io.flush().map_err(ErrorEnum::Io)?;
                         if io.out_buf.len() < old_len {
                             self.last_byte_written = Instant::now();
@@ -246,31 +532,71 @@ impl<S, D: Dispatcher<S>> PureProto<S, D> {
                     if let Some((rc, mut codec)) = self.waiting.pop_front() {
                         self.response_deadline = Instant::now()
                             + self.config.output_body_whole_timeout;
-                        let e = encoder::new(io, rc);
+                        let e = response_encoder(io, rc, &self.config,
+                            &self.reclaim, matches!(self.reading, Hijack));
+                        self.handler_deadline = handler_timeout(
+                                &self.config, &rc)
+                            .map(|timeo| (Instant::now() + timeo, rc));
                         if matches!(self.reading, Hijack) {
                             (Switch(codec.start_response(e), codec), true)
                         } else {
                             (Write(codec.start_response(e)), true)
                         }
+                    } else if let Some((reply, rc)) = self.reject.take() {
+                        match reply {
+                            Reply::Trace(message) => {
+                                (Idle(reply_trace(io, rc, &message)), true)
+                            }
+                            Reply::Reject(status) => {
+                                (Idle(reply_and_close(io, rc, status)), true)
+                            }
+                        }
+                    } else if matches!(self.reading,
+                        Body(BodyState { continue_pending: true, .. }))
+                    {
+                        // all previous responses are sent, so we can
+                        // let client send the body
+                        if let Body(ref mut body) = self.reading {
+                            body.continue_pending = false;
+                            body.response_config.continue_sent = true;
+                        }
+                        io.out_buf.extend(b"HTTP/1.1 100 Continue\r\n\r\n");
+                        io.flush().map_err(ErrorEnum::Io)?;
+                        self.last_byte_written = Instant::now();
+                        (Idle(io), false)
                     } else {
                         match self.reading {
+                            Body(ref mut body)
+                            if matches!(body.mode, Progressive(_)) &&
+                                !body.response_started
+                            => {
+                                // response is started while the body is
+                                // still being received by the same codec
+                                self.response_deadline = Instant::now()
+                                    + self.config.output_body_whole_timeout;
+                                let rc = body.response_config;
+                                let e = response_encoder(io, rc,
+                                    &self.config, &self.reclaim, false);
+                                self.handler_deadline = handler_timeout(
+                                        &self.config, &rc)
+                                    .map(|timeo| (Instant::now() + timeo, rc));
+                                body.response_started = true;
+                                // codec may wait for the response to start
+                                body.paused = false;
+                                (Write(body.codec.start_response(e)), true)
+                            }
                             Body(BodyState { mode: BufferedUpfront(..), ..})
+                            | Body(BodyState { mode: Progressive(_), ..})
+                            | Drain(..)
                             | Closed | Headers | Connected | KeepAlive
                             => {
                                 (Idle(io), false)
                             }
-                            Body(BodyState { mode: Mode::Hijack, ..}) => {
+                            Body(BodyState { mode: Mode::Hijack, ..})
+                            | Body(BodyState { mode: Mode::Reject, ..})
+                            | Body(BodyState { mode: Mode::Ignore, ..}) => {
                                 unreachable!();
                             }
-                            Body(BodyState {
-                                mode: Progressive(_),
-                                codec: ref mut _codec, ..})
-                            => {
-                                self.response_deadline = Instant::now()
-                                    + self.config.output_body_whole_timeout;
-                                // TODO(tailhook) start writing now
-                                unimplemented!();
-                            }
                             Hijack => unreachable!(),
                         }
                     }
@@ -278,12 +604,46 @@ impl<S, D: Dispatcher<S>> PureProto<S, D> {
                 Write(mut f) => {
                     match f.poll()? {
                         Async::Ready(x) => {
-                            self.read_deadline = Instant::now()
-                                + self.config.keep_alive_timeout;
-                            (Idle(get_inner(x)), true)
+                            // body of a progressive request may still be
+                            // read, which has its own deadline
+                            if !matches!(self.reading, Body(..)) {
+                                self.read_deadline = Instant::now()
+                                    + self.config.keep_alive_timeout;
+                            }
+                            if encoder::is_aborted(&x) {
+                                debug!("Response aborted, closing connection");
+                                // Don't read and don't reply on any
+                                // more requests
+                                self.waiting.clear();
+                                self.reject = None;
+                                self.reading = InState::Closed;
+                            } else if encoder::will_close(&x) {
+                                debug!("Connection: close set by handler");
+                                self.waiting.clear();
+                                self.reject = None;
+                                self.reading = InState::Closed;
+                            }
+                            let mut io = get_inner(x);
+                            if self.config.no_delay_on_last_write {
+                                // flush final bytes of the response right
+                                // away, don't wait for the next response
+                                io.flush().map_err(ErrorEnum::Io)?;
+                                self.last_byte_written = Instant::now();
+                            }
+                            self.handler_deadline = None;
+                            (Idle(io), true)
                         }
                         Async::NotReady => {
-                            (Write(f), false)
+                            match self.handler_deadline {
+                                Some((deadline, rc))
+                                if Instant::now() >= deadline
+                                => {
+                                    drop(f);
+                                    self.handler_deadline = None;
+                                    (Idle(self.handler_timed_out(rc)?), true)
+                                }
+                                _ => (Write(f), false),
+                            }
                         }
                     }
                 }
@@ -314,10 +674,30 @@ impl<S, D: Dispatcher<S>> PureProto<S, D> {
 impl<S: AsyncRead+AsyncWrite, D: Dispatcher<S>> PureProto<S, D> {
     /// Does all needed processing and returns Ok(true) if connection is fine
     /// and Ok(false) if it needs to be closed
-    fn process(&mut self) -> Result<bool, Error> {
+    pub(crate) fn process(&mut self) -> Result<bool, Error> {
+        self.check_request_timeout();
         self.do_writes()?;
-        while self.do_reads()? {
-            self.do_writes()?;
+        loop {
+            while self.do_reads()? {
+                self.do_writes()?;
+            }
+            // finished responses may free a slot for a pipelined request
+            // which is already in the buffer
+            let buffered = self.inbuf.as_ref()
+                .map(|x| x.in_buf.len() > 0).unwrap_or(false);
+            if !(buffered && matches!(self.reading, InState::KeepAlive) &&
+                 self.in_flight() < self.config.inflight_request_limit &&
+                 !self.config.disable_pipelining)
+            {
+                break;
+            }
+        }
+        if matches!(self.reading, InState::Closed) &&
+            matches!(self.writing, OutState::Idle(ref io)
+                     if io.out_buf.len() == 0)
+        {
+            // Connection is closed after the last response is sent
+            return Ok(false);
         }
         if self.inbuf.as_ref().map(|x| x.done()).unwrap_or(true) {
             Ok(false)
@@ -325,12 +705,25 @@ impl<S: AsyncRead+AsyncWrite, D: Dispatcher<S>> PureProto<S, D> {
             Ok(true)
         }
     }
+    /// Number of requests whose codecs are alive (waiting or writing)
+    fn in_flight(&self) -> usize {
+        self.waiting.len() + match self.writing {
+            OutState::Write(..) | OutState::Switch(..) => 1,
+            OutState::Idle(..) | OutState::Void => 0,
+        }
+    }
     fn timeout(&mut self) -> Option<Instant> {
         use self::OutState::*;
 
         match self.writing {
             Idle(..) => {}
-            Write(..) => return Some(self.response_deadline),
+            Write(..) => {
+                return Some(match self.handler_deadline {
+                    Some((deadline, _)) if deadline < self.response_deadline
+                    => deadline,
+                    _ => self.response_deadline,
+                });
+            }
             Switch(..) => return None,  // TODO(tailhook) is it right?
             Void => return None,  // TODO(tailhook) is it reachable?
         }
@@ -338,7 +731,19 @@ impl<S: AsyncRead+AsyncWrite, D: Dispatcher<S>> PureProto<S, D> {
                                     // we don't have a read timeout
             return None;
         }
-        return Some(self.read_deadline);
+        let deadline = match self.config.body_read_idle_timeout {
+            // when body is being received, client isn't allowed to stall
+            Some(timeo) if matches!(self.reading,
+                                    InState::Body(..) | InState::Drain(..))
+            => min(self.read_deadline, self.last_byte_read + timeo),
+            _ => self.read_deadline,
+        };
+        match self.request_deadline {
+            Some(request)
+            if matches!(self.reading, InState::Headers | InState::Body(..))
+            => Some(min(deadline, request)),
+            _ => Some(deadline),
+        }
     }
 }
 
@@ -385,15 +790,29 @@ impl<S: AsyncRead+AsyncWrite, D: Dispatcher<S>> Future for Proto<S, D> {
 
 #[cfg(test)]
 mod test {
-    use std::sync::Arc;
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+    use std::cell::RefCell;
+    use std::rc::Rc;
     use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread::sleep;
+    use std::time::{Duration, Instant};
 
-    use futures::{Empty, Async, empty};
-    use tk_bufstream::{MockData, ReadBuf, WriteBuf};
+    use futures::{Future, Empty, Async, Poll, empty};
+    use futures::future::{ok, lazy, Either, FutureResult};
+    use futures::sync::oneshot::{channel, Sender, Receiver};
+    use tk_bufstream::{Buf, MockData, ReadBuf, WriteBuf};
+    use tokio_io::{AsyncRead, AsyncWrite};
 
-    use super::PureProto;
+    use tokio_core::reactor::Core;
+
+    use super::{Proto, PureProto, InState};
+    use chunked;
     use server::{Config, Dispatcher, Codec};
     use server::{Head, RecvMode, Error, Encoder, EncoderDone};
+    use server::error::ErrorEnum;
+    use test_util::Reset;
+    use {Status, Method};
 
     struct MockDisp<'a> {
         counter: &'a AtomicUsize,
@@ -432,6 +851,39 @@ mod test {
         }
     }
 
+    #[cfg(feature="http_types")]
+    struct HttpDisp<'a> {
+        counter: &'a AtomicUsize,
+        requests: &'a RefCell<Vec<::http::Request<()>>>,
+    }
+
+    #[cfg(feature="http_types")]
+    impl<'a> Dispatcher<MockData> for HttpDisp<'a> {
+        type Codec = MockCodec<'a>;
+
+        fn headers_received(&mut self, headers: &Head)
+            -> Result<Self::Codec, Error>
+        {
+            self.requests.borrow_mut().push(
+                headers.to_http_request().map_err(Error::custom)?);
+            Ok(MockCodec { counter: self.counter })
+        }
+    }
+
+    #[cfg(feature="http_types")]
+    fn parse_http_request(data: &[u8]) -> ::http::Request<()> {
+        let counter = AtomicUsize::new(0);
+        let requests = RefCell::new(Vec::new());
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(), &Config::new().done(),
+            HttpDisp { counter: &counter, requests: &requests });
+        mock.add_input(data);
+        proto.process().unwrap();
+        let mut requests = requests.into_inner();
+        assert_eq!(requests.len(), 1);
+        requests.pop().unwrap()
+    }
+
     impl<'a> Codec<MockData> for MockCodec<'a> {
         type ResponseFuture = Empty<EncoderDone<MockData>, Error>;
         fn recv_mode(&mut self) -> RecvMode {
@@ -456,6 +908,1163 @@ mod test {
         }
     }
 
+    /// Replies to every request with the response written by `F`
+    ///
+    /// Request body is received in the specified mode and discarded.
+    struct RespondDisp<F> {
+        mode: RecvMode,
+        respond: Rc<RefCell<F>>,
+    }
+
+    struct RespondCodec<F> {
+        mode: RecvMode,
+        respond: Rc<RefCell<F>>,
+    }
+
+    fn respond<S, F, R>(mode: RecvMode, respond: F) -> RespondDisp<F>
+        where F: FnMut(Encoder<S>) -> R,
+    {
+        RespondDisp { mode: mode, respond: Rc::new(RefCell::new(respond)) }
+    }
+
+    fn buffered() -> RecvMode {
+        RecvMode::buffered_upfront(1024)
+    }
+
+    impl<S, F, R> Dispatcher<S> for RespondDisp<F>
+        where F: FnMut(Encoder<S>) -> R,
+              R: Future<Item=EncoderDone<S>, Error=Error>,
+    {
+        type Codec = RespondCodec<F>;
+
+        fn headers_received(&mut self, _headers: &Head)
+            -> Result<Self::Codec, Error>
+        {
+            Ok(RespondCodec { mode: self.mode.clone(),
+                              respond: self.respond.clone() })
+        }
+    }
+
+    impl<S, F, R> Codec<S> for RespondCodec<F>
+        where F: FnMut(Encoder<S>) -> R,
+              R: Future<Item=EncoderDone<S>, Error=Error>,
+    {
+        type ResponseFuture = R;
+        fn recv_mode(&mut self) -> RecvMode {
+            self.mode.clone()
+        }
+        fn data_received(&mut self, data: &[u8], end: bool)
+            -> Result<Async<usize>, Error>
+        {
+            assert!(end);
+            Ok(Async::Ready(data.len()))
+        }
+        fn start_response(&mut self, e: Encoder<S>) -> R {
+            (&mut *self.respond.borrow_mut())(e)
+        }
+    }
+
+    /// Replies `200 OK` with an empty body
+    fn empty_response<S>(mut e: Encoder<S>)
+        -> FutureResult<EncoderDone<S>, Error>
+    {
+        e.status(Status::Ok);
+        e.add_length(0).unwrap();
+        e.done_headers().unwrap();
+        ok(e.done())
+    }
+
+    /// Replies with a tiny response
+    fn small_response<S>(mut e: Encoder<S>)
+        -> FutureResult<EncoderDone<S>, Error>
+    {
+        e.status(Status::Ok);
+        e.add_length(5).unwrap();
+        e.done_headers().unwrap();
+        e.write_body(b"hello");
+        ok(e.done())
+    }
+
+    /// Starts processing of every request as soon as headers are received,
+    /// the response body is sent later via `Sender`
+    struct DeferredDisp(Arc<Mutex<Vec<Sender<&'static str>>>>);
+
+    struct DeferredCodec(Option<Receiver<&'static str>>);
+
+    impl Dispatcher<MockData> for DeferredDisp {
+        type Codec = DeferredCodec;
+
+        fn headers_received(&mut self, _headers: &Head)
+            -> Result<Self::Codec, Error>
+        {
+            let (tx, rx) = channel();
+            self.0.lock().unwrap().push(tx);
+            Ok(DeferredCodec(Some(rx)))
+        }
+    }
+
+    impl Codec<MockData> for DeferredCodec {
+        type ResponseFuture = Box<Future<Item=EncoderDone<MockData>,
+                                         Error=Error>>;
+        fn recv_mode(&mut self) -> RecvMode {
+            RecvMode::buffered_upfront(1024)
+        }
+        fn data_received(&mut self, data: &[u8], end: bool)
+            -> Result<Async<usize>, Error>
+        {
+            assert!(end);
+            Ok(Async::Ready(data.len()))
+        }
+        fn start_response(&mut self, mut e: Encoder<MockData>)
+            -> Self::ResponseFuture
+        {
+            Box::new(self.0.take().unwrap().map_err(Error::custom)
+                .map(move |body| {
+                    e.status(Status::Ok);
+                    e.add_length(body.len() as u64).unwrap();
+                    e.done_headers().unwrap();
+                    e.write_body(body.as_bytes());
+                    e.done()
+                }))
+        }
+    }
+
+    /// Records request budget of every request, replies with `D`
+    struct BudgetDisp<D>(Vec<(Option<usize>, bool)>, D);
+
+    impl<S, D: Dispatcher<S>> Dispatcher<S> for BudgetDisp<D> {
+        type Codec = D::Codec;
+
+        fn headers_received(&mut self, headers: &Head)
+            -> Result<Self::Codec, Error>
+        {
+            self.0.push((headers.requests_remaining(),
+                         headers.is_last_request()));
+            self.1.headers_received(headers)
+        }
+    }
+
+    /// Dispatcher for tests that never receive a complete request
+    struct NoDisp;
+
+    impl Dispatcher<Reset> for NoDisp {
+        type Codec = Box<Codec<Reset,
+            ResponseFuture=Empty<EncoderDone<Reset>, Error>>>;
+
+        fn headers_received(&mut self, _headers: &Head)
+            -> Result<Self::Codec, Error>
+        {
+            unreachable!();
+        }
+    }
+
+    /// Counts write calls (i.e. syscalls) made to the mock
+    struct CountWrites(MockData, Arc<AtomicUsize>);
+
+    impl io::Read for CountWrites {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl AsyncRead for CountWrites {}
+
+    impl io::Write for CountWrites {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            self.0.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl AsyncWrite for CountWrites {
+        fn shutdown(&mut self) -> Poll<(), io::Error> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    fn count_writes(cfg: &Arc<Config>, input: &str) -> (usize, String) {
+        let mock = MockData::new();
+        let writes = Arc::new(AtomicUsize::new(0));
+        let mut proto = PureProto::new(
+            CountWrites(mock.clone(), writes.clone()), cfg,
+            respond(buffered(), small_response));
+        mock.add_input(input);
+        proto.process().unwrap();
+        (writes.load(Ordering::SeqCst),
+         String::from_utf8_lossy(&mock.output(..)).to_string())
+    }
+
+    /// Accepts body of `/upload` (and echoes it), rejects other requests
+    struct ExpectDisp;
+
+    struct ExpectCodec {
+        accept: bool,
+        body: Vec<u8>,
+    }
+
+    impl Dispatcher<MockData> for ExpectDisp {
+        type Codec = ExpectCodec;
+
+        fn headers_received(&mut self, headers: &Head)
+            -> Result<Self::Codec, Error>
+        {
+            assert!(headers.expect_continue());
+            Ok(ExpectCodec { accept: headers.path() == Some("/upload"),
+                             body: Vec::new() })
+        }
+    }
+
+    impl Codec<MockData> for ExpectCodec {
+        type ResponseFuture = FutureResult<EncoderDone<MockData>, Error>;
+        fn recv_mode(&mut self) -> RecvMode {
+            if self.accept {
+                RecvMode::buffered_upfront(1024)
+            } else {
+                RecvMode::reject_body()
+            }
+        }
+        fn data_received(&mut self, data: &[u8], end: bool)
+            -> Result<Async<usize>, Error>
+        {
+            assert!(self.accept);
+            assert!(end);
+            self.body.extend_from_slice(data);
+            Ok(Async::Ready(data.len()))
+        }
+        fn start_response(&mut self, mut e: Encoder<MockData>)
+            -> Self::ResponseFuture
+        {
+            if self.accept {
+                // no-op, as protocol has already sent `100 Continue`
+                e.response_continue();
+                e.status(Status::Ok);
+            } else {
+                e.status(Status::Forbidden);
+            }
+            e.add_length(self.body.len() as u64).unwrap();
+            e.done_headers().unwrap();
+            e.write_body(&self.body);
+            ok(e.done())
+        }
+    }
+
+    /// Checks that protocol wakes up `timeo` after the last `process()`
+    /// that was started at `start`, returns the deadline
+    fn deadline<S: AsyncRead + AsyncWrite, D: Dispatcher<S>>(
+        proto: &mut PureProto<S, D>, start: Instant, timeo: Duration)
+        -> Instant
+    {
+        let deadline = proto.timeout().expect("deadline is set");
+        assert!(deadline >= start + timeo);
+        assert!(deadline <= Instant::now() + timeo);
+        deadline
+    }
+
+    fn sleep_until(deadline: Instant) {
+        let now = Instant::now();
+        if deadline > now {
+            sleep(deadline - now);
+        }
+    }
+
+    fn pipelined_output(cfg: &Arc<Config>) -> Vec<u8> {
+        let counter = AtomicUsize::new(0);
+        let mock = MockData::new();
+        // replies to the first request immediately, and never to others
+        let mut proto = PureProto::new(mock.clone(), cfg,
+            respond(buffered(), |e| {
+                if counter.fetch_add(1, Ordering::SeqCst) > 0 {
+                    Either::A(empty())
+                } else {
+                    Either::B(empty_response(e))
+                }
+            }));
+        proto.process().unwrap();
+        mock.add_input("GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n");
+        proto.process().unwrap();
+        assert_eq!(counter.load(Ordering::SeqCst), 2);
+        mock.output(..)
+    }
+
+    fn first_response(cfg: &Arc<Config>, request: &str) -> String {
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(), cfg,
+            respond(buffered(), empty_response));
+        mock.add_input(request);
+        proto.process().unwrap();
+        String::from_utf8_lossy(&mock.output(..)).to_string()
+    }
+
+    #[test]
+    fn keep_alive_header() {
+        let cfg = Config::new().keep_alive_header(true).done();
+        assert_eq!(first_response(&cfg, "GET / HTTP/1.1\r\n\r\n"),
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\
+             Connection: keep-alive\r\n\r\n");
+        assert_eq!(first_response(&cfg,
+                "GET / HTTP/1.1\r\nConnection: close\r\n\r\n"),
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\
+             Connection: close\r\n\r\n");
+        assert_eq!(first_response(&cfg, "GET / HTTP/1.0\r\n\r\n"),
+            "HTTP/1.0 200 OK\r\nContent-Length: 0\r\n\
+             Connection: close\r\n\r\n");
+        assert_eq!(first_response(&Config::new().done(),
+                "GET / HTTP/1.1\r\n\r\n"),
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    #[cfg(feature="date_header")]
+    fn date_header() {
+        let cfg = Config::new().date_header(true).done();
+        assert!(first_response(&cfg, "GET / HTTP/1.1\r\n\r\n")
+            .starts_with("HTTP/1.1 200 OK\r\nContent-Length: 0\r\nDate: "));
+        let cfg = Config::new().date_header(false).done();
+        assert_eq!(first_response(&cfg, "GET / HTTP/1.1\r\n\r\n"),
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn header_hook() {
+        let cfg = Config::new().header_hook(|h| {
+            assert_eq!(h.get("content-length"), Some(&b"0"[..]));
+            h.add("X-Request-Id", "42");
+        }).done();
+        assert_eq!(first_response(&cfg, "GET / HTTP/1.1\r\n\r\n"),
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\
+             X-Request-Id: 42\r\n\r\n");
+    }
+
+    #[test]
+    fn no_delay_on_last_write() {
+        let input = "GET / HTTP/1.1\r\n\r\n\
+                     GET / HTTP/1.1\r\nExpect: x\r\n\r\n";
+        let (writes, output) = count_writes(&Config::new().done(), input);
+        // response is flushed before the protocol's own reply is written
+        assert_eq!(writes, 2);
+        let cfg = Config::new().no_delay_on_last_write(false).done();
+        // otherwise they are sent together
+        assert_eq!(count_writes(&cfg, input), (1, output));
+    }
+
+    #[test]
+    fn pipelining() {
+        let counter = AtomicUsize::new(0);
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Config::new().done(), MockDisp { counter: &counter });
+        mock.add_input("GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n");
+        proto.process().unwrap();
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+        assert_eq!(proto.waiting.len(), 1);
+    }
+
+    #[test]
+    fn disable_pipelining() {
+        let counter = AtomicUsize::new(0);
+        let mock = MockData::new();
+        let cfg = Config::new().disable_pipelining(true).done();
+        let mut proto = PureProto::new(mock.clone(),
+            &cfg, MockDisp { counter: &counter });
+        mock.add_input("GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n");
+        proto.process().unwrap();
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+        // second request is not parsed until first response is sent
+        assert_eq!(proto.waiting.len(), 0);
+        assert!(matches!(proto.reading, InState::KeepAlive));
+        // when first response is flushed, second one is processed
+        assert_eq!(&pipelined_output(&cfg)[..],
+                   &b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"[..]);
+    }
+
+    #[test]
+    fn close_mid_pipeline() {
+        let counter = AtomicUsize::new(0);
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(), &Config::new().done(),
+            respond(buffered(), |e| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                empty_response(e)
+            }));
+        mock.add_input("GET / HTTP/1.1\r\nConnection: close\r\n\r\n\
+                        GET / HTTP/1.1\r\n\r\n");
+        // connection is closed after the first response
+        assert!(!proto.process().unwrap());
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+        assert_eq!(proto.waiting.len(), 0);
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\
+             Connection: close\r\n\r\n");
+    }
+
+    #[test]
+    #[cfg(feature="unsafe_raw")]
+    fn replay_raw_response() {
+        let recorded = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello";
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Config::new().done(),
+            respond(buffered(), |e| ok(e.raw_response(recorded))));
+        mock.add_input("GET / HTTP/1.1\r\n\r\n\
+                        GET / HTTP/1.1\r\nConnection: close\r\n\r\n\
+                        GET / HTTP/1.1\r\n\r\n");
+        // connection is still closed after the second response
+        assert!(!proto.process().unwrap());
+        assert_eq!(proto.waiting.len(), 0);
+        assert_eq!(&mock.output(..)[..],
+                   &[&recorded[..], &recorded[..]].concat()[..]);
+    }
+
+    #[test]
+    fn requests_remaining() {
+        let mock = MockData::new();
+        let cfg = Config::new().max_requests_per_connection(3).done();
+        let mut proto = PureProto::new(mock.clone(), &cfg,
+            BudgetDisp(Vec::new(), respond(buffered(), empty_response)));
+        mock.add_input("GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n\
+                        GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n");
+        assert!(!proto.process().unwrap());
+        // fourth request is not processed
+        assert_eq!(proto.dispatcher.0, vec![
+            (Some(2), false),
+            (Some(1), false),
+            (Some(0), true),
+        ]);
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n\
+             HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n\
+             HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\
+             Connection: close\r\n\r\n");
+    }
+
+    #[test]
+    fn expect_continue_accept() {
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Config::new().done(), ExpectDisp);
+        mock.add_input("POST /upload HTTP/1.1\r\nExpect: 100-continue\r\n\
+                        Content-Length: 5\r\n\r\n");
+        assert!(proto.process().unwrap());
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "HTTP/1.1 100 Continue\r\n\r\n");
+        mock.add_input("hello");
+        assert!(proto.process().unwrap());
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "HTTP/1.1 100 Continue\r\n\r\n\
+             HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello");
+    }
+
+    #[test]
+    fn expect_continue_reject() {
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Config::new().done(), ExpectDisp);
+        mock.add_input("POST /secret HTTP/1.1\r\nExpect: 100-continue\r\n\
+                        Content-Length: 5\r\n\r\n");
+        assert!(!proto.process().unwrap());
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\
+             Connection: close\r\n\r\n");
+    }
+
+    const UNAUTHORIZED: &str =
+        "HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n";
+
+    /// Replies `401 Unauthorized` without reading the body
+    fn unauthorized<S>(mut e: Encoder<S>)
+        -> FutureResult<EncoderDone<S>, Error>
+    {
+        e.status(Status::Unauthorized);
+        e.add_length(0).unwrap();
+        e.done_headers().unwrap();
+        ok(e.done())
+    }
+
+    fn ignore_body(config: &Arc<Config>, input: &[&str]) -> (bool, String) {
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(), config,
+            respond(RecvMode::ignore_body(), unauthorized));
+        let mut alive = true;
+        for chunk in input {
+            mock.add_input(chunk);
+            alive = proto.process().unwrap();
+        }
+        (alive, String::from_utf8_lossy(&mock.output(..)).to_string())
+    }
+
+    #[test]
+    fn ignore_body_drained() {
+        let cfg = Config::new().done();
+        assert_eq!(ignore_body(&cfg, &[
+                "POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello\
+                 GET / HTTP/1.1\r\n\r\n",
+            ]),
+            (true, [UNAUTHORIZED, UNAUTHORIZED].concat()));
+    }
+
+    #[test]
+    fn ignore_body_reply_early() {
+        let cfg = Config::new().done();
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(), &cfg,
+            respond(RecvMode::ignore_body(), unauthorized));
+        mock.add_input("POST / HTTP/1.1\r\n\
+                        Transfer-Encoding: chunked\r\n\r\n");
+        assert!(proto.process().unwrap());
+        // response is sent before the body is received
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)), UNAUTHORIZED);
+        mock.add_input("5\r\nhello\r\n0\r\n\r\nGET / HTTP/1.1\r\n\r\n");
+        assert!(proto.process().unwrap());
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+                   [UNAUTHORIZED, UNAUTHORIZED].concat());
+    }
+
+    #[test]
+    fn ignore_body_close() {
+        let close = "HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\
+                     Connection: close\r\n\r\n";
+        let cfg = Config::new().max_body_drain(4).done();
+        // length is known to be over the limit
+        assert_eq!(ignore_body(&cfg, &[
+                "POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello\
+                 GET / HTTP/1.1\r\n\r\n",
+            ]),
+            (false, close.to_string()));
+        // client waits for `100 Continue`
+        assert_eq!(ignore_body(&cfg, &[
+                "POST / HTTP/1.1\r\nContent-Length: 1\r\n\
+                 Expect: 100-continue\r\n\r\n",
+            ]),
+            (false, close.to_string()));
+        // chunked body turns out to be over the limit
+        assert_eq!(ignore_body(&cfg, &[
+                "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n",
+                "5\r\nhello\r\n0\r\n\r\nGET / HTTP/1.1\r\n\r\n",
+            ]),
+            (false, UNAUTHORIZED.to_string()));
+    }
+
+    #[test]
+    fn handler_closes_connection() {
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Config::new().done(), respond(buffered(), |mut e| {
+                // `Connection: close` is added by the handler
+                e.status(Status::Ok);
+                e.add_header("Connection", "close").unwrap();
+                assert!(!e.will_keep_alive());
+                e.add_length(0).unwrap();
+                e.done_headers().unwrap();
+                ok(e.done())
+            }));
+        mock.add_input("GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n");
+        // second request is not served
+        assert!(!proto.process().unwrap());
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "HTTP/1.1 200 OK\r\nConnection: close\r\n\
+             Content-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn pipelined_out_of_order() {
+        let mut core = Core::new().unwrap();
+        let senders = Arc::new(Mutex::new(Vec::new()));
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Config::new().inflight_request_limit(3).done(),
+            DeferredDisp(senders.clone()));
+        let mut process = || core.run(lazy(|| proto.process())).unwrap();
+        mock.add_input("GET /1 HTTP/1.1\r\n\r\nGET /2 HTTP/1.1\r\n\r\n\
+                        GET /3 HTTP/1.1\r\n\r\nGET /4 HTTP/1.1\r\n\r\n");
+        assert!(process());
+        // three requests are processed at once, fourth one is not read
+        let mut pending = senders.lock().unwrap().drain(..)
+            .collect::<Vec<_>>();
+        assert_eq!(pending.len(), 3);
+        let first = pending.remove(0);
+        // later requests complete first
+        pending.pop().unwrap().send("3").unwrap();
+        pending.pop().unwrap().send("2").unwrap();
+        assert!(process());
+        assert_eq!(mock.output(..).len(), 0);
+        first.send("1").unwrap();
+        assert!(process());
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "HTTP/1.1 200 OK\r\nContent-Length: 1\r\n\r\n1\
+             HTTP/1.1 200 OK\r\nContent-Length: 1\r\n\r\n2\
+             HTTP/1.1 200 OK\r\nContent-Length: 1\r\n\r\n3");
+        // fourth request is read when there is room
+        assert_eq!(senders.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn abort_body() {
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Config::new().done(), respond(buffered(), |mut e| {
+                e.status(Status::Ok);
+                e.add_chunked().unwrap();
+                e.done_headers().unwrap();
+                e.write_body(b"hello");
+                ok(e.abort_body())
+            }));
+        mock.add_input("GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n");
+        // connection is closed and second request is not served
+        assert!(!proto.process().unwrap());
+        let output = mock.output(..);
+        assert_eq!(String::from_utf8_lossy(&output),
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+             5\r\nhello\r\n");
+        // client sees that body is not finished
+        let head_end = output.windows(4).position(|x| x == b"\r\n\r\n")
+            .unwrap() + 4;
+        let mut body = Buf::new();
+        body.extend(&output[head_end..]);
+        let mut state = chunked::State::new();
+        state.parse(&mut body).unwrap();
+        assert_eq!(state.buffered(), 5);
+        assert!(!state.is_done());
+    }
+
+    #[test]
+    fn abort_raw_body() {
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Config::new().done(), respond(buffered(), |mut e| {
+                // headers are flushed, and a chunk is written directly
+                e.status(Status::Ok);
+                e.add_chunked().unwrap();
+                e.done_headers().unwrap();
+                e.raw_body().map_err(Error::custom).map(|mut raw| {
+                    raw.write_all(b"5\r\nhello\r\n").unwrap();
+                    raw.abort()
+                })
+            }));
+        mock.add_input("GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n");
+        // connection is closed and second request is not served
+        assert!(!proto.process().unwrap());
+        let output = String::from_utf8_lossy(&mock.output(..)).to_string();
+        assert_eq!(output,
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+             5\r\nhello\r\n");
+        assert!(!output.contains("0\r\n\r\n"));
+    }
+
+    #[test]
+    fn handler_timeout() {
+        let counter = AtomicUsize::new(0);
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Config::new().handler_timeout(Duration::from_millis(10)).done(),
+            MockDisp { counter: &counter });
+        mock.add_input("GET / HTTP/1.1\r\n\r\n");
+        let start = Instant::now();
+        assert!(proto.process().unwrap());
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+        assert_eq!(&mock.output(..)[..], b"");
+        let deadline = deadline(&mut proto, start,
+                                Duration::from_millis(10));
+        sleep_until(deadline);
+        assert!(!proto.process().unwrap());
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "HTTP/1.1 503 Service Unavailable\r\n\
+             Content-Length: 0\r\nConnection: close\r\n\r\n");
+    }
+
+    #[test]
+    fn request_deadline() {
+        let counter = AtomicUsize::new(0);
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Config::new().respect_request_deadline(true).done(),
+            MockDisp { counter: &counter });
+        mock.add_input("GET / HTTP/1.1\r\nX-Request-Deadline: 10\r\n\r\n");
+        let start = Instant::now();
+        assert!(proto.process().unwrap());
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+        let deadline = deadline(&mut proto, start,
+                                Duration::from_millis(10));
+        sleep_until(deadline);
+        assert!(!proto.process().unwrap());
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "HTTP/1.1 503 Service Unavailable\r\n\
+             Content-Length: 0\r\nConnection: close\r\n\r\n");
+    }
+
+    #[test]
+    fn truncated_chunked_body() {
+        for &input in &[
+            // mid-chunk
+            &b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+               5\r\nhel"[..],
+            // between chunks
+            &b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+               5\r\nhello\r\n"[..],
+            // last chunk without the final empty line
+            &b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+               5\r\nhello\r\n0\r\n"[..],
+        ] {
+            let mut proto = PureProto::new(Reset(Some(input)),
+                &Config::new().done(),
+                respond(buffered(), |_: Encoder<Reset>| -> Empty<_, _> {
+                    unreachable!("truncated body received as complete");
+                }));
+            let err = proto.process().unwrap_err();
+            assert!(matches!(*err.kind(), ErrorEnum::ConnectionReset));
+        }
+    }
+
+    #[test]
+    fn idle_eof() {
+        // connection closed before the first request
+        let mut proto = PureProto::new(Reset(Some(b"")),
+            &Config::new().done(),
+            respond(buffered(), small_response));
+        assert!(!proto.process().unwrap());
+        // closed between keep-alive requests
+        let mut proto = PureProto::new(
+            Reset(Some(b"GET / HTTP/1.1\r\n\r\n")),
+            &Config::new().done(),
+            respond(buffered(), small_response));
+        assert!(!proto.process().unwrap());
+        // empty lines between requests are allowed (RFC 7230 section 3.5)
+        let mut proto = PureProto::new(
+            Reset(Some(b"GET / HTTP/1.1\r\n\r\n\r\n")),
+            &Config::new().done(),
+            respond(buffered(), small_response));
+        assert!(!proto.process().unwrap());
+    }
+
+    #[test]
+    fn eof_mid_second_request() {
+        let mut proto = PureProto::new(
+            Reset(Some(b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n")),
+            &Config::new().done(),
+            respond(buffered(), small_response));
+        let err = proto.process().unwrap_err();
+        assert!(matches!(*err.kind(), ErrorEnum::ConnectionReset));
+    }
+
+    #[test]
+    fn reset_mid_headers() {
+        let mut proto = PureProto::new(
+            Reset(Some(b"GET / HTTP/1.1\r\nHost: exam")),
+            &Config::new().done(), NoDisp);
+        let err = proto.process().unwrap_err();
+        assert!(matches!(*err.kind(), ErrorEnum::ConnectionReset));
+        assert!(err.is_connection_reset());
+        assert!(!err.is_protocol_error());
+    }
+
+    #[test]
+    fn single_write() {
+        let response = "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello";
+        // head and body of a small response are sent in one syscall
+        assert_eq!(count_writes(&Config::new().done(),
+                                "GET / HTTP/1.1\r\n\r\n"),
+            (1, response.to_string()));
+        // responses of handlers are never held for the next one
+        let cfg = Config::new().no_delay_on_last_write(false).done();
+        assert_eq!(count_writes(&cfg,
+                "GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n"),
+            (2, response.repeat(2)));
+        // but the protocol's own error reply shares the write
+        let (writes, output) = count_writes(&cfg,
+                "GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\nExpect: x\r\n\r\n");
+        assert_eq!(writes, 1);
+        assert!(output.starts_with(response));
+        assert!(output[response.len()..]
+                .starts_with("HTTP/1.1 417 Expectation Failed\r\n"));
+    }
+
+    #[test]
+    fn malformed_headers() {
+        let counter = AtomicUsize::new(0);
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Config::new().done(), MockDisp { counter: &counter });
+        mock.add_input("GET / HTTP/1.1\r\nHost example.com\r\n\r\n");
+        let err = proto.process().unwrap_err();
+        assert!(err.is_protocol_error());
+        assert!(!err.is_connection_reset());
+        assert_eq!(counter.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn normalize_path_escape() {
+        let counter = AtomicUsize::new(0);
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Config::new().normalize_path(true).done(),
+            MockDisp { counter: &counter });
+        mock.add_input("GET /../etc/passwd HTTP/1.1\r\n\r\n");
+        assert!(!proto.process().unwrap());
+        assert_eq!(counter.load(Ordering::SeqCst), 0);
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\
+             Connection: close\r\n\r\n");
+    }
+
+    #[test]
+    fn stalled_body() {
+        let counter = AtomicUsize::new(0);
+        let mut core = Core::new().unwrap();
+        let mock = MockData::new();
+        let proto = Proto::new(mock.clone(),
+            &Config::new()
+                .body_read_idle_timeout(Duration::from_millis(10)).done(),
+            MockDisp { counter: &counter }, &core.handle());
+        mock.add_input("POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nab");
+        let err = core.run(proto).unwrap_err();
+        assert!(matches!(*err.kind(), ErrorEnum::Timeout));
+        assert_eq!(counter.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn request_timeout() {
+        let counter = AtomicUsize::new(0);
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Config::new().request_timeout(Duration::from_millis(100)).done(),
+            MockDisp { counter: &counter });
+        mock.add_input("POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nab");
+        let start = Instant::now();
+        assert!(proto.process().unwrap());
+        let deadline = deadline(&mut proto, start,
+                                Duration::from_millis(100));
+        // reading the body doesn't move the deadline of the whole request
+        for _ in 0..2 {
+            mock.add_input("cd");
+            assert!(proto.process().unwrap());
+            assert_eq!(proto.timeout(), Some(deadline));
+        }
+        sleep_until(deadline);
+        assert!(!proto.process().unwrap());
+        assert_eq!(counter.load(Ordering::SeqCst), 0);
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "HTTP/1.1 408 Request Timeout\r\n\
+             Content-Length: 0\r\nConnection: close\r\n\r\n");
+    }
+
+    #[test]
+    #[cfg(feature="http_types")]
+    fn to_http_request() {
+        let req = parse_http_request(b"GET /hello?x=1 HTTP/1.0\r\n\
+            Host: example.com\r\nAccept: */*\r\nAccept: text/html\r\n\r\n");
+        assert_eq!(req.method(), ::http::Method::GET);
+        assert_eq!(req.uri(), "/hello?x=1");
+        assert_eq!(req.version(), ::http::Version::HTTP_10);
+        assert_eq!(req.headers()["host"], "example.com");
+        assert_eq!(req.headers().get_all("accept").iter().collect::<Vec<_>>(),
+                   vec!["*/*", "text/html"]);
+    }
+
+    #[test]
+    #[cfg(all(feature="http_types", feature="testing"))]
+    fn http_request_roundtrip() {
+        use client;
+        let req = parse_http_request(b"POST http://example.com/x HTTP/1.1\r\n\
+            Host: example.com\r\nX-Header: value\r\n\r\n");
+        let (mut enc, mock) = client::Encoder::for_test();
+        enc.http_request_head(&req).unwrap();
+        enc.done_headers().unwrap();
+        enc.flush().unwrap();
+        let req2 = parse_http_request(&mock.output(..));
+        assert_eq!(req2.method(), req.method());
+        assert_eq!(req2.uri(), "/x");
+        assert_eq!(req2.version(), req.version());
+        assert_eq!(req2.headers(), req.headers());
+    }
+
+    #[test]
+    fn unknown_expectation() {
+        let counter = AtomicUsize::new(0);
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Config::new().done(), MockDisp { counter: &counter });
+        mock.add_input("POST / HTTP/1.1\r\nExpect: foo\r\n\
+                        Content-Length: 3\r\n\r\n");
+        assert!(!proto.process().unwrap());
+        assert_eq!(counter.load(Ordering::SeqCst), 0);
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "HTTP/1.1 417 Expectation Failed\r\n\
+             Content-Length: 0\r\nConnection: close\r\n\r\n");
+    }
+
+    #[test]
+    fn unknown_expectation_allowed() {
+        let counter = AtomicUsize::new(0);
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Config::new().reject_unknown_expect(false).done(),
+            MockDisp { counter: &counter });
+        mock.add_input("GET / HTTP/1.1\r\nExpect: foo\r\n\r\n");
+        assert!(proto.process().unwrap());
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+    }
+
+    struct InspectDisp<'a, F> {
+        counter: &'a AtomicUsize,
+        inspect: F,
+    }
+
+    impl<'a, F: FnMut(&Head)> Dispatcher<MockData> for InspectDisp<'a, F> {
+        type Codec = MockCodec<'a>;
+
+        fn headers_received(&mut self, headers: &Head)
+            -> Result<Self::Codec, Error>
+        {
+            (self.inspect)(headers);
+            Ok(MockCodec { counter: self.counter })
+        }
+    }
+
+    /// Parses request headers and calls `inspect` with the parsed head
+    fn inspect_head<F: FnMut(&Head)>(data: &str, inspect: F)
+        -> Result<(), Error>
+    {
+        let counter = AtomicUsize::new(0);
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(), &Config::new().done(),
+            InspectDisp { counter: &counter, inspect: inspect });
+        mock.add_input(data);
+        proto.process()?;
+        Ok(())
+    }
+
+    fn transfer_encoding(value: &str) -> Result<Vec<String>, Error> {
+        let mut codings = Vec::new();
+        inspect_head(&format!("POST / HTTP/1.1\r\n\
+                               Transfer-Encoding: {}\r\n\r\n", value),
+            |head| {
+                assert_eq!(head.body_length(), None);
+                codings = head.transfer_encoding().iter()
+                    .map(|x| x.to_string()).collect();
+            })?;
+        Ok(codings)
+    }
+
+    #[test]
+    fn transfer_codings() {
+        assert_eq!(transfer_encoding("chunked").unwrap(), vec!["chunked"]);
+        assert_eq!(transfer_encoding("gzip, chunked").unwrap(),
+                   vec!["gzip", "chunked"]);
+        assert_eq!(transfer_encoding("x-custom,gzip ,Chunked").unwrap(),
+                   vec!["x-custom", "gzip", "Chunked"]);
+        let mut codings = Vec::new();
+        inspect_head("POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\
+                      Transfer-Encoding: chunked\r\n\r\n",
+            |head| codings = head.transfer_encoding().iter()
+                .map(|x| x.to_string()).collect())
+            .unwrap();
+        assert_eq!(codings, vec!["gzip", "chunked"]);
+    }
+
+    #[test]
+    fn typed_method() {
+        let mut methods = Vec::new();
+        inspect_head("PROPFIND / HTTP/1.1\r\nHost: example.com\r\n\r\n\
+                      PUT / HTTP/1.1\r\nHost: example.com\r\n\r\n",
+            |head| methods.push(head.typed_method().clone()))
+            .unwrap();
+        assert_eq!(methods, vec![Method::Extension("PROPFIND".into()),
+                                 Method::Put]);
+    }
+
+    #[test]
+    fn head_length() {
+        let first = "GET /index.html HTTP/1.1\r\nHost: example.com\r\n\
+                     Accept: */*\r\n\r\n";
+        let second = "GET / HTTP/1.1\r\n\r\n";
+        let mut lengths = Vec::new();
+        inspect_head(&format!("{}{}", first, second),
+            |head| lengths.push(head.head_length()))
+            .unwrap();
+        assert_eq!(lengths, vec![first.len(), second.len()]);
+    }
+
+    #[test]
+    fn invalid_transfer_codings() {
+        assert!(transfer_encoding("chunked, gzip").is_err());
+        assert!(transfer_encoding("gzip").is_err());
+        assert!(transfer_encoding("chunked, chunked").is_err());
+        assert!(inspect_head("POST / HTTP/1.1\r\n\
+                              Transfer-Encoding: chunked\r\n\
+                              Transfer-Encoding: gzip\r\n\r\n",
+                              |_| {}).is_err());
+    }
+
+    #[test]
+    fn obfuscated_transfer_codings() {
+        for value in &["\tchunked", "chunked\t", "gzip,\tchunked",
+                       "\"chunked\"", "chunked;x=1", "identity, chunked",
+                       "gzip chunked", "chunked, xchunked", "chunk\u{212a}d"]
+        {
+            let err = transfer_encoding(value).unwrap_err();
+            assert!(matches!(*err.kind(), ErrorEnum::TransferEncodingInvalid),
+                    "{:?}", value);
+        }
+        for value in &["\x0bchunked", "chunked\x0b", "chun\x0cked"] {
+            assert!(transfer_encoding(value).is_err(), "{:?}", value);
+        }
+        // obs-fold
+        assert!(inspect_head("POST / HTTP/1.1\r\nTransfer-Encoding:\r\n \
+                              chunked\r\n\r\n", |_| {}).is_err());
+        assert!(inspect_head("POST / HTTP/1.1\r\n\
+                              Transfer-Encoding : chunked\r\n\r\n",
+                              |_| {}).is_err());
+        // duplicate headers where the first one is used by some servers
+        assert!(inspect_head("POST / HTTP/1.1\r\n\
+                              Transfer-Encoding: chunked\r\n\
+                              Transfer-Encoding: cow\r\n\r\n",
+                              |_| {}).is_err());
+        assert!(inspect_head("POST / HTTP/1.1\r\n\
+                              Transfer-Encoding: identity\r\n\
+                              Transfer-Encoding: chunked\r\n\r\n",
+                              |_| {}).is_err());
+        // plain spaces and parameters of other codings are fine
+        assert_eq!(transfer_encoding("  gzip;level=1 ,  chunked  ").unwrap(),
+                   vec!["gzip;level=1", "chunked"]);
+    }
+
+    #[test]
+    fn raw_query() {
+        let mut query = None;
+        inspect_head("GET /search?q=a%20b&x HTTP/1.1\r\n\r\n", |head| {
+            assert_eq!(head.path(), Some("/search?q=a%20b&x"));
+            query = head.raw_query().map(|x| x.to_string());
+        }).unwrap();
+        assert_eq!(query, Some("q=a%20b&x".to_string()));
+        let mut called = false;
+        inspect_head("GET /search HTTP/1.1\r\n\r\n", |head| {
+            assert_eq!(head.raw_query(), None);
+            called = true;
+        }).unwrap();
+        assert!(called);
+    }
+
+    #[test]
+    fn huge_header_value() {
+        let counter = AtomicUsize::new(0);
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Config::new().max_header_value_size(1024).done(),
+            MockDisp { counter: &counter });
+        mock.add_input("GET / HTTP/1.1\r\nX-Ok: value\r\nX-Huge: ");
+        assert!(proto.process().is_ok());
+        // error is returned before the end of the header is received
+        mock.add_input(vec![b'x'; 2000]);
+        assert!(matches!(*proto.process().unwrap_err().kind(),
+                         ErrorEnum::HeaderValueTooLong));
+        assert_eq!(counter.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn header_value_limit() {
+        let counter = AtomicUsize::new(0);
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Config::new().max_header_value_size(5).done(),
+            MockDisp { counter: &counter });
+        mock.add_input("GET / HTTP/1.1\r\nX-A: 12345\r\n\
+                        X-B: 123456\r\n\r\n");
+        assert!(matches!(*proto.process().unwrap_err().kind(),
+                         ErrorEnum::HeaderValueTooLong));
+        assert_eq!(counter.load(Ordering::SeqCst), 0);
+    }
+
+    fn parse_request_line(cfg: &Arc<Config>, line: &str)
+        -> Result<usize, Error>
+    {
+        let counter = AtomicUsize::new(0);
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(), cfg,
+            MockDisp { counter: &counter });
+        mock.add_input(line);
+        mock.add_input("\r\nHost: example.com\r\n\r\n");
+        proto.process()?;
+        Ok(counter.load(Ordering::SeqCst))
+    }
+
+    #[test]
+    fn strict_request_line() {
+        let cfg = Config::new().done();
+        assert_eq!(parse_request_line(&cfg, "GET / HTTP/1.1").unwrap(), 1);
+        assert!(parse_request_line(&cfg, "GET  /  HTTP/1.1").is_err());
+        assert!(parse_request_line(&cfg, "GET\t/ HTTP/1.1").is_err());
+    }
+
+    #[test]
+    fn lenient_request_line() {
+        let cfg = Config::new().lenient_request_line(true).done();
+        assert_eq!(parse_request_line(&cfg, "GET / HTTP/1.1").unwrap(), 1);
+        assert_eq!(parse_request_line(&cfg, "GET  /  HTTP/1.1").unwrap(), 1);
+        assert_eq!(parse_request_line(&cfg, "GET\t/ \tHTTP/1.1").unwrap(), 1);
+    }
+
+    #[test]
+    fn conflicting_host() {
+        let cfg = Config::new().done();
+        assert_eq!(parse_request_line(&cfg,
+            "GET http://other.com/ HTTP/1.1").unwrap(), 1);
+        let cfg = Config::new().reject_conflicting_host(true).done();
+        assert_eq!(parse_request_line(&cfg,
+            "GET http://example.com/ HTTP/1.1").unwrap(), 1);
+        // host is case-insensitive and default port may be omitted
+        assert_eq!(parse_request_line(&cfg,
+            "GET http://Example.COM/ HTTP/1.1").unwrap(), 1);
+        assert_eq!(parse_request_line(&cfg,
+            "GET http://example.com:80/ HTTP/1.1").unwrap(), 1);
+        assert_eq!(parse_request_line(&cfg,
+            "GET http://example.com:8080/ HTTP/1.1").unwrap(), 0);
+        assert_eq!(parse_request_line(&cfg,
+            "GET https://example.com:80/ HTTP/1.1").unwrap(), 0);
+
+        let counter = AtomicUsize::new(0);
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(), &cfg,
+            MockDisp { counter: &counter });
+        mock.add_input("GET http://other.com/ HTTP/1.1\r\n\
+                        Host: example.com\r\n\r\n");
+        assert!(!proto.process().unwrap());
+        assert_eq!(counter.load(Ordering::SeqCst), 0);
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\
+             Connection: close\r\n\r\n");
+    }
+
+    #[test]
+    fn trace() {
+        let counter = AtomicUsize::new(0);
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Config::new().reply_to_trace(true).done(),
+            MockDisp { counter: &counter });
+        mock.add_input("TRACE /a?b HTTP/1.1\r\nHost: example.com\r\n\
+                        Cookie: secret=1\r\nVia: 1.1 proxy\r\n\r\n");
+        assert!(!proto.process().unwrap());
+        assert_eq!(counter.load(Ordering::SeqCst), 0);
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "HTTP/1.1 200 OK\r\nContent-Type: message/http\r\n\
+             Content-Length: 58\r\nConnection: close\r\n\r\n\
+             TRACE /a?b HTTP/1.1\r\nHost: example.com\r\n\
+             Via: 1.1 proxy\r\n\r\n");
+    }
+
+    #[test]
+    fn trace_disabled() {
+        let counter = AtomicUsize::new(0);
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Config::new().done(), MockDisp { counter: &counter });
+        mock.add_input("TRACE / HTTP/1.1\r\nHost: example.com\r\n\r\n");
+        assert!(proto.process().unwrap());
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+    }
+
     #[test]
     fn simple_get_request() {
         let counter = AtomicUsize::new(0);
@@ -0,0 +1,770 @@
+use std::fmt;
+use std::str;
+#[allow(unused_imports)]
+use std::ascii::AsciiExt;
+
+use futures::{Async, Future, Poll};
+use futures::sync::oneshot::{channel, Sender, Receiver};
+use tokio_io::AsyncWrite;
+
+use base_serializer::HeaderError;
+use client;
+use enums::Status;
+use server::{Encoder, EncoderDone, Error, Head, RecvMode};
+
+
+/// Copies response received by the client into the server response
+///
+/// This is a building block for reverse proxies. Create it from the
+/// encoder of the downstream response and call `headers_received` and
+/// `data_received` from the same methods of your `client::Codec`. The
+/// returned `PumpDone` future resolves when the whole body is forwarded,
+/// so you can return it from the server handler.
+///
+/// Framing is translated as follows:
+///
+/// * `Content-Length` is forwarded as is
+/// * chunked (or close-delimited) body is sent chunked, unless
+///   `buffer_up_to()` is set, in which case body is read into memory and
+///   sent with `Content-Length`
+/// * in the response to a `HEAD` request, `Content-Length` and
+///   `Transfer-Encoding` headers of the upstream response are forwarded
+///   as is, as they describe the body which is not sent
+///
+/// To apply backpressure, upstream data is not consumed (and no more data
+/// is read from the upstream connection) while more than `watermark`
+/// bytes (64KiB by default) are waiting to be sent downstream.
+///
+/// If the pump is dropped before the response is started (i.e. if
+/// upstream request failed) `502 Bad Gateway` is sent instead. If it's
+/// dropped in the middle of the body the downstream response is aborted
+/// and the connection is closed.
+pub struct ResponsePump<S> {
+    encoder: Option<Encoder<S>>,
+    sender: Option<Sender<EncoderDone<S>>>,
+    buffer_limit: Option<usize>,
+    /// Status and headers, kept until the whole body is buffered
+    head: Option<PendingHead>,
+    watermark: usize,
+}
+
+struct PendingHead {
+    code: u16,
+    reason: String,
+    headers: Vec<(String, Vec<u8>)>,
+}
+
+/// A future returned by `ResponsePump::new`, resolves when body is pumped
+pub struct PumpDone<S>(Receiver<EncoderDone<S>>);
+
+/// Copies request body received by the server into the client request
+///
+/// This is the other half of a reverse proxy. Create it in
+/// `Dispatcher::headers_received`, call `recv_mode` and `data_received`
+/// from the same methods of your server `Codec`, and pass the returned
+/// `RequestStart` to the `client::Codec` of the upstream request.
+///
+/// Framing is translated the same way as in `ResponsePump`: fixed length
+/// is forwarded as is, chunked body is sent chunked or, with
+/// `buffer_up_to()`, read into memory and sent with `Content-Length`.
+/// Body received before upstream connection is ready, or while more than
+/// `watermark` bytes are waiting to be sent upstream, is not consumed, so
+/// no more data is read from the downstream connection.
+///
+/// If the upstream request is dropped before it's started the request
+/// body is discarded. If the pump is dropped before the whole body is
+/// forwarded, the upstream request fails.
+pub struct RequestPump<S> {
+    encoder: Option<client::Encoder<S>>,
+    receiver: Option<Receiver<client::Encoder<S>>>,
+    sender: Option<Sender<client::EncoderDone<S>>>,
+    body_length: Option<u64>,
+    /// Request has `Content-Length` (otherwise fixed length is zero)
+    length_header: bool,
+    buffer_limit: Option<usize>,
+    watermark: usize,
+}
+
+/// Upstream half of the `RequestPump`, starts the upstream request body
+pub struct RequestStart<S> {
+    headers: Vec<(String, Vec<u8>)>,
+    sender: Sender<client::Encoder<S>>,
+    receiver: Receiver<client::EncoderDone<S>>,
+}
+
+/// A future returned by `RequestStart::start_write`
+///
+/// Resolves when the whole request body is forwarded, so you can return
+/// it from `client::Codec::start_write`.
+pub struct RequestDone<S> {
+    receiver: Receiver<client::EncoderDone<S>>,
+    error: Option<client::Error>,
+}
+
+/// Body length headers of the upstream response
+///
+/// Only useful for a response to `HEAD`, where body length is reported as
+/// zero.
+fn length_headers(head: &client::Head) -> (Option<u64>, bool) {
+    let mut length = None;
+    let mut chunked = false;
+    for header in head.all_headers() {
+        if header.name.eq_ignore_ascii_case("Content-Length") {
+            length = length.or_else(|| str::from_utf8(header.value).ok()
+                .and_then(|x| x.trim().parse().ok()));
+        } else if header.name.eq_ignore_ascii_case("Transfer-Encoding") {
+            chunked = true;
+        }
+    }
+    (length, chunked)
+}
+
+fn write_head<'x, S, I>(e: &mut Encoder<S>, code: u16, reason: &str,
+    headers: I)
+    -> Result<(), client::Error>
+    where I: IntoIterator<Item=(&'x str, &'x [u8])>
+{
+    e.custom_status(code, reason);
+    for (name, value) in headers {
+        e.add_header(name, value).map_err(client::Error::custom)?;
+    }
+    Ok(())
+}
+
+impl<S> ResponsePump<S> {
+    /// Create a pump writing into the downstream encoder
+    pub fn new(e: Encoder<S>) -> (ResponsePump<S>, PumpDone<S>) {
+        let (tx, rx) = channel();
+        let pump = ResponsePump {
+            encoder: Some(e),
+            sender: Some(tx),
+            buffer_limit: None,
+            head: None,
+            watermark: 65536,
+        };
+        (pump, PumpDone(rx))
+    }
+    /// Read bodies of unknown length into memory, up to `limit` bytes
+    ///
+    /// This allows to send such bodies with `Content-Length` (some clients
+    /// don't support chunked encoding). Body longer than the limit fails
+    /// the upstream request.
+    pub fn buffer_up_to(&mut self, limit: usize) -> &mut Self {
+        self.buffer_limit = Some(limit);
+        self
+    }
+    /// Number of bytes in the downstream buffer that pauses reading upstream
+    pub fn watermark(&mut self, bytes: usize) -> &mut Self {
+        self.watermark = bytes;
+        self
+    }
+}
+
+impl<S: AsyncWrite> ResponsePump<S> {
+    /// Forward status and headers, call it from `Codec::headers_received`
+    ///
+    /// Hop-by-hop headers are not forwarded.
+    ///
+    /// # Panics
+    ///
+    /// When called twice
+    pub fn headers_received(&mut self, head: &client::Head)
+        -> Result<client::RecvMode, client::Error>
+    {
+        let e = self.encoder.as_mut().expect("headers are received once");
+        assert!(!e.is_started() && self.head.is_none(),
+                "headers are received once");
+        // before status is written, this only depends on request method
+        let head_request = !e.response_allows_body();
+        let (code, reason) = head.raw_status();
+        let bodyless = code < 200 || code == 204 || code == 304;
+        match (head.body_length(), self.buffer_limit) {
+            _ if bodyless => {
+                write_head(e, code, reason, head.headers())?;
+            }
+            _ if head_request => {
+                write_head(e, code, reason, head.headers())?;
+                match length_headers(head) {
+                    (Some(length), _) => {
+                        e.add_length(length)
+                            .map_err(client::Error::custom)?;
+                    }
+                    (None, true) => match e.add_chunked() {
+                        Ok(()) | Err(HeaderError::ChunkedNotAllowed) => {}
+                        Err(err) => return Err(client::Error::custom(err)),
+                    },
+                    (None, false) => {}
+                }
+            }
+            (Some(length), _) => {
+                write_head(e, code, reason, head.headers())?;
+                e.add_length(length).map_err(client::Error::custom)?;
+            }
+            (None, Some(limit)) => {
+                // framing is decided when the whole body is received, so
+                // `502` can still be sent if upstream fails until then
+                self.head = Some(PendingHead {
+                    code: code,
+                    reason: reason.to_string(),
+                    headers: head.headers()
+                        .map(|(name, value)| {
+                            (name.to_string(), value.to_vec())
+                        })
+                        .collect(),
+                });
+                return Ok(client::RecvMode::buffered(limit));
+            }
+            (None, None) => {
+                write_head(e, code, reason, head.headers())?;
+                e.add_chunked().map_err(client::Error::custom)?;
+            }
+        }
+        e.done_headers().map_err(client::Error::custom)?;
+        Ok(client::RecvMode::progressive(1))
+    }
+    /// Forward a chunk of the body, call it from `Codec::data_received`
+    pub fn data_received(&mut self, data: &[u8], end: bool)
+        -> Result<Async<usize>, client::Error>
+    {
+        {
+            let e = match self.encoder.as_mut() {
+                Some(e) => e,
+                None => return Ok(Async::Ready(data.len())),
+            };
+            if let Some(PendingHead { code, reason, headers })
+                = self.head.take()
+            {
+                write_head(e, code, &reason, headers.iter()
+                    .map(|&(ref name, ref value)| (&name[..], &value[..])))?;
+                e.add_length(data.len() as u64)
+                    .map_err(client::Error::custom)?;
+                e.done_headers().map_err(client::Error::custom)?;
+            }
+            if data.len() > 0 {
+                if e.bytes_buffered() >= self.watermark {
+                    // schedules a wakeup when downstream is writable
+                    e.flush().map_err(client::Error::custom)?;
+                    if e.bytes_buffered() >= self.watermark {
+                        return Ok(Async::NotReady);
+                    }
+                }
+                e.write_body(data);
+            }
+        }
+        if end {
+            let done = self.encoder.take()
+                .expect("encoder is not done").done();
+            self.sender.take().expect("pump is not done").send(done)
+                .map_err(|_| debug!("Unused proxied response")).ok();
+        }
+        Ok(Async::Ready(data.len()))
+    }
+}
+
+impl<S> RequestPump<S> {
+    /// Create a pump for the body of the downstream request
+    ///
+    /// End-to-end headers of the request are forwarded, except `Host`,
+    /// which you should write in `client::Codec::start_write` along with
+    /// the request line.
+    pub fn new(head: &Head) -> (RequestPump<S>, RequestStart<S>) {
+        let (encoder_tx, encoder_rx) = channel();
+        let (done_tx, done_rx) = channel();
+        let pump = RequestPump {
+            encoder: None,
+            receiver: Some(encoder_rx),
+            sender: Some(done_tx),
+            body_length: head.body_length(),
+            length_header: head.all_headers().iter()
+                .any(|h| h.name.eq_ignore_ascii_case("Content-Length")),
+            buffer_limit: None,
+            watermark: 65536,
+        };
+        let start = RequestStart {
+            headers: head.headers()
+                .map(|(name, value)| (name.to_string(), value.to_vec()))
+                .collect(),
+            sender: encoder_tx,
+            receiver: done_rx,
+        };
+        (pump, start)
+    }
+    /// Read bodies of unknown length into memory, up to `limit` bytes
+    ///
+    /// This allows to send such bodies with `Content-Length` (some servers
+    /// don't support chunked requests). Body longer than the limit fails
+    /// the downstream request.
+    pub fn buffer_up_to(&mut self, limit: usize) -> &mut Self {
+        self.buffer_limit = Some(limit);
+        self
+    }
+    /// Number of bytes in the upstream buffer that pauses reading downstream
+    pub fn watermark(&mut self, bytes: usize) -> &mut Self {
+        self.watermark = bytes;
+        self
+    }
+    /// Receive mode for the downstream request, call it from
+    /// `Codec::recv_mode`
+    pub fn recv_mode(&self) -> RecvMode {
+        match (self.body_length, self.buffer_limit) {
+            (None, Some(limit)) => RecvMode::buffered_upfront(limit),
+            _ => RecvMode::progressive(1),
+        }
+    }
+}
+
+impl<S: AsyncWrite> RequestPump<S> {
+    /// Forward a chunk of the body, call it from `Codec::data_received`
+    pub fn data_received(&mut self, data: &[u8], end: bool)
+        -> Result<Async<usize>, Error>
+    {
+        if self.encoder.is_none() {
+            let mut e = match self.receiver.as_mut().map(|x| x.poll()) {
+                Some(Ok(Async::Ready(e))) => e,
+                Some(Ok(Async::NotReady)) => return Ok(Async::NotReady),
+                Some(Err(_)) => {
+                    debug!("Upstream request is dropped, discarding body");
+                    self.receiver = None;
+                    return Ok(Async::Ready(data.len()));
+                }
+                None => return Ok(Async::Ready(data.len())),
+            };
+            self.receiver = None;
+            match (self.body_length, self.buffer_limit) {
+                (Some(length), _) if self.length_header => {
+                    e.add_length(length).map_err(Error::custom)?;
+                }
+                // request has no body
+                (Some(_), _) => {}
+                (None, Some(_)) => {
+                    // whole body is received at once
+                    e.add_length(data.len() as u64).map_err(Error::custom)?;
+                }
+                (None, None) => {
+                    e.add_chunked().map_err(Error::custom)?;
+                }
+            }
+            e.done_headers().map_err(Error::custom)?;
+            self.encoder = Some(e);
+        }
+        {
+            let e = self.encoder.as_mut().expect("encoder is not done");
+            if data.len() > 0 {
+                if e.bytes_buffered() >= self.watermark {
+                    // schedules a wakeup when upstream is writable
+                    e.flush().map_err(Error::custom)?;
+                    if e.bytes_buffered() >= self.watermark {
+                        return Ok(Async::NotReady);
+                    }
+                }
+                e.write_body(data);
+            }
+        }
+        if end {
+            let done = self.encoder.take()
+                .expect("encoder is not done").done();
+            self.sender.take().expect("pump is not done").send(done)
+                .map_err(|_| debug!("Unused proxied request")).ok();
+        }
+        Ok(Async::Ready(data.len()))
+    }
+}
+
+impl<S> RequestStart<S> {
+    /// Forward headers and start the body, call it from
+    /// `client::Codec::start_write` after writing the request line
+    ///
+    /// Body length headers are added when the body starts arriving, so
+    /// you can't add headers to the encoder after calling this method.
+    pub fn start_write(self, mut e: client::Encoder<S>) -> RequestDone<S> {
+        let mut error = None;
+        for &(ref name, ref value) in &self.headers {
+            if let Err(err) = e.add_header(name, value) {
+                error = Some(client::Error::custom(err));
+                break;
+            }
+        }
+        if error.is_none() {
+            self.sender.send(e)
+                .map_err(|_| debug!("Unused proxied request body")).ok();
+        }
+        RequestDone { receiver: self.receiver, error: error }
+    }
+}
+
+impl<S> Drop for ResponsePump<S> {
+    fn drop(&mut self) {
+        if let Some(mut e) = self.encoder.take() {
+            let done = if e.is_started() {
+                e.abort_body()
+            } else {
+                e.status(Status::BadGateway);
+                e.add_length(0).expect("can add length");
+                e.done_headers().expect("can finish headers");
+                e.done()
+            };
+            self.sender.take().map(|tx| tx.send(done).ok());
+        }
+    }
+}
+
+impl<S> fmt::Debug for ResponsePump<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ResponsePump")
+            .field("done", &self.encoder.is_none())
+            .field("buffer_limit", &self.buffer_limit)
+            .field("watermark", &self.watermark)
+            .finish()
+    }
+}
+
+impl<S> fmt::Debug for PumpDone<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("PumpDone")
+    }
+}
+
+impl<S> fmt::Debug for RequestPump<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RequestPump")
+            .field("done", &self.sender.is_none())
+            .field("body_length", &self.body_length)
+            .field("buffer_limit", &self.buffer_limit)
+            .field("watermark", &self.watermark)
+            .finish()
+    }
+}
+
+impl<S> fmt::Debug for RequestStart<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("RequestStart")
+    }
+}
+
+impl<S> fmt::Debug for RequestDone<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("RequestDone")
+    }
+}
+
+impl<S> Future for PumpDone<S> {
+    type Item = EncoderDone<S>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<EncoderDone<S>, Error> {
+        self.0.poll().map_err(Error::custom)
+    }
+}
+
+impl<S> Future for RequestDone<S> {
+    type Item = client::EncoderDone<S>;
+    type Error = client::Error;
+    fn poll(&mut self) -> Poll<client::EncoderDone<S>, client::Error> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.receiver.poll().map_err(client::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use futures::{Async, Future, Sink};
+    use futures::future::{FutureResult, ok, lazy};
+    use tokio_core::reactor::Core;
+
+    use client;
+    use enums::{Status, Version};
+    use server::{self, Config, Dispatcher, Head, RecvMode};
+    use server::encoder::{for_test_io, get_inner};
+    use server::proto::PureProto;
+    use test_util::Conn;
+    use super::{ResponsePump, RequestPump, RequestStart, RequestDone};
+
+    struct Proxy(ResponsePump<Conn>, &'static str);
+
+    impl client::Codec<Conn> for Proxy {
+        type Future = FutureResult<client::EncoderDone<Conn>,
+                                   client::Error>;
+        fn start_write(&mut self, mut e: client::Encoder<Conn>)
+            -> Self::Future
+        {
+            e.request_line(self.1, "/", Version::Http11);
+            e.done_headers().unwrap();
+            ok(e.done())
+        }
+        fn headers_received(&mut self, headers: &client::Head)
+            -> Result<client::RecvMode, client::Error>
+        {
+            self.0.headers_received(headers)
+        }
+        fn data_received(&mut self, data: &[u8], end: bool)
+            -> Result<Async<usize>, client::Error>
+        {
+            self.0.data_received(data, end)
+        }
+    }
+
+    fn downstream(is_head: bool) -> (server::Encoder<Conn>, Conn) {
+        let conn = Conn::new();
+        (for_test_io(conn.clone(), is_head), conn)
+    }
+
+    fn pump(method: &'static str, response: &str, buffer: Option<usize>)
+        -> String
+    {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let upstream = Conn::new();
+        let (e, downstream) = downstream(method == "HEAD");
+        let (mut pump, mut done) = ResponsePump::new(e);
+        if let Some(limit) = buffer {
+            pump.buffer_up_to(limit);
+        }
+        let mut proto = client::Proto::new(upstream.clone(), &handle,
+            &client::Config::new().done());
+        let done = core.run(lazy(|| {
+            assert!(proto.start_send(Proxy(pump, method))
+                    .unwrap().is_ready());
+            proto.poll_complete().unwrap();
+            upstream.mock.add_input(response);
+            proto.poll_complete().unwrap();
+            Ok::<_, ()>(done.poll())
+        })).unwrap();
+        match done.unwrap() {
+            Async::Ready(done) => get_inner(done).flush().unwrap(),
+            Async::NotReady => panic!("body is not pumped"),
+        }
+        downstream.output()
+    }
+
+    fn proxy(response: &str, buffer: Option<usize>) -> String {
+        pump("GET", response, buffer)
+    }
+
+    #[test]
+    fn chunked() {
+        assert_eq!(proxy("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\
+                          X-Test: 1\r\n\r\n\
+                          5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n", None),
+            "HTTP/1.1 200 OK\r\nX-Test: 1\r\n\
+             Transfer-Encoding: chunked\r\n\r\n\
+             b\r\nhello world\r\n0\r\n\r\n");
+    }
+
+    #[test]
+    fn chunked_to_length() {
+        assert_eq!(proxy("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\
+                          \r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+                         Some(1024)),
+            "HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nhello world");
+    }
+
+    #[test]
+    fn fixed_length() {
+        assert_eq!(proxy("HTTP/1.1 404 Not Found\r\nContent-Length: 5\r\n\
+                          \r\nhello", None),
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 5\r\n\r\nhello");
+    }
+
+    #[test]
+    fn head() {
+        assert_eq!(pump("HEAD", "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\
+                                 \r\n", None),
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n");
+        assert_eq!(pump("HEAD", "HTTP/1.1 200 OK\r\n\
+                                 Transfer-Encoding: chunked\r\n\r\n",
+                        Some(1024)),
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n");
+    }
+
+    #[test]
+    fn bad_gateway() {
+        let (e, downstream) = downstream(false);
+        let (pump, done) = ResponsePump::new(e);
+        drop(pump);
+        get_inner(done.wait().unwrap()).flush().unwrap();
+        assert_eq!(downstream.output(),
+            "HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn bad_gateway_while_buffering() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let upstream = Conn::new();
+        let (e, downstream) = downstream(false);
+        let (mut pump, done) = ResponsePump::new(e);
+        pump.buffer_up_to(1024);
+        let mut proto = client::Proto::new(upstream.clone(), &handle,
+            &client::Config::new().done());
+        core.run(lazy(|| {
+            assert!(proto.start_send(Proxy(pump, "GET")).unwrap().is_ready());
+            proto.poll_complete().unwrap();
+            upstream.mock.add_input("HTTP/1.1 200 OK\r\n\
+                                     Transfer-Encoding: chunked\r\n\r\n\
+                                     5\r\nhello\r\n");
+            proto.poll_complete().unwrap();
+            Ok::<_, ()>(())
+        })).unwrap();
+        // upstream connection fails, so the pump is dropped
+        drop(proto);
+        get_inner(done.wait().unwrap()).flush().unwrap();
+        assert_eq!(downstream.output(),
+            "HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn backpressure() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let upstream = Conn::new();
+        let (e, downstream) = downstream(false);
+        downstream.blocked.set(true);
+        let (mut pump, mut done) = ResponsePump::new(e);
+        pump.watermark(1024);
+        let mut proto = client::Proto::new(upstream.clone(), &handle,
+            &client::Config::new().done());
+        let body = vec![b'x'; 1 << 20];
+        let output = core.run(lazy(|| {
+            assert!(proto.start_send(Proxy(pump, "GET")).unwrap().is_ready());
+            proto.poll_complete().unwrap();
+            upstream.mock.add_input("HTTP/1.1 200 OK\r\n\
+                                     Content-Length: 1048576\r\n\r\n");
+            upstream.mock.add_input(&body);
+            for _ in 0..10 {
+                proto.poll_complete().unwrap();
+            }
+            // upstream is not read while downstream is blocked
+            assert!(upstream.read.get() < body.len() / 2);
+            downstream.blocked.set(false);
+            loop {
+                proto.poll_complete().unwrap();
+                if let Async::Ready(done) = done.poll().unwrap() {
+                    break Ok::<_, ()>(done);
+                }
+            }
+        })).unwrap();
+        get_inner(output).flush().unwrap();
+        assert_eq!(downstream.mock.output(..).len(),
+                   "HTTP/1.1 200 OK\r\nContent-Length: 1048576\r\n\r\n"
+                   .len() + body.len());
+    }
+
+    struct Forward(Option<usize>, Rc<RefCell<Option<RequestStart<Conn>>>>);
+    struct ForwardBody(RequestPump<Conn>);
+    struct Upstream(Option<RequestStart<Conn>>);
+
+    impl Dispatcher<Conn> for Forward {
+        type Codec = ForwardBody;
+        fn headers_received(&mut self, headers: &Head)
+            -> Result<Self::Codec, server::Error>
+        {
+            let (mut pump, start) = RequestPump::new(headers);
+            if let Some(limit) = self.0 {
+                pump.buffer_up_to(limit);
+            }
+            *self.1.borrow_mut() = Some(start);
+            Ok(ForwardBody(pump))
+        }
+    }
+
+    impl server::Codec<Conn> for ForwardBody {
+        type ResponseFuture = FutureResult<server::EncoderDone<Conn>,
+                                           server::Error>;
+        fn recv_mode(&mut self) -> RecvMode {
+            self.0.recv_mode()
+        }
+        fn data_received(&mut self, data: &[u8], end: bool)
+            -> Result<Async<usize>, server::Error>
+        {
+            self.0.data_received(data, end)
+        }
+        fn start_response(&mut self, mut e: server::Encoder<Conn>)
+            -> Self::ResponseFuture
+        {
+            e.status(Status::NoContent);
+            e.done_headers().unwrap();
+            ok(e.done())
+        }
+    }
+
+    impl client::Codec<Conn> for Upstream {
+        type Future = RequestDone<Conn>;
+        fn start_write(&mut self, mut e: client::Encoder<Conn>)
+            -> RequestDone<Conn>
+        {
+            e.request_line("POST", "/", Version::Http11);
+            e.add_header("Host", "upstream").unwrap();
+            self.0.take().unwrap().start_write(e)
+        }
+        fn headers_received(&mut self, _headers: &client::Head)
+            -> Result<client::RecvMode, client::Error>
+        {
+            Ok(client::RecvMode::buffered(0))
+        }
+        fn data_received(&mut self, data: &[u8], _end: bool)
+            -> Result<Async<usize>, client::Error>
+        {
+            Ok(Async::Ready(data.len()))
+        }
+    }
+
+    fn forward(request: &str, buffer: Option<usize>) -> String {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let downstream = Conn::new();
+        let upstream = Conn::new();
+        let start = Rc::new(RefCell::new(None));
+        let mut server = PureProto::new(downstream.clone(),
+            &Config::new().done(), Forward(buffer, start.clone()));
+        let mut client = client::Proto::new(upstream.clone(), &handle,
+            &client::Config::new().done());
+        core.run(lazy(|| {
+            downstream.mock.add_input(request);
+            server.process().unwrap();
+            // body is not consumed until upstream request is started
+            let start = start.borrow_mut().take().expect("request started");
+            assert!(client.start_send(Upstream(Some(start)))
+                    .unwrap().is_ready());
+            client.poll_complete().unwrap();
+            server.process().unwrap();
+            client.poll_complete().unwrap();
+            Ok::<_, ()>(())
+        })).unwrap();
+        upstream.output()
+    }
+
+    #[test]
+    fn request_chunked() {
+        assert_eq!(forward("POST / HTTP/1.1\r\nHost: example.com\r\n\
+                            Transfer-Encoding: chunked\r\nX-Test: 1\r\n\r\n\
+                            5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n", None),
+            "POST / HTTP/1.1\r\nHost: upstream\r\nX-Test: 1\r\n\
+             Transfer-Encoding: chunked\r\n\r\n\
+             b\r\nhello world\r\n0\r\n\r\n");
+    }
+
+    #[test]
+    fn request_chunked_to_length() {
+        assert_eq!(forward("POST / HTTP/1.1\r\nHost: example.com\r\n\
+                            Transfer-Encoding: chunked\r\n\r\n\
+                            5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+                           Some(1024)),
+            "POST / HTTP/1.1\r\nHost: upstream\r\n\
+             Content-Length: 11\r\n\r\nhello world");
+    }
+
+    #[test]
+    fn request_fixed_length() {
+        assert_eq!(forward("POST / HTTP/1.1\r\nHost: example.com\r\n\
+                            Content-Length: 5\r\n\r\nhello", None),
+            "POST / HTTP/1.1\r\nHost: upstream\r\n\
+             Content-Length: 5\r\n\r\nhello");
+        assert_eq!(forward("POST / HTTP/1.1\r\nHost: example.com\r\n\r\n",
+                           None),
+            "POST / HTTP/1.1\r\nHost: upstream\r\n\r\n");
+    }
+}
@@ -8,6 +8,8 @@ pub enum Mode {
     BufferedUpfront(usize),
     Progressive(usize),
     Hijack,
+    Reject,
+    Ignore,
 }
 
 impl RecvMode {
@@ -27,10 +29,12 @@ impl RecvMode {
     }
     /// Fetch data chunk-by-chunk.
     ///
-    /// Note, your response handler can start either before or after
-    /// progressive body has started or ended to read. I mean they are
-    /// completely independent, and actual sequence of events depends on other
-    /// requests coming in and performance of a client.
+    /// Note, response handler is started as soon as responses to the
+    /// previous requests are written, i.e. usually before the body is
+    /// received, so the body may be streamed into the response.
+    /// `data_received` may return `Async::NotReady` to wait for something
+    /// else (e.g. for output buffer to be flushed or for an upstream
+    /// connection in a proxy).
     ///
     /// The parameter denotes minimum number of bytes that may be passed
     /// to the protocol handler. This is for performance tuning (i.e. less
@@ -54,6 +58,42 @@ impl RecvMode {
         RecvMode { mode: Mode::Hijack, timeout: None }
     }
 
+    /// Don't read request body, send the response and close the connection
+    ///
+    /// This is useful to reject requests with `Expect: 100-continue`
+    /// (e.g. if authorization is failed or body is too large): client
+    /// doesn't send the body until `100 Continue` is received, which is
+    /// never sent in this mode. The connection is closed because we don't
+    /// know whether client sends the body anyway.
+    ///
+    /// Note: `data_received` method of Codec is never called in this mode.
+    pub fn reject_body() -> RecvMode {
+        RecvMode { mode: Mode::Reject, timeout: None }
+    }
+
+    /// Don't read request body, start the response right away
+    ///
+    /// This is for handlers that don't need the body, or want to reply
+    /// without looking at it (e.g. with `401 Unauthorized`). Unlike other
+    /// modes the body is never buffered, and `start_response()` is called
+    /// as soon as responses to previous requests are written.
+    ///
+    /// The body is discarded in background to keep connection alive, if
+    /// it's known to be not larger than `Config::max_body_drain`. Otherwise
+    /// connection is closed after the response, which is the case for:
+    ///
+    /// * `Content-Length` larger than the limit
+    /// * chunked body that turns out to be larger than the limit while
+    ///   being discarded (response might already be sent with keep-alive
+    ///   by then, it's fine to close the connection anyway)
+    /// * `Expect: 100-continue` requests, as `100 Continue` is never sent
+    ///   and we don't know whether client sends the body anyway
+    ///
+    /// Note: `data_received` method of Codec is never called in this mode.
+    pub fn ignore_body() -> RecvMode {
+        RecvMode { mode: Mode::Ignore, timeout: None }
+    }
+
     /// Change timeout for reading the whole request body to this value
     /// instead of configured default
     ///
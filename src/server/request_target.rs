@@ -1,3 +1,7 @@
+use std::borrow::Cow;
+#[allow(unused_imports)]
+use std::ascii::AsciiExt;
+
 /// A middle part of the request line
 ///
 /// Most people get used to having path there or maybe asterisk. But in the
@@ -74,10 +78,78 @@ pub fn parse(s: &str) -> Option<RequestTarget> {
     return None;
 }
 
+fn is_dot(segment: &str) -> bool {
+    segment == "." || segment.eq_ignore_ascii_case("%2e")
+}
+
+fn is_dotdot(segment: &str) -> bool {
+    segment == ".." ||
+        segment.eq_ignore_ascii_case(".%2e") ||
+        segment.eq_ignore_ascii_case("%2e.") ||
+        segment.eq_ignore_ascii_case("%2e%2e")
+}
+
+/// Returns query string of the path (after the first `?`), not decoded
+pub fn raw_query(path: &str) -> Option<&str> {
+    path.find('?').map(|idx| &path[idx+1..])
+}
+
+/// Normalizes path portion of the request target
+///
+/// This resolves `.` and `..` segments (including percent-encoded ones) and
+/// collapses duplicate slashes, the query string is kept intact. Returns
+/// `None` if path tries to escape above the root (e.g. `/../etc/passwd`).
+///
+/// Path is borrowed if it is already normalized.
+pub fn normalize_path(path: &str) -> Option<Cow<str>> {
+    let (path_only, query) = match path.find('?') {
+        Some(idx) => path.split_at(idx),
+        None => (path, ""),
+    };
+    let mut segments = Vec::new();
+    let mut trailing_slash = false;
+    for segment in path_only.split('/') {
+        trailing_slash = true;
+        if segment.len() == 0 || is_dot(segment) {
+            continue;
+        } else if is_dotdot(segment) {
+            if segments.pop().is_none() {
+                return None;
+            }
+        } else {
+            segments.push(segment);
+            trailing_slash = false;
+        }
+    }
+    let mut result = String::with_capacity(path.len());
+    for segment in &segments {
+        result.push('/');
+        result.push_str(segment);
+    }
+    if trailing_slash || segments.len() == 0 {
+        result.push('/');
+    }
+    if result == path_only {
+        Some(Cow::Borrowed(path))
+    } else {
+        result.push_str(query);
+        Some(Cow::Owned(result))
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use std::borrow::Cow;
     use super::RequestTarget::*;
-    use super::parse;
+    use super::{parse, normalize_path, raw_query};
+
+    #[test]
+    fn test_raw_query() {
+        assert_eq!(raw_query("/hello"), None);
+        assert_eq!(raw_query("/hello?"), Some(""));
+        assert_eq!(raw_query("/hello?a=1&b=%20"), Some("a=1&b=%20"));
+        assert_eq!(raw_query("/hello?a=?b"), Some("a=?b"));
+    }
 
     #[test]
     fn test_empty() {
@@ -128,4 +200,22 @@ mod test {
                                         path: "/hello?world" }));
     }
 
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize_path("/a/../b").unwrap(), "/b");
+        assert_eq!(normalize_path("/a//b").unwrap(), "/a/b");
+        assert_eq!(normalize_path("/a/./b/").unwrap(), "/a/b/");
+        assert_eq!(normalize_path("/a/b/..").unwrap(), "/a/");
+        assert_eq!(normalize_path("/a/%2E%2e/b?x=/../").unwrap(), "/b?x=/../");
+        assert_eq!(normalize_path("").unwrap(), "/");
+        assert_matches!(normalize_path("/a/b?c"), Some(Cow::Borrowed("/a/b?c")));
+        assert_matches!(normalize_path("/"), Some(Cow::Borrowed("/")));
+    }
+
+    #[test]
+    fn test_normalize_escape() {
+        assert_eq!(normalize_path("/../etc/passwd"), None);
+        assert_eq!(normalize_path("/a/../../etc/passwd"), None);
+        assert_eq!(normalize_path("/%2e%2e/etc/passwd"), None);
+    }
 }
@@ -0,0 +1,247 @@
+use std::time::Duration;
+
+use base_serializer::HeaderError;
+use super::Encoder;
+
+
+/// Values of the `Referrer-Policy` header
+const REFERRER_POLICIES: &'static [&'static str] = &[
+    "no-referrer",
+    "no-referrer-when-downgrade",
+    "origin",
+    "origin-when-cross-origin",
+    "same-origin",
+    "strict-origin",
+    "strict-origin-when-cross-origin",
+    "unsafe-url",
+];
+
+/// Value of the `X-Frame-Options` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameOptions {
+    /// Page can't be displayed in a frame
+    Deny,
+    /// Page can only be displayed in a frame on the same origin
+    SameOrigin,
+}
+
+/// A set of response headers hardening the page in browsers
+///
+/// By default `X-Content-Type-Options: nosniff`,
+/// `X-Frame-Options: SAMEORIGIN` and
+/// `Referrer-Policy: strict-origin-when-cross-origin` are sent. Every header
+/// may be disabled, `Content-Security-Policy` and `Expect-CT` are only
+/// sent when configured. Example:
+///
+/// ```ignore
+/// let mut security = SecurityHeaders::new();
+/// security.content_security_policy(Some("default-src 'self'"))?;
+/// e.status(Status::Ok);
+/// security.add_headers(&mut e)?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct SecurityHeaders {
+    nosniff: bool,
+    frame_options: Option<FrameOptions>,
+    referrer_policy: Option<String>,
+    content_security_policy: Option<String>,
+    expect_ct: Option<(Duration, bool)>,
+}
+
+/// Returns true if value may be sent as a header value
+fn valid_value(value: &str) -> bool {
+    value.len() > 0 && value.bytes().all(|c| c >= b' ' && c < 0x7F)
+}
+
+impl SecurityHeaders {
+    /// Create the default set of headers
+    pub fn new() -> SecurityHeaders {
+        SecurityHeaders {
+            nosniff: true,
+            frame_options: Some(FrameOptions::SameOrigin),
+            referrer_policy: Some("strict-origin-when-cross-origin".into()),
+            content_security_policy: None,
+            expect_ct: None,
+        }
+    }
+    /// Send `X-Content-Type-Options: nosniff`
+    ///
+    /// Browsers don't guess content type of the response then, so make
+    /// sure that `Content-Type` is set correctly. Default is `true`.
+    pub fn nosniff(&mut self, value: bool) -> &mut Self {
+        self.nosniff = value;
+        self
+    }
+    /// Value of `X-Frame-Options`, `None` disables the header
+    pub fn frame_options(&mut self, value: Option<FrameOptions>)
+        -> &mut Self
+    {
+        self.frame_options = value;
+        self
+    }
+    /// Value of `Referrer-Policy`, `None` disables the header
+    ///
+    /// A comma-separated list of policies is allowed, browsers use the
+    /// last one they support.
+    ///
+    /// Returns `InvalidHeaderValue` if any of the policies is unknown, the
+    /// value is not changed then.
+    pub fn referrer_policy(&mut self, value: Option<&str>)
+        -> Result<&mut Self, HeaderError>
+    {
+        if let Some(value) = value {
+            if !value.split(',').all(|x| {
+                REFERRER_POLICIES.contains(&x.trim_matches(' '))
+            }) {
+                return Err(HeaderError::InvalidHeaderValue);
+            }
+        }
+        self.referrer_policy = value.map(|x| x.to_string());
+        Ok(self)
+    }
+    /// Value of `Content-Security-Policy`, `None` disables the header
+    ///
+    /// The policy itself is not checked, as it's specific to the site.
+    /// Default is `None`.
+    ///
+    /// Returns `InvalidHeaderValue` if value is empty or contains
+    /// non-printable or non-ASCII characters, the value is not changed then.
+    pub fn content_security_policy(&mut self, value: Option<&str>)
+        -> Result<&mut Self, HeaderError>
+    {
+        if let Some(value) = value {
+            if !valid_value(value) {
+                return Err(HeaderError::InvalidHeaderValue);
+            }
+        }
+        self.content_security_policy = value.map(|x| x.to_string());
+        Ok(self)
+    }
+    /// Send `Expect-CT` header with the specified `max-age`
+    ///
+    /// With `enforce` browsers refuse connection if the certificate is not
+    /// in certificate transparency logs, otherwise they only report. `None`
+    /// disables the header, which is the default.
+    pub fn expect_ct(&mut self, max_age: Option<Duration>, enforce: bool)
+        -> &mut Self
+    {
+        self.expect_ct = max_age.map(|x| (x, enforce));
+        self
+    }
+    /// Add configured headers to the response
+    ///
+    /// Must be called after the status is written. Values are validated
+    /// by the setters, so an error is only returned if encoder doesn't
+    /// accept headers any more (e.g. `HeadersDone`).
+    pub fn add_headers<S>(&self, e: &mut Encoder<S>)
+        -> Result<(), HeaderError>
+    {
+        if self.nosniff {
+            e.add_header("X-Content-Type-Options", "nosniff")?;
+        }
+        if let Some(value) = self.frame_options {
+            e.add_header("X-Frame-Options", match value {
+                FrameOptions::Deny => "DENY",
+                FrameOptions::SameOrigin => "SAMEORIGIN",
+            })?;
+        }
+        if let Some(ref value) = self.referrer_policy {
+            e.add_header("Referrer-Policy", value)?;
+        }
+        if let Some(ref value) = self.content_security_policy {
+            e.add_header("Content-Security-Policy", value)?;
+        }
+        if let Some((max_age, enforce)) = self.expect_ct {
+            if enforce {
+                e.format_header("Expect-CT",
+                    format_args!("max-age={}, enforce", max_age.as_secs()))?;
+            } else {
+                e.format_header("Expect-CT",
+                    format_args!("max-age={}", max_age.as_secs()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> SecurityHeaders {
+        SecurityHeaders::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use base_serializer::HeaderError;
+    use enums::Status;
+    use server::encoder::{for_test, get_inner};
+    use super::{SecurityHeaders, FrameOptions};
+
+    fn respond(security: &SecurityHeaders) -> String {
+        let (mut e, mock) = for_test(false);
+        e.status(Status::Ok);
+        security.add_headers(&mut e).unwrap();
+        e.add_length(0).unwrap();
+        e.done_headers().unwrap();
+        get_inner(e.done()).flush().unwrap();
+        String::from_utf8_lossy(&mock.output(..)).to_string()
+    }
+
+    #[test]
+    fn defaults() {
+        assert_eq!(respond(&SecurityHeaders::new()),
+            "HTTP/1.1 200 OK\r\n\
+             X-Content-Type-Options: nosniff\r\n\
+             X-Frame-Options: SAMEORIGIN\r\n\
+             Referrer-Policy: strict-origin-when-cross-origin\r\n\
+             Content-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn all_configured() {
+        let mut security = SecurityHeaders::new();
+        security.frame_options(Some(FrameOptions::Deny))
+            .referrer_policy(Some("no-referrer, strict-origin")).unwrap()
+            .content_security_policy(Some("default-src 'self'")).unwrap()
+            .expect_ct(Some(Duration::from_secs(86400)), true);
+        assert_eq!(respond(&security),
+            "HTTP/1.1 200 OK\r\n\
+             X-Content-Type-Options: nosniff\r\n\
+             X-Frame-Options: DENY\r\n\
+             Referrer-Policy: no-referrer, strict-origin\r\n\
+             Content-Security-Policy: default-src 'self'\r\n\
+             Expect-CT: max-age=86400, enforce\r\n\
+             Content-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn disabled() {
+        let mut security = SecurityHeaders::new();
+        security.nosniff(false).frame_options(None)
+            .referrer_policy(None).unwrap()
+            .expect_ct(Some(Duration::from_secs(60)), false);
+        assert_eq!(respond(&security),
+            "HTTP/1.1 200 OK\r\nExpect-CT: max-age=60\r\n\
+             Content-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn invalid_referrer_policy() {
+        let mut security = SecurityHeaders::new();
+        assert_matches!(
+            security.referrer_policy(Some("origin, everywhere")),
+            Err(HeaderError::InvalidHeaderValue));
+        assert_eq!(respond(&security), respond(&SecurityHeaders::new()));
+    }
+
+    #[test]
+    fn invalid_content_security_policy() {
+        let mut security = SecurityHeaders::new();
+        assert_matches!(security
+            .content_security_policy(Some("default-src 'self'\r\nX: y")),
+            Err(HeaderError::InvalidHeaderValue));
+        assert_eq!(respond(&security), respond(&SecurityHeaders::new()));
+    }
+}
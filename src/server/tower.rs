@@ -0,0 +1,242 @@
+//! Adapter for serving requests with a `tower_service::Service`
+//!
+//! This module is enabled by `tower` feature. It's built on top of
+//! `server::buffered`, so the mapping is the following:
+//!
+//! * Request is a `buffered::Request`: the parsed head (hop-by-hop headers
+//!   stripped) and the whole request body read into memory
+//! * Response is a `tower::Response`: status, headers and the body that
+//!   is sent with `Content-Length`
+//! * Errors returned by the service (either from `poll_ready` or from the
+//!   future) are wrapped with `Error::custom` and close the connection
+//!
+//! Websockets are not supported by this adapter.
+//!
+//! Example:
+//!
+//! ```ignore
+//! let service = TowerService::new(my_service);
+//! Proto::new(socket, &cfg,
+//!     BufferedDispatcher::new(addr, &handle, service),
+//!     &handle)
+//! ```
+use std::error::Error as StdError;
+
+use futures::{Future, Async, Poll};
+use futures::future::FutureResult;
+use tk_bufstream::{ReadFramed, WriteFramed};
+use tower_service::Service as TowerServiceTrait;
+
+use websocket::{ServerCodec as WebsocketCodec};
+use super::{Error, Encoder, EncoderDone};
+use super::buffered::{Request, NewService, Service};
+use enums::Status;
+
+
+/// A response returned by tower service
+#[derive(Debug, Clone)]
+pub struct Response {
+    status: Status,
+    headers: Vec<(String, Vec<u8>)>,
+    body: Vec<u8>,
+}
+
+/// A wrapper which turns `tower_service::Service` into `NewService`
+///
+/// The service is cloned for each request, so it should be cheap to clone
+/// (i.e. it's usually an `Arc` or a zero-sized struct)
+pub struct TowerService<T> {
+    service: T,
+}
+
+/// An instance of the `TowerService` for a single request
+pub struct TowerInstance<T> {
+    service: Option<T>,
+}
+
+/// A future returned by `TowerInstance`
+pub struct TowerFuture<S, T: TowerServiceTrait<Request>> {
+    service: T,
+    request: Option<Request>,
+    encoder: Option<Encoder<S>>,
+    future: Option<T::Future>,
+}
+
+impl Response {
+    /// Create a response with specified status and no body
+    pub fn new(status: Status) -> Response {
+        Response {
+            status: status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+    /// Add a header to the response
+    ///
+    /// Note: `Content-Length` and `Transfer-Encoding` are not allowed here,
+    /// content length is added automatically
+    pub fn add_header<N, V>(&mut self, name: N, value: V) -> &mut Self
+        where N: Into<String>,
+              V: Into<Vec<u8>>,
+    {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+    /// Set response body
+    pub fn set_body<B: Into<Vec<u8>>>(&mut self, body: B) -> &mut Self {
+        self.body = body.into();
+        self
+    }
+    /// Get response status
+    pub fn status(&self) -> Status {
+        self.status
+    }
+    /// Get response headers
+    pub fn headers(&self) -> &[(String, Vec<u8>)] {
+        &self.headers
+    }
+    /// Get response body
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+}
+
+impl<T> TowerService<T> {
+    /// Wrap a tower service
+    pub fn new(service: T) -> TowerService<T> {
+        TowerService {
+            service: service,
+        }
+    }
+}
+
+fn write_response<S>(mut e: Encoder<S>, response: Response)
+    -> Result<EncoderDone<S>, Error>
+{
+    e.status(response.status);
+    for &(ref name, ref value) in &response.headers {
+        e.add_header(name, value).map_err(Error::custom)?;
+    }
+    e.add_length(response.body.len() as u64).map_err(Error::custom)?;
+    if e.done_headers().map_err(Error::custom)? {
+        e.write_body(&response.body);
+    }
+    Ok(e.done())
+}
+
+impl<S, T> NewService<S> for TowerService<T>
+    where T: TowerServiceTrait<Request, Response=Response> + Clone,
+          T::Error: Into<Box<StdError + Send + Sync>>,
+{
+    type Future = TowerFuture<S, T>;
+    type Instance = TowerInstance<T>;
+    fn new(&self) -> TowerInstance<T> {
+        TowerInstance {
+            service: Some(self.service.clone()),
+        }
+    }
+}
+
+impl<S, T> Service<S> for TowerInstance<T>
+    where T: TowerServiceTrait<Request, Response=Response>,
+          T::Error: Into<Box<StdError + Send + Sync>>,
+{
+    type Future = TowerFuture<S, T>;
+    type WebsocketFuture = FutureResult<(), ()>;
+    fn call(&mut self, request: Request, encoder: Encoder<S>)
+        -> TowerFuture<S, T>
+    {
+        TowerFuture {
+            service: self.service.take()
+                .expect("service is called once per request"),
+            request: Some(request),
+            encoder: Some(encoder),
+            future: None,
+        }
+    }
+    fn start_websocket(&mut self, _output: WriteFramed<S, WebsocketCodec>,
+                                  _input: ReadFramed<S, WebsocketCodec>)
+        -> Self::WebsocketFuture
+    {
+        // Websockets are not supported by tower adapter
+        Ok(()).into()
+    }
+}
+
+impl<S, T> Future for TowerFuture<S, T>
+    where T: TowerServiceTrait<Request, Response=Response>,
+          T::Error: Into<Box<StdError + Send + Sync>>,
+{
+    type Item = EncoderDone<S>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<EncoderDone<S>, Error> {
+        if self.future.is_none() {
+            match self.service.poll_ready().map_err(Error::custom)? {
+                Async::Ready(()) => {}
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+            let request = self.request.take()
+                .expect("future is polled after completion");
+            self.future = Some(self.service.call(request));
+        }
+        let response = match self.future.as_mut().unwrap().poll()
+            .map_err(Error::custom)?
+        {
+            Async::Ready(response) => response,
+            Async::NotReady => return Ok(Async::NotReady),
+        };
+        let e = self.encoder.take()
+            .expect("future is polled after completion");
+        write_response(e, response).map(Async::Ready)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+
+    use futures::{Async, Poll};
+    use futures::future::{FutureResult, ok};
+    use tk_bufstream::MockData;
+    use tokio_core::reactor::Core;
+    use tower_service::Service;
+
+    use server::Config;
+    use server::buffered::{Request, BufferedDispatcher};
+    use server::proto::PureProto;
+    use enums::Status;
+    use super::{TowerService, Response};
+
+    #[derive(Clone)]
+    struct Hello;
+
+    impl Service<Request> for Hello {
+        type Response = Response;
+        type Error = io::Error;
+        type Future = FutureResult<Response, io::Error>;
+        fn poll_ready(&mut self) -> Poll<(), io::Error> {
+            Ok(Async::Ready(()))
+        }
+        fn call(&mut self, req: Request) -> Self::Future {
+            let mut resp = Response::new(Status::Ok);
+            resp.add_header("X-Path", req.path());
+            resp.set_body("hello");
+            ok(resp)
+        }
+    }
+
+    #[test]
+    fn smoke() {
+        let core = Core::new().unwrap();
+        let mock = MockData::new();
+        let disp = BufferedDispatcher::new("127.0.0.1:1".parse().unwrap(),
+            &core.handle(), TowerService::new(Hello));
+        let mut proto = PureProto::new(mock.clone(),
+            &Config::new().done(), disp);
+        mock.add_input("GET /test HTTP/1.1\r\nHost: example.com\r\n\r\n");
+        proto.process().unwrap();
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "HTTP/1.1 200 OK\r\nX-Path: /test\r\nContent-Length: 5\r\n\r\n\
+             hello");
+    }
+}
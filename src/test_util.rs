@@ -0,0 +1,96 @@
+//! Transports shared by tests of the client and server protocols
+use std::cell::Cell;
+use std::io;
+use std::rc::Rc;
+
+use futures::{Async, Poll};
+use tk_bufstream::MockData;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+
+/// A connection which is reset by peer after sending some data
+///
+/// Note: `tk_bufstream` treats `ConnectionReset` the same as the end of
+/// stream, so protocol sees a connection closed by peer.
+pub struct Reset(pub Option<&'static [u8]>);
+
+impl io::Read for Reset {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.take() {
+            Some(data) => {
+                buf[..data.len()].copy_from_slice(data);
+                Ok(data.len())
+            }
+            None => Err(io::ErrorKind::ConnectionReset.into()),
+        }
+    }
+}
+
+impl AsyncRead for Reset {}
+
+impl io::Write for Reset {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncWrite for Reset {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        Ok(Async::Ready(()))
+    }
+}
+
+/// A connection that counts bytes read and may refuse writes
+///
+/// Clones share the same data, so a test can keep one to check the
+/// output, or to unblock writes, after passing another to the protocol.
+#[derive(Clone)]
+pub struct Conn {
+    pub mock: MockData,
+    pub read: Rc<Cell<usize>>,
+    pub blocked: Rc<Cell<bool>>,
+}
+
+impl Conn {
+    pub fn new() -> Conn {
+        Conn {
+            mock: MockData::new(),
+            read: Rc::new(Cell::new(0)),
+            blocked: Rc::new(Cell::new(false)),
+        }
+    }
+    pub fn output(&self) -> String {
+        String::from_utf8_lossy(&self.mock.output(..)).to_string()
+    }
+}
+
+impl io::Read for Conn {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes = self.mock.read(buf)?;
+        self.read.set(self.read.get() + bytes);
+        Ok(bytes)
+    }
+}
+
+impl AsyncRead for Conn {}
+
+impl io::Write for Conn {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.blocked.get() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        self.mock.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncWrite for Conn {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        Ok(Async::Ready(()))
+    }
+}
@@ -0,0 +1,261 @@
+use std::fmt;
+
+
+quick_error! {
+    /// Error building or parsing `Warning` header
+    #[derive(Debug, PartialEq, Eq)]
+    pub enum WarningError {
+        /// Warning code is not a three-digit number
+        InvalidCode {
+            description("warning code must be a three-digit number")
+        }
+        /// Agent is empty or contains invalid characters
+        InvalidAgent {
+            description("warning agent is empty or contains \
+                         invalid characters")
+        }
+        /// Text or date contains control characters
+        InvalidText {
+            description("warning text contains invalid characters")
+        }
+        /// Header value can't be parsed
+        Syntax {
+            description("invalid syntax of the Warning header")
+        }
+    }
+}
+
+/// A value of the `Warning` header
+///
+/// See [RFC 7234](https://tools.ietf.org/html/rfc7234#section-5.5) for the
+/// description of the header and the list of codes. `Display` formats the
+/// value suitable for `Encoder::add_warning` or `format_header`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    code: u16,
+    agent: String,
+    text: String,
+    date: Option<String>,
+}
+
+fn valid_agent(agent: &str) -> bool {
+    agent.len() > 0 &&
+        agent.chars().all(|c| c.is_ascii_graphic() && c != '"' && c != ',')
+}
+
+fn valid_text(text: &str) -> bool {
+    text.chars().all(|c| c == '\t' || !c.is_control())
+}
+
+impl Warning {
+    /// Create a warning with specified code, agent and text
+    ///
+    /// Agent is usually a host name (with optional port) or a pseudonym
+    /// of the proxy that adds the warning, or `-` if unknown.
+    pub fn new(code: u16, agent: &str, text: &str)
+        -> Result<Warning, WarningError>
+    {
+        if code < 100 || code > 999 {
+            return Err(WarningError::InvalidCode);
+        }
+        if !valid_agent(agent) {
+            return Err(WarningError::InvalidAgent);
+        }
+        if !valid_text(text) {
+            return Err(WarningError::InvalidText);
+        }
+        Ok(Warning {
+            code: code,
+            agent: agent.to_string(),
+            text: text.to_string(),
+            date: None,
+        })
+    }
+    /// Set warning date
+    ///
+    /// Date should be formatted as HTTP-date (same as in `Date` header),
+    /// which is not validated here.
+    pub fn with_date(self, date: &str) -> Result<Warning, WarningError> {
+        if !valid_text(date) {
+            return Err(WarningError::InvalidText);
+        }
+        Ok(Warning { date: Some(date.to_string()), ..self })
+    }
+    /// Warning code
+    pub fn code(&self) -> u16 {
+        self.code
+    }
+    /// Host or pseudonym of the agent that added the warning
+    pub fn agent(&self) -> &str {
+        &self.agent
+    }
+    /// Warning text (unquoted)
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+    /// Warning date if specified (unquoted)
+    pub fn date(&self) -> Option<&str> {
+        self.date.as_ref().map(|x| &x[..])
+    }
+    /// Parse all warnings from a single header value
+    ///
+    /// Header may contain multiple comma-separated warnings. Note: if
+    /// there are multiple `Warning` headers you should call this method for
+    /// each of them.
+    pub fn parse(value: &str) -> Result<Vec<Warning>, WarningError> {
+        use self::WarningError::*;
+
+        let mut result = Vec::new();
+        let mut rest = value;
+        loop {
+            rest = rest.trim_start_matches(
+                |c| c == ' ' || c == '\t' || c == ',');
+            if rest.len() == 0 {
+                return Ok(result);
+            }
+            let code = match rest.get(..3).and_then(|x| x.parse().ok()) {
+                Some(code) if code >= 100 => code,
+                _ => return Err(InvalidCode),
+            };
+            if !rest[3..].starts_with(' ') {
+                return Err(Syntax);
+            }
+            rest = &rest[4..];
+            let agent_end = rest.find(' ').ok_or(Syntax)?;
+            let agent = &rest[..agent_end];
+            if !valid_agent(agent) {
+                return Err(InvalidAgent);
+            }
+            let (text, tail) = parse_quoted(&rest[agent_end+1..])?;
+            rest = tail;
+            let date = if rest.starts_with(" \"") {
+                let (date, tail) = parse_quoted(&rest[1..])?;
+                rest = tail;
+                Some(date)
+            } else {
+                None
+            };
+            rest = rest.trim_start_matches(|c| c == ' ' || c == '\t');
+            if rest.len() > 0 && !rest.starts_with(',') {
+                return Err(Syntax);
+            }
+            result.push(Warning {
+                code: code,
+                agent: agent.to_string(),
+                text: text,
+                date: date,
+            });
+        }
+    }
+}
+
+/// Parses quoted string at the start of `value`, returns unescaped string
+/// and the rest of the value
+fn parse_quoted(value: &str) -> Result<(String, &str), WarningError> {
+    if !value.starts_with('"') {
+        return Err(WarningError::Syntax);
+    }
+    let mut result = String::new();
+    let mut chars = value.char_indices().skip(1);
+    while let Some((idx, c)) = chars.next() {
+        match c {
+            '"' => return Ok((result, &value[idx+1..])),
+            '\\' => match chars.next() {
+                Some((_, c)) => result.push(c),
+                None => break,
+            },
+            c if c != '\t' && c.is_control() => {
+                return Err(WarningError::InvalidText);
+            }
+            c => result.push(c),
+        }
+    }
+    Err(WarningError::Syntax)
+}
+
+struct Quoted<'a>(&'a str);
+
+impl<'a> fmt::Display for Quoted<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("\"")?;
+        for c in self.0.chars() {
+            if c == '"' || c == '\\' {
+                f.write_str("\\")?;
+            }
+            write!(f, "{}", c)?;
+        }
+        f.write_str("\"")
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.code, self.agent, Quoted(&self.text))?;
+        if let Some(ref date) = self.date {
+            write!(f, " {}", Quoted(date))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Warning, WarningError};
+
+    #[test]
+    fn validate() {
+        assert_eq!(Warning::new(99, "-", "x"), Err(WarningError::InvalidCode));
+        assert_eq!(Warning::new(1000, "-", "x"),
+                   Err(WarningError::InvalidCode));
+        assert_eq!(Warning::new(110, "", "x"), Err(WarningError::InvalidAgent));
+        assert_eq!(Warning::new(110, "a b", "x"),
+                   Err(WarningError::InvalidAgent));
+        assert_eq!(Warning::new(110, "-", "a\r\nb"),
+                   Err(WarningError::InvalidText));
+        assert!(Warning::new(299, "proxy:8080", "").is_ok());
+    }
+
+    #[test]
+    fn display() {
+        let w = Warning::new(110, "anderson/1.3.37", "Response is Stale")
+            .unwrap();
+        assert_eq!(w.to_string(), r#"110 anderson/1.3.37 "Response is Stale""#);
+        let w = Warning::new(199, "-", r#"say "hi" \o/"#).unwrap()
+            .with_date("Sat, 25 Aug 2012 23:34:45 GMT").unwrap();
+        assert_eq!(w.to_string(), r#"199 - "say \"hi\" \\o/" "#.to_string()
+            + r#""Sat, 25 Aug 2012 23:34:45 GMT""#);
+    }
+
+    #[test]
+    fn parse_multiple() {
+        let ws = Warning::parse(concat!(
+            r#"110 - "Response is Stale", "#,
+            r#"112 px:80 "Disconnected \"operation\"" "#,
+            r#""Sat, 25 Aug 2012 23:34:45 GMT""#)).unwrap();
+        assert_eq!(ws.len(), 2);
+        assert_eq!(ws[0].code(), 110);
+        assert_eq!(ws[0].agent(), "-");
+        assert_eq!(ws[0].text(), "Response is Stale");
+        assert_eq!(ws[0].date(), None);
+        assert_eq!(ws[1].code(), 112);
+        assert_eq!(ws[1].agent(), "px:80");
+        assert_eq!(ws[1].text(), r#"Disconnected "operation""#);
+        assert_eq!(ws[1].date(), Some("Sat, 25 Aug 2012 23:34:45 GMT"));
+    }
+
+    #[test]
+    fn roundtrip() {
+        let w = Warning::new(214, "-", r#"Transformation "applied""#)
+            .unwrap().with_date("Sat, 25 Aug 2012 23:34:45 GMT").unwrap();
+        assert_eq!(Warning::parse(&w.to_string()).unwrap(), vec![w]);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(Warning::parse("11 - \"x\""), Err(WarningError::InvalidCode));
+        assert_eq!(Warning::parse("110 - x"), Err(WarningError::Syntax));
+        assert_eq!(Warning::parse("110 - \"x"), Err(WarningError::Syntax));
+        assert_eq!(Warning::parse("110 - \"x\" y"), Err(WarningError::Syntax));
+        assert_eq!(Warning::parse(""), Ok(vec![]));
+    }
+}
@@ -1,4 +1,5 @@
 use std::time::Duration;
+use std::usize;
 use std::sync::Arc;
 
 use websocket::{Config};
@@ -11,6 +12,8 @@ impl Config {
             message_timeout: Duration::new(30, 0),
             byte_timeout: Duration::new(30, 0),
             max_packet_size: 10 << 20,
+            idle_timeout: None,
+            max_outbound_frame_size: usize::MAX,
         }
     }
     /// Set ping interval
@@ -96,6 +99,36 @@ impl Config {
         self
     }
 
+    /// Set idle timeout
+    ///
+    /// Default is `None` (no idle timeout).
+    ///
+    /// If no text or binary message is received or sent within this
+    /// interval, we initiate a clean close handshake with code `1000`.
+    /// Unlike `message_timeout` pings and pongs do not reset this timer,
+    /// so it allows to close connections that are alive but unused.
+    pub fn idle_timeout(&mut self, dur: Duration) -> &mut Self {
+        self.idle_timeout = Some(dur);
+        self
+    }
+
+    /// Maximum payload size of the frame sent
+    ///
+    /// Default is unlimited.
+    ///
+    /// Text and binary messages that are larger than this are split into
+    /// multiple frames (continuation frames). Control frames are never
+    /// split.
+    ///
+    /// # Panics
+    ///
+    /// When size is zero
+    pub fn max_outbound_frame_size(&mut self, size: usize) -> &mut Self {
+        assert!(size > 0, "frame size must be positive");
+        self.max_outbound_frame_size = size;
+        self
+    }
+
     /// Create a Arc'd config clone to pass to the constructor
     ///
     /// This is just a convenience method.
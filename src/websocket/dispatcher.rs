@@ -1,13 +1,12 @@
 use std::cmp::min;
 use std::fmt;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Instant, Duration};
 
 use futures::{Future, Async, Stream};
 use futures::future::{FutureResult, ok};
 use futures::stream;
 use tk_bufstream::{ReadFramed, WriteFramed, ReadBuf, WriteBuf};
-use tk_bufstream::{Encode};
 use tokio_io::{AsyncRead, AsyncWrite};
 use tokio_core::reactor::{Handle, Timeout};
 
@@ -48,6 +47,7 @@ pub struct Loop<S, T, D: Dispatcher> {
     last_message_received: Instant,
     last_ping: Instant,
     last_byte: Instant,
+    last_activity: Instant,
     timeout: Timeout,
 }
 
@@ -70,6 +70,15 @@ enum LoopState {
     Done,
 }
 
+fn initial_timeout(config: &Config) -> Duration {
+    let dur = min(config.byte_timeout,
+                  min(config.ping_interval, config.message_timeout));
+    match config.idle_timeout {
+        Some(idle) => min(dur, idle),
+        None => dur,
+    }
+}
+
 // TODO(tailhook) Stream::Error should be Void here
 impl<S, T, D, E> Loop<S, T, D>
     where T: Stream<Item=Packet, Error=E>,
@@ -98,12 +107,10 @@ impl<S, T, D, E> Loop<S, T, D>
             last_message_received: Instant::now(),
             last_ping: Instant::now(),
             last_byte: Instant::now(),
+            last_activity: Instant::now(),
             // Note: we expect that loop is polled immediately, so timeout
             // is polled too
-            timeout: Timeout::new(
-                min(config.byte_timeout,
-                    min(config.ping_interval, config.message_timeout)),
-                handle)
+            timeout: Timeout::new(initial_timeout(config), handle)
                 .expect("Can always set timeout"),
         }
     }
@@ -129,12 +136,10 @@ impl<S, T, D, E> Loop<S, T, D>
             last_message_received: Instant::now(),
             last_ping: Instant::now(),
             last_byte: Instant::now(),
+            last_activity: Instant::now(),
             // Note: we expect that loop is polled immediately, so timeout
             // is polled too
-            timeout: Timeout::new(
-                min(config.byte_timeout,
-                    min(config.ping_interval, config.message_timeout)),
-                handle)
+            timeout: Timeout::new(initial_timeout(config), handle)
                 .expect("Can always set timeout"),
         }
     }
@@ -180,12 +185,10 @@ impl<S> Loop<S, stream::Empty<Packet, VoidError>, BlackHole>
             last_message_received: Instant::now(),
             last_ping: Instant::now(),
             last_byte: Instant::now(),
+            last_activity: Instant::now(),
             // Note: we expect that loop is polled immediately, so timeout
             // is polled too
-            timeout: Timeout::new(
-                min(config.byte_timeout,
-                    min(config.ping_interval, config.message_timeout)),
-                handle)
+            timeout: Timeout::new(initial_timeout(config), handle)
                 .expect("Can always set timeout"),
         }
     }
@@ -208,13 +211,10 @@ impl<S, T, D, E> Loop<S, T, D>
                 match stream.poll()? {
                     Async::Ready(value) => match value {
                         Some(pkt) => {
-                            if self.server {
-                                ServerCodec.encode(pkt,
-                                    &mut self.output.out_buf);
-                            } else {
-                                ClientCodec.encode(pkt,
-                                    &mut self.output.out_buf);
-                            }
+                            self.last_activity = Instant::now();
+                            Frame::from(&pkt).write_fragmented(
+                                &mut self.output.out_buf, !self.server,
+                                self.config.max_outbound_frame_size);
                         }
                         None => {
                             match self.state {
@@ -241,6 +241,26 @@ impl<S, T, D, E> Loop<S, T, D>
         self.stream = None;
         Ok(())
     }
+    fn idle_expired(&self) -> bool {
+        match self.config.idle_timeout {
+            Some(idle) if self.state == LoopState::Open => {
+                Instant::now() >= self.last_activity + idle
+            }
+            _ => false,
+        }
+    }
+    fn next_deadline(&self) -> Instant {
+        let deadline = min(self.last_message_received +
+                self.config.message_timeout,
+            min(self.last_ping + self.config.ping_interval,
+                self.last_byte + self.config.byte_timeout));
+        match self.config.idle_timeout {
+            Some(idle) if self.state == LoopState::Open => {
+                min(deadline, self.last_activity + idle)
+            }
+            _ => deadline,
+        }
+    }
     /// Returns number of messages read
     fn read_messages(&mut self) -> Result<usize, Error> {
         if let Some(mut back) = self.backpressure.take() {
@@ -281,6 +301,7 @@ impl<S, T, D, E> Loop<S, T, D>
                                     &Frame::Close(code, reply)))
                             }
                             pkt @ Frame::Text(_) | pkt @ Frame::Binary(_) => {
+                                self.last_activity = Instant::now();
                                 Some(self.dispatcher.frame(&pkt))
                             }
                         };
@@ -340,13 +361,8 @@ impl<S, T, D, E> Future for Loop<S, T, D>
         }
         if self.read_messages()? > 0 {
             self.last_message_received = Instant::now();
-            self.timeout = Timeout::new_at(
-                min(self.last_message_received +
-                        self.config.message_timeout,
-                min(self.last_ping + self.config.ping_interval,
-                    self.last_byte + self.config.byte_timeout)),
-                &self.handle,
-            ).expect("can always set timeout");
+            self.timeout = Timeout::new_at(self.next_deadline(), &self.handle)
+                .expect("can always set timeout");
         }
         loop {
             match self.timeout.poll().map_err(|_| ErrorEnum::Timeout)? {
@@ -358,6 +374,12 @@ impl<S, T, D, E> Future for Loop<S, T, D>
                     if Instant::now() > deadline {
                         self.state = LoopState::Done;
                         return Ok(Async::Ready(()));
+                    } else if self.idle_expired() {
+                        debug!("Closing idle websocket");
+                        write_close(&mut self.output.out_buf,
+                                    1000, "", !self.server);
+                        self.state = LoopState::CloseSent;
+                        self.output.flush().map_err(ErrorEnum::Io)?;
                     } else if Instant::now() >
                         self.last_ping + self.config.ping_interval
                     {
@@ -373,12 +395,8 @@ impl<S, T, D, E> Future for Loop<S, T, D>
                         self.last_ping = Instant::now();
                     }
 
-                    self.timeout = Timeout::new_at(
-                        min(self.last_message_received +
-                                self.config.message_timeout,
-                        min(self.last_ping + self.config.ping_interval,
-                            self.last_byte + self.config.byte_timeout)),
-                        &self.handle)
+                    self.timeout = Timeout::new_at(self.next_deadline(),
+                                                   &self.handle)
                         .expect("can always set timeout");
                     match self.timeout.poll()
                           .map_err(|_| ErrorEnum::Timeout)?
@@ -409,3 +427,41 @@ impl fmt::Display for VoidError {
         unreachable!();
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use futures::{Future, Stream};
+    use futures::sync::mpsc::unbounded;
+    use tk_bufstream::{IoBuf, MockData};
+    use tokio_core::reactor::{Core, Timeout};
+
+    use websocket::{Config, ServerCodec, Packet};
+    use super::{Loop, BlackHole, VoidError};
+
+    #[test]
+    fn close_idle() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let mock = MockData::new();
+        let (out, inp) = IoBuf::new(mock.clone()).split();
+        let (tx, rx) = unbounded::<Packet>();
+        let idle = Duration::from_millis(50);
+        let cfg = Config::new().idle_timeout(idle).done();
+        let start = Instant::now();
+        let lp = Loop::server(out.framed(ServerCodec), inp.framed(ServerCodec),
+            rx.map_err(|()| VoidError), BlackHole, &cfg, &handle);
+        handle.spawn(lp.map_err(|e| panic!("websocket error: {}", e)));
+        // don't rely on exact timings, a loaded machine may be slow
+        while mock.output(..).len() == 0 {
+            assert!(start.elapsed() < Duration::from_secs(10),
+                    "connection is not closed");
+            core.run(Timeout::new(Duration::from_millis(10), &handle)
+                     .unwrap()).unwrap();
+        }
+        assert!(start.elapsed() >= idle);
+        assert_eq!(mock.output(..), b"\x88\x02\x03\xe8");
+        drop(tx);
+    }
+}
@@ -18,7 +18,7 @@ pub use self::codec::{ServerCodec, ClientCodec};
 pub use self::dispatcher::{Loop, Dispatcher};
 pub use self::error::Error;
 pub use self::keys::{GUID, Accept, Key};
-pub use self::zero_copy::Frame;
+pub use self::zero_copy::{Frame, RawFrame, RawFrames};
 
 
 /// Configuration of a `websocket::Loop` object (a server-side websocket
@@ -29,4 +29,6 @@ pub struct Config {
     message_timeout: Duration,
     byte_timeout: Duration,
     max_packet_size: usize,
+    idle_timeout: Option<Duration>,
+    max_outbound_frame_size: usize,
 }
@@ -1,3 +1,4 @@
+use std::mem::replace;
 use std::str::from_utf8;
 
 use rand::{thread_rng, Rng};
@@ -62,53 +63,18 @@ impl<'a> Frame<'a> {
     {
         use self::Frame::*;
 
-        if buf.len() < 2 {
-            return Ok(None);
-        }
-        let (size, fsize) = {
-            match buf[1] & 0x7F {
-                126 => {
-                    if buf.len() < 4 {
-                        return Ok(None);
-                    }
-                    (BigEndian::read_u16(&buf[2..4]) as u64, 4)
-                }
-                127 => {
-                    if buf.len() < 10 {
-                        return Ok(None);
-                    }
-                    (BigEndian::read_u64(&buf[2..10]), 10)
-                }
-                size => (size as u64, 2),
-            }
+        let (raw, nbytes) = match RawFrame::parse(&mut buf[..], limit)? {
+            Some(pair) => pair,
+            None => return Ok(None),
         };
-        if size > limit as u64 {
-            return Err(ErrorEnum::TooLong);
-        }
-        let size = size as usize;
-        let start = fsize + if masked { 4 } else { 0 } /* mask size */;
-        if buf.len() < start + size {
-            return Ok(None);
-        }
-
-        let fin = buf[0] & 0x80 != 0;
-        let opcode = buf[0] & 0x0F;
-        // TODO(tailhook) should we assert that reserved bits are zero?
-        let mask = buf[1] & 0x80 != 0;
-        if !fin {
+        if !raw.fin {
             return Err(ErrorEnum::Fragmented);
         }
-        if mask != masked {
+        if raw.masked != masked {
             return Err(ErrorEnum::Unmasked);
         }
-        if mask {
-            let mask = [buf[start-4], buf[start-3], buf[start-2], buf[start-1]];
-            for idx in 0..size { // hopefully llvm is smart enough to optimize it
-                buf[start + idx] ^= mask[idx % 4];
-            }
-        }
-        let data = &buf[start..(start + size)];
-        let frame = match opcode {
+        let data = raw.payload;
+        let frame = match raw.opcode {
             0x9 => Ping(data),
             0xA => Pong(data),
             0x1 => Text(from_utf8(data)?),
@@ -123,7 +89,7 @@ impl<'a> Frame<'a> {
             }
             x => return Err(ErrorEnum::InvalidOpcode(x)),
         };
-        return Ok(Some((frame, start + size)));
+        return Ok(Some((frame, nbytes)));
     }
 
     /// Write a frame into specified buffer
@@ -140,12 +106,228 @@ impl<'a> Frame<'a> {
             Close(c, t) => write_close(buf, c, &t, masked),
         }
     }
+
+    /// Write a frame splitting text and binary messages into multiple
+    /// frames if they are larger than `max_frame_size`
+    ///
+    /// Control frames (ping, pong, close) can't be fragmented, so they are
+    /// written as is.
+    ///
+    /// # Panics
+    ///
+    /// When `max_frame_size` is zero
+    pub fn write_fragmented(&self, buf: &mut Buf, masked: bool,
+        max_frame_size: usize)
+    {
+        use self::Frame::*;
+        match *self {
+            Text(data) => write_fragmented(buf, 0x1, data.as_bytes(),
+                                           masked, max_frame_size),
+            Binary(data) => write_fragmented(buf, 0x2, &data,
+                                             masked, max_frame_size),
+            _ => self.write(buf, masked),
+        }
+    }
+}
+
+/// A borrowed frame with all the metadata of the frame header
+///
+/// Unlike `Frame` this one is not interpreted in any way: fragmented
+/// frames, frames with reserved bits set and frames with unknown opcodes
+/// are returned as is. This is useful to implement extensions on top of
+/// this crate.
+///
+/// Payload is already unmasked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawFrame<'a> {
+    opcode: u8,
+    fin: bool,
+    rsv: u8,
+    masked: bool,
+    payload: &'a [u8],
+}
+
+/// An iterator over raw frames in the buffer
+///
+/// Iteration stops when there is no full frame in the buffer or on the
+/// first error. After frames are processed you should use
+/// `buf.consume(frames.consumed())`.
+#[derive(Debug)]
+pub struct RawFrames<'a> {
+    data: &'a mut [u8],
+    limit: usize,
+    consumed: usize,
+    failed: bool,
+}
+
+/// Returns the payload offset and size if the full frame is in the buffer
+fn frame_bounds(data: &[u8], limit: usize)
+    -> Result<Option<(usize, usize)>, ErrorEnum>
+{
+    if data.len() < 2 {
+        return Ok(None);
+    }
+    let (size, fsize) = {
+        match data[1] & 0x7F {
+            126 => {
+                if data.len() < 4 {
+                    return Ok(None);
+                }
+                (BigEndian::read_u16(&data[2..4]) as u64, 4)
+            }
+            127 => {
+                if data.len() < 10 {
+                    return Ok(None);
+                }
+                (BigEndian::read_u64(&data[2..10]), 10)
+            }
+            size => (size as u64, 2),
+        }
+    };
+    if size > limit as u64 {
+        return Err(ErrorEnum::TooLong);
+    }
+    let size = size as usize;
+    let masked = data[1] & 0x80 != 0;
+    let start = fsize + if masked { 4 } else { 0 } /* mask size */;
+    if data.len() < start + size {
+        return Ok(None);
+    }
+    Ok(Some((start, size)))
+}
+
+impl<'a> RawFrame<'a> {
+    /// Parse a raw frame from the specified buffer
+    ///
+    /// Returns a frame and a number of bytes or None if no full frame was
+    /// in the buffer. Payload is unmasked in place, so you should
+    /// `buf.consume(nbytes)` after frame is processed.
+    pub fn parse<'x>(data: &'x mut [u8], limit: usize)
+        -> Result<Option<(RawFrame<'x>, usize)>, ErrorEnum>
+    {
+        let (start, size) = match frame_bounds(data, limit)? {
+            Some(pair) => pair,
+            None => return Ok(None),
+        };
+        let fin = data[0] & 0x80 != 0;
+        let rsv = (data[0] & 0x70) >> 4;
+        let opcode = data[0] & 0x0F;
+        let masked = data[1] & 0x80 != 0;
+        if masked {
+            let mask = [data[start-4], data[start-3],
+                        data[start-2], data[start-1]];
+            for idx in 0..size { // hopefully llvm is smart enough to optimize it
+                data[start + idx] ^= mask[idx % 4];
+            }
+        }
+        Ok(Some((RawFrame {
+            opcode: opcode,
+            fin: fin,
+            rsv: rsv,
+            masked: masked,
+            payload: &data[start..(start + size)],
+        }, start + size)))
+    }
+    /// Opcode of the frame (lower four bits of the first byte)
+    pub fn opcode(&self) -> u8 {
+        self.opcode
+    }
+    /// Returns true if this is a final fragment of the message
+    pub fn is_fin(&self) -> bool {
+        self.fin
+    }
+    /// Value of the RSV1 bit (used by permessage-deflate, for example)
+    pub fn rsv1(&self) -> bool {
+        self.rsv & 0x4 != 0
+    }
+    /// Value of the RSV2 bit
+    pub fn rsv2(&self) -> bool {
+        self.rsv & 0x2 != 0
+    }
+    /// Value of the RSV3 bit
+    pub fn rsv3(&self) -> bool {
+        self.rsv & 0x1 != 0
+    }
+    /// Returns true if the frame was masked
+    pub fn is_masked(&self) -> bool {
+        self.masked
+    }
+    /// Payload of the frame (unmasked)
+    pub fn payload(&self) -> &'a [u8] {
+        self.payload
+    }
+}
+
+impl<'a> RawFrames<'a> {
+    /// Iterate over frames in the buffer
+    ///
+    /// If some frame declares size larger than the `limit` iterator yields
+    /// an error.
+    pub fn new(data: &'a mut [u8], limit: usize) -> RawFrames<'a> {
+        RawFrames {
+            data: data,
+            limit: limit,
+            consumed: 0,
+            failed: false,
+        }
+    }
+    /// Number of bytes of the frames returned so far
+    pub fn consumed(&self) -> usize {
+        self.consumed
+    }
+}
+
+impl<'a> Iterator for RawFrames<'a> {
+    type Item = Result<RawFrame<'a>, ErrorEnum>;
+    fn next(&mut self) -> Option<Result<RawFrame<'a>, ErrorEnum>> {
+        if self.failed {
+            return None;
+        }
+        let nbytes = match frame_bounds(self.data, self.limit) {
+            Ok(Some((start, size))) => start + size,
+            Ok(None) => return None,
+            Err(e) => {
+                self.failed = true;
+                return Some(Err(e));
+            }
+        };
+        let data = replace(&mut self.data, &mut []);
+        let (head, tail) = data.split_at_mut(nbytes);
+        self.data = tail;
+        self.consumed += nbytes;
+        match RawFrame::parse(head, self.limit) {
+            Ok(Some((frame, _))) => Some(Ok(frame)),
+            _ => unreachable!(),
+        }
+    }
 }
 
 pub(crate) fn write_packet(buf: &mut Buf, opcode: u8, data: &[u8], mask: bool)
 {
     debug_assert!(opcode & 0xF0 == 0);
-    let first_byte = opcode | 0x80;  // always fin
+    write_frame(buf, opcode | 0x80 /* always fin */, data, mask);
+}
+
+/// Write a text or binary message splitting it into frames of at most
+/// `max_frame_size` bytes of payload
+pub(crate) fn write_fragmented(buf: &mut Buf, opcode: u8, data: &[u8],
+    mask: bool, max_frame_size: usize)
+{
+    debug_assert!(opcode & 0xF0 == 0);
+    assert!(max_frame_size > 0);
+    if data.len() <= max_frame_size {
+        return write_packet(buf, opcode, data, mask);
+    }
+    let mut chunks = data.chunks(max_frame_size).peekable();
+    let mut opcode = opcode;
+    while let Some(chunk) = chunks.next() {
+        let fin = if chunks.peek().is_none() { 0x80 } else { 0 };
+        write_frame(buf, opcode | fin, chunk, mask);
+        opcode = 0x0;  // continuation
+    }
+}
+
+fn write_frame(buf: &mut Buf, first_byte: u8, data: &[u8], mask: bool) {
     let mask_bit = if mask { 0x80 } else { 0 };
     match data.len() {
         len @ 0...125 => {
@@ -210,7 +392,7 @@ pub(crate) fn write_close(buf: &mut Buf, code: u16, reason: &str, mask: bool) {
 mod test {
     use netbuf::Buf;
     use std::iter::repeat;
-    use super::Frame;
+    use super::{Frame, RawFrames};
     use super::Frame::*;
 
     #[test]
@@ -334,4 +516,64 @@ mod test {
         assert_eq!(Frame::parse(&mut buf, 4096, false).unwrap(),
             Some((Text(&repeat('x').take(4096).collect::<String>()), 4100)));
     }
+
+    #[test]
+    fn raw_frames() {
+        let mut buf = Buf::new();
+        // text frame with RSV1 set, not final
+        buf.extend(b"\x41\x85\x01\x02\x03\x04igohn");
+        // final continuation frame with RSV3 set
+        buf.extend(b"\x90\x02!!");
+        // incomplete frame
+        buf.extend(b"\x81\x05hel");
+        let nbytes = {
+            let mut frames = RawFrames::new(&mut buf[..], 1000);
+            let first = frames.next().unwrap().unwrap();
+            assert_eq!(first.opcode(), 0x1);
+            assert!(!first.is_fin());
+            assert!(first.rsv1());
+            assert!(!first.rsv2());
+            assert!(!first.rsv3());
+            assert!(first.is_masked());
+            assert_eq!(first.payload(), b"hello");
+            let second = frames.next().unwrap().unwrap();
+            assert_eq!(second.opcode(), 0x0);
+            assert!(second.is_fin());
+            assert!(!second.rsv1());
+            assert!(second.rsv3());
+            assert!(!second.is_masked());
+            assert_eq!(second.payload(), b"!!");
+            assert!(frames.next().is_none());
+            frames.consumed()
+        };
+        assert_eq!(nbytes, 15);
+        buf.consume(nbytes);
+        assert_eq!(&buf[..], b"\x81\x05hel");
+    }
+
+    #[test]
+    fn fragmented_message() {
+        let data = (0..1 << 20).map(|x| x as u8).collect::<Vec<_>>();
+        let mut buf = Buf::new();
+        Binary(&data).write_fragmented(&mut buf, true, 64 << 10);
+        let mut frames = RawFrames::new(&mut buf[..], 64 << 10);
+        let mut result: Vec<u8> = Vec::new();
+        for i in 0..16 {
+            let frame = frames.next().unwrap().unwrap();
+            assert_eq!(frame.opcode(), if i == 0 { 0x2 } else { 0x0 });
+            assert_eq!(frame.is_fin(), i == 15);
+            assert_eq!(frame.payload().len(), 64 << 10);
+            result.extend(frame.payload());
+        }
+        assert!(frames.next().is_none());
+        assert!(result == data);
+    }
+
+    #[test]
+    fn control_frames_not_fragmented() {
+        let mut buf = Buf::new();
+        Text("hello").write_fragmented(&mut buf, false, 3);
+        Ping(b"hello").write_fragmented(&mut buf, false, 3);
+        assert_eq!(&buf[..], &b"\x01\x03hel\x80\x02lo\x89\x05hello"[..]);
+    }
 }